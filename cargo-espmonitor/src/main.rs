@@ -16,18 +16,64 @@
 // along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
 
 use cargo_project::{Artifact, Profile, Project};
-use espmonitor::{AppArgs, Chip, Framework, run};
-use pico_args::Arguments;
+use clap::{Args, Parser};
+use espmonitor::{AppArgs, Chip, Framework, MonitorOpts, run, version_info};
 use std::{
     convert::TryFrom,
-    env,
     error::Error,
     ffi::OsString,
     io,
     process::Command,
 };
 
-const DEFAULT_FLASH_BAUD_RATE: u32 = 460_800;
+const DEFAULT_FLASH_BAUD_RATE: &str = "460800";
+
+const EXAMPLES: &str = "EXAMPLES:
+    # Build and flash the release binary, then monitor it
+    cargo espmonitor --release --flash /dev/ttyUSB0
+
+    # Monitor an example built for the ESP32 with ESP-IDF
+    cargo espmonitor --target xtensa-esp32-espidf --example blinky";
+
+// Cargo runs us as `cargo-espmonitor espmonitor ARGS...`.
+#[derive(Parser)]
+#[clap(bin_name = "cargo")]
+enum Cargo {
+    #[clap(about, version, disable_version_flag = true, after_help = EXAMPLES)]
+    Espmonitor(CargoMonitorArgs),
+}
+
+#[derive(Args)]
+struct CargoMonitorArgs {
+    // Not clap's own --version, which would only show the version number.
+    #[clap(short = 'V', long = "version", help = "Show the version, supported chips and enabled features")]
+    show_version: bool,
+    #[clap(flatten)]
+    cargo: CargoOpts,
+    #[clap(flatten)]
+    monitor: MonitorOpts,
+}
+
+#[derive(Args)]
+#[clap(next_help_heading = "CARGO OPTIONS")]
+struct CargoOpts {
+    #[clap(long, help = "Flashes image to device (building first if necessary; requires 'cargo-espflash')")]
+    flash: bool,
+    #[clap(long, value_name = "BAUD", default_value = DEFAULT_FLASH_BAUD_RATE, help = "Baud rate when flashing")]
+    flash_speed: u32,
+    #[clap(long, value_name = "FEATURES", help = "If flashing, build with these features first")]
+    features: Option<String>,
+    #[clap(long, value_name = "TARGET", help = "Infer chip and framework from target triple")]
+    target: Option<String>,
+    #[clap(long, value_name = "FRAMEWORK", conflicts_with = "target", parse(try_from_str = Framework::try_from), help = "Which framework to target: baremetal or esp-idf")]
+    framework: Option<Framework>,
+    #[clap(long, help = "Use the release build")]
+    release: bool,
+    #[clap(long, value_name = "EXAMPLE", help = "Use the named example app binary (and flash it, with --flash)")]
+    example: Option<String>,
+    #[clap(long, value_name = "ELF", parse(from_os_str), help = "Also decode addresses in ELF, e.g. the bootloader (repeatable)")]
+    extra_bin: Vec<OsString>,
+}
 
 struct CargoAppArgs {
     flash: bool,
@@ -39,24 +85,22 @@ struct CargoAppArgs {
 }
 
 fn main() {
-    // Skip first two args ('cargo', 'espmonitor')
-    let args = env::args().skip(2).map(OsString::from).collect();
-
-    if let Err(err) = parse_args(args).and_then(|cargo_app_args|
-        cargo_app_args
-            .map(|mut cargo_app_args| {
-                if cargo_app_args.flash {
-                    run_flash(&mut cargo_app_args)?;
-                }
-                run(cargo_app_args.app_args)
-            })
-            .unwrap_or(Ok(()))
-    ) {
+    let Cargo::Espmonitor(args) = Cargo::parse();
+
+    let result = if args.show_version {
+        println!("{}", version_info("cargo-espmonitor", env!("CARGO_PKG_VERSION")));
+        Ok(())
+    } else {
+        cargo_app_args(args).and_then(|mut cargo_app_args| {
+            if cargo_app_args.flash {
+                run_flash(&mut cargo_app_args)?;
+            }
+            Ok(run(cargo_app_args.app_args)?)
+        })
+    };
+
+    if let Err(err) = result {
         eprintln!("Error: {}", err);
-        eprintln!();
-        if err.downcast::<pico_args::Error>().is_ok() {
-            print_usage();
-        }
         std::process::exit(1);
     }
 }
@@ -89,75 +133,45 @@ fn run_flash(cargo_app_args: &mut CargoAppArgs) -> Result<(), Box<dyn Error>> {
     }
 }
 
-fn parse_args(args: Vec<OsString>) -> Result<Option<CargoAppArgs>, Box<dyn Error>> {
-    let mut args = Arguments::from_vec(args);
+fn cargo_app_args(args: CargoMonitorArgs) -> Result<CargoAppArgs, Box<dyn Error>> {
+    let CargoMonitorArgs { cargo, monitor, .. } = args;
 
-    if args.contains("-h") || args.contains("--help") {
-        print_usage();
-        Ok(None)
-    } else {
-        let (chip, framework) = match args.opt_value_from_str::<&str, String>("--target")? {
-            Some(ref target) => (
-                Chip::from_target(target)?,
-                Framework::from_target(target)?,
-            ),
-            None => (
-                #[allow(clippy::redundant_closure)]
-                args.opt_value_from_fn("--chip", |s| Chip::try_from(s))?.unwrap_or_default(),
-                #[allow(clippy::redundant_closure)]
-                args.opt_value_from_fn("--framework", |s| Framework::try_from(s))?.unwrap_or_default(),
-            )
-        };
-
-        let release = args.contains("--release");
-        let example: Option<String> = args.opt_value_from_str("--example")?;
-
-        let project = Project::query(".").unwrap();
-        let artifact = match example.as_ref() {
-            Some(example) => Artifact::Example(example.as_str()),
-            None => Artifact::Bin(project.name()),
-        };
-        let profile = if release { Profile::Release } else { Profile::Dev };
-
-        let host = "x86_64-unknown-linux-gnu";  // FIXME: does this even matter?
-        let bin = project.path(artifact, profile, Some(&chip.target(framework)), host)?;
-
-        Ok(Some(
-            CargoAppArgs {
-                flash: args.contains("--flash"),
-                flash_speed: args.opt_value_from_fn("--flash-speed", |s| s.parse::<u32>())?.unwrap_or(DEFAULT_FLASH_BAUD_RATE),
-                release: args.contains("--release"),
-                example: args.opt_value_from_str("--example")?,
-                features: args.opt_value_from_str("--features")?,
-                app_args: AppArgs {
-                    chip,
-                    framework,
-                    reset: args.contains("--reset") || !args.contains("--no-reset"),
-                    speed: args.opt_value_from_fn("--speed", |s| s.parse::<usize>())?,
-                    bin: Some(bin.as_os_str().to_os_string()),
-                    serial: args.free_from_str()?,
-                }
-            }
-        ))
-    }
-}
+    let (chip, framework, detect_chip) = match cargo.target.as_ref() {
+        Some(target) => (
+            Chip::from_target(target)?,
+            Framework::from_target(target)?,
+            false,
+        ),
+        None => (
+            monitor.chip.unwrap_or_default(),
+            cargo.framework.unwrap_or_default(),
+            monitor.chip.is_none(),
+        ),
+    };
+
+    let project = Project::query(".").unwrap();
+    let artifact = match cargo.example.as_ref() {
+        Some(example) => Artifact::Example(example.as_str()),
+        None => Artifact::Bin(project.name()),
+    };
+    let profile = if cargo.release { Profile::Release } else { Profile::Dev };
+
+    let host = "x86_64-unknown-linux-gnu";  // FIXME: does this even matter?
+    let bin = project.path(artifact, profile, Some(&chip.target(framework)), host)?;
+
+    let mut app_args = monitor.into_app_args(None)?;
+    app_args.chip = chip;
+    app_args.detect_chip = detect_chip;
+    app_args.framework = framework;
+    app_args.bin = Some(bin.as_os_str().to_os_string());
+    app_args.extra_bins = cargo.extra_bin;
 
-fn print_usage() {
-    let usage = "Usage: cargo espmonitor [OPTIONS] SERIAL_DEVICE\n\
-        \n\
-        \x20   --flash                         Flashes image to device (building first if necessary; requires 'cargo-espflash')\n\
-        \x20   --flash-speed                   Baud rate when flashing (default 460800)\n\
-        \x20   --example EXAMPLE               If flashing, flash this example app\n\
-        \x20   --features FEATURES             If flashing, build with these features first\n\
-        \x20   --target TARGET                 Infer chip and framework from target triple\n\
-        \x20   --chip {esp32|esp32c3|esp8266}  Which ESP chip to target\n\
-        \x20   --framework {baremetal,esp-idf} Which framework to target\n\
-        \x20   --release                       Use the release build\n\
-        \x20   --example EXAMPLE               Use the named example app binary\n\
-        \x20   --reset                         Reset the chip on start (default)\n\
-        \x20   --no-reset                      Do not reset thechip on start\n\
-        \x20   --speed BAUD                    Baud rate of serial device (default: 115200)\n\
-        \x20   SERIAL_DEVICE                   Path to the serial device";
-
-    println!("{}", usage);
+    Ok(CargoAppArgs {
+        flash: cargo.flash,
+        flash_speed: cargo.flash_speed,
+        release: cargo.release,
+        example: cargo.example,
+        features: cargo.features,
+        app_args,
+    })
 }
@@ -0,0 +1,50 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use espmonitor::{SerialState, handle_eof, handle_serial, synthetic_log};
+use std::io;
+
+const LOG_BYTES: usize = 1024 * 1024;
+const CHUNK_SIZE: usize = 4096;
+
+// Line assembly, escape stripping, crash detection and formatting, without
+// a terminal or ELF; `espmonitor --throughput-test` covers the rest.
+fn pipeline(c: &mut Criterion) {
+    let data = synthetic_log(LOG_BYTES);
+
+    let mut group = c.benchmark_group("pipeline");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("synthetic_log", |b| {
+        b.iter_batched(
+            || SerialState::new(None),
+            |mut state| {
+                let mut output = io::sink();
+                for chunk in data.chunks(CHUNK_SIZE) {
+                    handle_serial(&mut state, chunk, &mut output).unwrap();
+                }
+                handle_eof(&mut state, &mut output).unwrap();
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, pipeline);
+criterion_main!(benches);
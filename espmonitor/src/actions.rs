@@ -0,0 +1,113 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::idflog::strip_ansi;
+use regex::Regex;
+use std::{
+    convert::TryFrom,
+    io::{Error as IoError, ErrorKind, Write},
+    process::{Command, Stdio},
+    sync::{Arc, atomic::{AtomicBool, Ordering}},
+    thread,
+};
+
+// Runs a host command whenever a line matches, e.g. to grab a core dump or
+// power-cycle a relay on "Guru Meditation".
+#[derive(Debug, Clone)]
+pub struct MatchAction {
+    re: Regex,
+    command: String,
+}
+
+impl TryFrom<&str> for MatchAction {
+    type Error = IoError;
+    // REGEX:COMMAND, splitting at the first ':' that isn't escaped as '\:'.
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let mut escaped = false;
+        let split = value.char_indices().find(|(_, c)| {
+            let found = *c == ':' && !escaped;
+            escaped = *c == '\\' && !escaped;
+            found
+        });
+        let (pattern, command) = match split {
+            Some((i, _)) => (&value[..i], &value[i + 1..]),
+            None => return Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' should be REGEX:COMMAND", value))),
+        };
+        if command.trim().is_empty() {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is missing a command", value)));
+        }
+
+        let re = Regex::new(&pattern.replace("\\:", ":"))
+            .map_err(|err| IoError::new(ErrorKind::InvalidInput, format!("Invalid --on-match pattern: {}", err)))?;
+        Ok(MatchAction {
+            re,
+            command: command.to_string(),
+        })
+    }
+}
+
+pub(crate) struct ActionRunner {
+    // Each action's flag is set while its command runs, so a burst of
+    // matching lines doesn't start a pile of copies.
+    actions: Vec<(MatchAction, Arc<AtomicBool>)>,
+}
+
+impl ActionRunner {
+    pub fn new(actions: Vec<MatchAction>) -> Self {
+        Self {
+            actions: actions.into_iter().map(|action| (action, Arc::new(AtomicBool::new(false)))).collect(),
+        }
+    }
+
+    pub fn check(&self, line: &str, port: &str) {
+        if self.actions.is_empty() {
+            return;
+        }
+
+        let plain = strip_ansi(line);
+        for (action, running) in self.actions.iter() {
+            if !action.re.is_match(&plain) || running.swap(true, Ordering::SeqCst) {
+                continue;
+            }
+
+            let (shell, shell_arg) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+            let child = Command::new(shell)
+                .arg(shell_arg)
+                .arg(&action.command)
+                .env("ESPMONITOR_LINE", plain.as_ref())
+                .env("ESPMONITOR_PORT", port)
+                .stdin(Stdio::piped())
+                .spawn();
+
+            match child {
+                Ok(mut child) => {
+                    let running = Arc::clone(running);
+                    let input = format!("{}\n", plain);
+                    thread::spawn(move || {
+                        if let Some(mut stdin) = child.stdin.take() {
+                            // Commands that don't read stdin just close it.
+                            let _ = stdin.write_all(input.as_bytes());
+                        }
+                        let _ = child.wait();
+                        running.store(false, Ordering::SeqCst);
+                    });
+                },
+                Err(_) => running.store(false, Ordering::SeqCst),
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// Finds the firmware ELF of an Arduino or PlatformIO project, looking where
+// arduino-cli ("build/<fqbn>/<sketch>.ino.elf", or directly in the build
+// path) and PlatformIO (".pio/build/<env>/firmware.elf") leave it.  With
+// several candidates, the most recently built one wins.
+pub fn find_arduino_elf<P: AsRef<Path>>(project_dir: P) -> Option<PathBuf> {
+    let project_dir = project_dir.as_ref();
+    let build_dir = project_dir.join("build");
+    let pio_dir = project_dir.join(".pio").join("build");
+
+    let mut candidates = Vec::new();
+    candidates.extend(elf_files(&build_dir, |name| name.ends_with(".ino.elf")));
+    for dir in subdirs(&build_dir) {
+        candidates.extend(elf_files(&dir, |name| name.ends_with(".ino.elf")));
+    }
+    for dir in subdirs(&pio_dir) {
+        candidates.extend(elf_files(&dir, |name| name == "firmware.elf"));
+    }
+
+    candidates
+        .into_iter()
+        .max_by_key(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).unwrap_or(SystemTime::UNIX_EPOCH))
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect())
+        .unwrap_or_default()
+}
+
+fn elf_files<F: Fn(&str) -> bool>(dir: &Path, matches: F) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.file_name().and_then(|name| name.to_str()).map(&matches).unwrap_or(false))
+            .collect())
+        .unwrap_or_default()
+}
@@ -0,0 +1,113 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io;
+
+// The serial crate folds EBUSY into its generic "no device" error, so
+// probe the port directly to tell "in use" apart from "not there".
+#[cfg(unix)]
+pub(crate) fn is_busy(path: &str, _err: &io::Error) -> bool {
+    use nix::{errno::Errno, fcntl::{OFlag, open}, sys::stat::Mode, unistd::close};
+
+    match open(path, OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_NONBLOCK, Mode::empty()) {
+        Ok(fd) => {
+            let _ = close(fd);
+            false
+        },
+        Err(errno) => errno == Errno::EBUSY,
+    }
+}
+
+#[cfg(windows)]
+pub(crate) fn is_busy(_path: &str, err: &io::Error) -> bool {
+    // ERROR_ACCESS_DENIED is what opening a COM port someone else has
+    // open looks like.
+    err.to_string().contains("Access is denied")
+}
+
+pub(crate) struct Holder {
+    pub pid: u32,
+    pub name: String,
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn holders(path: &str) -> Vec<Holder> {
+    use std::fs;
+
+    let target = match fs::canonicalize(path) {
+        Ok(target) => target,
+        Err(_) => return Vec::new(),
+    };
+
+    fs::read_dir("/proc")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().to_string_lossy().parse::<u32>().ok().map(|pid| (pid, entry.path())))
+                .filter(|(pid, _)| *pid != std::process::id())
+                .filter(|(_, proc_dir)| {
+                    // Other users' processes aren't readable; there's
+                    // nothing to be done about those.
+                    fs::read_dir(proc_dir.join("fd"))
+                        .map(|fds| fds.filter_map(|fd| fd.ok()).any(|fd| fs::read_link(fd.path()).map(|link| link == target).unwrap_or(false)))
+                        .unwrap_or(false)
+                })
+                .map(|(pid, proc_dir)| Holder {
+                    pid,
+                    name: fs::read_to_string(proc_dir.join("comm")).map(|comm| comm.trim().to_string()).unwrap_or_default(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(target_os = "macos")]
+pub(crate) fn holders(path: &str) -> Vec<Holder> {
+    use std::process::Command;
+
+    let output = match Command::new("lsof").arg("-F").arg("pc").arg("--").arg(path).output() {
+        Ok(output) => output,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut holders: Vec<Holder> = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if let Some(pid) = line.strip_prefix('p').and_then(|pid| pid.parse::<u32>().ok()) {
+            holders.push(Holder { pid, name: String::new() });
+        } else if let (Some(name), Some(holder)) = (line.strip_prefix('c'), holders.last_mut()) {
+            holder.name = name.to_string();
+        }
+    }
+    holders
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub(crate) fn holders(_path: &str) -> Vec<Holder> {
+    Vec::new()
+}
+
+pub(crate) fn describe_holders(path: &str) -> String {
+    let holders = holders(path);
+    if holders.is_empty() {
+        String::new()
+    } else {
+        format!(
+            " by {}",
+            holders.iter().map(|holder| format!("{} (PID {})", holder.name, holder.pid)).collect::<Vec<_>>().join(", "),
+        )
+    }
+}
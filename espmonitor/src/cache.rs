@@ -0,0 +1,75 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::Location;
+use std::collections::HashMap;
+
+pub(crate) const DEFAULT_DECODE_CACHE_SIZE: usize = 1024;
+
+// A small LRU cache of decoded addresses.  Backtraces and tight logging
+// loops tend to print the same handful of addresses over and over, and
+// DWARF lookups are far from free.  A capacity of 0 disables caching.
+pub(crate) struct DecodeCache {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<u64, (Location, u64)>,
+}
+
+impl DecodeCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn get_or_insert_with<F: FnOnce() -> Location>(&mut self, addr: u64, f: F) -> Location {
+        if self.capacity == 0 {
+            return f();
+        }
+
+        self.tick += 1;
+        if let Some((location, last_used)) = self.entries.get_mut(&addr) {
+            *last_used = self.tick;
+            return location.clone();
+        }
+
+        if self.entries.len() >= self.capacity {
+            if let Some(oldest) = self.entries.iter().min_by_key(|(_, (_, last_used))| *last_used).map(|(addr, _)| *addr) {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        let location = f();
+        self.entries.insert(addr, (location.clone(), self.tick));
+        location
+    }
+}
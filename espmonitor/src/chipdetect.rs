@@ -0,0 +1,50 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{CHIP_SPECS, Chip, RomBanner};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref ESP_ROM_RE: Regex = Regex::new(r"^ESP-ROM:(esp32[a-z0-9]*)")
+        .expect("Failed to parse ESP-ROM regex");
+}
+
+pub(crate) enum DetectedChip {
+    Known(Chip),
+    // A chip whose ROM we recognize but that espmonitor doesn't support.
+    Unsupported(String),
+}
+
+// Works out the chip from the first line the ROM prints after a reset.
+// Newer chips name themselves ("ESP-ROM:esp32c3-api1-20210207"); the
+// original ESP32 and the ESP8266 only print their ROM build dates.
+pub(crate) fn detect_chip(line: &str) -> Option<DetectedChip> {
+    if let Some(caps) = ESP_ROM_RE.captures(line) {
+        let name = &caps[1];
+        Some(match CHIP_SPECS.iter().find(|spec| spec.name == name) {
+            Some(spec) => DetectedChip::Known(spec.chip),
+            None => DetectedChip::Unsupported(name.to_string()),
+        })
+    } else {
+        let line = line.trim_start();
+        CHIP_SPECS
+            .iter()
+            .find(|spec| matches!(spec.rom_banner, RomBanner::Dated(date) if line.starts_with(date)))
+            .map(|spec| DetectedChip::Known(spec.chip))
+    }
+}
@@ -0,0 +1,375 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use clap::Args;
+use crate::{
+    AppArgs,
+    Chip,
+    DEFAULT_LOG_KEEP,
+    DEFAULT_QEMU_IMAGE,
+    FlowControl,
+    FrameFormat,
+    Framing,
+    GaugeRule,
+    HighlightRule,
+    Latency,
+    LogRotation,
+    MatchAction,
+    MqttConfig,
+    OutputFormat,
+    PathRemap,
+    PortSelector,
+    Protocol,
+    ResetBehavior,
+    ResetMode,
+    RotateWhen,
+    SinkConfig,
+    SyslogTarget,
+    TransferMode,
+    parse_flow_control,
+    parse_line_level,
+    parse_offset,
+    select_port,
+};
+use regex::Regex;
+use std::{
+    convert::TryFrom,
+    ffi::OsString,
+    io,
+    num::{NonZeroUsize, ParseIntError},
+    time::Duration,
+};
+
+// Options understood by both espmonitor and cargo-espmonitor. Each binary
+// works out the chip, framework and ELF files its own way, and fills those
+// in on the AppArgs this returns.
+#[derive(Args)]
+#[clap(next_help_heading = "MONITOR OPTIONS")]
+pub struct MonitorOpts {
+    #[clap(long, value_name = "CHIP", parse(try_from_str = Chip::try_from), help = "Which ESP chip to target: esp32, esp32s2, esp32s3, esp32c3, esp32c6, esp32h2 or esp8266")]
+    pub chip: Option<Chip>,
+    #[clap(long, overrides_with = "no-reset", help = "Reset the chip on start (default)")]
+    pub reset: bool,
+    #[clap(long, overrides_with = "reset", help = "Do not reset the chip on start")]
+    pub no_reset: bool,
+    #[clap(long, value_name = "BAUD", help = "Baud rate of serial device (default: 115200)")]
+    pub speed: Option<usize>,
+    #[clap(long, value_name = "low|normal", parse(try_from_str = Latency::try_from), help = "Use low for snappier output at the cost of more wakeups (default: normal)")]
+    pub latency: Option<Latency>,
+    #[clap(long, value_name = "BYTES", help = "Maximum bytes per serial read (default: 1024)")]
+    pub read_buffer: Option<NonZeroUsize>,
+    #[clap(long, value_name = "MS", parse(try_from_str = parse_millis), help = "Show unterminated lines after this long (default: 5000)")]
+    pub line_timeout: Option<Duration>,
+    #[clap(long, help = "Show partial lines as they arrive")]
+    pub stream: bool,
+    #[clap(long, help = "Do not wrap long lines to the terminal width")]
+    pub no_wrap: bool,
+    #[clap(long, value_name = "SECS", parse(try_from_str = parse_interval), help = "Show statistics every SECS seconds (toggle with CTRL+T S)")]
+    pub stats_interval: Option<Duration>,
+    #[clap(long, value_name = "MODE", parse(try_from_str = ResetMode::try_from), help = "How to reset the chip: classic, usb-jtag, none, or custom")]
+    pub reset_mode: Option<ResetMode>,
+    #[clap(long, value_name = "SEQ", parse(try_from_str = ResetMode::custom), help = "Custom DTR/RTS reset sequence, e.g. 'D0|R1|W0.1|R0'")]
+    pub reset_sequence: Option<ResetMode>,
+    #[clap(long, value_name = "MS", parse(try_from_str = parse_millis), help = "How long to hold the chip in reset")]
+    pub reset_delay: Option<Duration>,
+    #[clap(long, value_name = "MODE", parse(try_from_str = ResetBehavior::try_from), help = "Mark device reboots with a separator, clear the screen, or do nothing (default)")]
+    pub reset_behavior: Option<ResetBehavior>,
+    #[clap(long, value_name = "terminal|vscode", parse(try_from_str = OutputFormat::try_from), help = "Print decoded addresses and warnings as 'file:line:col: severity: message' with vscode")]
+    pub output: Option<OutputFormat>,
+    #[clap(long, value_name = "CMD", help = "Command to run on CTRL+F while the port is released")]
+    pub flash_cmd: Option<String>,
+    #[clap(long, value_name = "DIR", parse(from_os_str), help = "Run the flash command whenever files in DIR change")]
+    pub watch: Option<OsString>,
+    #[clap(long, value_name = "N", help = "How many decoded addresses to cache (default: 1024)")]
+    pub decode_cache_size: Option<usize>,
+    #[clap(long, conflicts_with = "decode-cache-size", help = "Resolve every address from scratch")]
+    pub no_decode_cache: bool,
+    #[clap(long, help = "Collapse repeated identical lines into a count")]
+    pub dedup: bool,
+    #[clap(long, help = "Highlight what changed from the previous, similar line")]
+    pub highlight_changes: bool,
+    #[clap(long, value_name = "FILE", parse(from_os_str), help = "Append output to FILE (repeatable)")]
+    pub log: Vec<OsString>,
+    #[clap(long, value_name = "FILE", parse(from_os_str), help = "Append output to FILE as JSON lines (repeatable)")]
+    pub log_json: Vec<OsString>,
+    #[clap(long, value_name = "SIZE|INTERVAL", parse(try_from_str = RotateWhen::try_from), help = "Start a new log file once it reaches SIZE (e.g. 10M) or is INTERVAL old (e.g. 30min, 6h, 1d)")]
+    pub log_rotate: Option<RotateWhen>,
+    #[clap(long, value_name = "COUNT", requires = "log-rotate", help = "Rotated log files to keep (default: 5)")]
+    pub log_keep: Option<usize>,
+    #[clap(long, requires = "log-rotate", help = "Compress rotated log files with gzip")]
+    pub log_gzip: bool,
+    #[clap(long, value_name = "HOST:PORT", help = "Send output to a TCP listener (repeatable)")]
+    pub forward: Vec<String>,
+    #[clap(long, help = "Also send lines to the system log")]
+    pub syslog: bool,
+    #[clap(long, help = "Also send lines to the systemd journal (Linux)")]
+    pub journald: bool,
+    #[clap(long, value_name = "URL", help = "Publish lines to an MQTT broker (mqtt://[user[:pass]@]host[:port])")]
+    pub mqtt: Option<String>,
+    #[clap(long, value_name = "TOPIC", default_value = "espmonitor", help = "MQTT topic to publish to")]
+    pub mqtt_topic: String,
+    #[clap(long, requires = "mqtt", help = "Publish JSON records instead of plain lines")]
+    pub mqtt_json: bool,
+    #[clap(long, value_name = "SN", help = "Use the USB serial device with this serial number")]
+    pub port_serial: Option<String>,
+    #[clap(long, value_name = "REGEX", conflicts_with = "port-serial", parse(try_from_str = PortSelector::pattern), help = "Use the serial device whose path or description matches")]
+    pub port_match: Option<PortSelector>,
+    #[clap(long, help = "No raw mode or keyboard commands; for CI, cron and pipes")]
+    pub non_interactive: bool,
+    #[clap(long, value_name = "SECS", parse(try_from_str = parse_secs), help = "Stop monitoring after SECS seconds")]
+    pub exit_after: Option<Duration>,
+    #[clap(long, help = "If the serial device is in use, wait until it is released")]
+    pub wait_for_port: bool,
+    #[clap(long, value_name = "FORMAT", parse(try_from_str = FrameFormat::try_from), help = "Data bits, parity and stop bits (default: 8N1)")]
+    pub serial_config: Option<FrameFormat>,
+    #[clap(long, value_name = "MODE", parse(try_from_str = parse_flow_control), help = "Flow control: none (default), software, or hardware")]
+    pub flow_control: Option<FlowControl>,
+    #[clap(long, value_name = "high|low", parse(try_from_str = parse_line_level), help = "DTR level to set after opening the port and after resets")]
+    pub dtr: Option<bool>,
+    #[clap(long, value_name = "high|low", parse(try_from_str = parse_line_level), help = "RTS level to set after opening the port and after resets")]
+    pub rts: Option<bool>,
+    #[clap(long, value_name = "FILE", parse(from_os_str), help = "Save raw serial data to FILE for espmonitor replay")]
+    pub record: Option<OsString>,
+    #[clap(long, value_name = "FILE", parse(from_os_str), help = "Send FILE to the device once the port is open")]
+    pub send_file: Option<OsString>,
+    #[clap(long, value_name = "MODE", parse(try_from_str = TransferMode::try_from), help = "How to send files: raw (the default), lines (pausing after each), or ymodem")]
+    pub send_mode: Option<TransferMode>,
+    #[clap(long, value_name = "FILE", parse(from_os_str), help = "Run the expect/send script in FILE, exiting when it finishes and failing if it does")]
+    pub script: Option<OsString>,
+    #[clap(long, value_name = "FILE", parse(from_os_str), help = "Write the results of --script or espmonitor test to FILE as JUnit XML")]
+    pub junit: Option<OsString>,
+    #[clap(long, help = "Print failures from --script or espmonitor test as GitHub Actions annotations")]
+    pub github_annotations: bool,
+    #[clap(long, help = "Run the firmware in QEMU instead of monitoring a device")]
+    pub qemu: bool,
+    #[clap(long, value_name = "IMAGE", parse(from_os_str), help = "Flash image for --qemu (default: build/qemu_flash.bin)")]
+    pub qemu_image: Option<OsString>,
+    #[clap(long, value_name = "TAG[:COLOR]", parse(try_from_str = HighlightRule::try_from), help = "Highlight lines logged with this ESP-IDF tag (repeatable)")]
+    pub highlight: Vec<HighlightRule>,
+    #[clap(long, value_name = "REGEX:CMD", parse(try_from_str = MatchAction::try_from), help = "Run CMD when a line matches REGEX (repeatable)")]
+    pub on_match: Vec<MatchAction>,
+    #[clap(long, help = "Show a desktop notification on panics")]
+    pub notify: bool,
+    #[clap(long, value_name = "KEYWORD", help = "Also notify when a line contains KEYWORD (repeatable)")]
+    pub notify_on: Vec<String>,
+    #[clap(long, help = "Drop DTR and RTS when exiting")]
+    pub deassert_on_exit: bool,
+    #[clap(long, conflicts_with_all = &["reset", "reset-mode", "reset-sequence", "dtr", "rts", "deassert-on-exit"], help = "Never change DTR or RTS: no reset on start, and no reset or bootloader keys")]
+    pub no_touch: bool,
+    #[clap(long, value_name = "FILE", parse(from_os_str), help = "Also write the session summary printed on exit to FILE")]
+    pub summary: Option<OsString>,
+    #[clap(long, value_name = "DIR", parse(from_os_str), help = "Write a report for every panic (context and backtrace) into DIR")]
+    pub crash_dir: Option<OsString>,
+    #[clap(long, value_name = "N", help = "Lines before a panic to include in crash reports (default: 50)")]
+    pub crash_context: Option<usize>,
+    #[clap(long, value_name = "LINES", help = "Lines kept in memory for CTRL+S (default: 10000)")]
+    pub scrollback: Option<usize>,
+    #[clap(long, value_name = "PATH", parse(from_os_str), help = "Show lines written to the FIFO at PATH (created if needed) as markers")]
+    pub marker_pipe: Option<OsString>,
+    #[clap(long, value_name = "ADDR", help = "Accept commands on a localhost PORT or unix:PATH socket")]
+    pub control: Option<String>,
+    #[clap(long, help = "Show the source lines around decoded addresses")]
+    pub show_source: bool,
+    #[clap(long, value_name = "DIR", parse(from_os_str), help = "Look for sources that were built elsewhere under DIR (implies --show-source)")]
+    pub source_path: Option<OsString>,
+    #[clap(long, value_name = "FROM=TO", parse(try_from_str = PathRemap::try_from), help = "Show decoded file names starting with FROM as starting with TO (repeatable)")]
+    pub remap_path: Vec<PathRemap>,
+    #[clap(long, help = "Show Rust and C++ function names as they are in the binary")]
+    pub no_demangle: bool,
+    #[clap(long, value_name = "OFFSET", parse(try_from_str = parse_offset), help = "Flash offset the running app is at, if not where its ELF expects (ESP8266 OTA slots)")]
+    pub app_offset: Option<u64>,
+    #[clap(long, value_name = "OFFSET", parse(try_from_str = parse_offset), help = "Flash offset the ELF was built to run from (default: 0x10000)")]
+    pub link_offset: Option<u64>,
+    #[clap(long, help = "Write crash reports as Markdown for bug trackers (into --crash-dir, or the current directory)")]
+    pub report: bool,
+    #[clap(long, help = "Show the free heap, as the firmware logs it, in a status line with its min and max")]
+    pub heap_gauge: bool,
+    #[clap(long, value_name = "NAME:REGEX", parse(try_from_str = GaugeRule::try_from), help = "Also show the number the REGEX captures from matching lines as NAME (repeatable)")]
+    pub gauge: Vec<GaugeRule>,
+    #[clap(long, value_name = "REGEX", parse(try_from_str = Regex::new), help = "Pick out the numbers REGEX captures from matching lines, for --extract-out or --plot")]
+    pub extract: Option<Regex>,
+    #[clap(long, value_name = "FILE", parse(from_os_str), requires = "extract", help = "CSV file to append extracted rows to, with a timestamp each")]
+    pub extract_out: Option<OsString>,
+    #[clap(long, requires = "extract", help = "Plot the extracted numbers as sparklines at the bottom of the terminal")]
+    pub plot: bool,
+    #[clap(long, value_name = "PROTOCOL", parse(try_from_str = Protocol::try_from), help = "Explain the lines of a device tunneled through the log: nmea (GPS sentences) or at (modem commands and responses); repeatable")]
+    pub protocol: Vec<Protocol>,
+    #[clap(long, value_name = "FORMAT", parse(try_from_str = Framing::try_from), help = "Decode binary frames mixed in with the text: slip (as hex) or slip-tlv (as type-length-value records); repeatable")]
+    pub framing: Vec<Framing>,
+    #[clap(long, help = "Close the port for a while when esptool or espflash starts using it, instead of stealing its replies")]
+    pub yield_to_flasher: bool,
+    #[clap(long, overrides_with = "shared", help = "Keep other programs from opening the serial port while monitoring (default)")]
+    pub exclusive: bool,
+    #[clap(long, overrides_with = "exclusive", help = "Let other programs, e.g. a separate logger, open the serial port too (not on Windows)")]
+    pub shared: bool,
+    #[clap(long, help = "Open the serial port even if another program has locked it (/var/lock/LCK..*)")]
+    pub force: bool,
+    #[clap(long, value_name = "DIR", parse(from_os_str), help = "Save the log, raw capture, crash reports and summary of this run in a new directory under DIR")]
+    pub session_dir: Option<OsString>,
+    #[clap(long, help = "Measure how fast generated log output is processed with the other options given, instead of monitoring")]
+    pub throughput_test: bool,
+    #[clap(long, help = "Listen for a few seconds, then say whether the speed looks right and suggest others if not, instead of monitoring")]
+    pub probe: bool,
+    #[clap(long, conflicts_with = "speed", help = "Reset the device at 74880 to 921600 baud in turn, and monitor at whichever gives the most readable output")]
+    pub detect_speed: bool,
+    #[clap(long, value_name = "PREFIX", help = "Toolchain binaries' prefix, for its addr2line, e.g. xtensa-esp-elf- or /opt/esp/bin/xtensa-esp32-elf- (default: the chip's)")]
+    pub toolchain_prefix: Option<String>,
+    #[clap(long, value_name = "PATH", parse(from_os_str), help = "addr2line to ask about addresses the built-in decoder can't resolve (overrides --toolchain-prefix)")]
+    pub addr2line: Option<OsString>,
+    #[clap(value_name = "SERIAL_DEVICE", help_heading = "ARGS", help = "Serial device, FIFO, unix:SOCKET, tcp:HOST:PORT, ws://URL, log file, or - for stdin")]
+    pub serial: Option<String>,
+}
+
+impl MonitorOpts {
+    // The port comes from the command line, then `default_serial` (e.g.
+    // from platformio.ini), then --port-serial/--port-match, and finally
+    // the port picker.
+    pub fn into_app_args(self, default_serial: Option<String>) -> io::Result<AppArgs> {
+        let qemu = if self.qemu {
+            Some(self.qemu_image.unwrap_or_else(|| OsString::from(DEFAULT_QEMU_IMAGE)))
+        } else {
+            None
+        };
+        let port_selector = match self.port_serial {
+            Some(serial_number) => Some(PortSelector::SerialNumber(serial_number)),
+            None => self.port_match,
+        };
+        let serial = match self.serial.or(default_serial) {
+            Some(serial) => serial,
+            None if qemu.is_some() => "QEMU".to_string(),
+            None if self.throughput_test => "throughput-test".to_string(),
+            None => match port_selector.as_ref() {
+                Some(selector) => selector.resolve()?,
+                None => select_port()?,
+            },
+        };
+
+        let mut sinks = Vec::new();
+        sinks.extend(self.log.into_iter().map(SinkConfig::File));
+        sinks.extend(self.log_json.into_iter().map(SinkConfig::Json));
+        sinks.extend(self.forward.into_iter().map(SinkConfig::Tcp));
+        if self.syslog {
+            sinks.push(SinkConfig::Syslog(SyslogTarget::Syslog));
+        }
+        if self.journald {
+            sinks.push(SinkConfig::Syslog(SyslogTarget::Journald));
+        }
+        if let Some(url) = self.mqtt {
+            sinks.push(SinkConfig::Mqtt(MqttConfig::from_url(&url, self.mqtt_topic, self.mqtt_json)?));
+        }
+        let (log_keep, log_gzip) = (self.log_keep, self.log_gzip);
+        let log_rotation = self.log_rotate.map(|when| LogRotation {
+            when,
+            keep: log_keep.unwrap_or(DEFAULT_LOG_KEEP),
+            gzip: log_gzip,
+        });
+
+        Ok(AppArgs {
+            serial,
+            port_selector,
+            wait_for_port: self.wait_for_port,
+            chip: self.chip.unwrap_or_default(),
+            detect_chip: self.chip.is_none(),
+            speed: self.speed,
+            latency: self.latency.unwrap_or_default(),
+            read_buffer_size: self.read_buffer,
+            frame_format: self.serial_config,
+            flow_control: self.flow_control,
+            dtr: self.dtr,
+            rts: self.rts,
+            deassert_on_exit: self.deassert_on_exit,
+            reset: (self.reset || !self.no_reset) && !self.no_touch,
+            no_touch: self.no_touch,
+            shared: self.shared,
+            reset_mode: self.reset_sequence.or(self.reset_mode),
+            reset_delay: self.reset_delay,
+            reset_behavior: self.reset_behavior.unwrap_or_default(),
+            output_format: self.output.unwrap_or_default(),
+            app_offset: self.app_offset,
+            link_offset: self.link_offset,
+            decode_cache_size: if self.no_decode_cache { Some(0) } else { self.decode_cache_size },
+            show_source: self.show_source,
+            source_path: self.source_path,
+            path_remaps: self.remap_path,
+            demangle: !self.no_demangle,
+            scrollback_lines: self.scrollback,
+            flash_cmd: self.flash_cmd,
+            watch: self.watch,
+            line_timeout: self.line_timeout,
+            stream: self.stream,
+            wrap: !self.no_wrap,
+            stats_interval: self.stats_interval,
+            dedup: self.dedup,
+            highlight_changes: self.highlight_changes,
+            highlights: self.highlight,
+            match_actions: self.on_match,
+            notify: self.notify,
+            notify_keywords: self.notify_on,
+            sinks,
+            non_interactive: self.non_interactive,
+            exit_after: self.exit_after,
+            record: self.record,
+            send_file: self.send_file,
+            send_mode: self.send_mode.unwrap_or_default(),
+            script: self.script,
+            junit: self.junit,
+            github_annotations: self.github_annotations,
+            marker_pipe: self.marker_pipe,
+            control: self.control,
+            crash_dir: self.crash_dir,
+            crash_context: self.crash_context,
+            summary: self.summary,
+            markdown_reports: self.report,
+            heap_gauge: self.heap_gauge,
+            gauges: self.gauge,
+            extract: self.extract,
+            extract_out: self.extract_out,
+            plot: self.plot,
+            protocols: self.protocol,
+            framing: self.framing,
+            yield_to_flasher: self.yield_to_flasher,
+            force: self.force,
+            log_rotation,
+            session_dir: self.session_dir,
+            throughput_test: self.throughput_test,
+            probe: self.probe,
+            detect_speed: self.detect_speed,
+            toolchain_prefix: self.toolchain_prefix,
+            addr2line: self.addr2line,
+            qemu,
+            ..AppArgs::default()
+        })
+    }
+}
+
+fn parse_millis(value: &str) -> Result<Duration, ParseIntError> {
+    value.parse::<u64>().map(Duration::from_millis)
+}
+
+fn parse_secs(value: &str) -> Result<Duration, ParseIntError> {
+    value.parse::<u64>().map(Duration::from_secs)
+}
+
+// Every 0 seconds would be a report per loop iteration.
+fn parse_interval(value: &str) -> Result<Duration, io::Error> {
+    match value.parse::<u64>() {
+        Ok(0) => Err(io::Error::new(io::ErrorKind::InvalidInput, "the interval must be at least 1 second")),
+        Ok(secs) => Ok(Duration::from_secs(secs)),
+        Err(err) => Err(io::Error::new(io::ErrorKind::InvalidInput, err)),
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{AppArgs, Chip, Framework, HighlightRule, PortSelector, ResetMode, SinkConfig, port::is_stream_path};
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::File,
+    io,
+    path::Path,
+    time::Duration,
+};
+
+// ESP UARTs top out at 5 Mbaud; anything past that is a typo.
+const MAX_SPEED: usize = 5_000_000;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    MissingPort,
+    PortNotFound(String),
+    PortSelection(io::Error),
+    UnsupportedSpeed(usize),
+    BinUnreadable(OsString, io::Error),
+    WatchWithoutFlashCmd,
+    ExtractWithoutOutput,
+    NoTouchWithLineLevels,
+    ResultsWithoutScript,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingPort => write!(f, "No serial device was given"),
+            ConfigError::PortNotFound(port) => write!(f, "Serial device {} does not exist", port),
+            ConfigError::PortSelection(err) => write!(f, "{}", err),
+            ConfigError::UnsupportedSpeed(speed) => write!(f, "Unsupported speed {} (must be between 1 and {})", speed, MAX_SPEED),
+            ConfigError::BinUnreadable(bin, err) => write!(f, "Can't read {}: {}", bin.to_string_lossy(), err),
+            ConfigError::WatchWithoutFlashCmd => write!(f, "--watch requires --flash-cmd"),
+            ConfigError::ExtractWithoutOutput => write!(f, "--extract requires --extract-out or --plot"),
+            ConfigError::NoTouchWithLineLevels => write!(f, "--no-touch can't be combined with --dtr, --rts or --deassert-on-exit"),
+            ConfigError::ResultsWithoutScript => write!(f, "--junit and --github-annotations need --script, or espmonitor test"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::PortSelection(err) => Some(err),
+            ConfigError::BinUnreadable(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConfigError> for io::Error {
+    fn from(err: ConfigError) -> Self {
+        let kind = match err {
+            ConfigError::PortNotFound(_) => io::ErrorKind::NotFound,
+            ConfigError::PortSelection(ref err) => err.kind(),
+            _ => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl AppArgs {
+    // Catches mistakes up front rather than halfway through opening the
+    // port and resetting the chip.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.qemu.is_none() && self.port_selector.is_none() {
+            if self.serial.is_empty() {
+                return Err(ConfigError::MissingPort);
+            }
+            // Windows COM names don't live in the filesystem.
+            if cfg!(unix) && !self.wait_for_port && !is_stream_path(&self.serial) && !Path::new(&self.serial).exists() {
+                return Err(ConfigError::PortNotFound(self.serial.clone()));
+            }
+        }
+
+        if let Some(speed) = self.speed {
+            if speed == 0 || speed > MAX_SPEED {
+                return Err(ConfigError::UnsupportedSpeed(speed));
+            }
+        }
+
+        if let Some(bin) = self.bin.as_ref() {
+            File::open(bin).map_err(|err| ConfigError::BinUnreadable(bin.clone(), err))?;
+        }
+
+        if self.watch.is_some() && self.flash_cmd.is_none() {
+            return Err(ConfigError::WatchWithoutFlashCmd);
+        }
+
+        if self.extract.is_some() && self.extract_out.is_none() && !self.plot {
+            return Err(ConfigError::ExtractWithoutOutput);
+        }
+
+        if self.no_touch && (self.dtr.is_some() || self.rts.is_some() || self.deassert_on_exit) {
+            return Err(ConfigError::NoTouchWithLineLevels);
+        }
+
+        if (self.junit.is_some() || self.github_annotations) && self.script.is_none() && !self.test {
+            return Err(ConfigError::ResultsWithoutScript);
+        }
+
+        Ok(())
+    }
+}
+
+// Builds an AppArgs for frontends that don't go through the command line,
+// e.g. GUIs or other cargo subcommands.
+#[derive(Debug)]
+pub struct AppArgsBuilder {
+    args: AppArgs,
+}
+
+impl AppArgsBuilder {
+    pub fn new<S: Into<String>>(serial: S) -> Self {
+        Self {
+            args: AppArgs {
+                serial: serial.into(),
+                reset: true,
+                wrap: true,
+                demangle: true,
+                ..AppArgs::default()
+            },
+        }
+    }
+
+    // Picks the port when the configuration is built, by serial number or
+    // USB id.
+    pub fn with_port_selector(selector: PortSelector) -> Self {
+        let mut builder = Self::new("");
+        builder.args.port_selector = Some(selector);
+        builder
+    }
+
+    pub fn chip(mut self, chip: Chip) -> Self {
+        self.args.chip = chip;
+        self
+    }
+
+    pub fn framework(mut self, framework: Framework) -> Self {
+        self.args.framework = framework;
+        self
+    }
+
+    pub fn speed(mut self, speed: usize) -> Self {
+        self.args.speed = Some(speed);
+        self
+    }
+
+    pub fn bin<P: Into<OsString>>(mut self, bin: P) -> Self {
+        self.args.bin = Some(bin.into());
+        self
+    }
+
+    pub fn reset(mut self, reset: bool) -> Self {
+        self.args.reset = reset;
+        self
+    }
+
+    pub fn shared(mut self, shared: bool) -> Self {
+        self.args.shared = shared;
+        self
+    }
+
+    pub fn no_touch(mut self, no_touch: bool) -> Self {
+        self.args.no_touch = no_touch;
+        if no_touch {
+            self.args.reset = false;
+        }
+        self
+    }
+
+    pub fn reset_mode(mut self, reset_mode: ResetMode) -> Self {
+        self.args.reset_mode = Some(reset_mode);
+        self
+    }
+
+    pub fn wait_for_port(mut self, wait_for_port: bool) -> Self {
+        self.args.wait_for_port = wait_for_port;
+        self
+    }
+
+    pub fn flash_cmd<S: Into<String>>(mut self, flash_cmd: S) -> Self {
+        self.args.flash_cmd = Some(flash_cmd.into());
+        self
+    }
+
+    pub fn watch<P: Into<OsString>>(mut self, dir: P) -> Self {
+        self.args.watch = Some(dir.into());
+        self
+    }
+
+    pub fn toolchain_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.args.toolchain_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn addr2line<P: Into<OsString>>(mut self, addr2line: P) -> Self {
+        self.args.addr2line = Some(addr2line.into());
+        self
+    }
+
+    pub fn line_timeout(mut self, line_timeout: Duration) -> Self {
+        self.args.line_timeout = Some(line_timeout);
+        self
+    }
+
+    pub fn non_interactive(mut self, non_interactive: bool) -> Self {
+        self.args.non_interactive = non_interactive;
+        self
+    }
+
+    pub fn exit_after(mut self, exit_after: Duration) -> Self {
+        self.args.exit_after = Some(exit_after);
+        self
+    }
+
+    pub fn sink(mut self, sink: SinkConfig) -> Self {
+        self.args.sinks.push(sink);
+        self
+    }
+
+    pub fn highlight(mut self, rule: HighlightRule) -> Self {
+        self.args.highlights.push(rule);
+        self
+    }
+
+    pub fn record<P: Into<OsString>>(mut self, file: P) -> Self {
+        self.args.record = Some(file.into());
+        self
+    }
+
+    pub fn build(mut self) -> Result<AppArgs, ConfigError> {
+        if let Some(selector) = self.args.port_selector.as_ref() {
+            self.args.serial = selector.resolve().map_err(ConfigError::PortSelection)?;
+        }
+        self.args.validate()?;
+        Ok(self.args)
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::TransferMode;
+use crossterm::{
+    QueueableCommand,
+    event::{KeyCode, KeyEvent, KeyModifiers},
+    style::Print,
+    terminal::{Clear, ClearType},
+};
+use regex::Regex;
+use std::{
+    convert::TryFrom,
+    io::{self, Error as IoError, ErrorKind, Write},
+    path::PathBuf,
+};
+
+// Shared by the console and the control socket; the console wants a ':'
+// in front of each.
+pub(crate) const HELP: &[&str] = &[
+    "filter REGEX    Only show lines matching REGEX ('filter' alone shows everything again)",
+    "baud SPEED      Change the port's speed",
+    "reset           Reset chip",
+    "save [FILE]     Save recent output to FILE, or a timestamped file",
+    "log [FILE]      Start copying output to FILE ('log' alone stops)",
+    "send TEXT       Send TEXT to the device (\\r, \\n, \\t and \\xHH escapes work)",
+    "sendfile FILE   Send FILE to the device ('sendfile ymodem FILE' and 'sendfile lines FILE' pick how)",
+    "mark TEXT       Show TEXT as a marker line",
+    "quit            Exit",
+];
+
+pub(crate) enum ConsoleCommand {
+    Filter(Option<Regex>),
+    Baud(usize),
+    Reset,
+    Save(Option<PathBuf>),
+    Log(Option<PathBuf>),
+    Send(Vec<u8>),
+    SendFile(Option<TransferMode>, PathBuf),
+    Mark(String),
+    Help,
+    Quit,
+}
+
+impl TryFrom<&str> for ConsoleCommand {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        let (name, arg) = match value.find(char::is_whitespace) {
+            Some(pos) => (&value[..pos], value[pos..].trim()),
+            None => (value, ""),
+        };
+        match (name, arg) {
+            ("filter", "") => Ok(ConsoleCommand::Filter(None)),
+            ("filter", pattern) => Regex::new(pattern)
+                .map(|re| ConsoleCommand::Filter(Some(re)))
+                .map_err(|err| IoError::new(ErrorKind::InvalidInput, format!("Invalid filter: {}", err))),
+            ("baud", speed) => speed.parse::<usize>()
+                .ok()
+                .filter(|speed| *speed > 0)
+                .map(ConsoleCommand::Baud)
+                .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid speed", speed))),
+            ("reset", "") => Ok(ConsoleCommand::Reset),
+            ("save", "") => Ok(ConsoleCommand::Save(None)),
+            ("save", file) => Ok(ConsoleCommand::Save(Some(PathBuf::from(file)))),
+            ("log", "") => Ok(ConsoleCommand::Log(None)),
+            ("log", file) => Ok(ConsoleCommand::Log(Some(PathBuf::from(file)))),
+            ("send", text) if !text.is_empty() => unescape(text).map(ConsoleCommand::Send),
+            ("sendfile", arg) if !arg.is_empty() => {
+                let (mode, file) = match arg.split_once(char::is_whitespace) {
+                    Some((mode, file)) => (TransferMode::try_from(mode).ok(), file.trim_start()),
+                    None => (TransferMode::try_from(arg).ok(), ""),
+                };
+                match mode {
+                    Some(_) if file.is_empty() => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' needs a file to send", value))),
+                    Some(mode) => Ok(ConsoleCommand::SendFile(Some(mode), PathBuf::from(file))),
+                    None => Ok(ConsoleCommand::SendFile(None, PathBuf::from(arg))),
+                }
+            },
+            ("mark", text) if !text.is_empty() => Ok(ConsoleCommand::Mark(text.to_string())),
+            ("help", "") | ("?", "") => Ok(ConsoleCommand::Help),
+            ("quit", "") | ("q", "") => Ok(ConsoleCommand::Quit),
+            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("Unknown command '{}' (try 'help')", value))),
+        }
+    }
+}
+
+pub(crate) fn unescape(text: &str) -> Result<Vec<u8>, IoError> {
+    let invalid = || IoError::new(ErrorKind::InvalidInput, format!("'{}' has an invalid escape", text));
+
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                if hex.len() != 2 {
+                    return Err(invalid());
+                }
+                bytes.push(u8::from_str_radix(&hex, 16).map_err(|_| invalid())?);
+            },
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(bytes)
+}
+
+// Keys for the CTRL+T menu, like idf.py monitor's; each may also be
+// pressed with CTRL held.
+pub(crate) const MENU_HELP: &[&str] = &[
+    "CTRL+T S         Toggle statistics",
+    "CTRL+T T         Toggle timestamps",
+    "CTRL+T L         Start or stop copying output to a log file",
+    "CTRL+T B         Change the baud rate",
+    "CTRL+T P         Reset chip into the serial bootloader",
+    "CTRL+T R         Reset chip",
+    "CTRL+T X         Exit",
+    "CTRL+T H         Show this list",
+    "CTRL+T CTRL+T    Send CTRL+T to the device",
+];
+
+pub(crate) enum MenuCommand {
+    Stats,
+    Timestamps,
+    Log,
+    Baud,
+    Bootloader,
+    Reset,
+    Quit,
+    Help,
+    SendPrefix,
+}
+
+// The ':' prompt. Device output keeps arriving while a command is typed,
+// so the prompt is hidden while lines are printed and drawn again after.
+// It also keeps track of a pending CTRL+T.
+pub(crate) struct Console {
+    input: Option<String>,
+    menu: bool,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self { input: None, menu: false }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.input.is_some()
+    }
+
+    pub fn open(&mut self, output: &mut dyn Write) -> io::Result<()> {
+        self.open_with("", output)
+    }
+
+    pub fn open_with(&mut self, text: &str, output: &mut dyn Write) -> io::Result<()> {
+        self.input = Some(text.to_string());
+        self.show(output)
+    }
+
+    pub fn open_menu(&mut self) {
+        self.menu = true;
+    }
+
+    // The key after CTRL+T, if one is pending; Err for a key that isn't on
+    // the menu.
+    pub fn menu_key(&mut self, key_event: KeyEvent) -> Option<Result<MenuCommand, IoError>> {
+        if !self.menu {
+            return None;
+        }
+        self.menu = false;
+        let c = match key_event.code {
+            KeyCode::Char(c) if key_event.modifiers == KeyModifiers::CONTROL && c == 't' => return Some(Ok(MenuCommand::SendPrefix)),
+            KeyCode::Char(c) if (key_event.modifiers - KeyModifiers::SHIFT - KeyModifiers::CONTROL).is_empty() => c.to_ascii_lowercase(),
+            _ => return Some(Err(IoError::new(ErrorKind::InvalidInput, "That key isn't on the CTRL+T menu (CTRL+T H lists them)"))),
+        };
+        Some(match c {
+            's' => Ok(MenuCommand::Stats),
+            't' => Ok(MenuCommand::Timestamps),
+            'l' => Ok(MenuCommand::Log),
+            'b' => Ok(MenuCommand::Baud),
+            'p' => Ok(MenuCommand::Bootloader),
+            'r' => Ok(MenuCommand::Reset),
+            'x' | 'q' => Ok(MenuCommand::Quit),
+            'h' | '?' => Ok(MenuCommand::Help),
+            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' isn't on the CTRL+T menu (CTRL+T H lists them)", c))),
+        })
+    }
+
+    // Returns the command line once ENTER is pressed.
+    pub fn key(&mut self, key_event: KeyEvent, output: &mut dyn Write) -> io::Result<Option<String>> {
+        let input = match self.input.as_mut() {
+            Some(input) => input,
+            None => return Ok(None),
+        };
+        match key_event.code {
+            KeyCode::Enter => {
+                let line = self.input.take();
+                self.hide(output)?;
+                return Ok(line);
+            },
+            KeyCode::Esc => self.input = None,
+            KeyCode::Char('c') if key_event.modifiers == KeyModifiers::CONTROL => self.input = None,
+            // Like vim, backspacing over the ':' leaves command mode.
+            KeyCode::Backspace if input.is_empty() => self.input = None,
+            KeyCode::Backspace => {
+                input.pop();
+            },
+            KeyCode::Char(c) if !key_event.modifiers.contains(KeyModifiers::CONTROL) => input.push(c),
+            _ => (),
+        }
+        self.show(output)?;
+        Ok(None)
+    }
+
+    pub fn hide(&self, output: &mut dyn Write) -> io::Result<()> {
+        output.queue(Print("\r"))?;
+        output.queue(Clear(ClearType::CurrentLine))?;
+        output.flush()
+    }
+
+    // Also erases the prompt once the console has been closed.
+    pub fn show(&self, output: &mut dyn Write) -> io::Result<()> {
+        output.queue(Print("\r"))?;
+        output.queue(Clear(ClearType::CurrentLine))?;
+        if let Some(input) = self.input.as_ref() {
+            output.queue(Print(format!(":{}", input)))?;
+        }
+        output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_file(line: &str) -> Result<(Option<TransferMode>, PathBuf), IoError> {
+        match ConsoleCommand::try_from(line)? {
+            ConsoleCommand::SendFile(mode, file) => Ok((mode, file)),
+            _ => panic!("'{}' isn't a sendfile command", line),
+        }
+    }
+
+    #[test]
+    fn parses_sendfile() {
+        assert_eq!(send_file("sendfile fw.bin").unwrap(), (None, PathBuf::from("fw.bin")));
+        assert_eq!(send_file("sendfile ymodem  fw.bin").unwrap(), (Some(TransferMode::Ymodem), PathBuf::from("fw.bin")));
+        assert_eq!(send_file("sendfile lines my notes.txt").unwrap(), (Some(TransferMode::Lines), PathBuf::from("my notes.txt")));
+        assert_eq!(send_file("sendfile my notes.txt").unwrap(), (None, PathBuf::from("my notes.txt")));
+        assert_eq!(send_file("sendfile ./raw").unwrap(), (None, PathBuf::from("./raw")));
+    }
+
+    #[test]
+    fn rejects_sendfile_without_a_file() {
+        for line in &["sendfile", "sendfile ymodem", "sendfile raw "] {
+            assert_eq!(ConsoleCommand::try_from(*line).err().map(|err| err.kind()), Some(ErrorKind::InvalidInput), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    io::{self, BufRead, BufReader, Error as IoError, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+// A command line from a client, and where the reply goes.
+pub(crate) struct ControlRequest {
+    pub line: String,
+    reply: Sender<String>,
+}
+
+impl ControlRequest {
+    pub fn reply(self, reply: String) {
+        let _ = self.reply.send(reply);
+    }
+}
+
+// Lets scripts and editor extensions drive a running monitor: clients send
+// one command per line (the same ones the ':' console takes) and get an
+// "ok" or "error: ..." line back for each.
+pub(crate) struct ControlServer {
+    requests: Receiver<ControlRequest>,
+}
+
+impl ControlServer {
+    // "unix:/path/to.sock", or a port or address on localhost. Clients can
+    // send bytes to the device, so other hosts aren't let in.
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let (tx, requests) = mpsc::channel();
+
+        if let Some(path) = addr.strip_prefix("unix:") {
+            bind_unix(path, tx)?;
+        } else {
+            let addr = match addr.parse::<u16>() {
+                Ok(port) => SocketAddr::from(([127, 0, 0, 1], port)),
+                Err(_) => addr.parse::<SocketAddr>()
+                    .map_err(|_| IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid control address (expected PORT, HOST:PORT or unix:PATH)", addr)))?,
+            };
+            if !addr.ip().is_loopback() {
+                return Err(IoError::new(ErrorKind::InvalidInput, format!("The control socket must be on localhost, not {}", addr.ip())));
+            }
+            let listener = TcpListener::bind(addr)?;
+            thread::spawn(move || {
+                for stream in listener.incoming().filter_map(Result::ok) {
+                    if let Ok(reader) = stream.try_clone() {
+                        serve(reader, stream, tx.clone());
+                    }
+                }
+            });
+        }
+
+        Ok(Self { requests })
+    }
+
+    pub fn try_recv(&self) -> Option<ControlRequest> {
+        self.requests.try_recv().ok()
+    }
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str, tx: Sender<ControlRequest>) -> io::Result<()> {
+    use std::{fs, os::unix::{fs::FileTypeExt, net::UnixListener}};
+
+    // Left over from an earlier run that didn't get to clean up.
+    if fs::symlink_metadata(path).map(|metadata| metadata.file_type().is_socket()).unwrap_or(false) {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().filter_map(Result::ok) {
+            if let Ok(reader) = stream.try_clone() {
+                serve(reader, stream, tx.clone());
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(windows)]
+fn bind_unix(_path: &str, _tx: Sender<ControlRequest>) -> io::Result<()> {
+    Err(IoError::new(ErrorKind::Other, "unix: control sockets are not supported on Windows"))
+}
+
+fn serve<R, W>(reader: R, mut writer: W, tx: Sender<ControlRequest>)
+where
+    R: Read + Send + 'static,
+    W: Write + Send + 'static,
+{
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let line = match line {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            let (reply_tx, reply_rx) = mpsc::channel();
+            if tx.send(ControlRequest { line, reply: reply_tx }).is_err() {
+                break;
+            }
+            let reply = reply_rx.recv().unwrap_or_else(|_| "error: espmonitor is exiting".to_string());
+            if writeln!(writer, "{}", reply).and_then(|()| writer.flush()).is_err() {
+                break;
+            }
+        }
+    });
+}
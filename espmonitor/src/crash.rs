@@ -0,0 +1,302 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{AppInfo, Backtrace, Chip, Location, PathRemap, Symbols, idflog::strip_ansi, remap::remap_location, resolve_address, scrollback::recycle, symbols::load_bin_files};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    collections::VecDeque,
+    ffi::OsString,
+    fmt::Write as _,
+    fs,
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub(crate) const DEFAULT_CRASH_CONTEXT: usize = 50;
+// Caps what's kept of a runaway panic dump.
+const MAX_PANIC_LINES: usize = 1000;
+
+lazy_static! {
+    // ESP-IDF's bootloader and startup code print these on every boot, e.g.
+    // "I (313) cpu_start: ESP-IDF:          v4.4".
+    static ref BOOT_INFO_RE: Regex = Regex::new(r"(ESP-IDF|App version|Project name):?\s+(\S+)")
+        .expect("Failed to parse boot info regex");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ReportFormat {
+    Text,
+    // For pasting into a bug tracker.
+    Markdown,
+}
+
+// Writes a report file for every panic, with the lines leading up to it,
+// the panic output itself and the decoded backtrace, so crashes that happen
+// while nobody is watching can still be looked into.
+pub(crate) struct CrashReporter {
+    dir: PathBuf,
+    port: String,
+    bin: Option<OsString>,
+    extra_bins: Vec<OsString>,
+    remaps: Vec<PathRemap>,
+    format: ReportFormat,
+    // The versions the device printed when it last booted.
+    boot_info: Vec<(String, String)>,
+    // Loaded on the first crash, and again if the binary changes.
+    symbols: Option<(SystemTime, Symbols)>,
+    recent: VecDeque<String>,
+    context_lines: usize,
+    current: Option<CrashLines>,
+}
+
+struct CrashLines {
+    before: Vec<String>,
+    during: Vec<String>,
+}
+
+impl CrashReporter {
+    pub fn new(dir: PathBuf, port: &str, bin: Option<OsString>, extra_bins: Vec<OsString>, context_lines: usize, remaps: Vec<PathRemap>, format: ReportFormat) -> Self {
+        Self {
+            dir,
+            port: port.to_string(),
+            bin,
+            extra_bins,
+            remaps,
+            format,
+            boot_info: Vec::new(),
+            symbols: None,
+            recent: VecDeque::with_capacity(context_lines),
+            context_lines,
+            current: None,
+        }
+    }
+
+    // Needs to see each line after the panic collector has, so a panic
+    // that ends the previous one's report doesn't end up in it.
+    // Takes lines with their escape codes already stripped.
+    pub fn line(&mut self, line: &str, is_panic: bool) {
+        if let Some(caps) = BOOT_INFO_RE.captures(line) {
+            let (name, value) = (caps[1].to_string(), caps[2].to_string());
+            match self.boot_info.iter_mut().find(|(known, _)| *known == name) {
+                Some(info) => info.1 = value,
+                None => self.boot_info.push((name, value)),
+            }
+        }
+        if is_panic && self.current.is_none() {
+            self.current = Some(CrashLines {
+                before: self.recent.iter().cloned().collect(),
+                during: Vec::new(),
+            });
+        }
+        if let Some(current) = self.current.as_mut() {
+            if current.during.len() < MAX_PANIC_LINES {
+                current.during.push(line.to_string());
+            }
+        }
+
+        if self.context_lines > 0 {
+            let entry = recycle(&mut self.recent, self.context_lines, line);
+            self.recent.push_back(entry);
+        }
+    }
+
+    pub fn write(&mut self, backtrace: &Backtrace, app_info: Option<&AppInfo>, chip: Chip) -> io::Result<PathBuf> {
+        let lines = self.current.take().unwrap_or(CrashLines { before: Vec::new(), during: vec![backtrace.message.clone()] });
+        self.load_symbols();
+
+        let mut details = vec![
+            ("Chip".to_string(), chip.to_string()),
+            ("Time".to_string(), format_utc(backtrace.timestamp)),
+            ("Port".to_string(), self.port.clone()),
+        ];
+        details.extend(self.boot_info.iter().cloned());
+        if let Some(bin) = self.bin.as_ref() {
+            details.push(("ELF".to_string(), bin.to_string_lossy().into_owned()));
+        }
+        if let Some(app_info) = app_info {
+            details.push(("ELF SHA256".to_string(), app_info.elf_sha256.clone()));
+            if let Some(project_name) = app_info.project_name.as_ref() {
+                details.push(("Project".to_string(), format!("{} {}", project_name, app_info.version.as_deref().unwrap_or(""))));
+            }
+        }
+        let message = strip_ansi(&backtrace.message).trim().to_string();
+        let frames = self.describe_frames(backtrace);
+
+        let (report, extension) = match self.format {
+            ReportFormat::Text => (text_report(&details, &message, &frames, &lines), "txt"),
+            ReportFormat::Markdown => (markdown_report(&details, &message, &frames, &lines), "md"),
+        };
+
+        fs::create_dir_all(&self.dir)?;
+        let path = timestamped_path(&self.dir, "crash", backtrace.timestamp, extension);
+        fs::write(&path, report)?;
+        Ok(path)
+    }
+
+    // One line per frame, then one per function it was inlined into.
+    fn describe_frames(&self, backtrace: &Backtrace) -> Vec<String> {
+        let mut frames = Vec::new();
+        for frame in &backtrace.frames {
+            let location = frame.location.clone()
+                .or_else(|| self.symbols.as_ref().map(|(_, symbols)| remap_location(&self.remaps, resolve_address(symbols, frame.address))));
+            let location = match location {
+                Some(location) => location,
+                None => {
+                    frames.push(format!("0x{:08x}", frame.address));
+                    continue;
+                },
+            };
+            frames.push(format!("0x{:08x} {}", frame.address, describe(&location)));
+            for caller in &location.inlined_into {
+                frames.push(format!("           inlined into {}", describe(caller)));
+            }
+        }
+        frames
+    }
+
+    fn load_symbols(&mut self) {
+        let bin = match self.bin.as_ref() {
+            Some(bin) => bin,
+            None => return,
+        };
+        let modified = match fs::metadata(bin).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(_) => return,
+        };
+        if self.symbols.as_ref().map(|(loaded, _)| *loaded != modified).unwrap_or(true) {
+            self.symbols = load_bin_files(bin, &self.extra_bins).ok().map(|symbols| (modified, symbols));
+        }
+    }
+}
+
+fn text_report(details: &[(String, String)], message: &str, frames: &[String], lines: &CrashLines) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "ESPMonitor {} crash report", env!("CARGO_PKG_VERSION"));
+    for (name, value) in details {
+        let _ = writeln!(report, "{}: {}", name, value);
+    }
+    let _ = writeln!(report);
+    let _ = writeln!(report, "{}", message);
+
+    if !frames.is_empty() {
+        let _ = writeln!(report);
+        let _ = writeln!(report, "Backtrace:");
+        for frame in frames {
+            let _ = writeln!(report, "  {}", frame);
+        }
+    }
+
+    let _ = writeln!(report);
+    let _ = writeln!(report, "Last {} lines before the panic:", lines.before.len());
+    for line in &lines.before {
+        let _ = writeln!(report, "{}", line);
+    }
+    let _ = writeln!(report);
+    let _ = writeln!(report, "Panic output:");
+    for line in &lines.during {
+        let _ = writeln!(report, "{}", line);
+    }
+    report
+}
+
+// The long parts are folded away in <details>, which GitHub and GitLab
+// both render.
+fn markdown_report(details: &[(String, String)], message: &str, frames: &[String], lines: &CrashLines) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "### Crash: `{}`", message.replace('`', "'"));
+    let _ = writeln!(report);
+    for (name, value) in details {
+        let _ = writeln!(report, "- **{}**: `{}`", name, value);
+    }
+    let _ = writeln!(report, "- **ESPMonitor**: `{}`", env!("CARGO_PKG_VERSION"));
+
+    if !frames.is_empty() {
+        let _ = writeln!(report);
+        let _ = writeln!(report, "#### Backtrace");
+        let _ = writeln!(report);
+        let _ = writeln!(report, "```");
+        for frame in frames {
+            let _ = writeln!(report, "{}", frame);
+        }
+        let _ = writeln!(report, "```");
+    }
+
+    for (summary, lines) in [("Panic output", &lines.during), ("Log before the panic", &lines.before)] {
+        if lines.is_empty() {
+            continue;
+        }
+        let _ = writeln!(report);
+        let _ = writeln!(report, "<details>");
+        let _ = writeln!(report, "<summary>{} ({} lines)</summary>", summary, lines.len());
+        let _ = writeln!(report);
+        let _ = writeln!(report, "```");
+        for line in lines.iter() {
+            let _ = writeln!(report, "{}", line);
+        }
+        let _ = writeln!(report, "```");
+        let _ = writeln!(report);
+        let _ = writeln!(report, "</details>");
+    }
+    report
+}
+
+fn describe(location: &Location) -> String {
+    format!(
+        "{} at {}:{}",
+        location.function.as_deref().unwrap_or("??"),
+        location.file.as_deref().unwrap_or("??"),
+        location.line.map(|line| line.to_string()).unwrap_or_else(|| "??".to_string()),
+    )
+}
+
+// e.g. "crash-20210608T002257Z.txt", with a counter added if that's taken.
+// Directories get no extension.
+pub(crate) fn timestamped_path(dir: &Path, prefix: &str, time: SystemTime, extension: &str) -> PathBuf {
+    let stamp = format_utc(time).replace(&['-', ':'][..], "");
+    let extension = if extension.is_empty() { String::new() } else { format!(".{}", extension) };
+    let mut path = dir.join(format!("{}-{}{}", prefix, stamp, extension));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("{}-{}-{}{}", prefix, stamp, n, extension));
+    }
+    path
+}
+
+// "2021-06-08T00:22:57Z"; there's no date crate to lean on, so this does
+// the civil-from-days conversion itself.
+pub(crate) fn format_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs_of_day / 3600, secs_of_day / 60 % 60, secs_of_day % 60,
+    )
+}
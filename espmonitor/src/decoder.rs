@@ -0,0 +1,112 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{
+    Location,
+    Symbols,
+    PathRemap,
+    cache::DecodeCache,
+    remap::remap_location,
+    resolve_address,
+};
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+pub(crate) type Decoded = Vec<(String, Location)>;
+
+enum Request {
+    Symbols(Option<Box<Symbols>>),
+    Decode(Vec<(String, u64)>),
+    Resolve(Vec<u64>, Sender<Vec<Option<Location>>>),
+}
+
+// Resolves addresses on a worker thread, so that a long backtrace doesn't
+// hold up reading from the serial port (and risk overflowing the UART's
+// buffer) while DWARF info is being searched.
+pub(crate) struct AsyncDecoder {
+    requests: Sender<Request>,
+    results: Receiver<Decoded>,
+}
+
+impl AsyncDecoder {
+    pub fn spawn(symbols: Option<Symbols>, cache_capacity: usize, remaps: Vec<PathRemap>) -> Self {
+        let (requests, request_rx) = mpsc::channel();
+        let (result_tx, results) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut symbols = symbols;
+            let mut cache = DecodeCache::new(cache_capacity);
+
+            for request in request_rx {
+                match request {
+                    Request::Symbols(new_symbols) => {
+                        symbols = new_symbols.map(|symbols| *symbols);
+                        cache.clear();
+                    },
+                    Request::Decode(addrs) => if let Some(symbols) = symbols.as_ref() {
+                        let decoded = addrs.into_iter()
+                            .map(|(text, addr)| {
+                                let location = cache.get_or_insert_with(addr, || remap_location(&remaps, resolve_address(symbols, addr)));
+                                (text, location)
+                            })
+                            .collect();
+                        if result_tx.send(decoded).is_err() {
+                            break;
+                        }
+                    },
+                    Request::Resolve(addrs, reply) => {
+                        let locations = addrs.into_iter()
+                            .map(|addr| symbols.as_ref().map(|symbols| cache.get_or_insert_with(addr, || remap_location(&remaps, resolve_address(symbols, addr)))))
+                            .collect();
+                        let _ = reply.send(locations);
+                    },
+                }
+            }
+        });
+
+        Self {
+            requests,
+            results,
+        }
+    }
+
+    pub fn set_symbols(&self, symbols: Option<Symbols>) {
+        let _ = self.requests.send(Request::Symbols(symbols.map(Box::new)));
+    }
+
+    pub fn decode(&self, addrs: Vec<(String, u64)>) {
+        let _ = self.requests.send(Request::Decode(addrs));
+    }
+
+    // Waits for the locations of a panic's frames, which go along with the
+    // backtrace rather than being printed.  The lines they were found in
+    // have been decoded already, so they're usually in the cache.
+    pub fn resolve(&self, addrs: Vec<u64>) -> Vec<Option<Location>> {
+        let count = addrs.len();
+        let (reply, locations) = mpsc::channel();
+        if self.requests.send(Request::Resolve(addrs, reply)).is_err() {
+            return vec![None; count];
+        }
+        locations.recv().unwrap_or_else(|_| vec![None; count])
+    }
+
+    pub fn try_recv(&self) -> Option<Decoded> {
+        self.results.try_recv().ok()
+    }
+}
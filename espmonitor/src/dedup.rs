@@ -0,0 +1,107 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref TOKEN_RE: Regex = Regex::new(r"\s+|[^\s]+")
+        .expect("Failed to parse token regex");
+}
+
+pub(crate) enum Dedup {
+    // The line is identical to the previous one and shouldn't be shown.
+    Repeat,
+    // The line is new; `repeats` says how many copies of the previous line
+    // were hidden, and `spans` (when highlighting is on) is the line split
+    // into pieces marked true where they differ from the previous line.
+    New {
+        repeats: usize,
+        spans: Option<Vec<(String, bool)>>,
+    },
+}
+
+pub(crate) struct RepeatFilter {
+    collapse: bool,
+    highlight: bool,
+    last: Option<String>,
+    repeats: usize,
+}
+
+impl RepeatFilter {
+    pub fn new(collapse: bool, highlight: bool) -> Self {
+        Self {
+            collapse,
+            highlight,
+            last: None,
+            repeats: 0,
+        }
+    }
+
+    pub fn check(&mut self, line: &str) -> Dedup {
+        if !self.collapse && !self.highlight {
+            return Dedup::New { repeats: 0, spans: None };
+        }
+
+        if self.collapse && self.last.as_deref() == Some(line) {
+            self.repeats += 1;
+            return Dedup::Repeat;
+        }
+
+        let spans =
+            if self.highlight {
+                self.last.as_ref().and_then(|last| diff_tokens(last, line))
+            } else {
+                None
+            };
+        let repeats = self.take_repeats();
+        match self.last.as_mut() {
+            Some(last) => {
+                last.clear();
+                last.push_str(line);
+            },
+            None => self.last = Some(line.to_string()),
+        }
+
+        Dedup::New { repeats, spans }
+    }
+
+    pub fn take_repeats(&mut self) -> usize {
+        std::mem::take(&mut self.repeats)
+    }
+}
+
+// Only lines with the same shape (same number of tokens, and mostly the
+// same tokens) count as "nearly identical"; highlighting the differences
+// between unrelated lines would just be noise.
+fn diff_tokens(old: &str, new: &str) -> Option<Vec<(String, bool)>> {
+    let old_tokens = TOKEN_RE.find_iter(old).map(|mat| mat.as_str()).collect::<Vec<_>>();
+    let new_tokens = TOKEN_RE.find_iter(new).map(|mat| mat.as_str()).collect::<Vec<_>>();
+    if old_tokens.len() != new_tokens.len() {
+        return None;
+    }
+
+    let changed = old_tokens.iter().zip(new_tokens.iter()).filter(|(old, new)| old != new).count();
+    if changed == 0 || changed * 2 > new_tokens.len() {
+        return None;
+    }
+
+    Some(old_tokens.into_iter()
+        .zip(new_tokens)
+        .map(|(old, new)| (new.to_string(), old != new))
+        .collect())
+}
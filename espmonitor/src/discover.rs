@@ -0,0 +1,90 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{Arch, Chip, Framework};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+const EM_XTENSA: u16 = 94;
+const EM_RISCV: u16 = 243;
+const ET_EXEC: u16 = 2;
+
+// Finds the most recently built firmware for `chip` in a Cargo project
+// ("target/<triple>/{debug,release}", plus examples) or an idf.py project
+// ("build/*.elf"), so --bin can usually be left out. Only ESP-IDF projects
+// are built with idf.py, and a Cargo project's targets say which framework
+// they're for.
+pub fn find_elf<P: AsRef<Path>>(chip: Chip, framework: Framework, project_dir: P) -> Option<PathBuf> {
+    let project_dir = project_dir.as_ref();
+
+    let (mut dirs, triple_suffix) = match framework {
+        Framework::Baremetal => (Vec::new(), "-none-elf"),
+        Framework::EspIdf => (vec![project_dir.join("build")], "-espidf"),
+        Framework::Arduino => (vec![project_dir.join("build")], ""),
+    };
+    if let Ok(entries) = fs::read_dir(project_dir.join("target")) {
+        let triple_dirs = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.to_string_lossy().ends_with(triple_suffix));
+        for triple_dir in triple_dirs {
+            for profile in &["debug", "release"] {
+                dirs.push(triple_dir.join(profile));
+                dirs.push(triple_dir.join(profile).join("examples"));
+            }
+        }
+    }
+
+    dirs.iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()))
+        .filter(|path| path.is_file() && is_firmware_for(chip, path))
+        .max_by_key(|path| fs::metadata(path).and_then(|metadata| metadata.modified()).unwrap_or(SystemTime::UNIX_EPOCH))
+}
+
+// idf.py builds the second-stage bootloader alongside the app.
+pub fn find_bootloader_elf<P: AsRef<Path>>(project_dir: P) -> Option<PathBuf> {
+    let path = project_dir.as_ref().join("build").join("bootloader").join("bootloader.elf");
+    if path.is_file() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+// Executable ELF files for the chip's architecture; this skips host build
+// scripts and the like that also end up under target/.
+fn is_firmware_for(chip: Chip, path: &Path) -> bool {
+    let mut header = [0u8; 20];
+    let read = File::open(path).and_then(|mut file| file.read_exact(&mut header));
+    if read.is_err() || &header[0..4] != b"\x7fELF" {
+        return false;
+    }
+
+    // Both architectures are little-endian.
+    let e_type = u16::from_le_bytes([header[16], header[17]]);
+    let e_machine = u16::from_le_bytes([header[18], header[19]]);
+    let expected_machine = match chip.spec().arch {
+        Arch::RiscV => EM_RISCV,
+        Arch::Xtensa => EM_XTENSA,
+    };
+    e_type == ET_EXEC && e_machine == expected_machine
+}
@@ -0,0 +1,179 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{available_ports, toolchain::GENERIC_ADDR2LINES};
+use std::{env, path::PathBuf};
+
+// Looks for the usual reasons monitoring doesn't work on a new machine and
+// says how to fix them.  Returns whether everything looked fine.
+pub fn doctor() -> bool {
+    let mut report = Report { problems: 0 };
+
+    check_ports(&mut report);
+    #[cfg(target_os = "linux")]
+    check_interfering_services(&mut report);
+    check_tools(&mut report);
+
+    println!();
+    if report.problems == 0 {
+        println!("No problems found.");
+    } else {
+        println!("{} problem(s) found.", report.problems);
+    }
+    report.problems == 0
+}
+
+struct Report {
+    problems: usize,
+}
+
+impl Report {
+    fn ok(&mut self, message: &str) {
+        println!("[ok] {}", message);
+    }
+
+    fn note(&mut self, message: &str) {
+        println!("[--] {}", message);
+    }
+
+    fn problem(&mut self, message: &str, fix: &str) {
+        self.problems += 1;
+        println!("[!!] {}", message);
+        println!("     fix: {}", fix);
+    }
+}
+
+#[cfg(unix)]
+fn check_ports(report: &mut Report) {
+    use nix::{errno::Errno, fcntl::{OFlag, open}, sys::stat::Mode, unistd::close};
+
+    let ports = available_ports();
+    if ports.is_empty() {
+        report.problem("No USB serial devices found", no_ports_fix());
+        return;
+    }
+
+    for port in ports {
+        match open(port.path.as_str(), OFlag::O_RDWR | OFlag::O_NOCTTY | OFlag::O_NONBLOCK, Mode::empty()) {
+            Ok(fd) => {
+                let _ = close(fd);
+                report.ok(&format!("{} can be opened", port.description()));
+            },
+            Err(Errno::EACCES) => report.problem(
+                &format!("Permission denied opening {}", port.description()),
+                &permission_fix(&port.path),
+            ),
+            Err(Errno::EBUSY) => report.problem(
+                &format!("{} is in use{}", port.description(), crate::busy::describe_holders(&port.path)),
+                "Close the other program (another monitor, a flasher, or a serial console) first",
+            ),
+            Err(err) => report.problem(&format!("Unable to open {}: {}", port.description(), err), "Unplug the device and plug it back in"),
+        }
+    }
+}
+
+// COM ports can't be enumerated here, so try opening the usual ones.
+#[cfg(windows)]
+fn check_ports(report: &mut Report) {
+    let mut found = false;
+    for n in 1..=32 {
+        let name = format!("COM{}", n);
+        match serial::open(&name) {
+            Ok(_) => {
+                found = true;
+                report.ok(&format!("{} can be opened", name));
+            },
+            Err(err) if err.to_string().contains("Access is denied") => {
+                found = true;
+                report.problem(&format!("{} is in use", name), "Close the other program (another monitor, a flasher, or a serial console) first");
+            },
+            Err(_) => (),
+        }
+    }
+    if !found {
+        report.problem("No COM ports found", no_ports_fix());
+    }
+}
+
+fn no_ports_fix() -> &'static str {
+    if cfg!(target_os = "linux") {
+        "Check that the cable carries data (not just power), and look in `dmesg` for the USB serial driver (cp210x, ch341, ftdi_sio or cdc_acm)"
+    } else if cfg!(target_os = "macos") {
+        "Check that the cable carries data; boards with a CP210x or CH34x bridge may need the vendor's driver installed"
+    } else {
+        "Check that the cable carries data, and install the CP210x or CH34x driver if Device Manager shows an unknown device"
+    }
+}
+
+#[cfg(unix)]
+fn permission_fix(path: &str) -> String {
+    use nix::unistd::{Gid, Group};
+    use std::os::unix::fs::MetadataExt;
+
+    let group = std::fs::metadata(path)
+        .ok()
+        .and_then(|metadata| Group::from_gid(Gid::from_raw(metadata.gid())).ok().flatten())
+        .map(|group| group.name);
+    match group {
+        Some(group) => format!("Add yourself to the '{}' group (`sudo usermod -aG {} $USER`), then log out and back in", group, group),
+        None => "Add a udev rule giving your user access to the device".to_string(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_interfering_services(report: &mut Report) {
+    let running = |name: &str| std::fs::read_dir("/proc")
+        .map(|entries| entries
+            .filter_map(|entry| entry.ok())
+            .any(|entry| std::fs::read_to_string(entry.path().join("comm")).map(|comm| comm.trim() == name).unwrap_or(false)))
+        .unwrap_or(false);
+
+    if running("ModemManager") {
+        report.problem(
+            "ModemManager is running; it probes new serial devices and can garble or reset them",
+            "`sudo systemctl disable --now ModemManager`, or add a udev rule setting ENV{ID_MM_DEVICE_IGNORE}=\"1\" for your board",
+        );
+    }
+    if running("brltty") {
+        report.problem(
+            "brltty is running; it grabs CH340/CH341 serial adapters as braille displays",
+            "Uninstall brltty if you don't use a braille display",
+        );
+    }
+}
+
+fn check_tools(report: &mut Report) {
+    report.ok("Backtraces are decoded with the built-in DWARF reader; the toolchain's addr2line is only asked about addresses it can't place");
+    match GENERIC_ADDR2LINES.iter().find_map(|tool| find_in_path(tool).map(|path| (tool, path))) {
+        Some((tool, path)) => report.ok(&format!("{} found at {}, for when the toolchain's addr2line isn't", tool, path.display())),
+        None => report.note("No llvm-addr2line or addr2line found; only the toolchain's (see --toolchain-prefix) can help the built-in reader out"),
+    }
+
+    let tools = [("espflash", "flashing with cargo espmonitor --flash"), ("cargo-espflash", "flashing with cargo espmonitor --flash")];
+    for (tool, purpose) in tools.iter() {
+        match find_in_path(tool) {
+            Some(path) => report.ok(&format!("{} found at {}", tool, path.display())),
+            None => report.note(&format!("{} not found; it's only needed for {} (`cargo install {}`)", tool, purpose, tool)),
+        }
+    }
+}
+
+fn find_in_path(tool: &str) -> Option<PathBuf> {
+    let file_name = if cfg!(windows) { format!("{}.exe", tool) } else { tool.to_string() };
+    env::var_os("PATH")
+        .and_then(|path| env::split_paths(&path).map(|dir| dir.join(&file_name)).find(|candidate| candidate.is_file()))
+}
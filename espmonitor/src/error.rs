@@ -0,0 +1,92 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::ConfigError;
+use std::{ffi::OsString, fmt, io};
+
+#[derive(Debug)]
+pub enum Error {
+    SerialOpen {
+        port: String,
+        source: io::Error,
+    },
+    // Reading from or controlling a port that was open.
+    SerialIo(io::Error),
+    Terminal(io::Error),
+    Symbolication {
+        bin: OsString,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    Config(ConfigError),
+    // A --script step that failed, and the line it's on.
+    Script {
+        line: usize,
+        message: String,
+    },
+    // `espmonitor test` found failures, or couldn't get results.
+    Tests(String),
+    // Everything else: flash commands, recordings, output sinks.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::SerialOpen { port, source } => {
+                write!(f, "Unable to open {}: {}", port, source)?;
+                match source.kind() {
+                    io::ErrorKind::PermissionDenied if cfg!(unix) =>
+                        write!(f, " (is your user in the dialout/uucp group?)"),
+                    io::ErrorKind::NotFound =>
+                        write!(f, " (is the device plugged in? Leave out the port to pick from a list)"),
+                    _ => Ok(()),
+                }
+            },
+            Error::SerialIo(err) => write!(f, "Serial I/O failed: {}", err),
+            Error::Terminal(err) => write!(f, "Terminal I/O failed: {}", err),
+            Error::Symbolication { bin, source } => write!(f, "Unable to load symbols from {}: {}", bin.to_string_lossy(), source),
+            Error::Config(err) => write!(f, "{}", err),
+            Error::Script { line, message } => write!(f, "Script failed at line {}: {}", line, message),
+            Error::Tests(message) => write!(f, "{}", message),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::SerialOpen { source, .. } => Some(source),
+            Error::SerialIo(err) | Error::Terminal(err) | Error::Io(err) => Some(err),
+            Error::Symbolication { source, .. } => Some(source.as_ref()),
+            Error::Config(err) => Some(err),
+            Error::Script { .. } | Error::Tests(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Self {
+        Error::Config(err)
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{Chip, panic::xtensa_cause};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+lazy_static! {
+    static ref EXCEPTION_RE: Regex = Regex::new(r"^(?:Exception \((\d+)\):|Fatal exception \(?(\d+)\)?)")
+        .expect("Failed to parse ESP8266 exception regex");
+    static ref EPC_RE: Regex = Regex::new(r"epc1=0x([0-9a-fA-F]{8}).*excvaddr=0x([0-9a-fA-F]{8})")
+        .expect("Failed to parse ESP8266 epc regex");
+    static ref CTX_RE: Regex = Regex::new(r"^ctx: (\w+)")
+        .expect("Failed to parse ESP8266 ctx regex");
+    static ref STACK_LINE_RE: Regex = Regex::new(r"^[0-9a-fA-F]{8}:\s+((?:[0-9a-fA-F]{8}\s*)+)")
+        .expect("Failed to parse ESP8266 stack line regex");
+}
+
+pub(crate) enum Esp8266Report {
+    Exception(String),
+    Stack(String),
+}
+
+// Decodes the crash reports printed by the ESP8266 Arduino core and the
+// NONOS SDK: an "Exception (N):" line with epc1/excvaddr, followed by a
+// raw stack dump between ">>>stack>>>" and "<<<stack<<<".  Like the old
+// ESP Exception Decoder, every stack word that points into code is taken
+// to be a return address.
+pub(crate) struct Esp8266CrashDecoder {
+    cause: Option<u64>,
+    ctx: Option<String>,
+    stack: Option<Vec<u64>>,
+}
+
+impl Esp8266CrashDecoder {
+    pub fn new() -> Self {
+        Self {
+            cause: None,
+            ctx: None,
+            stack: None,
+        }
+    }
+
+    // Returns a summary once the exception registers, or the whole stack
+    // dump, have been seen.
+    pub fn feed(&mut self, line: &str) -> Option<Esp8266Report> {
+        let line = line.trim();
+
+        if let Some(caps) = EXCEPTION_RE.captures(line) {
+            self.cause = caps.get(1).or_else(|| caps.get(2)).and_then(|cause| cause.as_str().parse().ok());
+            return None;
+        }
+
+        if let Some(caps) = EPC_RE.captures(line) {
+            let epc1 = u64::from_str_radix(&caps[1], 16).ok()?;
+            let excvaddr = u64::from_str_radix(&caps[2], 16).ok()?;
+            let cause = self.cause.take()
+                .map(|cause| format!("Exception: {}, ", xtensa_cause(cause)))
+                .unwrap_or_else(|| "Exception: ".to_string());
+            return Some(Esp8266Report::Exception(format!("{}PC 0x{:08x}, fault address 0x{:08x}", cause, epc1, excvaddr)));
+        }
+
+        if line == ">>>stack>>>" {
+            self.stack = Some(Vec::new());
+            self.ctx = None;
+            return None;
+        }
+
+        let stack = self.stack.as_mut()?;
+        if let Some(caps) = CTX_RE.captures(line) {
+            self.ctx = Some(caps[1].to_string());
+        } else if let Some(caps) = STACK_LINE_RE.captures(line) {
+            stack.extend(
+                caps[1]
+                    .split_whitespace()
+                    .filter_map(|word| u64::from_str_radix(word, 16).ok())
+                    .filter(|addr| Chip::ESP8266.is_code_address(*addr)),
+            );
+        } else if line == "<<<stack<<<" {
+            let stack = self.stack.take().unwrap_or_default();
+            let ctx = self.ctx.take().map(|ctx| format!(" (ctx: {})", ctx)).unwrap_or_default();
+            return Some(Esp8266Report::Stack(if stack.is_empty() {
+                format!("No code addresses in stack dump{}", ctx)
+            } else {
+                let addrs = stack.iter().map(|addr| format!("0x{:08x}", addr)).collect::<Vec<_>>();
+                format!("Backtrace from stack dump{}: {}", ctx, addrs.join(" "))
+            }));
+        }
+
+        None
+    }
+}
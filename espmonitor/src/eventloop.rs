@@ -0,0 +1,68 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::port::Port;
+use std::{io, time::Duration};
+
+// What's worth looking at after a wait.  Both are set when we can't tell,
+// in which case the port's own read timeout does the waiting.
+pub(crate) struct Readiness {
+    pub port: bool,
+    pub keyboard: bool,
+}
+
+// Waits for whichever comes first of serial data, a key press, or the
+// timeout, so neither has to sit behind the other's timeout.
+#[cfg(unix)]
+pub(crate) fn wait(dev: &mut Port, keyboard: bool, timeout: Duration) -> io::Result<Readiness> {
+    use crate::SerialSource;
+    use crossterm::tty::IsTty;
+    use nix::poll::{PollFd, PollFlags, poll};
+    use std::os::unix::io::AsRawFd;
+
+    let port_fd = match dev.poll_fd() {
+        Some(fd) => fd,
+        None => return Ok(Readiness { port: true, keyboard: true }),
+    };
+    let stdin = io::stdin();
+    let keyboard_fd = if keyboard && stdin.is_tty() { Some(stdin.as_raw_fd()) } else { None };
+
+    let mut fds = vec![PollFd::new(port_fd, PollFlags::POLLIN)];
+    if let Some(fd) = keyboard_fd {
+        fds.push(PollFd::new(fd, PollFlags::POLLIN));
+    }
+    match poll(&mut fds, timeout.as_millis() as i32) {
+        Ok(_) => (),
+        // Most likely SIGWINCH, which crossterm turns into a resize event.
+        Err(nix::errno::Errno::EINTR) => return Ok(Readiness { port: false, keyboard }),
+        Err(err) => return Err(io::Error::from(err)),
+    }
+
+    let is_ready = |fd: &PollFd| fd.revents().map(|revents| !revents.is_empty()).unwrap_or(false);
+    Ok(Readiness {
+        // Hangups and errors count too; the read will report them.
+        port: is_ready(&fds[0]),
+        // Without a terminal on stdin, crossterm reads /dev/tty itself, so
+        // just check every time around.
+        keyboard: keyboard && keyboard_fd.map(|_| is_ready(&fds[1])).unwrap_or(true),
+    })
+}
+
+#[cfg(windows)]
+pub(crate) fn wait(_dev: &mut Port, keyboard: bool, _timeout: Duration) -> io::Result<Readiness> {
+    Ok(Readiness { port: true, keyboard })
+}
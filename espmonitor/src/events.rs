@@ -0,0 +1,167 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{Location, LogRecord};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    mem,
+    sync::mpsc::{self, Receiver, Sender},
+    time::SystemTime,
+};
+
+lazy_static! {
+    static ref RESET_CAUSE_RE: Regex = Regex::new(r"rst:0x([0-9a-fA-F]+)(?: \(([A-Za-z0-9_]+)\))?|rst cause:(\d+)")
+        .expect("Failed to parse reset cause regex");
+}
+
+// What library users see instead of (or as well as) terminal output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Line(LineRecord),
+    Reset(ResetInfo),
+    Panic(Backtrace),
+    Disconnected,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineRecord {
+    pub text: String,
+    pub timestamp: SystemTime,
+    pub is_panic: bool,
+    // Set for lines in the ESP-IDF log format.
+    pub log: Option<LogRecord>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResetInfo {
+    pub line: String,
+    pub timestamp: SystemTime,
+    pub cause: Option<u32>,
+    pub reason: Option<String>,
+}
+
+impl ResetInfo {
+    pub(crate) fn from_boot_line(line: &str) -> Self {
+        let caps = RESET_CAUSE_RE.captures(line);
+        Self {
+            line: line.to_string(),
+            timestamp: SystemTime::now(),
+            cause: caps.as_ref().and_then(|caps| {
+                caps.get(1).map(|hex| u32::from_str_radix(hex.as_str(), 16))
+                    .or_else(|| caps.get(3).map(|dec| dec.as_str().parse::<u32>()))
+                    .and_then(Result::ok)
+            }),
+            reason: caps.as_ref().and_then(|caps| caps.get(2)).map(|reason| reason.as_str().to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backtrace {
+    // The line that announced the panic.
+    pub message: String,
+    pub timestamp: SystemTime,
+    pub frames: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub address: u64,
+    // Only filled in when symbols are loaded.
+    pub location: Option<Location>,
+}
+
+// Fans events out to every subscriber, forgetting ones that hung up.
+pub(crate) struct EventBus {
+    subscribers: Vec<Sender<Event>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+
+// Gathers the frames that follow a panic message.  The backtrace is
+// considered complete when the chip reboots, another panic starts, or the
+// device goes quiet.
+pub(crate) struct PanicCollector {
+    current: Option<Backtrace>,
+    // Addresses from "Backtrace:" lines, which are preferred over whatever
+    // else (register dumps, stack memory) happened to look like code.
+    backtrace_frames: Vec<Frame>,
+}
+
+impl PanicCollector {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            backtrace_frames: Vec::new(),
+        }
+    }
+
+    pub fn start(&mut self, message: &str) -> Option<Backtrace> {
+        let finished = self.finish();
+        self.current = Some(Backtrace {
+            message: message.to_string(),
+            timestamp: SystemTime::now(),
+            frames: Vec::new(),
+        });
+        finished
+    }
+
+    pub fn is_collecting(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn add_frames(&mut self, line: &str, frames: Vec<Frame>) {
+        if let Some(current) = self.current.as_mut() {
+            if line.trim_start().starts_with("Backtrace") {
+                self.backtrace_frames.extend(frames);
+            } else {
+                current.frames.extend(frames);
+            }
+        }
+    }
+
+    pub fn finish(&mut self) -> Option<Backtrace> {
+        let backtrace_frames = mem::take(&mut self.backtrace_frames);
+        self.current.take().map(|mut backtrace| {
+            if !backtrace_frames.is_empty() {
+                backtrace.frames = backtrace_frames;
+            }
+            backtrace
+        })
+    }
+}
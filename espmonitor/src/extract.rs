@@ -0,0 +1,89 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::crash::format_utc;
+use regex::Regex;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::SystemTime,
+};
+
+// Pulls the numbers captured from matching lines, and optionally appends
+// them to a CSV file, one timestamped row per line.  Captures that aren't
+// numbers are left empty.
+pub(crate) struct Extractor {
+    regex: Regex,
+    file: Option<File>,
+}
+
+impl Extractor {
+    pub fn create<P: AsRef<Path>>(regex: Regex, path: Option<P>) -> io::Result<Self> {
+        let file = match path {
+            Some(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                // Appending to an earlier capture keeps its header.
+                if file.metadata()?.len() == 0 {
+                    writeln!(file, "time,{}", column_names(&regex).join(","))?;
+                }
+                Some(file)
+            },
+            None => None,
+        };
+        Ok(Self { regex, file })
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        column_names(&self.regex)
+    }
+
+    pub fn line(&mut self, line: &str) -> Option<Vec<Option<f64>>> {
+        let caps = self.regex.captures(line)?;
+        let values = caps.iter()
+            .skip(1)
+            .map(|cap| cap.and_then(|cap| cap.as_str().trim().parse::<f64>().ok()))
+            .collect::<Vec<_>>();
+
+        if let Some(file) = self.file.as_mut() {
+            let cells = values.iter()
+                .map(|value| value.map(|value| value.to_string()).unwrap_or_default())
+                .collect::<Vec<_>>();
+            // Like the log sinks, a full disk shouldn't stop the monitor.
+            let _ = writeln!(file, "{},{}", format_utc_millis(SystemTime::now()), cells.join(","));
+        }
+        Some(values)
+    }
+}
+
+// Named groups keep their names; the others are numbered from 1.
+fn column_names(regex: &Regex) -> Vec<String> {
+    regex.capture_names()
+        .skip(1)
+        .enumerate()
+        .map(|(i, name)| name.map(str::to_string).unwrap_or_else(|| format!("value{}", i + 1)))
+        .collect()
+}
+
+// "2021-06-08T00:22:57.123Z"; sensors are usually read more than once a
+// second.
+fn format_utc_millis(time: SystemTime) -> String {
+    let stamp = format_utc(time);
+    let millis = time.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.subsec_millis()).unwrap_or(0);
+    format!("{}.{:03}Z", stamp.trim_end_matches('Z'), millis)
+}
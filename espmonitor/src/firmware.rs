@@ -0,0 +1,98 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use lazy_static::lazy_static;
+use object::read::{Object, ObjectSection};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+// esp_app_desc_t, as placed by ESP-IDF at the start of the app's rodata.
+const APP_DESC_SECTIONS: &[&str] = &[".flash.appdesc", ".rodata_desc"];
+const APP_DESC_MAGIC: u32 = 0xabcd5432;
+const APP_DESC_VERSION_OFFSET: usize = 16;
+const APP_DESC_PROJECT_NAME_OFFSET: usize = 48;
+const APP_DESC_STRING_LEN: usize = 32;
+
+lazy_static! {
+    static ref ELF_SHA_RE: Regex = Regex::new(r"ELF file SHA256:\s+([0-9a-fA-F]+)")
+        .expect("Failed to parse ELF SHA regex");
+    static ref PROJECT_NAME_RE: Regex = Regex::new(r"Project name:\s+([^\x1b]*?)\s*(?:\x1b\[[0-9;]*m)?$")
+        .expect("Failed to parse project name regex");
+    static ref APP_VERSION_RE: Regex = Regex::new(r"App version:\s+([^\x1b]*?)\s*(?:\x1b\[[0-9;]*m)?$")
+        .expect("Failed to parse app version regex");
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppInfo {
+    pub elf_sha256: String,
+    pub project_name: Option<String>,
+    pub version: Option<String>,
+}
+
+impl AppInfo {
+    pub fn from_elf(data: &[u8], obj: &object::File) -> Self {
+        let app_desc = APP_DESC_SECTIONS.iter()
+            .filter_map(|name| obj.section_by_name(name))
+            .filter_map(|section| section.data().ok())
+            .find(|data| data.len() >= APP_DESC_PROJECT_NAME_OFFSET + APP_DESC_STRING_LEN && read_magic(data, obj.is_little_endian()) == APP_DESC_MAGIC);
+
+        AppInfo {
+            elf_sha256: format!("{:x}", Sha256::digest(data)),
+            project_name: app_desc.and_then(|desc| read_c_string(&desc[APP_DESC_PROJECT_NAME_OFFSET..])),
+            version: app_desc.and_then(|desc| read_c_string(&desc[APP_DESC_VERSION_OFFSET..])),
+        }
+    }
+
+    // Compares against what the app prints at boot.  The firmware only
+    // prints a prefix of its ELF SHA, so that's all we compare.
+    pub fn check_boot_line(&self, line: &str) -> Option<String> {
+        if let Some(caps) = ELF_SHA_RE.captures(line) {
+            let running = caps[1].to_lowercase();
+            if !self.elf_sha256.starts_with(&running) {
+                return Some(format!("running firmware's ELF SHA256 is {}..., but the ELF being used for decoding is {}...", running, &self.elf_sha256[..running.len().min(self.elf_sha256.len())]));
+            }
+        } else if let (Some(caps), Some(project_name)) = (PROJECT_NAME_RE.captures(line), self.project_name.as_ref()) {
+            if &caps[1] != project_name {
+                return Some(format!("running firmware is project '{}', but the ELF being used for decoding is '{}'", &caps[1], project_name));
+            }
+        } else if let (Some(caps), Some(version)) = (APP_VERSION_RE.captures(line), self.version.as_ref()) {
+            if &caps[1] != version {
+                return Some(format!("running firmware is version '{}', but the ELF being used for decoding is '{}'", &caps[1], version));
+            }
+        }
+        None
+    }
+}
+
+fn read_magic(data: &[u8], little_endian: bool) -> u32 {
+    let bytes = [data[0], data[1], data[2], data[3]];
+    if little_endian {
+        u32::from_le_bytes(bytes)
+    } else {
+        u32::from_be_bytes(bytes)
+    }
+}
+
+fn read_c_string(data: &[u8]) -> Option<String> {
+    let data = &data[..APP_DESC_STRING_LEN.min(data.len())];
+    let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    if len > 0 {
+        Some(String::from_utf8_lossy(&data[..len]).into_owned())
+    } else {
+        None
+    }
+}
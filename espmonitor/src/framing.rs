@@ -0,0 +1,421 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::{
+    convert::TryFrom,
+    io::{Error as IoError, ErrorKind},
+    mem,
+    time::{Duration, Instant},
+};
+
+const SLIP_END: u8 = 0xc0;
+const SLIP_ESC: u8 = 0xdb;
+const SLIP_ESC_END: u8 = 0xdc;
+const SLIP_ESC_ESC: u8 = 0xdd;
+
+// Anything longer is more likely a stray start byte than a real frame.
+const MAX_FRAME_LEN: usize = 64 * 1024;
+// The longest bootloader reply (SPI_FLASH_MD5's) is under 64 bytes; this
+// leaves room for every byte of it being escaped.
+const MAX_ESPTOOL_FRAME_LEN: usize = 256;
+
+pub enum FrameParse {
+    // The frame continues in data that hasn't arrived yet.
+    Incomplete,
+    // The start byte was just part of the text.
+    NotAFrame,
+    // The first len bytes were a frame, shown as lines.
+    Frame { len: usize, lines: Vec<String> },
+}
+
+// Decodes binary frames mixed in with the text, e.g. telemetry or binary
+// log records, so they don't go through the line splitter as garbage.
+pub trait FrameParser {
+    fn is_start(&self, byte: u8) -> bool;
+
+    // buf starts with a byte that is_start() accepted.
+    fn parse(&mut self, buf: &[u8]) -> FrameParse;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    // SLIP frames (with a leading and trailing END byte), shown as hex.
+    Slip,
+    // SLIP frames holding type-length-value records, with a one byte type
+    // and a one byte length.
+    SlipTlv,
+}
+
+impl Framing {
+    pub fn parser(self) -> Box<dyn FrameParser> {
+        Box::new(SlipParser { tlv: self == Framing::SlipTlv })
+    }
+}
+
+impl TryFrom<&str> for Framing {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "slip" => Ok(Framing::Slip),
+            "slip-tlv" => Ok(Framing::SlipTlv),
+            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid framing (expected slip or slip-tlv)", value))),
+        }
+    }
+}
+
+pub(crate) enum Chunk {
+    Text(Vec<u8>),
+    Frame(Vec<String>),
+}
+
+// Splits the frames the registered parsers recognize out of the serial
+// data; everything else is passed on as text.
+pub(crate) struct Deframer {
+    parsers: Vec<Box<dyn FrameParser>>,
+    pending: Vec<u8>,
+    // When the oldest unfinished frame in pending started waiting.
+    pending_since: Option<Instant>,
+}
+
+impl Deframer {
+    // Flashers' replies are always recognized, so they don't show up as
+    // garbage.
+    pub fn new() -> Self {
+        Self {
+            parsers: vec![Box::new(EsptoolParser::new())],
+            pending: Vec::new(),
+            pending_since: None,
+        }
+    }
+
+    pub fn add_parser(&mut self, parser: Box<dyn FrameParser>) {
+        self.parsers.push(parser);
+    }
+
+    pub fn feed(&mut self, buf: &[u8]) -> Vec<Chunk> {
+        self.pending.extend_from_slice(buf);
+
+        let mut chunks = Vec::new();
+        let mut text_start = 0;
+        let mut pos = 0;
+        while pos < self.pending.len() {
+            let byte = self.pending[pos];
+            let pending = &self.pending[pos..];
+            // The first parser that recognizes the frame gets it.
+            let parsed = self.parsers.iter_mut()
+                .filter(|parser| parser.is_start(byte))
+                .map(|parser| parser.parse(pending))
+                .find(|parsed| !matches!(parsed, FrameParse::NotAFrame))
+                .unwrap_or(FrameParse::NotAFrame);
+            match parsed {
+                FrameParse::Incomplete if self.pending.len() - pos <= MAX_FRAME_LEN => break,
+                FrameParse::Frame { len, lines } => {
+                    if pos > text_start {
+                        chunks.push(Chunk::Text(self.pending[text_start..pos].to_vec()));
+                    }
+                    chunks.push(Chunk::Frame(lines));
+                    pos += len.max(1);
+                    text_start = pos;
+                },
+                _ => pos += 1,
+            }
+        }
+
+        if pos > text_start {
+            chunks.push(Chunk::Text(self.pending[text_start..pos].to_vec()));
+        }
+        self.pending.drain(..pos);
+        if self.pending.is_empty() {
+            self.pending_since = None;
+        } else if pos > 0 || self.pending_since.is_none() {
+            self.pending_since = Some(Instant::now());
+        }
+        chunks
+    }
+
+    // A frame that never finished was probably just text after all, even
+    // if more data has arrived since.
+    pub fn take_stale(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+        match self.pending_since {
+            Some(since) if since.elapsed() > timeout => {
+                self.pending_since = None;
+                Some(mem::take(&mut self.pending))
+            },
+            _ => None,
+        }
+    }
+}
+
+struct SlipParser {
+    tlv: bool,
+}
+
+impl FrameParser for SlipParser {
+    fn is_start(&self, byte: u8) -> bool {
+        byte == SLIP_END
+    }
+
+    fn parse(&mut self, buf: &[u8]) -> FrameParse {
+        // Back-to-back frames share END bytes.
+        if buf.get(1) == Some(&SLIP_END) {
+            return FrameParse::Frame { len: 1, lines: Vec::new() };
+        }
+        let (len, payload) = match slip_frame(buf) {
+            Ok(frame) => frame,
+            Err(parse) => return parse,
+        };
+
+        let lines = if self.tlv {
+            tlv_lines(&payload)
+        } else {
+            vec![format!("[slip] {}", hex(&payload))]
+        };
+        FrameParse::Frame { len, lines }
+    }
+}
+
+// The bootloader's replies to esptool and espflash.  Seeing them means a
+// flasher is talking to the device through the same port.  A flash is
+// thousands of the same reply, so only changes are shown.
+struct EsptoolParser {
+    last_command: Option<u8>,
+}
+
+impl EsptoolParser {
+    pub fn new() -> Self {
+        Self { last_command: None }
+    }
+}
+
+impl FrameParser for EsptoolParser {
+    fn is_start(&self, byte: u8) -> bool {
+        byte == SLIP_END
+    }
+
+    fn parse(&mut self, buf: &[u8]) -> FrameParse {
+        // Replies have a direction byte of 1; bail before waiting for the
+        // end of something that's just text.
+        if buf.len() > 1 && buf[1] != 0x01 && buf[1] != b'O' {
+            return FrameParse::NotAFrame;
+        }
+        // Text that happens to contain the start of a reply shouldn't be
+        // held up waiting for an end that isn't coming.
+        let (len, payload) = match slip_frame(&buf[..buf.len().min(MAX_ESPTOOL_FRAME_LEN)]) {
+            Ok(frame) => frame,
+            Err(FrameParse::Incomplete) if buf.len() >= MAX_ESPTOOL_FRAME_LEN => return FrameParse::NotAFrame,
+            Err(parse) => return parse,
+        };
+
+        // The flasher stub says hello once it's running.
+        if payload == b"OHAI" {
+            self.last_command = None;
+            return FrameParse::Frame { len, lines: vec!["[esptool] Flasher stub started".to_string()] };
+        }
+        let size = match payload.get(2..4) {
+            Some(size) => u16::from_le_bytes([size[0], size[1]]) as usize,
+            None => return FrameParse::NotAFrame,
+        };
+        if payload[0] != 0x01 || payload.len() != size + 8 {
+            return FrameParse::NotAFrame;
+        }
+
+        let command = payload[1];
+        if self.last_command == Some(command) {
+            return FrameParse::Frame { len, lines: Vec::new() };
+        }
+        self.last_command = Some(command);
+        FrameParse::Frame { len, lines: vec![format!("[esptool] {} reply", esptool_command_name(command))] }
+    }
+}
+
+fn esptool_command_name(command: u8) -> String {
+    match command {
+        0x02 => "FLASH_BEGIN".to_string(),
+        0x03 => "FLASH_DATA".to_string(),
+        0x04 => "FLASH_END".to_string(),
+        0x05 => "MEM_BEGIN".to_string(),
+        0x06 => "MEM_END".to_string(),
+        0x07 => "MEM_DATA".to_string(),
+        0x08 => "SYNC".to_string(),
+        0x09 => "WRITE_REG".to_string(),
+        0x0a => "READ_REG".to_string(),
+        0x0b => "SPI_SET_PARAMS".to_string(),
+        0x0d => "SPI_ATTACH".to_string(),
+        0x0f => "CHANGE_BAUDRATE".to_string(),
+        0x10 => "FLASH_DEFL_BEGIN".to_string(),
+        0x11 => "FLASH_DEFL_DATA".to_string(),
+        0x12 => "FLASH_DEFL_END".to_string(),
+        0x13 => "SPI_FLASH_MD5".to_string(),
+        0x14 => "GET_SECURITY_INFO".to_string(),
+        0xd0 => "ERASE_FLASH".to_string(),
+        0xd1 => "ERASE_REGION".to_string(),
+        0xd2 => "READ_FLASH".to_string(),
+        0xd3 => "RUN_USER_CODE".to_string(),
+        other => format!("command 0x{:02x}", other),
+    }
+}
+
+// Finds the END byte that closes the frame starting at buf[0], and
+// unescapes what's between them.
+fn slip_frame(buf: &[u8]) -> Result<(usize, Vec<u8>), FrameParse> {
+    let end = match buf.iter().skip(1).position(|byte| *byte == SLIP_END) {
+        Some(end) => end + 1,
+        None => return Err(FrameParse::Incomplete),
+    };
+
+    let mut payload = Vec::with_capacity(end);
+    let mut escaped = false;
+    for byte in &buf[1..end] {
+        match (escaped, *byte) {
+            (false, SLIP_ESC) => escaped = true,
+            (false, byte) => payload.push(byte),
+            (true, SLIP_ESC_END) => { payload.push(SLIP_END); escaped = false; },
+            (true, SLIP_ESC_ESC) => { payload.push(SLIP_ESC); escaped = false; },
+            (true, _) => return Err(FrameParse::NotAFrame),
+        }
+    }
+    Ok((end + 1, payload))
+}
+
+fn tlv_lines(payload: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = payload;
+    while let [kind, len, value @ ..] = rest {
+        let len = *len as usize;
+        if value.len() < len {
+            break;
+        }
+        lines.push(format!("[tlv] type 0x{:02x}: {}", kind, describe_value(&value[..len])));
+        rest = &value[len..];
+    }
+    if !rest.is_empty() {
+        lines.push(format!("[tlv] {} trailing bytes: {}", rest.len(), hex(rest)));
+    }
+    lines
+}
+
+// Printable values are most likely strings.
+fn describe_value(value: &[u8]) -> String {
+    match std::str::from_utf8(value) {
+        Ok(text) if !text.is_empty() && text.chars().all(|c| !c.is_control()) => format!("\"{}\"", text),
+        _ => hex(value),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(chunks: &[Chunk]) -> Vec<u8> {
+        chunks.iter().flat_map(|chunk| match chunk {
+            Chunk::Text(text) => text.clone(),
+            Chunk::Frame(_) => Vec::new(),
+        }).collect()
+    }
+
+    fn frames(chunks: &[Chunk]) -> Vec<String> {
+        chunks.iter().flat_map(|chunk| match chunk {
+            Chunk::Text(_) => Vec::new(),
+            Chunk::Frame(lines) => lines.clone(),
+        }).collect()
+    }
+
+    #[test]
+    fn recognizes_esptool_replies() {
+        let mut deframer = Deframer::new();
+        let chunks = deframer.feed(b"before\xc0\x01\x08\x02\x00\x00\x00\x00\x00\x00\x00\xc0after");
+        assert_eq!(text(&chunks), b"beforeafter");
+        assert_eq!(frames(&chunks), vec!["[esptool] SYNC reply"]);
+    }
+
+    #[test]
+    fn gives_up_on_long_esptool_replies() {
+        let mut deframer = Deframer::new();
+        assert!(deframer.feed(b"\xc0\x01 looks like a reply").is_empty());
+        let mut data = Vec::new();
+        for _ in 0..MAX_ESPTOOL_FRAME_LEN / 8 {
+            data.extend_from_slice(b"noise!\r\n");
+        }
+        let chunks = deframer.feed(&data);
+        assert!(!chunks.is_empty());
+        assert!(text(&chunks).starts_with(b"\xc0\x01 looks like a reply"));
+        assert!(frames(&chunks).is_empty());
+    }
+
+    #[test]
+    fn times_out_pending_data_while_more_arrives() {
+        let mut deframer = Deframer::new();
+        deframer.add_parser(Framing::Slip.parser());
+        assert!(deframer.feed(b"\xc0partial").is_empty());
+        assert!(deframer.take_stale(Duration::from_secs(60)).is_none());
+        assert!(deframer.feed(b" frame").is_empty());
+        assert_eq!(deframer.take_stale(Duration::ZERO).unwrap(), b"\xc0partial frame");
+        assert!(deframer.take_stale(Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn decodes_slip_frames() {
+        let mut deframer = Deframer::new();
+        deframer.add_parser(Framing::Slip.parser());
+        let chunks = deframer.feed(b"a\xc0\x10\xdb\xdc\xdb\xdd\xc0\xc0\x20\xc0b");
+        assert_eq!(text(&chunks), b"ab");
+        assert_eq!(frames(&chunks), vec!["[slip] 10 c0 db", "[slip] 20"]);
+    }
+
+    #[test]
+    fn waits_for_the_end_of_a_frame() {
+        let mut deframer = Deframer::new();
+        deframer.add_parser(Framing::Slip.parser());
+        assert_eq!(text(&deframer.feed(b"a\xc0\x10")), b"a");
+        let chunks = deframer.feed(b"\x11\xc0");
+        assert_eq!(frames(&chunks), vec!["[slip] 10 11"]);
+    }
+
+    #[test]
+    fn passes_bad_escapes_through_as_text() {
+        let mut deframer = Deframer::new();
+        deframer.add_parser(Framing::Slip.parser());
+        let chunks = deframer.feed(b"\xc0\xdb\x00\xc0");
+        assert!(frames(&chunks).is_empty());
+        assert_eq!(text(&chunks), b"\xc0\xdb\x00");
+    }
+
+    #[test]
+    fn decodes_tlv_records() {
+        assert_eq!(
+            tlv_lines(b"\x01\x05hello\x02\x02\x00\xff\x03\x00\x04\x09ab"),
+            vec![
+                "[tlv] type 0x01: \"hello\"",
+                "[tlv] type 0x02: 00 ff",
+                "[tlv] type 0x03: ",
+                "[tlv] 4 trailing bytes: 04 09 61 62",
+            ],
+        );
+    }
+
+    #[test]
+    fn parses_framing_names() {
+        assert_eq!(Framing::try_from("slip").unwrap(), Framing::Slip);
+        assert_eq!(Framing::try_from("slip-tlv").unwrap(), Framing::SlipTlv);
+        assert_eq!(Framing::try_from("cobs").unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    convert::TryFrom,
+    io::{Error as IoError, ErrorKind},
+    ops::Range,
+};
+
+lazy_static! {
+    // What esp_get_minimum_free_heap_size() and esp_get_free_heap_size()
+    // usually get logged as, e.g. "Minimum free heap size: 123456" or
+    // "esp_get_free_heap_size() = 123456".
+    static ref MIN_HEAP_RE: Regex = Regex::new(r"(?i)min(?:imum)?[ _](?:free[ _])?heap(?:[ _]size)?\D{0,8}?(\d+)")
+        .expect("Failed to parse minimum heap regex");
+    static ref FREE_HEAP_RE: Regex = Regex::new(r"(?i)free[ _]heap(?:[ _]size)?\D{0,8}?(\d+)")
+        .expect("Failed to parse free heap regex");
+}
+
+// Tracks a number logged by the firmware; the first capture group of the
+// regex is the value.
+#[derive(Debug, Clone)]
+pub struct GaugeRule {
+    name: String,
+    regex: Regex,
+}
+
+impl TryFrom<&str> for GaugeRule {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let (name, regex) = match value.find(':') {
+            Some(colon) if colon > 0 => (&value[..colon], &value[colon + 1..]),
+            _ => return Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid gauge (expected NAME:REGEX)", value))),
+        };
+        let regex = Regex::new(regex).map_err(|err| IoError::new(ErrorKind::InvalidInput, err.to_string()))?;
+        if regex.captures_len() < 2 {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!("The regex for gauge '{}' needs a capture group for the value", name)));
+        }
+        Ok(GaugeRule {
+            name: name.to_string(),
+            regex,
+        })
+    }
+}
+
+struct Gauge {
+    name: String,
+    current: f64,
+    min: f64,
+    max: f64,
+}
+
+pub(crate) struct Gauges {
+    rules: Vec<GaugeRule>,
+    gauges: Vec<Gauge>,
+}
+
+impl Gauges {
+    pub fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            gauges: Vec::new(),
+        }
+    }
+
+    pub fn add_heap_rules(&mut self) {
+        // The minimum goes first, so its lines aren't also taken for the
+        // current free heap.
+        self.rules.push(GaugeRule { name: "min heap".to_string(), regex: MIN_HEAP_RE.clone() });
+        self.rules.push(GaugeRule { name: "heap".to_string(), regex: FREE_HEAP_RE.clone() });
+    }
+
+    pub fn add_rule(&mut self, rule: GaugeRule) {
+        self.rules.push(rule);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Earlier rules win where matches overlap.  Returns whether any gauge
+    // was updated.
+    pub fn feed(&mut self, line: &str) -> bool {
+        let mut claimed: Vec<Range<usize>> = Vec::new();
+        let mut updates = Vec::new();
+        for rule in &self.rules {
+            let found = rule.regex.captures_iter(line)
+                .filter_map(|caps| caps.get(0).zip(caps.get(1)))
+                .find(|(whole, _)| !claimed.iter().any(|range| range.start < whole.end() && whole.start() < range.end));
+            if let Some((whole, value)) = found {
+                claimed.push(whole.range());
+                if let Ok(value) = value.as_str().parse::<f64>() {
+                    updates.push((rule.name.clone(), value));
+                }
+            }
+        }
+
+        let updated = !updates.is_empty();
+        for (name, value) in updates {
+            match self.gauges.iter_mut().find(|gauge| gauge.name == name) {
+                Some(gauge) => {
+                    gauge.current = value;
+                    gauge.min = gauge.min.min(value);
+                    gauge.max = gauge.max.max(value);
+                },
+                None => self.gauges.push(Gauge { name, current: value, min: value, max: value }),
+            }
+        }
+        updated
+    }
+
+    // e.g. "heap 123456 (min 120000, max 130000)", one entry per gauge.
+    pub fn describe(&self) -> Vec<String> {
+        self.gauges.iter()
+            .map(|gauge| format!("{} {} (min {}, max {})", gauge.name, gauge.current, gauge.min, gauge.max))
+            .collect()
+    }
+}
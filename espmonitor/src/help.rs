@@ -0,0 +1,39 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::Chip;
+
+pub fn version_info(program: &str, version: &str) -> String {
+    let chips = Chip::all().map(|chip| chip.to_string()).collect::<Vec<_>>().join(", ");
+
+    let mut features = Vec::new();
+    if cfg!(feature = "gzip") {
+        features.push("gzip");
+    }
+    if cfg!(feature = "notify") {
+        features.push("notify");
+    }
+
+    format!(
+        "{} {}\nChips: {}\nFeatures: {}",
+        program,
+        version,
+        chips,
+        if features.is_empty() { "none".to_string() } else { features.join(", ") },
+    )
+}
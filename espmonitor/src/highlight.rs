@@ -0,0 +1,74 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crossterm::style::Color;
+use std::{
+    convert::TryFrom,
+    io::{Error as IoError, ErrorKind},
+};
+
+// Makes lines from one ESP-IDF log tag stand out; without a color they're
+// just shown in bold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HighlightRule {
+    tag: String,
+    color: Option<Color>,
+}
+
+impl HighlightRule {
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub(crate) fn color(&self) -> Option<Color> {
+        self.color
+    }
+}
+
+impl TryFrom<&str> for HighlightRule {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let (tag, color) = match value.rfind(':') {
+            Some(colon) => (&value[..colon], Some(&value[colon + 1..])),
+            None => (value, None),
+        };
+        if tag.is_empty() {
+            return Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is missing a tag to highlight", value)));
+        }
+
+        let color = match color {
+            None => None,
+            Some("red") => Some(Color::Red),
+            Some("green") => Some(Color::Green),
+            Some("yellow") => Some(Color::Yellow),
+            Some("blue") => Some(Color::Blue),
+            Some("magenta") => Some(Color::Magenta),
+            Some("cyan") => Some(Color::Cyan),
+            Some("white") => Some(Color::White),
+            Some("grey") | Some("gray") => Some(Color::Grey),
+            Some(color) => return Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!("'{}' is not a valid color (expected red, green, yellow, blue, magenta, cyan, white, or grey)", color),
+            )),
+        };
+
+        Ok(HighlightRule {
+            tag: tag.to_string(),
+            color,
+        })
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    env,
+    fs,
+    io,
+    path::PathBuf,
+};
+
+// Remembers the last port (and speed) that was successfully opened from
+// each project directory, so machines with several boards attached don't
+// need the port spelled out on every run.
+struct Entry {
+    dir: String,
+    port: String,
+    speed: Option<usize>,
+}
+
+fn state_file() -> Option<PathBuf> {
+    let dir =
+        if cfg!(windows) {
+            env::var_os("LOCALAPPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_STATE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))
+        };
+    dir.map(|dir| dir.join("espmonitor").join("last-ports"))
+}
+
+fn project_dir() -> Option<String> {
+    env::current_dir().ok().map(|dir| dir.to_string_lossy().to_string())
+}
+
+fn load() -> Vec<Entry> {
+    state_file()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|contents| {
+            contents
+                .lines()
+                .filter_map(|line| {
+                    let mut fields = line.split('\t');
+                    Some(Entry {
+                        dir: fields.next()?.to_string(),
+                        port: fields.next()?.to_string(),
+                        speed: fields.next().and_then(|speed| speed.parse::<usize>().ok()),
+                    })
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn last_port() -> Option<String> {
+    let dir = project_dir()?;
+    load().into_iter().find(|entry| entry.dir == dir).map(|entry| entry.port)
+}
+
+pub(crate) fn last_speed(port: &str) -> Option<usize> {
+    let dir = project_dir()?;
+    load().into_iter().find(|entry| entry.dir == dir && entry.port == port).and_then(|entry| entry.speed)
+}
+
+pub(crate) fn remember(port: &str, speed: Option<usize>) -> io::Result<()> {
+    let (path, dir) = match (state_file(), project_dir()) {
+        (Some(path), Some(dir)) => (path, dir),
+        _ => return Ok(()),
+    };
+
+    let mut entries = load();
+    entries.retain(|entry| entry.dir != dir);
+    entries.push(Entry {
+        dir,
+        port: port.to_string(),
+        speed,
+    });
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let contents = entries
+        .iter()
+        .map(|entry| format!("{}\t{}\t{}\n", entry.dir, entry.port, entry.speed.map(|speed| speed.to_string()).unwrap_or_default()))
+        .collect::<String>();
+    fs::write(path, contents)
+}
@@ -0,0 +1,94 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::borrow::Cow;
+
+lazy_static! {
+    static ref ANSI_RE: Regex = Regex::new(r"\x1b\[[0-9;]*[A-Za-z]")
+        .expect("Failed to parse ANSI escape regex");
+    // "I (1234) wifi: message", or with CONFIG_LOG_TIMESTAMP_SOURCE_SYSTEM,
+    // "I (12:34:56.789) wifi: message".
+    static ref IDF_LOG_RE: Regex = Regex::new(r"^([EWIDV]) \(([0-9:.]+)\) ([^:]+): ?(.*)$")
+        .expect("Failed to parse log line regex");
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Verbose,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Verbose => "verbose",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub level: LogLevel,
+    // Milliseconds since boot, unless the firmware logs wall-clock time.
+    pub ticks: Option<u64>,
+    pub timestamp: String,
+    pub tag: String,
+    pub message: String,
+}
+
+impl LogRecord {
+    // Lines that don't follow the ESP-IDF log format (ROM output, printf(),
+    // Rust's log crate with a different formatter) give None.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = strip_ansi(line);
+        let caps = IDF_LOG_RE.captures(&line)?;
+        Some(LogRecord {
+            level: match &caps[1] {
+                "E" => LogLevel::Error,
+                "W" => LogLevel::Warn,
+                "I" => LogLevel::Info,
+                "D" => LogLevel::Debug,
+                _ => LogLevel::Verbose,
+            },
+            ticks: caps[2].parse::<u64>().ok(),
+            timestamp: caps[2].to_string(),
+            tag: caps[3].to_string(),
+            message: caps[4].to_string(),
+        })
+    }
+}
+
+pub(crate) fn strip_ansi(line: &str) -> Cow<'_, str> {
+    // Most lines have no escapes; skip the regex for those.
+    if !has_ansi(line) {
+        return Cow::Borrowed(line);
+    }
+    ANSI_RE.replace_all(line, "")
+}
+
+pub(crate) fn has_ansi(line: &str) -> bool {
+    line.contains('\x1b')
+}
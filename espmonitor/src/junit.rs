@@ -0,0 +1,190 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::crash::format_utc;
+use std::{
+    fs,
+    io,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct TestCase {
+    pub name: String,
+    // Where the test lives, e.g. its source file; CI tools group by this.
+    pub classname: String,
+    pub time: Duration,
+    pub outcome: Outcome,
+    // The file and line a failure points at, for annotations.
+    pub location: Option<(String, u32)>,
+}
+
+// Writes `cases` as a single JUnit test suite, the format most CI systems
+// can show results from.
+pub(crate) fn write(path: &Path, suite: &str, started_at: SystemTime, elapsed: Duration, cases: &[TestCase]) -> io::Result<()> {
+    let failures = cases.iter().filter(|case| matches!(case.outcome, Outcome::Failed(_))).count();
+    let skipped = cases.iter().filter(|case| matches!(case.outcome, Outcome::Skipped(_))).count();
+    let counts = format!(r#"tests="{}" failures="{}" errors="0" skipped="{}" time="{:.3}""#, cases.len(), failures, skipped, elapsed.as_secs_f64());
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!("<testsuites {}>\n", counts));
+    xml.push_str(&format!("  <testsuite name=\"{}\" {} timestamp=\"{}\">\n", escape(suite), counts, format_utc(started_at)));
+    for case in cases {
+        xml.push_str(&format!(
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape(&case.name),
+            escape(&case.classname),
+            case.time.as_secs_f64(),
+        ));
+        match &case.outcome {
+            Outcome::Passed => xml.push_str("/>\n"),
+            Outcome::Failed(message) => xml.push_str(&format!(">\n      <failure message=\"{}\"/>\n    </testcase>\n", escape(message))),
+            Outcome::Skipped(message) => xml.push_str(&format!(">\n      <skipped message=\"{}\"/>\n    </testcase>\n", escape(message))),
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    fs::write(path, xml)
+}
+
+// Workflow commands that make GitHub Actions show each failure on the run's
+// summary page, and on the line it points at if it's in the repository.
+pub(crate) fn annotations(cases: &[TestCase]) -> Vec<String> {
+    cases
+        .iter()
+        .filter_map(|case| match &case.outcome {
+            Outcome::Failed(message) => Some((case, message)),
+            _ => None,
+        })
+        .map(|(case, message)| {
+            let mut properties = Vec::new();
+            if let Some((file, line)) = case.location.as_ref() {
+                properties.push(format!("file={}", escape_property(file)));
+                properties.push(format!("line={}", line));
+            }
+            properties.push(format!("title={}", escape_property(&case.name)));
+            let message = if message.is_empty() { "Failed" } else { message.as_str() };
+            format!("::error {}::{}", properties.join(","), escape_data(message))
+        })
+        .collect()
+}
+
+fn escape_data(text: &str) -> String {
+    text.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+fn escape_property(text: &str) -> String {
+    escape_data(text).replace(':', "%3A").replace(',', "%2C")
+}
+
+fn escape(text: &str) -> String {
+    text.chars()
+        // Control characters aren't allowed in XML 1.0 at all.
+        .filter(|c| !c.is_control() || *c == '\t' || *c == '\n')
+        .fold(String::with_capacity(text.len()), |mut escaped, c| {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&apos;"),
+                '\n' => escaped.push_str("&#10;"),
+                _ => escaped.push(c),
+            }
+            escaped
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process, time::UNIX_EPOCH};
+
+    fn case(name: &str, outcome: Outcome, location: Option<(&str, u32)>) -> TestCase {
+        TestCase {
+            name: name.to_string(),
+            classname: "test_app.c".to_string(),
+            time: Duration::from_millis(1500),
+            outcome,
+            location: location.map(|(file, line)| (file.to_string(), line)),
+        }
+    }
+
+    #[test]
+    fn escapes_xml() {
+        assert_eq!(escape(r#"a & b < c > d "e" 'f'"#), "a &amp; b &lt; c &gt; d &quot;e&quot; &apos;f&apos;");
+        assert_eq!(escape("one\ntwo\tthree"), "one&#10;two\tthree");
+        assert_eq!(escape("\x1b[0;31mred\x1b[0m\r\x07"), "[0;31mred[0m");
+    }
+
+    #[test]
+    fn escapes_workflow_commands() {
+        assert_eq!(escape_data("100% done\r\nnext"), "100%25 done%0D%0Anext");
+        assert_eq!(escape_property("C:\\src\\a.c, line 3\n%"), "C%3A\\src\\a.c%2C line 3%0A%25");
+    }
+
+    #[test]
+    fn annotates_failures() {
+        let cases = [
+            case("adds", Outcome::Passed, None),
+            case("divides: by zero, 100%", Outcome::Failed("Expected 1\nWas 0".to_string()), Some(("main/test_app.c", 42))),
+            case("crash", Outcome::Failed(String::new()), None),
+            case("slow", Outcome::Skipped("needs hardware".to_string()), None),
+        ];
+        assert_eq!(annotations(&cases), vec![
+            "::error file=main/test_app.c,line=42,title=divides%3A by zero%2C 100%25::Expected 1%0AWas 0".to_string(),
+            "::error title=crash::Failed".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn writes_a_suite() {
+        let path = env::temp_dir().join(format!("espmonitor-junit-{}.xml", process::id()));
+        let cases = [
+            case("adds", Outcome::Passed, None),
+            case("a < b", Outcome::Failed("Expected \"1\"".to_string()), Some(("test_app.c", 7))),
+            case("slow", Outcome::Skipped("needs hardware".to_string()), None),
+        ];
+        write(&path, "esp32 & co", UNIX_EPOCH + Duration::from_secs(86400), Duration::from_millis(4250), &cases).unwrap();
+        let xml = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let counts = r#"tests="3" failures="1" errors="0" skipped="1" time="4.250""#;
+        assert_eq!(xml, format!(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<testsuites {counts}>\n",
+            "  <testsuite name=\"esp32 &amp; co\" {counts} timestamp=\"1970-01-02T00:00:00Z\">\n",
+            "    <testcase name=\"adds\" classname=\"test_app.c\" time=\"1.500\"/>\n",
+            "    <testcase name=\"a &lt; b\" classname=\"test_app.c\" time=\"1.500\">\n",
+            "      <failure message=\"Expected &quot;1&quot;\"/>\n",
+            "    </testcase>\n",
+            "    <testcase name=\"slow\" classname=\"test_app.c\" time=\"1.500\">\n",
+            "      <skipped message=\"needs hardware\"/>\n",
+            "    </testcase>\n",
+            "  </testsuite>\n",
+            "</testsuites>\n",
+        ), counts = counts));
+    }
+}
@@ -15,294 +15,2278 @@
 // You should have received a copy of the GNU General Public License
 // along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
 
-use addr2line::Context;
 use crossterm::{
     QueueableCommand,
-    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
-    style::{Color, Print, PrintStyledContent, Stylize},
-    terminal::{disable_raw_mode, enable_raw_mode},
+    cursor::{MoveTo, MoveUp},
+    event::{self, Event as TermEvent, KeyCode, KeyEvent, KeyModifiers},
+    style::{Color, ContentStyle, Print, PrintStyledContent, Stylize},
+    terminal::{self, Clear, ClearType, disable_raw_mode, enable_raw_mode},
+    tty::IsTty,
 };
-use gimli::{EndianRcSlice, RunTimeEndian};
 use lazy_static::lazy_static;
-use object::read::Object;
 use regex::Regex;
-use serial::{self, BaudRate, SerialPort, SystemPort};
+use serial::{self, BaudRate, SerialPort};
 use std::{
-    fs,
+    collections::VecDeque,
+    convert::TryFrom,
+    ffi::{OsStr, OsString},
+    fs::{self, File},
     io::{self, ErrorKind, Read, Write, stdout},
-    process::exit,
-    time::{Duration, Instant},
+    mem,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{atomic::{AtomicBool, Ordering}, mpsc::Receiver},
+    thread,
+    time::{Duration, Instant, SystemTime},
 };
 
+mod actions;
+mod arduino;
+mod busy;
+mod cache;
+mod chipdetect;
+mod cli;
+mod config;
+mod console;
+mod control;
+mod crash;
+mod decoder;
+mod dedup;
+mod discover;
+mod doctor;
+mod error;
+mod esp8266;
+mod eventloop;
+mod events;
+mod extract;
+mod firmware;
+mod framing;
+mod gauges;
+mod help;
+mod highlight;
+mod history;
+mod idflog;
+mod json;
+mod junit;
+mod lines;
+mod lockfile;
+mod marker;
+mod mock;
+mod mqtt;
+mod notify;
+mod panic;
+mod pio;
+mod plot;
+mod plain;
+mod port;
+mod ports;
+mod probe;
+mod profile;
+mod protocol;
+mod qemu;
+mod queue;
+mod record;
+mod remap;
+mod reset;
+mod rotate;
+mod script;
+mod scrollback;
+mod session;
+mod settings;
+mod shutdown;
+mod sink;
+mod source;
+mod stats;
+mod status;
+mod summary;
+mod symbols;
+mod throughput;
+mod toolchain;
+mod syslog;
+mod testrun;
+mod transfer;
 mod types;
+mod utf8;
+mod watch;
+mod websocket;
+mod wrap;
 
-pub use types::{AppArgs, Chip, Framework};
+use actions::ActionRunner;
+pub use actions::MatchAction;
+pub use arduino::find_arduino_elf;
+use cache::{DecodeCache, DEFAULT_DECODE_CACHE_SIZE};
+use chipdetect::DetectedChip;
+pub use cli::MonitorOpts;
+pub use config::{AppArgsBuilder, ConfigError};
+use console::{Console, ConsoleCommand, MenuCommand};
+use control::ControlServer;
+use crash::{CrashReporter, DEFAULT_CRASH_CONTEXT, ReportFormat, format_utc, timestamped_path};
+use decoder::AsyncDecoder;
+use dedup::{Dedup, RepeatFilter};
+pub use discover::{find_bootloader_elf, find_elf};
+pub use doctor::doctor;
+pub use error::Error;
+pub use highlight::HighlightRule;
+pub use idflog::{LogLevel, LogRecord};
+use esp8266::{Esp8266CrashDecoder, Esp8266Report};
+use events::{EventBus, PanicCollector};
+use extract::Extractor;
+pub use events::{Backtrace, Event, Frame, LineRecord, ResetInfo};
+pub use firmware::AppInfo;
+pub use framing::{FrameParse, FrameParser, Framing};
+use framing::{Chunk, Deframer};
+use gauges::Gauges;
+pub use gauges::GaugeRule;
+pub use help::version_info;
+use junit::{Outcome, TestCase};
+use lines::LineAssembler;
+use lockfile::PortLock;
+use marker::{MarkerPipe, Stopwatch, format_marker, format_time_of_day};
+use notify::Notifier;
+pub use mock::MockPort;
+pub use mqtt::{MqttConfig, MqttSink};
+use panic::{RegisterDumpDecoder, explain_crash_message};
+pub use pio::PioEnv;
+pub use port::SerialSource;
+use plain::PlainWriter;
+use plot::Plot;
+use port::{Port, StreamPort};
+use qemu::Qemu;
+use queue::{DEFAULT_QUEUE_BYTES, QueuedWriter};
+pub use qemu::DEFAULT_QEMU_IMAGE;
+pub use ports::{PortInfo, PortSelector, available_ports, list_ports, select_port};
+pub use profile::Profile;
+pub use protocol::Protocol;
+use protocol::ProtocolPrinter;
+use script::{Script, ScriptStatus};
+use scrollback::{DEFAULT_SCROLLBACK_LINES, Scrollback};
+use stats::Stats;
+use status::StatusLine;
+use summary::SessionSummary;
+use testrun::TestRun;
+use throughput::DEFAULT_THROUGHPUT_TEST_BYTES;
+use utf8::Utf8Decoder;
+pub use sink::{CallbackSink, JsonSink, OutputSink, SinkConfig, WriterSink};
+use source::SourceSnippets;
+pub use syslog::{SyslogSink, SyslogTarget};
+pub use symbols::{Location, Symbols, find_function_name, find_inlined_callers, find_location, load_bin_context, resolve_address};
+use watch::PathWatcher;
+use record::{Recorder, Recording};
+pub use remap::PathRemap;
+use remap::remap_location;
+pub use reset::{ResetMode, ResetStep};
+pub use rotate::{DEFAULT_LOG_KEEP, LogRotation, RotateWhen};
+pub use serial::FlowControl;
+pub use settings::{FrameFormat, Latency, OutputFormat, ResetBehavior, parse_flow_control, parse_line_level, parse_offset};
+pub use throughput::synthetic_log;
+pub use transfer::TransferMode;
+pub use types::{AppArgs, Arch, CHIP_SPECS, Chip, ChipSpec, DecodeArgs, Framework, QemuMachine, ReplayArgs, RomBanner};
 
-const DEFAULT_BAUD_RATE: BaudRate = BaudRate::Baud115200;
-const UNFINISHED_LINE_TIMEOUT: Duration = Duration::from_secs(5);
+// Panics kept for the session summary.
+const MAX_SUMMARY_PANICS: usize = 20;
+const DEFAULT_READ_BUFFER_SIZE: usize = 1024;
+// Where the factory app goes in the default partition tables.
+const DEFAULT_LINK_OFFSET: u64 = 0x10000;
+const DEFAULT_LINE_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_TEST_TIMEOUT: Duration = Duration::from_secs(300);
+const REOPEN_TIMEOUT: Duration = Duration::from_secs(5);
+const PORT_WAIT_INTERVAL: Duration = Duration::from_millis(500);
+// Long enough for most flashes; if the flasher is still going when the
+// port is reopened, its traffic shows up again and we yield again.
+const FLASHER_YIELD_DELAY: Duration = Duration::from_secs(10);
 
 lazy_static! {
-    static ref LINE_SEP_RE: Regex = Regex::new("\r?\n")
-        .expect("Failed to parse line separator regex");
-    static ref FUNC_ADDR_RE: Regex = Regex::new(r"0x4[0-9a-fA-F]{7}")
-        .expect("Failed to parse program address regex");
+    static ref ADDR_RE: Regex = Regex::new(r"0x[0-9a-fA-F]{8}")
+        .expect("Failed to parse address regex");
+    // A prompt on its own ("> "), or a shell-style one ending in a space
+    // ("esp32> ", "root@dev:~# "), but not a log line still on its way
+    // ("I (123) tag: ").
+    static ref PROMPT_RE: Regex = Regex::new(r"^\s*[>$#?]\s*$|[>$#] +$")
+        .expect("Failed to parse prompt regex");
+    static ref PANIC_RE: Regex = Regex::new(r"Guru Meditation Error|panicked at|abort\(\) was called|^Fatal exception|^Exception \(\d+\)|A stack overflow in task|CORRUPT HEAP")
+        .expect("Failed to parse panic regex");
+    // What only some frameworks print when they crash: esp-backtrace's
+    // panic and exception headers, ESP-IDF's assertions, and the ESP8266
+    // Arduino core's panics.
+    static ref BAREMETAL_PANIC_RE: Regex = Regex::new(r"^!! A panic occured|^Exception occured|^=+ PANIC =+$|^Exception '[A-Za-z]+'")
+        .expect("Failed to parse baremetal panic regex");
+    static ref ESP_IDF_PANIC_RE: Regex = Regex::new(r"^assert failed: |^ESP_ERROR_CHECK failed")
+        .expect("Failed to parse ESP-IDF panic regex");
+    static ref ARDUINO_PANIC_RE: Regex = Regex::new(r"^assert failed: |^Panic \S+:\d+|^Abort called|^User exception \(panic/abort/assert\)|^Soft WDT reset")
+        .expect("Failed to parse Arduino panic regex");
+    static ref BOOT_RE: Regex = Regex::new(r"^rst:0x[0-9a-fA-F]+|rst cause:\d+")
+        .expect("Failed to parse boot regex");
+    // The ROM waiting after a reset into download mode, and a flasher's
+    // traffic once it's talking to it.
+    static ref FLASHER_RE: Regex = Regex::new(r"^\[esptool\]|^waiting for download")
+        .expect("Failed to parse flasher regex");
+    // From the ESP-IDF (and ESP8266 RTOS SDK) second-stage bootloader, which
+    // says where in flash the app it's starting is.
+    static ref APP_OFFSET_RE: Regex = Regex::new(r"Loaded app from partition at offset (0x[0-9a-fA-F]+)")
+        .expect("Failed to parse app offset regex");
+    // Printed by the ESP-IDF and Arduino panic handlers once they're done.
+    static ref REBOOTING_RE: Regex = Regex::new(r"^Rebooting\.\.\.")
+        .expect("Failed to parse rebooting regex");
 }
 
-macro_rules! rprintln {
-    () => (print!("\r\n"));
-    ($fmt:literal) => (print!(concat!($fmt, "\r\n")));
-    ($fmt:literal, $($arg:tt)+) => (print!(concat!($fmt, "\r\n"), $($arg)*));
+// Raw mode needs explicit CRs; pipes and log files don't want them.
+static RAW_OUTPUT: AtomicBool = AtomicBool::new(true);
+
+fn line_ending() -> &'static str {
+    if RAW_OUTPUT.load(Ordering::Relaxed) { "\r\n" } else { "\n" }
 }
 
-pub struct Symbols<'a> {
-    obj: object::read::File<'a, &'a [u8]>,
-    context: Context<EndianRcSlice<RunTimeEndian>>,
+macro_rules! rprintln {
+    () => (print!("{}", line_ending()));
+    ($fmt:literal) => (print!(concat!($fmt, "{}"), line_ending()));
+    ($fmt:literal, $($arg:tt)+) => (print!(concat!($fmt, "{}"), $($arg)*, line_ending()));
 }
 
-pub struct SerialState<'a> {
-    unfinished_line: String,
-    last_unfinished_line_at: Instant,
-    symbols: Option<Symbols<'a>>,
+pub struct SerialState {
+    chip: Chip,
+    // Toggled from the CTRL+T menu.
+    timestamps: bool,
+    // Which crash messages to look for beyond the ones every framework
+    // prints.
+    framework: Framework,
+    // How far the running app is from where its ELF was linked to run
+    // from, for chips that map flash at a fixed window.
+    app_shift: Option<i64>,
+    detect_chip: bool,
+    chip_checked: bool,
+    deframer: Deframer,
+    flasher_seen: bool,
+    utf8: Utf8Decoder,
+    lines: LineAssembler,
+    line_timeout: Duration,
+    stream: bool,
+    reset_behavior: ResetBehavior,
+    output_format: OutputFormat,
+    shown_partial: usize,
+    stats: Stats,
+    gauges: Gauges,
+    // Shows the gauges at the bottom of the terminal.
+    status: Option<StatusLine>,
+    extractor: Option<Extractor>,
+    plot: Option<Plot>,
+    protocols: ProtocolPrinter,
+    stopwatch: Stopwatch,
+    scrollback: Scrollback,
+    app_info: Option<AppInfo>,
+    symbols: Option<Symbols>,
+    decode_cache: DecodeCache,
+    path_remaps: Vec<PathRemap>,
+    sources: Option<SourceSnippets>,
+    decoder: Option<AsyncDecoder>,
+    register_dump: RegisterDumpDecoder,
+    esp8266_crash: Esp8266CrashDecoder,
+    repeats: RepeatFilter,
+    // Set from the console; hides lines that don't match.
+    filter: Option<Regex>,
+    sinks: Vec<Box<dyn OutputSink>>,
+    // Started and stopped with the "log" command.
+    command_log: Option<Box<dyn OutputSink>>,
+    highlights: Vec<HighlightRule>,
+    actions: ActionRunner,
+    notifier: Notifier,
+    port_name: String,
+    events: EventBus,
+    panic: PanicCollector,
+    panics: VecDeque<Backtrace>,
+    crash: Option<CrashReporter>,
+    script: Option<Script>,
+    tests: Option<TestRun>,
 }
 
-impl<'a> SerialState<'a> {
-    pub fn new(symbols: Option<Symbols<'a>>) -> Self {
+impl SerialState {
+    pub fn new(symbols: Option<Symbols>) -> Self {
         Self {
-            unfinished_line: "".to_owned(),
-            last_unfinished_line_at: Instant::now(),
-            symbols
+            chip: Chip::default(),
+            timestamps: false,
+            framework: Framework::default(),
+            app_shift: None,
+            detect_chip: false,
+            chip_checked: false,
+            deframer: Deframer::new(),
+            flasher_seen: false,
+            utf8: Utf8Decoder::new(),
+            lines: LineAssembler::new(),
+            line_timeout: DEFAULT_LINE_TIMEOUT,
+            stream: false,
+            reset_behavior: ResetBehavior::default(),
+            output_format: OutputFormat::default(),
+            shown_partial: 0,
+            stats: Stats::new(),
+            gauges: Gauges::new(),
+            extractor: None,
+            plot: None,
+            protocols: ProtocolPrinter::new(&[]),
+            status: None,
+            stopwatch: Stopwatch::new(),
+            scrollback: Scrollback::new(DEFAULT_SCROLLBACK_LINES),
+            app_info: symbols.as_ref().map(|symbols| symbols.app_info().clone()),
+            symbols,
+            decode_cache: DecodeCache::new(DEFAULT_DECODE_CACHE_SIZE),
+            path_remaps: Vec::new(),
+            sources: None,
+            decoder: None,
+            register_dump: RegisterDumpDecoder::new(),
+            esp8266_crash: Esp8266CrashDecoder::new(),
+            repeats: RepeatFilter::new(false, false),
+            filter: None,
+            sinks: Vec::new(),
+            command_log: None,
+            highlights: Vec::new(),
+            actions: ActionRunner::new(Vec::new()),
+            notifier: Notifier::new(false, Vec::new()),
+            port_name: String::new(),
+            events: EventBus::new(),
+            panic: PanicCollector::new(),
+            panics: VecDeque::new(),
+            crash: None,
+            script: None,
+            tests: None,
         }
     }
-}
 
-#[cfg(unix)]
-pub fn run(args: AppArgs) -> Result<(), Box<dyn std::error::Error>> {
-    use nix::{sys::wait::{WaitStatus, waitpid}, unistd::{ForkResult, fork}};
+    pub fn set_chip(&mut self, chip: Chip) {
+        self.chip = chip;
+    }
 
-    enable_raw_mode()?;
+    pub fn set_framework(&mut self, framework: Framework) {
+        self.framework = framework;
+    }
 
-    match unsafe { fork() } {
-        Err(err) => {
-            disable_raw_mode()?;
-            Err(err.into())
-        },
-        Ok(ForkResult::Parent { child }) => loop {
-            match waitpid(child, None) {
-                Ok(WaitStatus::Exited(_, status)) => {
-                    disable_raw_mode()?;
-                    exit(status);
-                },
-                Ok(WaitStatus::Signaled(_, _, _)) => {
-                    disable_raw_mode()?;
-                    exit(255);
+    pub fn set_app_offset(&mut self, app_offset: u64, link_offset: u64) {
+        self.app_shift = Some(app_offset as i64 - link_offset as i64);
+        self.decode_cache.clear();
+    }
+
+    // Otherwise, a boot banner from a different chip only gets a warning.
+    pub fn set_detect_chip(&mut self, detect_chip: bool) {
+        self.detect_chip = detect_chip;
+    }
+
+    pub fn set_line_timeout(&mut self, line_timeout: Duration) {
+        self.line_timeout = line_timeout;
+    }
+
+    pub fn set_stream(&mut self, stream: bool) {
+        self.stream = stream;
+    }
+
+    pub fn set_reset_behavior(&mut self, reset_behavior: ResetBehavior) {
+        self.reset_behavior = reset_behavior;
+    }
+
+    pub fn set_output_format(&mut self, output_format: OutputFormat) {
+        self.output_format = output_format;
+    }
+
+    pub fn set_repeat_handling(&mut self, collapse: bool, highlight_changes: bool) {
+        self.repeats = RepeatFilter::new(collapse, highlight_changes);
+    }
+
+    pub fn set_highlights(&mut self, highlights: Vec<HighlightRule>) {
+        self.highlights = highlights;
+    }
+
+    // Commands are told which port they're about via ESPMONITOR_PORT.
+    pub fn set_match_actions(&mut self, actions: Vec<MatchAction>, port_name: &str) {
+        self.actions = ActionRunner::new(actions);
+        self.port_name = port_name.to_string();
+    }
+
+    pub fn set_notify(&mut self, enabled: bool, keywords: Vec<String>) {
+        self.notifier = Notifier::new(enabled, keywords);
+    }
+
+    pub fn set_gauges(&mut self, heap: bool, rules: Vec<GaugeRule>) {
+        if heap {
+            self.gauges.add_heap_rules();
+        }
+        for rule in rules {
+            self.gauges.add_rule(rule);
+        }
+    }
+
+    pub fn set_protocols(&mut self, protocols: &[Protocol]) {
+        self.protocols = ProtocolPrinter::new(protocols);
+    }
+
+    pub fn set_extractor(&mut self, regex: Regex, path: Option<&OsStr>, plot: bool) -> io::Result<()> {
+        let extractor = Extractor::create(regex, path)?;
+        if plot {
+            self.plot = Some(Plot::new(extractor.names()));
+        }
+        self.extractor = Some(extractor);
+        Ok(())
+    }
+
+    // Each panic then gets a report in dir with the last context_lines lines
+    // before it, decoded against bin.
+    pub fn set_crash_reports(&mut self, dir: PathBuf, bin: Option<OsString>, extra_bins: Vec<OsString>, context_lines: usize, remaps: Vec<PathRemap>, markdown: bool) {
+        let format = if markdown { ReportFormat::Markdown } else { ReportFormat::Text };
+        self.crash = Some(CrashReporter::new(dir, &self.port_name, bin, extra_bins, context_lines, remaps, format));
+    }
+
+    // Every processed line is copied to each sink, in the order they were added.
+    pub fn add_sink(&mut self, sink: Box<dyn OutputSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn add_frame_parser(&mut self, parser: Box<dyn FrameParser>) {
+        self.deframer.add_parser(parser);
+    }
+
+    fn sinks_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn OutputSink>> {
+        self.sinks.iter_mut().chain(self.command_log.iter_mut())
+    }
+
+    // Each receiver gets every event from here on; dropping it unsubscribes.
+    pub fn subscribe(&mut self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    fn panic_finished(&mut self, mut backtrace: Backtrace, output: &mut dyn Write) -> io::Result<()> {
+        // The frames couldn't be resolved as they came in if the symbols
+        // are with the async decoder.
+        if let Some(decoder) = self.decoder.as_ref() {
+            let locations = decoder.resolve(backtrace.frames.iter().map(|frame| frame.address).collect());
+            for (frame, location) in backtrace.frames.iter_mut().zip(locations) {
+                frame.location = location;
+            }
+        }
+        if self.panics.len() == MAX_SUMMARY_PANICS {
+            self.panics.pop_front();
+        }
+        self.panics.push_back(backtrace.clone());
+        if let Some(crash) = self.crash.as_mut() {
+            match crash.write(&backtrace, self.app_info.as_ref(), self.chip) {
+                Ok(path) => {
+                    queue_wrapped(&format!("Crash report written to {}", path.display()), None, output)?;
+                    output.flush()?;
                 },
-                _ => (),
+                Err(err) => print_warning(&format!("WARNING: Unable to write crash report: {}", err), self.output_format, output)?,
             }
-        },
-        Ok(ForkResult::Child) => run_child(args),
+        }
+        self.events.emit(Event::Panic(backtrace));
+        Ok(())
+    }
+
+    // Whether a flasher has shown signs of using the port since the last
+    // call.
+    pub fn take_flasher_seen(&mut self) -> bool {
+        mem::take(&mut self.flasher_seen)
+    }
+
+    pub fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.app_info = symbols.as_ref().map(|symbols| symbols.app_info().clone());
+        match self.decoder.as_ref() {
+            Some(decoder) => decoder.set_symbols(symbols),
+            None => self.symbols = symbols,
+        }
+        self.decode_cache.clear();
+    }
+
+    // Hands the symbols off to a worker thread; decoded addresses are then
+    // printed by handle_decoded() after the lines they appeared in.
+    pub fn enable_async_decode(&mut self) {
+        if self.decoder.is_none() {
+            self.decoder = Some(AsyncDecoder::spawn(self.symbols.take(), self.decode_cache.capacity(), self.path_remaps.clone()));
+        }
+    }
+
+    // Applied to decoded file names; needs to be set before
+    // enable_async_decode().
+    pub fn set_path_remaps(&mut self, remaps: Vec<PathRemap>) {
+        self.path_remaps = remaps;
+        self.decode_cache.clear();
     }
+
+    // Shows the code around each decoded address, looking for files that
+    // aren't where they were built under source_path.
+    pub fn set_show_source(&mut self, source_path: Option<PathBuf>) {
+        self.sources = Some(SourceSnippets::new(source_path));
+    }
+
+    pub fn set_decode_cache_size(&mut self, size: usize) {
+        self.decode_cache.set_capacity(size);
+    }
+
+    pub fn set_scrollback_lines(&mut self, lines: usize) {
+        self.scrollback = Scrollback::new(lines);
+    }
+
+    pub fn set_stats_interval(&mut self, interval: Duration) {
+        self.stats.enable_with_interval(interval);
+    }
+}
+
+pub fn run(mut args: AppArgs) -> Result<(), Error> {
+    symbols::set_demangle(args.demangle);
+    symbols::set_addr2line_tools(args.addr2line_tools());
+    if args.throughput_test {
+        return throughput::run(&args, DEFAULT_THROUGHPUT_TEST_BYTES);
+    }
+    if args.probe {
+        return probe::run(&args);
+    }
+    if let Some(dir) = args.session_dir.clone() {
+        let session = session::prepare(&mut args, Path::new(&dir))?;
+        rprintln!("Saving this session in {}", session.display());
+    }
+    if let Some(input) = input_source(&args)? {
+        return run_input(&args, input);
+    }
+    shutdown::install_handler()?;
+    if args.non_interactive {
+        return run_monitor(args);
+    }
+
+    enable_raw_mode().map_err(Error::Terminal)?;
+    let _raw_mode = RawModeGuard;
+    run_monitor(args)
 }
 
-#[cfg(windows)]
-pub fn run(args: AppArgs) -> Result<(), Box<dyn std::error::Error>> {
-    enable_raw_mode()?;
-    let result = run_child(args);
-    disable_raw_mode()?;
-    result
+// Restores the terminal however the monitor ends, including by panicking.
+struct RawModeGuard;
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+    }
 }
 
-fn run_child(args: AppArgs) -> Result<(), Box<dyn std::error::Error>> {
+fn run_monitor(mut args: AppArgs) -> Result<(), Error> {
+    RAW_OUTPUT.store(!args.non_interactive, Ordering::Relaxed);
+    if args.wrap && !args.non_interactive {
+        if let Ok((cols, _)) = terminal::size() {
+            wrap::enable(cols as usize);
+        }
+    }
+
     rprintln!("ESPMonitor {}", env!("CARGO_PKG_VERSION"));
     rprintln!();
-    rprintln!("Commands:");
-    rprintln!("    CTRL+R    Reset chip");
-    rprintln!("    CTRL+C    Exit");
-    rprintln!();
+    if !args.non_interactive {
+        rprintln!("Commands:");
+        rprintln!("    CTRL+R    Reset chip");
+        rprintln!("    CTRL+B    Reset chip into the serial bootloader");
+        rprintln!("    CTRL+F    Run the flash command and resume monitoring");
+        rprintln!("    CTRL+T    Menu: S statistics, T timestamps, L logging, B baud rate, P bootloader (H lists all)");
+        rprintln!("    CTRL+S    Save recent output to a file");
+        rprintln!("    CTRL+L    Insert a stopwatch mark");
+        rprintln!("    CTRL+D    Toggle DTR");
+        rprintln!("    CTRL+E    Toggle RTS");
+        rprintln!("    CTRL+K    Send a serial break");
+        rprintln!("    CTRL+C    Exit");
+        rprintln!("    :         Enter a command (':help' lists them)");
+        rprintln!();
+    }
+
+    if (args.junit.is_some() || args.github_annotations) && args.script.is_none() && !args.test {
+        return Err(ConfigError::ResultsWithoutScript.into());
+    }
+    // Mistakes in a script should show up before anything is reset.
+    let script = args.script.as_ref().map(|path| Script::load(Path::new(path))).transpose()?;
+    if args.test {
+        match args.flash_cmd.as_ref() {
+            Some(flash_cmd) => {
+                rprintln!("Flashing the tests with '{}'", flash_cmd);
+                if !flash(flash_cmd, &args)? {
+                    return Err(Error::Tests("Unable to flash the tests".to_string()));
+                }
+            },
+            None => rprintln!("No --flash-cmd; running the tests already on the device"),
+        }
+    }
 
-    let speed = args.speed.map(BaudRate::from_speed).unwrap_or(DEFAULT_BAUD_RATE);
+    if args.speed.is_none() && !args.detect_speed {
+        args.speed = history::last_speed(&args.serial);
+    }
+    let speed = BaudRate::from_speed(args.speed.unwrap_or_else(|| args.framework.default_speed(args.chip)));
     rprintln!("Opening {} with speed {}", args.serial, speed.speed());
 
-    let mut dev = serial::open(&args.serial)?;
-    dev.set_timeout(Duration::from_millis(200))?;
-    dev.reconfigure(&|settings| {
-        settings.set_baud_rate(speed)
-    })?;
+    let mut dev = open_free_port(&args).map_err(|source| Error::SerialOpen { port: args.serial.clone(), source })?;
+    if args.detect_speed {
+        detect_speed(&mut dev, &mut args).map_err(Error::SerialIo)?;
+    }
+    // Drivers generally assert both lines when the port is opened.
+    let mut control_lines = ControlLines {
+        dtr: args.dtr.unwrap_or(true),
+        rts: args.rts.unwrap_or(true),
+    };
+    // Not being able to save this is no reason to stop.
+    if args.qemu.is_none() {
+        let _ = history::remember(&args.serial, args.speed);
+    }
 
-    let bin_data = args.bin.as_ref().and_then(|bin_name| match fs::read(bin_name) {
-        Ok(bin_data) => {
+    let symbols = args.bin.as_ref().and_then(|bin_name| match symbols::load_bin_files(bin_name, &args.extra_bins) {
+        Ok(symbols) => {
             rprintln!("Using {} as flash image", bin_name.to_string_lossy());
-            Some(bin_data)
+            for extra_bin in args.extra_bins.iter() {
+                rprintln!("Also decoding addresses in {}", extra_bin.to_string_lossy());
+            }
+            Some(symbols)
         },
         Err(err) => {
-            rprintln!("WARNING: Unable to open flash image {}: {}", bin_name.to_string_lossy(), err);
+            rprintln!("WARNING: Unable to load flash image {}: {}", bin_name.to_string_lossy(), err);
             None
         },
     });
+    let mut bin_watcher = args.bin.as_ref().map(PathWatcher::new);
 
-    let symbols = bin_data.as_ref().and_then(|bin_data| match load_bin_context(bin_data.as_slice()) {
-        Ok(symbols) => Some(symbols),
-        Err(err) => {
-            rprintln!("WARNING: Failed to parse flash image: {}", err);
-            None
-        },
-    });
+    // QEMU only starts once we connect, so there's nothing to miss.
+    if args.reset && args.qemu.is_none() {
+        reset_chip(&mut dev, &args).map_err(Error::SerialIo)?;
+    }
 
-    if args.reset {
-        reset_chip(&mut dev)?;
+    let mut serial_state = new_serial_state(&args, symbols)?;
+    serial_state.enable_async_decode();
+    if let Some(path) = args.script.as_ref() {
+        rprintln!("Running script {}", path.to_string_lossy());
     }
+    serial_state.script = script;
+    if args.test {
+        serial_state.tests = Some(TestRun::new());
+    }
+    let pane_height = serial_state.plot.as_ref().map(Plot::height).unwrap_or(0);
+    if (!serial_state.gauges.is_empty() || pane_height > 0) && !args.non_interactive {
+        if let Ok((cols, rows)) = terminal::size() {
+            let status = StatusLine::enable(cols, rows, pane_height, !serial_state.gauges.is_empty(), &mut stdout()).map_err(Error::Terminal)?;
+            serial_state.status = Some(status);
+        }
+    }
+
+    let mut watcher = match (args.watch.as_ref(), args.flash_cmd.as_ref()) {
+        (Some(dir), Some(_)) => {
+            rprintln!("Watching {} for changes", dir.to_string_lossy());
+            Some(PathWatcher::new(dir))
+        },
+        (Some(_), None) => return Err(ConfigError::WatchWithoutFlashCmd.into()),
+        (None, _) => None,
+    };
 
+    // Written from a background thread so a slow terminal drops output
+    // rather than holding up reads until the port's buffer overflows.
+    // It's drained before printing anything directly, to keep the order.
+    let mut output =
+        if args.non_interactive {
+            QueuedWriter::new(PlainWriter::new(stdout()), DEFAULT_QUEUE_BYTES, "\n")
+        } else {
+            QueuedWriter::new(stdout(), DEFAULT_QUEUE_BYTES, "\r\n")
+        };
+    let output_dropped = output.dropped();
+    let mut recorder = match args.record.as_ref() {
+        Some(path) => {
+            rprintln!("Recording raw serial data to {}", path.to_string_lossy());
+            Some(Recorder::create(path)?)
+        },
+        None => None,
+    };
 
-    let mut serial_state = SerialState {
-        unfinished_line: String::new(),
-        last_unfinished_line_at: Instant::now(),
-        symbols,
+    let mut console = Console::new();
+    let marker_pipe = match args.marker_pipe.as_ref() {
+        Some(path) => {
+            rprintln!("Reading markers from {}", path.to_string_lossy());
+            Some(MarkerPipe::open(path)?)
+        },
+        None => None,
+    };
+    let control = match args.control.as_ref() {
+        Some(addr) => {
+            rprintln!("Accepting commands on {}", addr);
+            Some(ControlServer::bind(addr)?)
+        },
+        None => None,
     };
+    if let Some(file) = args.send_file.as_ref() {
+        let command = ConsoleCommand::SendFile(None, PathBuf::from(file));
+        let outcome = run_command(&mut dev, &args, &mut serial_state, command, &mut output)?;
+        report_outcome(&mut serial_state, outcome, &mut output)?;
+    }
+    if let Some(script) = serial_state.script.as_mut() {
+        script.start();
+    }
 
-    let mut output = stdout();
-    let mut buf = [0u8; 1024];
-    loop {
-        match dev.read(&mut buf) {
-            Ok(bytes) if bytes > 0 => handle_serial(&mut serial_state, &buf[0..bytes], &mut output)?,
-            Ok(_) => if dev.read_dsr().is_err() {
+    let started_at = Instant::now();
+    let session_started_at = SystemTime::now();
+    let mut buf = vec![0u8; args.read_buffer_size.map(NonZeroUsize::get).unwrap_or(DEFAULT_READ_BUFFER_SIZE)];
+    let mut warned_about_flasher = false;
+    let result = 'monitor: loop {
+        if args.exit_after.map(|exit_after| started_at.elapsed() >= exit_after).unwrap_or(false) {
+            break Ok(());
+        }
+        if shutdown::requested() {
+            output.drain();
+            rprintln!("Received exit signal");
+            break Ok(());
+        }
+
+        let ready = eventloop::wait(&mut dev, !args.non_interactive, args.latency.read_timeout()).map_err(Error::SerialIo)?;
+        if console.is_active() {
+            console.hide(&mut output).map_err(Error::Terminal)?;
+        }
+        let read_result =
+            if ready.port {
+                dev.read(&mut buf)
+            } else if ready.keyboard {
+                Err(ErrorKind::WouldBlock.into())
+            } else {
+                Err(ErrorKind::TimedOut.into())
+            };
+        match read_result {
+            Ok(bytes) if bytes > 0 => {
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.record(&buf[0..bytes])?;
+                }
+                handle_serial(&mut serial_state, &buf[0..bytes], &mut output)?;
+            },
+            Ok(_) => if dev.is_disconnected() {
+                handle_disconnect(&mut serial_state, &mut output).map_err(Error::Terminal)?;
+                output.drain();
                 rprintln!("Device disconnected; exiting");
                 break Ok(());
+            } else {
+                handle_idle(&mut serial_state, &mut output)?;
             },
-            Err(err) if err.kind() == ErrorKind::TimedOut => (),
+            Err(err) if err.kind() == ErrorKind::TimedOut => handle_idle(&mut serial_state, &mut output)?,
             Err(err) if err.kind() == ErrorKind::WouldBlock => (),
             Err(err) if err.kind() == ErrorKind::Interrupted => (),
-            Err(err) => break Err(err.into()),
+            Err(err) => break Err(Error::SerialIo(err)),
+        }
+
+        if serial_state.take_flasher_seen() {
+            if args.yield_to_flasher {
+                drop(dev);
+                output.drain();
+                rprintln!("Closed {} for a flasher; reopening in {}s", args.serial, FLASHER_YIELD_DELAY.as_secs());
+                thread::sleep(FLASHER_YIELD_DELAY);
+                dev = reopen_port(&args).map_err(|source| Error::SerialOpen { port: args.serial.clone(), source })?;
+            } else if !warned_about_flasher {
+                warned_about_flasher = true;
+                output.drain();
+                rprintln!("WARNING: The device is in download mode; if a flasher is using {}, pass --yield-to-flasher so espmonitor doesn't steal its replies", args.serial);
+            }
         }
 
-        while event::poll(Duration::ZERO)? {
+        while ready.keyboard && event::poll(Duration::ZERO).map_err(Error::Terminal)? {
             match event::read() {
-                Ok(Event::Key(key_event)) => handle_input(&mut dev, key_event)?,
+                Ok(TermEvent::Key(key_event)) => {
+                    // Resets, line toggles and the like print straight to
+                    // the terminal.
+                    output.drain();
+                    match handle_input(&mut dev, &args, &mut serial_state, &mut control_lines, &mut console, key_event, &mut output)? {
+                        Some(PortAction::Flash(flash_cmd)) => {
+                            drop(dev);
+                            output.drain();
+                            run_flash_cmd(&flash_cmd, &args)?;
+                            dev = reopen_port(&args).map_err(|source| Error::SerialOpen { port: args.serial.clone(), source })?;
+                        },
+                        Some(PortAction::Quit) => break 'monitor Ok(()),
+                        None => (),
+                    }
+                },
+                Ok(TermEvent::Resize(cols, rows)) => {
+                    wrap::resize(cols as usize);
+                    if let Some(status) = serial_state.status.as_mut() {
+                        status.resize(cols, rows, &mut output).map_err(Error::Terminal)?;
+                    }
+                    // The plot is as wide as the terminal.
+                    update_status(&mut serial_state, &mut output).map_err(Error::Terminal)?;
+                },
                 Ok(_) => (),
-                Err(err) => return Err(err.into()),
+                Err(err) => return Err(Error::Terminal(err)),
+            }
+        }
+
+        if let (Some(watcher), Some(flash_cmd)) = (watcher.as_mut(), args.flash_cmd.as_ref()) {
+            if watcher.changed() {
+                output.drain();
+                rprintln!("Change detected in {}", watcher.root().to_string_lossy());
+                drop(dev);
+                run_flash_cmd(flash_cmd, &args)?;
+                dev = reopen_port(&args).map_err(|source| Error::SerialOpen { port: args.serial.clone(), source })?;
+                // Building touches files too (e.g. Cargo.lock); don't
+                // treat those as new changes.
+                watcher.rescan();
+            }
+        }
+
+        if let (Some(bin_watcher), Some(bin_name)) = (bin_watcher.as_mut(), args.bin.as_ref()) {
+            if bin_watcher.changed() {
+                output.drain();
+                match symbols::load_bin_files(bin_name, &args.extra_bins) {
+                    Ok(symbols) => {
+                        rprintln!("Reloaded symbols from {}", bin_name.to_string_lossy());
+                        serial_state.set_symbols(Some(symbols));
+                    },
+                    // Probably still being written; it'll change again when it's done.
+                    Err(err) => rprintln!("WARNING: Unable to reload flash image {}: {}", bin_name.to_string_lossy(), err),
+                }
+            }
+        }
+
+        handle_decoded(&mut serial_state, &mut output)?;
+
+        if let Some(script) = serial_state.script.as_mut() {
+            let mut status = script.step(&mut dev).map_err(Error::SerialIo)?;
+            while let ScriptStatus::Reset = status {
+                output.drain();
+                reset_chip(&mut dev, &args).map_err(Error::SerialIo)?;
+                status = script.step(&mut dev).map_err(Error::SerialIo)?;
+            }
+            match status {
+                ScriptStatus::Running | ScriptStatus::Reset => (),
+                ScriptStatus::Passed => {
+                    output.drain();
+                    rprintln!("Script finished");
+                    break Ok(());
+                },
+                ScriptStatus::Failed { line, message } => {
+                    output.drain();
+                    break Err(Error::Script { line, message });
+                },
+            }
+        }
+
+        if let Some(tests) = serial_state.tests.as_mut() {
+            if let Some(input) = tests.take_input() {
+                dev.send(&input).map_err(Error::SerialIo)?;
+            }
+            if tests.is_finished() {
+                break Ok(());
+            }
+            if started_at.elapsed() >= args.test_timeout.unwrap_or(DEFAULT_TEST_TIMEOUT) {
+                output.drain();
+                rprintln!("Timed out waiting for the tests to finish");
+                break Ok(());
+            }
+        }
+
+        while let Some(marker) = marker_pipe.as_ref().and_then(MarkerPipe::try_recv) {
+            print_marker(&mut serial_state, &format_marker(&marker, SystemTime::now()), &mut output)?;
+        }
+
+        while let Some(request) = control.as_ref().and_then(ControlServer::try_recv) {
+            output.drain();
+            let outcome = match ConsoleCommand::try_from(request.line.as_str()) {
+                Ok(command) => run_command(&mut dev, &args, &mut serial_state, command, &mut output)?,
+                Err(err) => CommandOutcome::Failed(err.to_string()),
+            };
+            match outcome {
+                CommandOutcome::Done(message) if message.is_empty() => request.reply("ok".to_string()),
+                CommandOutcome::Done(message) => request.reply(format!("ok: {}", message.replace('\n', "; "))),
+                CommandOutcome::Failed(message) => request.reply(format!("error: {}", message)),
+                CommandOutcome::Quit => {
+                    request.reply("ok".to_string());
+                    break 'monitor Ok(());
+                },
             }
         }
+
+        if let Some(report) = serial_state.stats.take_report(false) {
+            print_stats(&mut serial_state, &report, &mut output)?;
+        }
+
+        if console.is_active() {
+            console.show(&mut output).map_err(Error::Terminal)?;
+        }
+    };
+
+    handle_eof(&mut serial_state, &mut output)?;
+    if let Some(status) = serial_state.status.take() {
+        status.disable(&mut output).map_err(Error::Terminal)?;
+    }
+    output.drain();
+    serial_state.stats.record_output_dropped(output_dropped.load(Ordering::Relaxed));
+    print_summary(&args, &serial_state, session_started_at, started_at.elapsed());
+    if args.deassert_on_exit {
+        // The device may already be gone.
+        let _ = dev.set_dtr(false);
+        let _ = dev.set_rts(false);
     }
+    let result = match serial_state.tests.as_ref() {
+        Some(tests) => result.and_then(|()| finish_tests(tests)),
+        None => result,
+    };
+    let cases = match (serial_state.tests.as_ref(), serial_state.script.as_ref()) {
+        (Some(tests), _) => tests.cases().to_vec(),
+        (None, Some(script)) => script.cases().into_iter().chain(panic_cases(&serial_state)).collect(),
+        (None, None) => return result,
+    };
+    let reported = report_results(&cases, &args, session_started_at, started_at.elapsed());
+    result.and(reported)
 }
 
-pub fn load_bin_context(data: &[u8]) -> Result<Symbols, Box<dyn std::error::Error + 'static>> {
-    let obj = object::File::parse(data)?;
-    let context = Context::new(&obj)?;
-    Ok(Symbols {
-        obj,
-        context,
-    })
+// Panics seen while a script ran, which fail the session as far as CI is
+// concerned even if the script didn't look for them.
+fn panic_cases(state: &SerialState) -> Vec<TestCase> {
+    state.panics
+        .iter()
+        .enumerate()
+        .map(|(index, panic)| TestCase {
+            name: format!("panic {}", index + 1),
+            classname: "panics".to_string(),
+            time: Duration::ZERO,
+            outcome: Outcome::Failed(panic.message.clone()),
+            location: None,
+        })
+        .collect()
 }
 
-fn reset_chip(dev: &mut SystemPort) -> io::Result<()> {
-    print!("Resetting device... ");
-    std::io::stdout().flush()?;
-    dev.set_dtr(false)?;
-    dev.set_rts(true)?;
-    dev.set_rts(false)?;
-    rprintln!("done");
+fn report_results(cases: &[TestCase], args: &AppArgs, started_at: SystemTime, elapsed: Duration) -> Result<(), Error> {
+    if args.github_annotations {
+        for annotation in junit::annotations(cases) {
+            rprintln!("{}", annotation);
+        }
+    }
+    if let Some(path) = args.junit.as_ref() {
+        let suite = args.bin.as_ref()
+            .and_then(|bin| Path::new(bin).file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "espmonitor".to_string());
+        junit::write(Path::new(path), &suite, started_at, elapsed, cases)?;
+        rprintln!("Wrote test results to {}", path.to_string_lossy());
+    }
     Ok(())
 }
 
-pub fn handle_serial(state: &mut SerialState, buf: &[u8], output: &mut dyn Write) -> io::Result<()> {
-    let data = String::from_utf8_lossy(buf);
-    let mut lines = LINE_SEP_RE.split(&data).collect::<Vec<&str>>();
+fn finish_tests(tests: &TestRun) -> Result<(), Error> {
+    for case in tests.failed() {
+        match &case.outcome {
+            Outcome::Failed(message) if !message.is_empty() => rprintln!("FAILED {}: {}", case.name, message),
+            _ => rprintln!("FAILED {}", case.name),
+        }
+    }
+    rprintln!("{}", tests.summary());
 
-    let new_unfinished_line =
-        if data.ends_with('\n') {
-            None
-        } else {
-            lines.pop()
-        };
+    let failed = tests.failed().count();
+    if failed > 0 {
+        Err(Error::Tests(format!("{} of {} tests failed", failed, tests.cases().len())))
+    } else if !tests.is_finished() {
+        Err(Error::Tests("The test run didn't finish".to_string()))
+    } else if tests.cases().is_empty() {
+        Err(Error::Tests("No test results were seen".to_string()))
+    } else {
+        Ok(())
+    }
+}
 
-    for line in lines {
-        let full_line =
-            if !state.unfinished_line.is_empty() {
-                state.unfinished_line.push_str(line);
-                state.unfinished_line.as_str()
-            } else {
-                line
-            };
+fn print_summary(args: &AppArgs, state: &SerialState, started_at: SystemTime, elapsed: Duration) {
+    let panics = state.panics.iter().cloned().collect::<Vec<_>>();
+    let summary = SessionSummary {
+        totals: state.stats.summary(elapsed),
+        counts: state.stats.counts(),
+        elapsed,
+        gauges: state.gauges.describe(),
+        started_at,
+        panics: &panics,
+        panic_count: state.stats.panic_count(),
+        logs: args.sinks.iter().filter_map(|sink| match sink {
+            SinkConfig::File(path) | SinkConfig::Json(path) => Some(path.clone()),
+            _ => None,
+        }).collect(),
+        symbols: state.symbols.as_ref(),
+        remaps: &args.path_remaps,
+    };
 
-        if !full_line.is_empty() {
-            output_line(state, full_line, output)?;
-            state.unfinished_line.clear();
+    let lines = summary.lines();
+    for line in lines.iter() {
+        rprintln!("{}", line);
+    }
+    if let Some(path) = args.summary.as_ref() {
+        let text = lines.iter().map(|line| format!("{}\n", line)).collect::<String>();
+        if let Err(err) = fs::write(path, text) {
+            rprintln!("WARNING: Unable to write summary to {}: {}", path.to_string_lossy(), err);
         }
     }
+    if let Some(path) = args.summary_json.as_ref() {
+        if let Err(err) = fs::write(path, summary.json()) {
+            rprintln!("WARNING: Unable to write summary to {}: {}", path.to_string_lossy(), err);
+        }
+    }
+}
+
+// Feeds a recording made with --record through the same processing as a
+// live port, keeping the original pacing (scaled by `rate`).
+pub fn replay(args: ReplayArgs) -> Result<(), Error> {
+    let mut recording = Recording::open(&args.file)?;
 
-    if let Some(nel) = new_unfinished_line {
-        state.unfinished_line.push_str(nel);
-        state.last_unfinished_line_at = Instant::now();
-    } else if !state.unfinished_line.is_empty() && state.last_unfinished_line_at.elapsed() > UNFINISHED_LINE_TIMEOUT {
-        output_line(state, &state.unfinished_line, output)?;
-        state.unfinished_line.clear();
+    let symbols = match args.bin.as_ref() {
+        Some(bin_name) => Some(symbols::load_bin_files(bin_name, &args.extra_bins).map_err(|source| Error::Symbolication { bin: bin_name.clone(), source })?),
+        None => None,
+    };
+    symbols::set_addr2line_tools(toolchain::addr2line_tools(args.chip, None, None));
+    let mut serial_state = SerialState::new(symbols);
+    serial_state.set_chip(args.chip);
+
+    let mut output = stdout();
+    let started_at = Instant::now();
+    while let Some((offset, data)) = recording.next_chunk()? {
+        if args.rate > 0.0 {
+            let due = offset.div_f64(args.rate);
+            if let Some(wait) = due.checked_sub(started_at.elapsed()) {
+                handle_idle(&mut serial_state, &mut output)?;
+                thread::sleep(wait);
+            }
+        }
+        handle_serial(&mut serial_state, &data, &mut output)?;
     }
 
+    handle_eof(&mut serial_state, &mut output)?;
     Ok(())
 }
 
-pub fn output_line(state: &SerialState, line: &str, output: &mut dyn Write) -> io::Result<()> {
-    output.queue(Print(line.to_string()))?;
+// Runs a saved log through the same line handling as a live device, so
+// crash dumps can be decoded later or on another machine.
+pub fn decode(args: DecodeArgs) -> Result<(), Error> {
+    let input: Box<dyn SerialSource> = match args.file.as_ref() {
+        Some(file) => Box::new(File::open(file)?),
+        None => Box::new(StreamPort::stdin()),
+    };
 
-    if let Some(symbols) = state.symbols.as_ref() {
-        for mat in FUNC_ADDR_RE.find_iter(line) {
-            let (function, file, lineno) = u64::from_str_radix(&mat.as_str()[2..], 16)
-                .ok()
-                .map(|addr| {
-                    let function = find_function_name(symbols, addr);
-                    let (file, lineno) = find_location(symbols, addr);
-                    (function, file, lineno)
-                })
-                .unwrap_or((None, None, None));
+    symbols::set_demangle(args.demangle);
+    let app_args = AppArgs {
+        serial: args.file.as_ref().map(|file| file.to_string_lossy().into_owned()).unwrap_or_else(|| "-".to_string()),
+        chip: args.chip,
+        detect_chip: args.detect_chip,
+        output_format: args.output_format,
+        bin: args.bin,
+        extra_bins: args.extra_bins,
+        app_offset: args.app_offset,
+        link_offset: args.link_offset,
+        show_source: args.show_source,
+        source_path: args.source_path,
+        path_remaps: args.path_remaps,
+        demangle: args.demangle,
+        toolchain_prefix: args.toolchain_prefix,
+        addr2line: args.addr2line,
+        non_interactive: true,
+        ..AppArgs::default()
+    };
+    symbols::set_addr2line_tools(app_args.addr2line_tools());
+    run_input(&app_args, input)
+}
 
-            fn or_qq(s: Option<String>) -> String {
-                s.unwrap_or_else(|| "??".to_string())
-            }
+// Serial devices given as "-" (stdin) or a regular file (e.g. a saved log)
+// are read to the end rather than monitored.
+fn input_source(args: &AppArgs) -> io::Result<Option<Box<dyn SerialSource>>> {
+    if args.qemu.is_some() {
+        Ok(None)
+    } else if args.serial == "-" {
+        Ok(Some(Box::new(StreamPort::stdin())))
+    } else if fs::metadata(&args.serial).map(|metadata| metadata.is_file()).unwrap_or(false) {
+        Ok(Some(Box::new(File::open(&args.serial)?)))
+    } else {
+        Ok(None)
+    }
+}
 
-            let symbolicated_name = format!(
-                    "\r\n{} - {}\r\n    at {}:{}",
-                    mat.as_str(),
-                    or_qq(function),
-                    or_qq(file),
-                    or_qq(lineno.map(|l| l.to_string())),
-                )
-                .with(Color::Yellow);
-            output.queue(PrintStyledContent(symbolicated_name))?;
-        }
+fn run_input(args: &AppArgs, mut input: Box<dyn SerialSource>) -> Result<(), Error> {
+    RAW_OUTPUT.store(false, Ordering::Relaxed);
+
+    let mut output: Box<dyn Write> =
+        if stdout().is_tty() {
+            Box::new(stdout())
+        } else {
+            Box::new(PlainWriter::new(stdout()))
+        };
+    run_source(args, input.as_mut(), &mut output)
+}
+
+// Monitors `source` until it ends, without a terminal or keyboard: just
+// the reset (if it has control lines) and the usual line handling.  Used
+// for stdin and saved logs, and lets a MockPort stand in for a device.
+pub fn run_source(args: &AppArgs, source: &mut dyn SerialSource, output: &mut dyn Write) -> Result<(), Error> {
+    let symbols = match args.bin.as_ref() {
+        Some(bin_name) => Some(symbols::load_bin_files(bin_name, &args.extra_bins).map_err(|source| Error::Symbolication { bin: bin_name.clone(), source })?),
+        None => None,
+    };
+    let mut serial_state = new_serial_state(args, symbols)?;
+    serial_state.set_stream(false);
+
+    source.set_timeout(args.latency.read_timeout()).map_err(Error::SerialIo)?;
+    if args.reset && source.has_control_lines() {
+        reset_with_control_lines(source, args).map_err(Error::SerialIo)?;
     }
 
-    output.write_all(b"\r\n")?;
-    output.flush()?;
+    let started_at = Instant::now();
+    let mut buf = [0u8; 8192];
+    loop {
+        if args.exit_after.map(|exit_after| started_at.elapsed() >= exit_after).unwrap_or(false) {
+            break;
+        }
+        match source.read(&mut buf) {
+            Ok(0) if source.is_disconnected() => {
+                // Only devices get unplugged; anything else has just ended.
+                if source.has_control_lines() {
+                    handle_disconnect(&mut serial_state, output).map_err(Error::Terminal)?;
+                }
+                break;
+            },
+            Ok(0) => handle_idle(&mut serial_state, output)?,
+            Ok(bytes) => handle_serial(&mut serial_state, &buf[0..bytes], output)?,
+            Err(err) if err.kind() == ErrorKind::TimedOut || err.kind() == ErrorKind::WouldBlock => handle_idle(&mut serial_state, output)?,
+            Err(err) if err.kind() == ErrorKind::Interrupted => (),
+            Err(err) => return Err(Error::SerialIo(err)),
+        }
+    }
 
+    handle_eof(&mut serial_state, output)?;
     Ok(())
 }
 
-fn handle_input(dev: &mut SystemPort, key_event: KeyEvent) -> io::Result<()> {
-    if key_event.modifiers == KeyModifiers::CONTROL {
-        match key_event.code {
-            KeyCode::Char('r') => reset_chip(dev),
-            KeyCode::Char('c') => exit(0),
-            _ => Ok(()),
+// Whatever's left over once the input ends is never going to be terminated.
+pub fn handle_eof(state: &mut SerialState, output: &mut dyn Write) -> io::Result<()> {
+    // In --stream mode the partial line is already on screen, and is about
+    // to be printed again as a whole line.
+    if state.shown_partial > 0 {
+        erase_partial(&state.lines.partial()[..state.shown_partial], output)?;
+        state.shown_partial = 0;
+    }
+    let (data, garbled) = state.utf8.finish();
+    if !data.is_empty() {
+        state.stats.record_bytes(0, garbled);
+        for line in state.lines.feed(&data) {
+            process_line(state, &line, output)?;
         }
-    } else {
-        Ok(())
     }
+    if !state.lines.partial().is_empty() {
+        let line = state.lines.take_partial();
+        process_line(state, &line, output)?;
+    }
+    print_repeats(state.repeats.take_repeats(), output)?;
+    if let Some(backtrace) = state.panic.finish() {
+        state.panic_finished(backtrace, output)?;
+    }
+    for sink in state.sinks_mut() {
+        let _ = sink.flush();
+    }
+    output.flush()
 }
 
-pub fn find_function_name(symbols: &Symbols<'_>, addr: u64) -> Option<String> {
-    symbols.context
-        .find_frames(addr)
-        .ok()
-        .and_then(|mut frames| frames.next().ok().flatten())
-        .and_then(|frame| frame.function.and_then(|f| f.demangle().ok().map(|c| c.into_owned())))
-        .or_else(|| symbols.obj.symbol_map().get(addr).map(|sym| sym.name().to_string()))
+fn new_serial_state(args: &AppArgs, symbols: Option<Symbols>) -> io::Result<SerialState> {
+    let mut serial_state = SerialState::new(symbols);
+    serial_state.set_chip(args.chip);
+    serial_state.set_framework(args.framework);
+    serial_state.set_detect_chip(args.detect_chip);
+    if let Some(app_offset) = args.app_offset {
+        serial_state.set_app_offset(app_offset, args.link_offset.unwrap_or(DEFAULT_LINK_OFFSET));
+    }
+    if let Some(line_timeout) = args.line_timeout {
+        serial_state.set_line_timeout(line_timeout);
+    }
+    // Streaming redraws partial lines in place, which needs a terminal.
+    serial_state.set_stream(args.stream && !args.non_interactive);
+    if let Some(stats_interval) = args.stats_interval {
+        serial_state.set_stats_interval(stats_interval);
+    }
+    if let Some(decode_cache_size) = args.decode_cache_size {
+        serial_state.set_decode_cache_size(decode_cache_size);
+    }
+    serial_state.set_path_remaps(args.path_remaps.clone());
+    if args.show_source || args.source_path.is_some() {
+        serial_state.set_show_source(args.source_path.as_ref().map(PathBuf::from));
+    }
+    if let Some(scrollback_lines) = args.scrollback_lines {
+        serial_state.set_scrollback_lines(scrollback_lines);
+    }
+    serial_state.set_reset_behavior(args.reset_behavior);
+    serial_state.set_output_format(args.output_format);
+    serial_state.set_repeat_handling(args.dedup, args.highlight_changes);
+    serial_state.set_highlights(args.highlights.clone());
+    serial_state.set_match_actions(args.match_actions.clone(), &args.serial);
+    if (args.notify || !args.notify_keywords.is_empty()) && !notify::is_supported() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "This build of espmonitor has no desktop notification support (rebuild with the 'notify' feature)"));
+    }
+    serial_state.set_notify(args.notify, args.notify_keywords.clone());
+    serial_state.set_gauges(args.heap_gauge, args.gauges.clone());
+    serial_state.set_protocols(&args.protocols);
+    for framing in args.framing.iter() {
+        serial_state.add_frame_parser(framing.parser());
+    }
+    if let Some(regex) = args.extract.as_ref() {
+        let path = args.extract_out.as_deref();
+        serial_state.set_extractor(regex.clone(), path, args.plot)
+            .map_err(|err| io::Error::new(err.kind(), format!("Can't open {}: {}", path.unwrap_or_default().to_string_lossy(), err)))?;
+    }
+    // Markdown reports go to the current directory if there's no --crash-dir.
+    let crash_dir = args.crash_dir.as_ref().map(PathBuf::from).or_else(|| if args.markdown_reports { Some(PathBuf::from(".")) } else { None });
+    if let Some(crash_dir) = crash_dir {
+        serial_state.set_crash_reports(crash_dir, args.bin.clone(), args.extra_bins.clone(), args.crash_context.unwrap_or(DEFAULT_CRASH_CONTEXT), args.path_remaps.clone(), args.markdown_reports);
+    }
+    if args.log_rotation.as_ref().map(|rotation| rotation.gzip).unwrap_or(false) && !rotate::is_gzip_supported() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "This build of espmonitor can't compress logs (rebuild with the 'gzip' feature)"));
+    }
+    for sink in args.sinks.iter() {
+        rprintln!("Copying output to {}", sink);
+        serial_state.add_sink(sink.open(&args.serial, args.log_rotation.as_ref())?);
+    }
+    Ok(serial_state)
 }
 
-pub fn find_location(symbols: &Symbols<'_>, addr: u64) -> (Option<String>, Option<u32>) {
-    symbols.context
-        .find_location(addr)
-        .ok()
-        .map(|location| (
-            location.as_ref().and_then(|location| location.file).map(|file| file.to_string()),
-            location.as_ref().and_then(|location| location.line)
-        ))
-        .unwrap_or((None, None))
+fn open_port(args: &AppArgs) -> io::Result<Port> {
+    let speed = BaudRate::from_speed(args.speed.unwrap_or_else(|| args.framework.default_speed(args.chip)));
+    let path = match args.port_selector.as_ref() {
+        Some(selector) => selector.resolve()?,
+        None => args.serial.clone(),
+    };
+    if path != args.serial {
+        rprintln!("The device matching {} is now {}", args.port_selector.as_ref().map(|s| s.to_string()).unwrap_or_default(), path);
+    }
+    if let Some(image) = args.qemu.as_ref() {
+        rprintln!("Starting QEMU with {}", image.to_string_lossy());
+        let mut dev = Port::Qemu(Qemu::launch(args.chip, image)?);
+        dev.set_timeout(args.latency.read_timeout())?;
+        return Ok(dev);
+    }
+    if port::is_stream_path(&path) {
+        let mut dev = Port::Stream(StreamPort::open(&path)?);
+        dev.set_timeout(args.latency.read_timeout())?;
+        return Ok(dev);
+    }
+
+    // A shared port is meant to be opened by others too, so it isn't locked.
+    let lock = if args.shared { None } else { PortLock::acquire(&path, args.force)? };
+    let mut dev = serial::open(&path)?;
+    if args.shared {
+        if let Err(err) = port::allow_shared_open(&mut dev) {
+            rprintln!("WARNING: Unable to share {}: {}", path, err);
+        }
+    }
+    if args.no_touch {
+        port::hold_control_lines(&mut dev)?;
+    }
+    SerialPort::set_timeout(&mut dev, args.latency.read_timeout())?;
+    let frame_format = args.frame_format.unwrap_or_default();
+    dev.reconfigure(&|settings| {
+        settings.set_baud_rate(speed)?;
+        settings.set_char_size(frame_format.char_size);
+        settings.set_parity(frame_format.parity);
+        settings.set_stop_bits(frame_format.stop_bits);
+        settings.set_flow_control(args.flow_control.unwrap_or(FlowControl::FlowNone));
+        Ok(())
+    })?;
+    let mut dev = if port::lacks_control_lines(&mut dev) {
+        Port::Pty(dev, lock)
+    } else {
+        Port::Serial(dev, lock)
+    };
+    apply_line_levels(&mut dev, args)?;
+    Ok(dev)
+}
+
+fn detect_speed(dev: &mut Port, args: &mut AppArgs) -> io::Result<()> {
+    if !matches!(dev, Port::Serial(..)) {
+        rprintln!("WARNING: Only serial ports have a speed to detect");
+        return Ok(());
+    }
+    let mut best: Option<probe::ScanResult> = None;
+    for &speed in probe::SCAN_SPEEDS {
+        let result = match probe::try_speed(dev, args, speed) {
+            Ok(result) => result,
+            // Not every adapter (or OS) can do the odd ones like 74880.
+            Err(err) if err.kind() == ErrorKind::InvalidInput => {
+                rprintln!("Skipping {} baud: {}", speed, err);
+                continue;
+            },
+            Err(err) => return Err(err),
+        };
+        rprintln!("Trying {} baud: {} bytes, {:.0}% text", speed, result.bytes, result.text_ratio * 100.0);
+        match best {
+            Some(ref best) if best.score >= result.score => (),
+            _ => best = Some(result),
+        }
+    }
+    let speed = match best.filter(probe::is_plausible) {
+        Some(result) => {
+            rprintln!("Detected {} baud", result.speed);
+            result.speed
+        },
+        None => {
+            let fallback = args.framework.default_speed(args.chip);
+            rprintln!("WARNING: No speed gave readable output; using {} baud", fallback);
+            fallback
+        },
+    };
+    dev.set_speed(speed)?;
+    args.speed = Some(speed);
+    Ok(())
+}
+
+// Some adapter/board combinations hold the chip in reset or the bootloader
+// with the driver's default DTR/RTS levels, so let those be overridden.
+fn apply_line_levels(dev: &mut dyn SerialSource, args: &AppArgs) -> io::Result<()> {
+    if let Some(dtr) = args.dtr {
+        dev.set_dtr(dtr)?;
+    }
+    if let Some(rts) = args.rts {
+        dev.set_rts(rts)?;
+    }
+    Ok(())
+}
+
+// Explains who has the port when it's in use, and optionally waits for
+// them to let go of it.
+fn open_free_port(args: &AppArgs) -> io::Result<Port> {
+    let mut waiting = false;
+    loop {
+        match open_port(args) {
+            Ok(dev) => break Ok(dev),
+            Err(err) if busy::is_busy(&args.serial, &err) => {
+                if !waiting {
+                    let holders = busy::describe_holders(&args.serial);
+                    if !args.wait_for_port {
+                        break Err(io::Error::new(err.kind(), format!("{} is in use{}; close it or pass --wait-for-port", args.serial, holders)));
+                    }
+                    rprintln!("{} is in use{}; waiting for it to be released", args.serial, holders);
+                    waiting = true;
+                }
+                thread::sleep(PORT_WAIT_INTERVAL);
+            },
+            Err(err) if err.kind() == ErrorKind::AddrInUse => {
+                if !waiting {
+                    if !args.wait_for_port {
+                        break Err(io::Error::new(err.kind(), format!("{}; pass --force to open it anyway, or --wait-for-port", err)));
+                    }
+                    rprintln!("{}; waiting for it to be released", err);
+                    waiting = true;
+                }
+                thread::sleep(PORT_WAIT_INTERVAL);
+            },
+            Err(err) => break Err(err),
+        }
+    }
+}
+
+// The device may briefly disappear after flashing (e.g. chips with native
+// USB re-enumerate when they reset), so keep trying for a little while.
+fn reopen_port(args: &AppArgs) -> io::Result<Port> {
+    let started_at = Instant::now();
+    loop {
+        match open_port(args) {
+            Ok(dev) => {
+                rprintln!("Reopened {}", args.serial);
+                break Ok(dev);
+            },
+            Err(_) if started_at.elapsed() < REOPEN_TIMEOUT => thread::sleep(Duration::from_millis(200)),
+            Err(err) => break Err(err),
+        }
+    }
+}
+
+fn run_flash_cmd(flash_cmd: &str, args: &AppArgs) -> io::Result<()> {
+    rprintln!("Closed {}; running '{}'", args.serial, flash_cmd);
+    flash(flash_cmd, args).map(|_| ())
+}
+
+// Whether the flash command succeeded.
+fn flash(flash_cmd: &str, args: &AppArgs) -> io::Result<bool> {
+    let (shell, shell_arg) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+
+    if !args.non_interactive {
+        disable_raw_mode()?;
+    }
+    let status = Command::new(shell)
+        .arg(shell_arg)
+        .arg(flash_cmd)
+        .env("ESPMONITOR_PORT", &args.serial)
+        .status();
+    if !args.non_interactive {
+        enable_raw_mode()?;
+    }
+
+    match status {
+        Ok(status) if status.success() => {
+            rprintln!("Flash command finished");
+            return Ok(true);
+        },
+        Ok(status) => rprintln!("WARNING: Flash command failed ({})", status),
+        Err(err) => rprintln!("WARNING: Unable to run flash command: {}", err),
+    }
+
+    Ok(false)
+}
+
+fn reset_chip(dev: &mut Port, args: &AppArgs) -> io::Result<()> {
+    if let Port::Qemu(qemu) = dev {
+        match qemu.reset() {
+            Ok(()) => rprintln!("Reset QEMU machine"),
+            Err(err) => rprintln!("WARNING: Unable to reset QEMU machine: {}", err),
+        }
+        return Ok(());
+    }
+    reset_with_control_lines(dev, args)
+}
+
+fn reset_with_control_lines(dev: &mut dyn SerialSource, args: &AppArgs) -> io::Result<()> {
+    if args.no_touch {
+        rprintln!("Not resetting; --no-touch leaves DTR and RTS alone");
+        return Ok(());
+    }
+    let steps = args.reset_mode().steps(args.reset_delay);
+    if steps.is_empty() {
+        rprintln!("Reset is disabled (reset mode 'none')");
+        return Ok(());
+    }
+    if !dev.has_control_lines() {
+        rprintln!("{} has no control lines; unable to reset", args.serial);
+        return Ok(());
+    }
+
+    print!("Resetting device... ");
+    std::io::stdout().flush()?;
+    reset::run_steps(dev, &steps)?;
+    apply_line_levels(dev, args)?;
+    rprintln!("done");
+    Ok(())
+}
+
+fn enter_bootloader(dev: &mut Port, args: &AppArgs) -> io::Result<()> {
+    if args.no_touch {
+        rprintln!("Not entering the bootloader; --no-touch leaves DTR and RTS alone");
+        return Ok(());
+    }
+    let steps = args.reset_mode().bootloader_steps(args.reset_delay);
+    if steps.is_empty() {
+        rprintln!("Reset is disabled (reset mode 'none')");
+        return Ok(());
+    }
+    if !dev.has_control_lines() {
+        rprintln!("{} has no control lines; unable to enter the bootloader", args.serial);
+        return Ok(());
+    }
+
+    print!("Entering bootloader... ");
+    std::io::stdout().flush()?;
+    reset::run_steps(dev, &steps)?;
+    apply_line_levels(dev, args)?;
+    rprintln!("done; exit with CTRL+C before flashing");
+    Ok(())
+}
+
+pub fn handle_serial(state: &mut SerialState, buf: &[u8], output: &mut dyn Write) -> io::Result<()> {
+    state.stats.record_bytes(buf.len(), 0);
+    for chunk in state.deframer.feed(buf) {
+        match chunk {
+            Chunk::Text(text) => handle_text(state, &text, output)?,
+            Chunk::Frame(lines) => handle_frame(state, lines, output)?,
+        }
+    }
+    // Devices that never go quiet would otherwise never get to idle.
+    if let Some(text) = state.deframer.take_stale(state.line_timeout) {
+        handle_text(state, &text, output)?;
+    }
+    Ok(())
+}
+
+// Frames are already split into lines, so they skip the line assembler,
+// but the text around them shouldn't get mixed up with them.
+fn handle_frame(state: &mut SerialState, lines: Vec<String>, output: &mut dyn Write) -> io::Result<()> {
+    if state.shown_partial > 0 {
+        erase_partial(&state.lines.partial()[..state.shown_partial], output)?;
+        state.shown_partial = 0;
+    }
+    for line in lines {
+        process_line(state, &line, output)?;
+    }
+    Ok(())
+}
+
+fn handle_text(state: &mut SerialState, buf: &[u8], output: &mut dyn Write) -> io::Result<()> {
+    let (data, garbled) = state.utf8.decode(buf);
+    state.stats.record_bytes(0, garbled);
+
+    for line in state.lines.feed(&data) {
+        if state.shown_partial > 0 {
+            erase_partial(&line[..state.shown_partial], output)?;
+            state.shown_partial = 0;
+        }
+        process_line(state, &line, output)?;
+    }
+
+    if state.stream {
+        let partial = state.lines.partial();
+        if partial.len() > state.shown_partial {
+            output.queue(Print(&partial[state.shown_partial..]))?;
+            output.flush()?;
+            state.shown_partial = partial.len();
+        }
+    } else if let Some(line) = state.lines.take_stale_partial(state.line_timeout) {
+        process_line(state, &line, output)?;
+    }
+
+    Ok(())
+}
+
+// Called when the device has been quiet for a read timeout.  Partial lines
+// that look like an interactive prompt are shown right away rather than
+// waiting for the full line timeout to elapse.
+pub fn handle_idle(state: &mut SerialState, output: &mut dyn Write) -> io::Result<()> {
+    for sink in state.sinks_mut() {
+        let _ = sink.idle();
+    }
+    if let Some(backtrace) = state.panic.finish() {
+        print_above_partial(state, output, |state, output| state.panic_finished(backtrace, output))?;
+    }
+    let repeats = state.repeats.take_repeats();
+    print_above_partial(state, output, |_, output| print_repeats(repeats, output))?;
+    if let Some(text) = state.deframer.take_stale(state.line_timeout) {
+        handle_text(state, &text, output)?;
+    }
+
+    if state.stream {
+        // Partial lines are already on screen.
+        return Ok(());
+    }
+
+    let line =
+        if PROMPT_RE.is_match(state.lines.partial()) {
+            Some(state.lines.take_partial())
+        } else {
+            state.lines.take_stale_partial(state.line_timeout)
+        };
+
+    if let Some(line) = line {
+        process_line(state, &line, output)?;
+    }
+
+    Ok(())
+}
+
+fn update_status(state: &mut SerialState, output: &mut dyn Write) -> io::Result<()> {
+    if let Some(status) = state.status.as_mut() {
+        let pane = state.plot.as_ref().map(|plot| plot.render(status.cols())).unwrap_or_default();
+        status.set(pane, state.gauges.describe().join(" | "), output)?;
+    }
+    Ok(())
+}
+
+fn is_panic_line(framework: Framework, line: &str) -> bool {
+    PANIC_RE.is_match(line) || match framework {
+        Framework::Baremetal => BAREMETAL_PANIC_RE.is_match(line),
+        Framework::EspIdf => ESP_IDF_PANIC_RE.is_match(line),
+        Framework::Arduino => ARDUINO_PANIC_RE.is_match(line),
+    }
+}
+
+fn process_line(state: &mut SerialState, line: &str, output: &mut dyn Write) -> io::Result<()> {
+    state.stats.record_line();
+    state.scrollback.push(line);
+    let is_panic = is_panic_line(state.framework, line);
+    if is_panic {
+        state.stats.record_panic();
+    }
+    for sink in state.sinks_mut() {
+        // A hiccup in a log collector shouldn't stop the monitor.
+        let _ = sink.line(line, is_panic);
+    }
+    if FLASHER_RE.is_match(line) {
+        state.flasher_seen = true;
+    }
+    if BOOT_RE.is_match(line) {
+        state.stats.record_reset();
+        mark_reset(state.reset_behavior, output)?;
+        if let Some(status) = state.status.as_ref() {
+            status.draw(output)?;
+        }
+    }
+    state.actions.check(line, &state.port_name);
+    if let Some(script) = state.script.as_mut() {
+        script.check_line(line);
+    }
+    state.notifier.check(line, is_panic, &state.port_name);
+    emit_line_events(state, line, is_panic, output)?;
+    // Everything that matches against the text rather than showing it.
+    let plain = idflog::strip_ansi(line);
+    if let Some(tests) = state.tests.as_mut() {
+        tests.line(&plain, is_panic);
+    }
+    if let Some(crash) = state.crash.as_mut() {
+        crash.line(&plain, is_panic);
+    }
+    let mut status_changed = !state.gauges.is_empty() && state.gauges.feed(&plain);
+    if let Some(values) = state.extractor.as_mut().and_then(|extractor| extractor.line(&plain)) {
+        if let Some(plot) = state.plot.as_mut() {
+            plot.push(&values);
+            status_changed = true;
+        }
+    }
+    if status_changed {
+        update_status(state, output)?;
+    }
+
+    if state.filter.as_ref().map(|filter| !filter.is_match(&plain)).unwrap_or(false) {
+        return Ok(());
+    }
+
+    let spans = match state.repeats.check(line) {
+        Dedup::Repeat => return Ok(()),
+        Dedup::New { repeats, spans } => {
+            print_repeats(repeats, output)?;
+            spans
+        },
+    };
+
+    if let Some(detected) = chipdetect::detect_chip(line) {
+        check_chip(state, detected, output)?;
+    }
+
+    if let Some(offset) = APP_OFFSET_RE.captures(line).and_then(|caps| parse_offset(&caps[1]).ok()) {
+        // The ESP32 family's MMU maps an app to the addresses it was linked
+        // for wherever it is in flash; the ESP8266 just has a fixed window.
+        if state.chip == Chip::ESP8266 && state.app_shift.is_none() && offset != DEFAULT_LINK_OFFSET {
+            output_line(state, line, output)?;
+            return print_warning(&format!("WARNING: The app is running from offset 0x{:x}; if it was built for 0x{:x}, pass --app-offset 0x{:x} to decode its addresses correctly", offset, DEFAULT_LINK_OFFSET, offset), state.output_format, output);
+        }
+    }
+
+    if let Some(mismatch) = state.app_info.as_ref().and_then(|app_info| app_info.check_boot_line(line)) {
+        output_line(state, line, output)?;
+        return print_warning(&format!("WARNING: Stale flash image? The {}; decoded addresses are probably wrong", mismatch), state.output_format, output);
+    }
+
+    // The summary is emitted when the line *after* the dump arrives, so
+    // show it before that line.
+    if let Some(summary) = state.register_dump.feed(line) {
+        queue_wrapped(&summary, Some(ContentStyle::new().with(Color::Red).bold()), output)?;
+        decode_addresses(state, &summary, output)?;
+    }
+
+    if state.chip == Chip::ESP8266 {
+        if let Some(report) = state.esp8266_crash.feed(line) {
+            output_line(state, line, output)?;
+            let summary = match &report {
+                Esp8266Report::Exception(summary) | Esp8266Report::Stack(summary) => summary,
+            };
+            queue_wrapped(summary, Some(ContentStyle::new().with(Color::Red).bold()), output)?;
+            // The exception's own addresses were already decoded along
+            // with the line they came from.
+            if let Esp8266Report::Stack(summary) = &report {
+                decode_addresses(state, summary, output)?;
+            }
+            return Ok(());
+        }
+    }
+
+    if let Some(explanation) = explain_crash_message(line) {
+        queue_wrapped(line, Some(ContentStyle::new().with(Color::Red).bold()), output)?;
+        decode_addresses(state, line, output)?;
+        return print_warning(&explanation, state.output_format, output);
+    }
+
+    if let Some(description) = state.protocols.describe(&plain) {
+        output_line(state, line, output)?;
+        queue_wrapped(&description, Some(ContentStyle::new().with(Color::DarkCyan)), output)?;
+        return output.flush();
+    }
+
+    if let Some(spans) = spans {
+        for (text, changed) in spans {
+            if changed {
+                output.queue(PrintStyledContent(text.with(Color::Cyan).bold()))?;
+            } else {
+                output.queue(Print(text))?;
+            }
+        }
+        output.write_all(b"\r\n")?;
+        return decode_addresses(state, line, output);
+    }
+
+    output_line(state, line, output)
+}
+
+fn mark_reset(behavior: ResetBehavior, output: &mut dyn Write) -> io::Result<()> {
+    // Clearing the screen would just leave escape codes in a log file.
+    let behavior = match behavior {
+        ResetBehavior::Clear if !RAW_OUTPUT.load(Ordering::Relaxed) => ResetBehavior::Separator,
+        behavior => behavior,
+    };
+    match behavior {
+        ResetBehavior::Separator => {
+            let width = terminal::size().map(|(cols, _)| cols as usize).unwrap_or(80);
+            let label = format!(" Reset at {} ", format_utc(SystemTime::now()));
+            let rule = format!("━━━━{}{}", label, "━".repeat(width.saturating_sub(label.chars().count() + 4)));
+            output.queue(PrintStyledContent(rule.with(Color::Magenta).bold()))?;
+            output.write_all(b"\r\n")?;
+        },
+        ResetBehavior::Clear => {
+            output.queue(Clear(ClearType::All))?;
+            output.queue(MoveTo(0, 0))?;
+        },
+        ResetBehavior::Nothing => (),
+    }
+    Ok(())
+}
+
+fn check_chip(state: &mut SerialState, detected: DetectedChip, output: &mut dyn Write) -> io::Result<()> {
+    // Once is enough; a crash loop would otherwise repeat it on every boot.
+    if state.chip_checked {
+        return Ok(());
+    }
+    state.chip_checked = true;
+
+    match detected {
+        DetectedChip::Known(chip) if chip == state.chip => Ok(()),
+        DetectedChip::Known(chip) if state.detect_chip => {
+            state.chip = chip;
+            output.queue(Print(format!("Detected {} from its boot messages", chip)))?;
+            output.write_all(b"\r\n")
+        },
+        DetectedChip::Known(chip) => print_warning(&format!("WARNING: The device says it's an {}, not an {}; try --chip {}", chip, state.chip, chip), state.output_format, output),
+        DetectedChip::Unsupported(name) => print_warning(&format!("WARNING: The device is an {}, which espmonitor doesn't know about; decoded addresses may be wrong", name), state.output_format, output),
+    }
+}
+
+fn emit_line_events(state: &mut SerialState, line: &str, is_panic: bool, output: &mut dyn Write) -> io::Result<()> {
+    let is_boot = BOOT_RE.is_match(line);
+    if is_panic || is_boot || REBOOTING_RE.is_match(line) {
+        let finished = if is_panic { state.panic.start(line) } else { state.panic.finish() };
+        if let Some(backtrace) = finished {
+            state.panic_finished(backtrace, output)?;
+        }
+    } else if state.panic.is_collecting() {
+        let (symbols, decode_cache, remaps) = (state.symbols.as_ref(), &mut state.decode_cache, &state.path_remaps);
+        let frames = find_addresses(state.chip, state.app_shift, line)
+            .into_iter()
+            .map(|(_, address)| Frame {
+                address,
+                location: symbols.map(|symbols| decode_cache.get_or_insert_with(address, || remap_location(remaps, resolve_address(symbols, address)))),
+            })
+            .collect();
+        state.panic.add_frames(line, frames);
+    }
+
+    // The panics are also wanted for the session summary; the rest isn't.
+    if state.events.is_empty() {
+        return Ok(());
+    }
+    if is_boot {
+        state.events.emit(Event::Reset(ResetInfo::from_boot_line(line)));
+    }
+    state.events.emit(Event::Line(LineRecord {
+        text: line.to_string(),
+        timestamp: SystemTime::now(),
+        is_panic,
+        log: LogRecord::parse(line),
+    }));
+    Ok(())
+}
+
+pub fn handle_disconnect(state: &mut SerialState, output: &mut dyn Write) -> io::Result<()> {
+    if let Some(backtrace) = state.panic.finish() {
+        print_above_partial(state, output, |state, output| state.panic_finished(backtrace, output))?;
+    }
+    state.events.emit(Event::Disconnected);
+    Ok(())
+}
+
+fn print_repeats(repeats: usize, output: &mut dyn Write) -> io::Result<()> {
+    if repeats > 0 {
+        let message = format!("(last line repeated {} more time{})", repeats, if repeats == 1 { "" } else { "s" });
+        output.queue(PrintStyledContent(message.dark_grey()))?;
+        output.write_all(b"\r\n")?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+// Moves the cursor back to where a streamed partial line started (which
+// may be several rows up if it wrapped) and clears it, so the completed
+// line can be rendered in its place.
+fn erase_partial(shown: &str, output: &mut dyn Write) -> io::Result<()> {
+    let width = terminal::size().map(|(cols, _)| cols as usize).unwrap_or(80).max(1);
+    // Colors don't take up any columns.
+    let rows = idflog::strip_ansi(shown).chars().count().saturating_sub(1) / width;
+    if rows > 0 {
+        output.queue(MoveUp(rows as u16))?;
+    }
+    output.queue(Print("\r"))?;
+    output.queue(Clear(ClearType::FromCursorDown))?;
+    Ok(())
+}
+
+// Anything printed while a streamed partial line is on screen goes in its
+// place, with the partial line drawn again underneath.
+fn print_above_partial(state: &mut SerialState, output: &mut dyn Write, print: impl FnOnce(&mut SerialState, &mut dyn Write) -> io::Result<()>) -> io::Result<()> {
+    if state.shown_partial == 0 {
+        return print(state, output);
+    }
+    erase_partial(&state.lines.partial()[..state.shown_partial], output)?;
+    print(state, output)?;
+    output.queue(Print(&state.lines.partial()[..state.shown_partial]))?;
+    output.flush()
+}
+
+pub fn output_line(state: &mut SerialState, line: &str, output: &mut dyn Write) -> io::Result<()> {
+    let stamp = if state.timestamps { format!("{} ", format_time_of_day(SystemTime::now())) } else { String::new() };
+    let stamped = |text: &str| format!("{}{}", stamp, text);
+    let record = LogRecord::parse(line);
+    let highlight = record.as_ref().and_then(|record| state.highlights.iter().find(|rule| rule.tag() == record.tag));
+    // Firmware built without CONFIG_LOG_COLORS still deserves to have its
+    // errors and warnings stand out.
+    let level = record.as_ref().filter(|_| !idflog::has_ansi(line)).map(|record| record.level);
+    match (highlight, level) {
+        (Some(rule), _) => {
+            let plain = idflog::strip_ansi(line);
+            let style = match rule.color() {
+                Some(color) => ContentStyle::new().with(color).bold(),
+                None => ContentStyle::new().bold(),
+            };
+            queue_wrapped(&stamped(&plain), Some(style), output)?
+        },
+        (None, Some(LogLevel::Error)) => queue_wrapped(&stamped(line), Some(ContentStyle::new().with(Color::Red)), output)?,
+        (None, Some(LogLevel::Warn)) => queue_wrapped(&stamped(line), Some(ContentStyle::new().with(Color::Yellow)), output)?,
+        _ => queue_wrapped(&stamped(line), None, output)?,
+    };
+    decode_addresses(state, line, output)
+}
+
+fn decode_addresses(state: &mut SerialState, line: &str, output: &mut dyn Write) -> io::Result<()> {
+    if let Some(decoder) = state.decoder.as_ref() {
+        let addrs = find_addresses(state.chip, state.app_shift, line);
+        if !addrs.is_empty() {
+            decoder.decode(addrs);
+        }
+    } else if let Some(symbols) = state.symbols.as_ref() {
+        for (text, addr) in find_addresses(state.chip, state.app_shift, line) {
+            let remaps = &state.path_remaps;
+            let location = state.decode_cache.get_or_insert_with(addr, || remap_location(remaps, resolve_address(symbols, addr)));
+            print_decoded(&text, &location, state.output_format, state.sources.as_mut(), output)?;
+        }
+    }
+
+    output.flush()
+}
+
+// Prints whatever the decoder thread has finished resolving so far.
+pub fn handle_decoded(state: &mut SerialState, output: &mut dyn Write) -> io::Result<()> {
+    while let Some(decoded) = state.decoder.as_ref().and_then(|decoder| decoder.try_recv()) {
+        print_above_partial(state, output, |state, output| {
+            for (text, location) in decoded {
+                print_decoded(&text, &location, state.output_format, state.sources.as_mut(), output)?;
+            }
+            output.flush()
+        })?;
+    }
+
+    Ok(())
+}
+
+// Addresses in flash-mapped code are moved back to where the ELF has them
+// if the app is running from somewhere else.
+fn find_addresses(chip: Chip, app_shift: Option<i64>, line: &str) -> Vec<(String, u64)> {
+    let flash_code = chip.flash_code_range();
+    ADDR_RE.find_iter(line)
+        .filter_map(|mat| u64::from_str_radix(&mat.as_str()[2..], 16).ok().map(|addr| (mat.as_str().to_string(), addr)))
+        .filter(|(_, addr)| chip.is_code_address(*addr))
+        .map(|(text, addr)| match app_shift {
+            Some(shift) if flash_code.contains(&addr) => (text, (addr as i64 - shift) as u64),
+            _ => (text, addr),
+        })
+        .collect()
+}
+
+fn print_decoded(text: &str, location: &Location, format: OutputFormat, sources: Option<&mut SourceSnippets>, output: &mut dyn Write) -> io::Result<()> {
+    fn or_qq(s: Option<&str>) -> &str {
+        s.unwrap_or("??")
+    }
+
+    // Without a file there's nothing for an editor to jump to.
+    if let (OutputFormat::Vscode, Some(file)) = (format, location.file.as_deref()) {
+        output.queue(Print(format!(
+            "{}:{}:1: note: {} - {}",
+            file,
+            location.line.unwrap_or(1),
+            text,
+            or_qq(location.function.as_deref()),
+        )))?;
+        output.write_all(b"\r\n")?;
+        for caller in location.inlined_into.iter() {
+            if let Some(file) = caller.file.as_deref() {
+                output.queue(Print(format!("{}:{}:1: note: inlined into {}", file, caller.line.unwrap_or(1), or_qq(caller.function.as_deref()))))?;
+                output.write_all(b"\r\n")?;
+            }
+        }
+        return Ok(());
+    }
+
+    let style = Some(ContentStyle::new().with(Color::Yellow));
+    queue_wrapped(&format!("{} - {}", text, or_qq(location.function.as_deref())), style, output)?;
+    queue_wrapped(
+        &format!(
+            "    at {}:{}",
+            or_qq(location.file.as_deref()),
+            location.line.map(|l| l.to_string()).as_deref().unwrap_or("??"),
+        ),
+        style,
+        output,
+    )?;
+
+    if let (Some(sources), Some(file), Some(line)) = (sources, location.file.as_deref(), location.line) {
+        for (number, text) in sources.around(file, line) {
+            let text = format!("      {:>5} | {}", number, text);
+            if number == line {
+                queue_wrapped(&text, Some(ContentStyle::new().bold()), output)?;
+            } else {
+                queue_wrapped(&text, Some(ContentStyle::new().dark_grey()), output)?;
+            }
+        }
+    }
+
+    for caller in location.inlined_into.iter() {
+        queue_wrapped(&format!("    inlined into {}", or_qq(caller.function.as_deref())), style, output)?;
+        queue_wrapped(
+            &format!(
+                "        at {}:{}",
+                or_qq(caller.file.as_deref()),
+                caller.line.map(|l| l.to_string()).as_deref().unwrap_or("??"),
+            ),
+            style,
+            output,
+        )?;
+    }
+    Ok(())
+}
+
+fn print_warning(message: &str, format: OutputFormat, output: &mut dyn Write) -> io::Result<()> {
+    match format {
+        // There's no location, so this is like a compiler's own warnings.
+        OutputFormat::Vscode => {
+            output.queue(Print(format!("espmonitor: warning: {}", message.trim_start_matches("WARNING: "))))?;
+            output.write_all(b"\r\n")?;
+        },
+        OutputFormat::Terminal => queue_wrapped(message, Some(ContentStyle::new().with(Color::Red).bold()), output)?,
+    }
+    output.flush()
+}
+
+// Prints a whole line, wrapped to the terminal width if that's enabled.
+fn queue_wrapped(text: &str, style: Option<ContentStyle>, output: &mut dyn Write) -> io::Result<()> {
+    for row in wrap::wrap(text) {
+        match style {
+            Some(style) => output.queue(PrintStyledContent(style.apply(row)))?,
+            None => output.queue(Print(row))?,
+        };
+        output.write_all(b"\r\n")?;
+    }
+    Ok(())
+}
+
+fn print_mark(state: &mut SerialState, output: &mut dyn Write) -> io::Result<()> {
+    let mark = state.stopwatch.mark();
+    print_marker(state, &mark, output)
+}
+
+// Marks also go to the log files, so they can be found there later.
+fn print_marker(state: &mut SerialState, marker: &str, output: &mut dyn Write) -> io::Result<()> {
+    for sink in state.sinks_mut() {
+        let _ = sink.line(marker, false);
+    }
+    state.scrollback.push(marker);
+    print_above_partial(state, output, |_, output| {
+        queue_wrapped(marker, Some(ContentStyle::new().with(Color::Green).bold()), output)?;
+        output.flush()
+    })
+}
+
+fn print_stats(state: &mut SerialState, report: &str, output: &mut dyn Write) -> io::Result<()> {
+    print_above_partial(state, output, |_, output| {
+        output.queue(PrintStyledContent(report.with(Color::Cyan)))?;
+        output.write_all(b"\r\n")?;
+        output.flush()
+    })
+}
+
+// Used by both the ':' console and the control socket.
+fn run_command(dev: &mut Port, args: &AppArgs, state: &mut SerialState, command: ConsoleCommand, output: &mut dyn Write) -> io::Result<CommandOutcome> {
+    Ok(match command {
+        ConsoleCommand::Filter(Some(filter)) => {
+            let message = format!("Only showing lines matching '{}'", filter);
+            state.filter = Some(filter);
+            CommandOutcome::Done(message)
+        },
+        ConsoleCommand::Filter(None) => {
+            state.filter = None;
+            CommandOutcome::Done("Showing all lines".to_string())
+        },
+        ConsoleCommand::Baud(speed) => match dev.set_speed(speed) {
+            Ok(()) => CommandOutcome::Done(format!("Speed set to {}", speed)),
+            Err(err) => CommandOutcome::Failed(format!("Unable to set speed: {}", err)),
+        },
+        ConsoleCommand::Reset => {
+            reset_chip(dev, args)?;
+            CommandOutcome::Done(String::new())
+        },
+        ConsoleCommand::Save(file) => {
+            let saved = match file {
+                Some(file) => state.scrollback.save_to(&file).map(|()| file),
+                None => state.scrollback.save(Path::new(".")),
+            };
+            match saved {
+                Ok(path) => CommandOutcome::Done(format!("Saved the last {} lines to {}", state.scrollback.len(), path.display())),
+                Err(err) => CommandOutcome::Failed(format!("Unable to save scrollback: {}", err)),
+            }
+        },
+        ConsoleCommand::Log(Some(file)) => match SinkConfig::File(file.clone().into_os_string()).open(&state.port_name, None) {
+            Ok(sink) => {
+                state.command_log = Some(sink);
+                CommandOutcome::Done(format!("Copying output to {}", file.display()))
+            },
+            Err(err) => CommandOutcome::Failed(format!("Unable to open {}: {}", file.display(), err)),
+        },
+        ConsoleCommand::Log(None) => match state.command_log.take() {
+            Some(mut sink) => {
+                let _ = sink.flush();
+                CommandOutcome::Done("Stopped copying output".to_string())
+            },
+            None => CommandOutcome::Failed("Not copying output anywhere".to_string()),
+        },
+        ConsoleCommand::Send(data) => match dev.send(&data) {
+            Ok(()) => CommandOutcome::Done(format!("Sent {} bytes", data.len())),
+            Err(err) => CommandOutcome::Failed(format!("Unable to send: {}", err)),
+        },
+        ConsoleCommand::SendFile(mode, file) => {
+            let mut received = Vec::new();
+            let sent = transfer::send_file(dev, &file, mode.unwrap_or(args.send_mode), &mut received);
+            handle_serial(state, &received, output)?;
+            match sent {
+                Ok(count) => CommandOutcome::Done(format!("Sent {} ({} bytes)", file.display(), count)),
+                Err(err) => CommandOutcome::Failed(format!("Unable to send {}: {}", file.display(), err)),
+            }
+        },
+        ConsoleCommand::Mark(text) => {
+            print_marker(state, &format_marker(&text, SystemTime::now()), output)?;
+            CommandOutcome::Done(String::new())
+        },
+        ConsoleCommand::Help => CommandOutcome::Done(console::HELP.join("\n")),
+        ConsoleCommand::Quit => CommandOutcome::Quit,
+    })
+}
+
+// What a console or control socket command did, for telling whoever sent it.
+enum CommandOutcome {
+    Done(String),
+    Failed(String),
+    Quit,
+}
+
+// Actions that need the port to be closed, which the main loop takes care of.
+enum PortAction {
+    Flash(String),
+    Quit,
+}
+
+// Goes through the output like the device's lines, so it can't overtake
+// ones still queued, e.g. those received while sending a file.
+fn report_outcome(state: &mut SerialState, outcome: CommandOutcome, output: &mut dyn Write) -> io::Result<Option<PortAction>> {
+    match outcome {
+        CommandOutcome::Done(message) => {
+            print_above_partial(state, output, |_, output| {
+                for line in message.lines() {
+                    queue_wrapped(line, None, output)?;
+                }
+                output.flush()
+            })?;
+            Ok(None)
+        },
+        CommandOutcome::Failed(message) => {
+            print_above_partial(state, output, |state, output| print_warning(&format!("WARNING: {}", message), state.output_format, output))?;
+            Ok(None)
+        },
+        CommandOutcome::Quit => Ok(Some(PortAction::Quit)),
+    }
+}
+
+fn run_menu_command(dev: &mut Port, args: &AppArgs, state: &mut SerialState, console: &mut Console, command: MenuCommand, output: &mut dyn Write) -> io::Result<Option<PortAction>> {
+    match command {
+        MenuCommand::Stats => if state.stats.toggle() {
+            let report = state.stats.take_report(true).unwrap_or_default();
+            print_stats(state, &report, output)?;
+        } else {
+            print_stats(state, "[stats] off", output)?;
+        },
+        MenuCommand::Timestamps => {
+            state.timestamps = !state.timestamps;
+            rprintln!("Timestamps {}", if state.timestamps { "on" } else { "off" });
+        },
+        MenuCommand::Log => {
+            let file = match state.command_log {
+                Some(_) => None,
+                None => Some(timestamped_path(Path::new("."), "espmonitor", SystemTime::now(), "log")),
+            };
+            let outcome = run_command(dev, args, state, ConsoleCommand::Log(file), output)?;
+            return report_outcome(state, outcome, output);
+        },
+        MenuCommand::Baud => console.open_with("baud ", output)?,
+        MenuCommand::Bootloader => enter_bootloader(dev, args)?,
+        MenuCommand::Reset => reset_chip(dev, args)?,
+        MenuCommand::Quit => return Ok(Some(PortAction::Quit)),
+        MenuCommand::Help => {
+            for line in console::MENU_HELP {
+                rprintln!("{}", line);
+            }
+        },
+        MenuCommand::SendPrefix => if let Err(err) = dev.send(b"\x14") {
+            rprintln!("WARNING: Unable to send CTRL+T: {}", err);
+        },
+    }
+    Ok(None)
+}
+
+// Last levels set from the keyboard, so they can be toggled.
+struct ControlLines {
+    dtr: bool,
+    rts: bool,
+}
+
+fn handle_input(dev: &mut Port, args: &AppArgs, state: &mut SerialState, control_lines: &mut ControlLines, console: &mut Console, key_event: KeyEvent, output: &mut dyn Write) -> io::Result<Option<PortAction>> {
+    if console.is_active() {
+        return match console.key(key_event, output)? {
+            Some(line) if line.trim().is_empty() => Ok(None),
+            Some(line) => match ConsoleCommand::try_from(line.as_str()) {
+                Ok(command) => {
+                    let outcome = run_command(dev, args, state, command, output)?;
+                    report_outcome(state, outcome, output)
+                },
+                Err(err) => {
+                    rprintln!("{}", err);
+                    Ok(None)
+                },
+            },
+            None => Ok(None),
+        };
+    }
+    match console.menu_key(key_event) {
+        Some(Ok(command)) => return run_menu_command(dev, args, state, console, command, output),
+        Some(Err(err)) => {
+            rprintln!("{}", err);
+            return Ok(None);
+        },
+        None => (),
+    }
+    if key_event.code == KeyCode::Char(':') && !key_event.modifiers.contains(KeyModifiers::CONTROL) {
+        console.open(output)?;
+        return Ok(None);
+    }
+
+    if key_event.modifiers == KeyModifiers::CONTROL {
+        match key_event.code {
+            KeyCode::Char('r') => reset_chip(dev, args)?,
+            KeyCode::Char('b') => enter_bootloader(dev, args)?,
+            KeyCode::Char('f') => match args.flash_cmd.as_ref() {
+                Some(flash_cmd) => return Ok(Some(PortAction::Flash(flash_cmd.clone()))),
+                None => rprintln!("No flash command configured; use --flash-cmd"),
+            },
+            KeyCode::Char('t') => console.open_menu(),
+            KeyCode::Char('l') => print_mark(state, output)?,
+            KeyCode::Char('s') => match state.scrollback.save(Path::new(".")) {
+                Ok(path) => rprintln!("Saved the last {} lines to {}", state.scrollback.len(), path.display()),
+                Err(err) => rprintln!("WARNING: Unable to save scrollback: {}", err),
+            },
+            KeyCode::Char('d') | KeyCode::Char('e') if args.no_touch => rprintln!("Not changing DTR or RTS; --no-touch leaves them alone"),
+            KeyCode::Char('d') => match dev.set_dtr(!control_lines.dtr) {
+                Ok(()) => {
+                    control_lines.dtr = !control_lines.dtr;
+                    rprintln!("DTR {}", if control_lines.dtr { "high" } else { "low" });
+                },
+                Err(err) => rprintln!("WARNING: Unable to set DTR: {}", err),
+            },
+            KeyCode::Char('e') => match dev.set_rts(!control_lines.rts) {
+                Ok(()) => {
+                    control_lines.rts = !control_lines.rts;
+                    rprintln!("RTS {}", if control_lines.rts { "high" } else { "low" });
+                },
+                Err(err) => rprintln!("WARNING: Unable to set RTS: {}", err),
+            },
+            KeyCode::Char('k') => match dev.send_break() {
+                Ok(()) => rprintln!("Sent break"),
+                Err(err) => rprintln!("WARNING: Unable to send break: {}", err),
+            },
+            KeyCode::Char('c') => return Ok(Some(PortAction::Quit)),
+            _ => (),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_prompts() {
+        for prompt in &[">", "> ", "$ ", "#", "?", "esp32> ", "root@dev:~# ", "nsh> ", "user@host:~$  "] {
+            assert!(PROMPT_RE.is_match(prompt), "'{}' isn't a prompt", prompt);
+        }
+        for partial in &["I (123) tag: ", "Enter password:", "Continue?", "W (5) wifi: rssi -70 > -80", "a->b", "cost: $", "item #"] {
+            assert!(!PROMPT_RE.is_match(partial), "'{}' is a prompt", partial);
+        }
+    }
+
+    // The partial line has to end up below the report, not run into it.
+    #[test]
+    fn prints_stats_above_a_streamed_partial() {
+        let mut state = SerialState::new(None);
+        state.set_stream(true);
+        let mut output = Vec::new();
+        handle_serial(&mut state, b"boot: waiting", &mut output).unwrap();
+        let shown = output.len();
+        print_stats(&mut state, "[stats] 13 bytes", &mut output).unwrap();
+        handle_serial(&mut state, b" for wifi\r\n", &mut output).unwrap();
+
+        let output = String::from_utf8_lossy(&output);
+        let stats = output.find("[stats] 13 bytes").unwrap();
+        assert!(output[shown..stats].contains("\r\x1b[J"), "the partial line wasn't erased: {:?}", output);
+        assert!(output[stats..].contains("\r\nboot: waiting"), "the partial line wasn't redrawn: {:?}", output);
+        assert!(output.ends_with("boot: waiting for wifi\r\n"), "{:?}", output);
+    }
+
+    #[test]
+    fn resolves_panic_frames_with_the_async_decoder() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/esp32-panic");
+        let mut state = SerialState::new(Some(symbols::load_bin_file(fixture.join("app.elf")).unwrap()));
+        state.set_chip(Chip::ESP32);
+        state.enable_async_decode();
+        let events = state.subscribe();
+        handle_serial(&mut state, &fs::read(fixture.join("input.log")).unwrap(), &mut io::sink()).unwrap();
+
+        let backtrace = events.try_iter()
+            .find_map(|event| match event {
+                Event::Panic(backtrace) => Some(backtrace),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(backtrace.frames.len(), 4);
+        assert!(backtrace.frames.iter().take(3).all(|frame| frame.location.is_some()), "{:?}", backtrace.frames);
+    }
+
+    // A pty opens like a serial port, so the default reset has to notice
+    // it has no control lines rather than fail, and it's never unplugged.
+    #[cfg(unix)]
+    #[test]
+    fn monitors_ptys_with_the_default_reset() {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let args = AppArgs {
+            serial: nix::unistd::ttyname(pty.slave).unwrap().to_string_lossy().into_owned(),
+            reset: true,
+            reset_delay: Some(Duration::from_millis(1)),
+            exit_after: Some(Duration::from_millis(300)),
+            non_interactive: true,
+            ..AppArgs::default()
+        };
+        let mut dev = open_port(&args).unwrap();
+        assert!(!dev.has_control_lines());
+        assert!(!dev.is_disconnected());
+        reset_chip(&mut dev, &args).unwrap();
+
+        nix::unistd::write(pty.master, b"boot\r\n").unwrap();
+        let mut output = Vec::new();
+        run_source(&args, &mut dev, &mut output).unwrap();
+        assert!(String::from_utf8_lossy(&output).contains("boot"));
+        let _ = nix::unistd::close(pty.master);
+        let _ = nix::unistd::close(pty.slave);
+    }
 }
@@ -0,0 +1,135 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    borrow::Cow,
+    mem,
+    time::{Duration, Instant},
+};
+
+// Assembles lines out of arbitrarily-chunked serial data.  CR, LF, CRLF
+// and LFCR are all treated as a single line terminator (as is CRCRLF, which
+// some stdio layers make of an LF sent as CRLF), so firmware that
+// uses bare CRs (e.g. to redraw progress percentages) produces one clean
+// line per update instead of text overwriting itself in the terminal.
+pub(crate) struct LineAssembler {
+    partial: String,
+    last_terminator: Option<char>,
+    last_data_at: Instant,
+}
+
+impl LineAssembler {
+    pub fn new() -> Self {
+        Self {
+            partial: String::new(),
+            last_terminator: None,
+            last_data_at: Instant::now(),
+        }
+    }
+
+    // Lines that start and end within data are borrowed from it; only a
+    // line continuing one from an earlier read needs a copy.
+    pub fn feed<'a>(&mut self, data: &'a str) -> Vec<Cow<'a, str>> {
+        let mut lines = Vec::new();
+        let mut line_start = 0;
+
+        for (i, c) in data.char_indices() {
+            match (c, self.last_terminator) {
+                ('\n', Some('\r')) | ('\r', Some('\n')) => {
+                    // Second half of a CRLF or LFCR pair; the line was
+                    // already emitted when we saw the first half.
+                    self.last_terminator = None;
+                    line_start = i + 1;
+                },
+                ('\r', Some('\r')) => line_start = i + 1,
+                ('\n', _) | ('\r', _) => {
+                    let rest = &data[line_start..i];
+                    if self.partial.is_empty() {
+                        lines.push(Cow::Borrowed(rest));
+                    } else {
+                        self.partial.push_str(rest);
+                        lines.push(Cow::Owned(mem::take(&mut self.partial)));
+                    }
+                    self.last_terminator = Some(c);
+                    line_start = i + 1;
+                },
+                _ => self.last_terminator = None,
+            }
+        }
+
+        if line_start < data.len() {
+            self.partial.push_str(&data[line_start..]);
+            self.last_data_at = Instant::now();
+        }
+        lines
+    }
+
+    pub fn partial(&self) -> &str {
+        &self.partial
+    }
+
+    pub fn take_partial(&mut self) -> String {
+        mem::take(&mut self.partial)
+    }
+
+    pub fn take_stale_partial(&mut self, timeout: Duration) -> Option<String> {
+        if !self.partial.is_empty() && self.last_data_at.elapsed() > timeout {
+            Some(mem::take(&mut self.partial))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn feed_all(chunks: &[&str]) -> (Vec<String>, String) {
+        let mut lines = LineAssembler::new();
+        let fed = chunks.iter().flat_map(|chunk| lines.feed(chunk).into_iter().map(Cow::into_owned).collect::<Vec<_>>()).collect();
+        (fed, lines.take_partial())
+    }
+
+    #[test]
+    fn treats_terminator_pairs_as_one() {
+        for data in &["a\nb\n", "a\rb\r", "a\r\nb\r\n", "a\n\rb\n\r", "a\r\r\nb\r\r\n"] {
+            assert_eq!(feed_all(&[data]), (vec!["a".to_string(), "b".to_string()], String::new()), "{:?}", data);
+        }
+        assert_eq!(feed_all(&["a\n\nb\r\n\r\nc"]).0, vec!["a", "", "b", ""]);
+        assert_eq!(feed_all(&["a\n\nb\r\n\r\nc"]).1, "c");
+    }
+
+    #[test]
+    fn joins_reads_split_mid_line_or_terminator() {
+        assert_eq!(feed_all(&["he", "llo\r", "\nwor", "ld\r", "\r", "\n"]).0, vec!["hello", "world"]);
+        assert_eq!(feed_all(&["one\n", "\rtwo\r"]).0, vec!["one", "two"]);
+        assert_eq!(feed_all(&["one\r", "", "\ntwo"]), (vec!["one".to_string()], "two".to_string()));
+    }
+
+    #[test]
+    fn gives_up_on_stale_partials() {
+        let mut lines = LineAssembler::new();
+        assert!(lines.feed("esp32> ").is_empty());
+        assert_eq!(lines.take_stale_partial(Duration::from_millis(50)), None);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(lines.take_stale_partial(Duration::from_millis(50)).as_deref(), Some("esp32> "));
+        assert_eq!(lines.partial(), "");
+        assert_eq!(lines.take_stale_partial(Duration::ZERO), None);
+    }
+}
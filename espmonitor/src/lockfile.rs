@@ -0,0 +1,96 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::io;
+
+// Advisory UUCP-style lock files ("/var/lock/LCK..ttyUSB0" holding the
+// owner's PID), as used by minicom, screen, tio and friends.  They only
+// help if everyone honors them, but they turn two programs silently
+// splitting a port's data between them into an error.
+pub(crate) struct PortLock {
+    #[cfg(unix)]
+    path: std::path::PathBuf,
+}
+
+impl PortLock {
+    // Returns None if there's nowhere we're allowed to put the lock file;
+    // locking is a courtesy, so that's not worth failing over.
+    #[cfg(unix)]
+    pub fn acquire(port: &str, force: bool) -> io::Result<Option<Self>> {
+        use nix::{errno::Errno, sys::signal::kill, unistd::Pid};
+        use std::{
+            fs::{self, OpenOptions},
+            io::{Error as IoError, ErrorKind, Write},
+            path::Path,
+            process,
+        };
+
+        const LOCK_DIRS: &[&str] = &["/var/lock", "/run/lock"];
+
+        // Symlinks like /dev/serial/by-id/... lock the device they point to.
+        let device = fs::canonicalize(port).unwrap_or_else(|_| Path::new(port).to_path_buf());
+        let name = match device.file_name() {
+            Some(name) => format!("LCK..{}", name.to_string_lossy()),
+            None => return Ok(None),
+        };
+        let path = match LOCK_DIRS.iter().map(Path::new).find(|dir| dir.is_dir()) {
+            Some(dir) => dir.join(name),
+            None => return Ok(None),
+        };
+
+        if let Ok(contents) = fs::read_to_string(&path) {
+            let owner = contents.trim().parse::<i32>().ok();
+            // Signal 0 only checks that the process exists; EPERM means it
+            // does, but belongs to someone else.
+            let alive = owner
+                .filter(|pid| *pid > 0)
+                .map(|pid| !matches!(kill(Pid::from_raw(pid), None), Err(Errno::ESRCH)))
+                .unwrap_or(false);
+            if alive && !force {
+                return Err(IoError::new(ErrorKind::AddrInUse, format!("{} is locked by PID {} ({})", port, owner.unwrap_or_default(), path.display())));
+            }
+            // Left behind by something that crashed, or we're forcing it.
+            let _ = fs::remove_file(&path);
+        }
+
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                return Err(IoError::new(ErrorKind::AddrInUse, format!("{} was just locked by another program ({})", port, path.display())));
+            },
+            Err(_) => return Ok(None),
+        };
+        // The traditional format: the PID as ten right-aligned characters.
+        let lock = Self { path };
+        writeln!(file, "{:>10}", process::id())?;
+        Ok(Some(lock))
+    }
+
+    #[cfg(windows)]
+    pub fn acquire(_port: &str, _force: bool) -> io::Result<Option<Self>> {
+        // Windows only lets one program open a COM port at a time anyway.
+        Ok(None)
+    }
+}
+
+impl Drop for PortLock {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -15,57 +15,330 @@
 // You should have received a copy of the GNU General Public License
 // along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
 
-use espmonitor::{AppArgs, Chip, Framework, run};
-use pico_args::Arguments;
+
+use clap::{Args, Parser, Subcommand};
+use espmonitor::{AppArgs, Chip, DecodeArgs, Framework, MonitorOpts, OutputFormat, PathRemap, ReplayArgs, PioEnv, Profile, decode, doctor, find_arduino_elf, find_bootloader_elf, find_elf, list_ports, parse_offset, replay, run, version_info};
 use std::convert::TryFrom;
+use std::env;
 use std::error::Error;
+use std::ffi::OsString;
+use std::io::{Error as IoError, ErrorKind};
+use std::time::Duration;
+
+const EXAMPLES: &str = "EXAMPLES:
+    # Monitor the only connected device, decoding addresses with the project's ELF
+    espmonitor
+
+    # Monitor an ESP8266 at 74880 baud without resetting it
+    espmonitor --chip esp8266 --speed 74880 --no-reset /dev/ttyUSB0
+
+    # Use the port, board and ELF from a PlatformIO project
+    espmonitor --pio
+
+    # Record a session and play it back later
+    espmonitor --record session.rec /dev/ttyUSB0 && espmonitor replay session.rec";
+
+#[derive(Parser)]
+// Options given more than once take the last value, so the command line
+// overrides a --profile's.
+#[clap(name = "espmonitor", about, version, disable_version_flag = true, after_help = EXAMPLES, args_conflicts_with_subcommands = true, args_override_self = true)]
+struct Cli {
+    // Not clap's own --version, which would only show the version number.
+    #[clap(short = 'V', long = "version", help = "Show the version, supported chips and enabled features")]
+    show_version: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+    // Monitoring is the default, so `espmonitor PORT` is short for
+    // `espmonitor monitor PORT`.
+    #[clap(flatten)]
+    monitor: MonitorArgs,
+}
+
+#[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
+enum Command {
+    #[clap(about = "Monitor a device (the default)")]
+    Monitor(MonitorArgs),
+    #[clap(alias = "list-ports", about = "List the available serial devices")]
+    List,
+    #[clap(about = "Play back a recording made with --record")]
+    Replay(ReplayOpts),
+    #[clap(about = "Decode the addresses and crash dumps in a saved log")]
+    Decode(DecodeOpts),
+    #[clap(about = "Check the serial setup for common problems")]
+    Doctor,
+    #[clap(about = "Flash and run on-target tests (Unity, embedded-test, libtest), reporting each case's result")]
+    Test(TestArgs),
+}
+
+#[derive(Args)]
+struct MonitorArgs {
+    #[clap(flatten)]
+    project: ProjectOpts,
+    #[clap(flatten)]
+    monitor: MonitorOpts,
+}
+
+#[derive(Args)]
+struct TestArgs {
+    #[clap(flatten)]
+    test: TestOpts,
+    #[clap(flatten)]
+    args: MonitorArgs,
+}
+
+#[derive(Args)]
+#[clap(next_help_heading = "TEST OPTIONS")]
+struct TestOpts {
+    #[clap(long, value_name = "SECS", help = "Give up if the tests haven't finished after SECS seconds (default: 300)")]
+    timeout: Option<u64>,
+}
+
+#[derive(Args)]
+#[clap(next_help_heading = "PROJECT OPTIONS")]
+struct ProjectOpts {
+    #[clap(long, value_name = "NAME", help = "Take options from [profile.NAME] in espmonitor.toml or the user's espmonitor/config.toml")]
+    profile: Option<String>,
+    #[clap(long, value_name = "FQBN", help = "Arduino/PlatformIO board; picks the chip and finds the sketch's ELF")]
+    board: Option<String>,
+    #[clap(long, help = "Take port, speed, board and ELF from platformio.ini")]
+    pio: bool,
+    #[clap(long, value_name = "ENV", help = "Use this PlatformIO environment (implies --pio)")]
+    pio_env: Option<String>,
+    #[clap(long, value_name = "TARGET", conflicts_with_all = &["framework", "board", "pio", "pio-env"], help = "Rust target triple the firmware is built for, e.g. xtensa-esp32s3-espidf; picks the chip, framework and toolchain")]
+    target: Option<String>,
+    #[clap(long, value_name = "FRAMEWORK", parse(try_from_str = Framework::try_from), help = "Which framework the firmware uses: baremetal, esp-idf or arduino (default: from the project)")]
+    framework: Option<Framework>,
+    #[clap(long, value_name = "BINARY", parse(from_os_str), help = "Path to executable matching what is on the device; repeat for other images that may run, e.g. the bootloader")]
+    bin: Vec<OsString>,
+    #[clap(long, help = "Don't look for an executable under target/ or build/")]
+    no_auto_bin: bool,
+}
+
+#[derive(Args)]
+struct ReplayOpts {
+    #[clap(long, value_name = "CHIP", parse(try_from_str = Chip::try_from), help = "Which ESP chip the recording is from")]
+    chip: Option<Chip>,
+    #[clap(long, value_name = "BINARY", parse(from_os_str), help = "Executable that was on the device (repeatable)")]
+    bin: Vec<OsString>,
+    #[clap(long, value_name = "FACTOR", default_value = "1", help = "Playback speed relative to the recording; 0 for no delays")]
+    rate: f64,
+    #[clap(value_name = "FILE", parse(from_os_str), help = "Recording made with --record")]
+    file: OsString,
+}
+
+#[derive(Args)]
+struct DecodeOpts {
+    #[clap(long, value_name = "CHIP", parse(try_from_str = Chip::try_from), help = "Which ESP chip the log is from (default: from the boot banner)")]
+    chip: Option<Chip>,
+    #[clap(long, value_name = "BINARY", parse(from_os_str), help = "Executable that was on the device; repeat for other images that may run, e.g. the bootloader")]
+    bin: Vec<OsString>,
+    #[clap(long, value_name = "terminal|vscode", parse(try_from_str = OutputFormat::try_from), help = "Print decoded addresses and warnings as 'file:line:col: severity: message' with vscode")]
+    output: Option<OutputFormat>,
+    #[clap(long, help = "Show the source lines around decoded addresses")]
+    show_source: bool,
+    #[clap(long, value_name = "DIR", parse(from_os_str), help = "Look for sources that were built elsewhere under DIR (implies --show-source)")]
+    source_path: Option<OsString>,
+    #[clap(long, value_name = "FROM=TO", parse(try_from_str = PathRemap::try_from), help = "Show decoded file names starting with FROM as starting with TO (repeatable)")]
+    remap_path: Vec<PathRemap>,
+    #[clap(long, help = "Show Rust and C++ function names as they are in the binary")]
+    no_demangle: bool,
+    #[clap(long, value_name = "OFFSET", parse(try_from_str = parse_offset), help = "Flash offset the app was running from, if not where its ELF expects (ESP8266 OTA slots)")]
+    app_offset: Option<u64>,
+    #[clap(long, value_name = "OFFSET", parse(try_from_str = parse_offset), help = "Flash offset the ELF was built to run from (default: 0x10000)")]
+    link_offset: Option<u64>,
+    #[clap(long, value_name = "PREFIX", help = "Toolchain binaries' prefix, for its addr2line (default: the chip's, e.g. xtensa-esp32-elf-)")]
+    toolchain_prefix: Option<String>,
+    #[clap(long, value_name = "PATH", parse(from_os_str), help = "addr2line to ask about addresses the built-in decoder can't resolve")]
+    addr2line: Option<OsString>,
+    #[clap(value_name = "FILE", parse(from_os_str), help = "Saved log to decode (default: standard input)")]
+    file: Option<OsString>,
+}
 
 fn main() {
     #[cfg(windows)]
     let _ = crossterm::ansi_support::supports_ansi();
     // supports_ansi() returns what it suggests, and as a side effect enables ANSI support
 
-    match parse_args().and_then(|args| args.map(run).unwrap_or(Ok(()))) {
-        Ok(_) => (),
-        Err(err) => {
-            println!("Error: {}", err);
-            println!();
-            if err.downcast::<pico_args::Error>().is_ok() {
-                print_usage();
+    let result = with_profile(env::args_os().collect()).and_then(|(args, profile)| {
+        let cli = Cli::parse_from(args);
+        if cli.show_version {
+            println!("{}", version_info("espmonitor", env!("CARGO_PKG_VERSION")));
+            Ok(())
+        } else {
+            match cli.command.unwrap_or(Command::Monitor(cli.monitor)) {
+                Command::Monitor(mut args) => {
+                    args.monitor.serial = args.monitor.serial.or_else(|| profile.and_then(|profile| profile.port));
+                    monitor(args)
+                },
+                Command::Test(mut args) => {
+                    args.args.monitor.serial = args.args.monitor.serial.or_else(|| profile.and_then(|profile| profile.port));
+                    test(args)
+                },
+                command => command.run(),
             }
-            std::process::exit(1);
-        },
+        }
+    });
+
+    if let Err(err) = result {
+        println!("Error: {}", err);
+        std::process::exit(1);
     }
 }
 
-fn parse_args() -> Result<Option<AppArgs>, Box<dyn Error>> {
-    let mut args = Arguments::from_env();
-    if args.contains("-h") || args.contains("--help") {
-        print_usage();
-        Ok(None)
+// A profile's options go in ahead of the command line's, so clap checks
+// them like any others and later ones override them.
+fn with_profile(mut args: Vec<OsString>) -> Result<(Vec<OsString>, Option<Profile>), Box<dyn Error>> {
+    let name = args
+        .iter()
+        .skip(1)
+        .take_while(|arg| *arg != "--")
+        .zip(args.iter().skip(2).map(Some).chain(std::iter::once(None)))
+        .find_map(|(arg, next)| {
+            let arg = arg.to_str()?;
+            if arg == "--profile" {
+                next.and_then(|next| next.to_str()).map(str::to_string)
+            } else {
+                arg.strip_prefix("--profile=").map(str::to_string)
+            }
+        });
+    let profile = match name {
+        Some(name) => Profile::load(&name)?,
+        None => return Ok((args, None)),
+    };
+    println!("Using profile {} from {}", profile.name, profile.path.display());
+
+    let at = if matches!(args.get(1).and_then(|arg| arg.to_str()), Some("monitor") | Some("test")) { 2 } else { 1 };
+    args.splice(at..at, profile.args.iter().cloned());
+    Ok((args, Some(profile)))
+}
+
+impl Command {
+    fn run(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Command::Monitor(args) => monitor(args),
+            Command::List => {
+                list_ports();
+                Ok(())
+            },
+            Command::Replay(opts) => Ok(replay(replay_args(opts))?),
+            Command::Decode(opts) => Ok(decode(decode_args(opts))?),
+            Command::Doctor => {
+                if !doctor() {
+                    std::process::exit(1);
+                }
+                Ok(())
+            },
+            Command::Test(args) => test(args),
+        }
+    }
+}
+
+fn monitor(args: MonitorArgs) -> Result<(), Box<dyn Error>> {
+    Ok(run(app_args(args)?)?)
+}
+
+fn test(args: TestArgs) -> Result<(), Box<dyn Error>> {
+    let TestArgs { test, args } = args;
+    let mut app_args = app_args(args)?;
+    app_args.test = true;
+    app_args.test_timeout = test.timeout.map(Duration::from_secs);
+    Ok(run(app_args)?)
+}
+
+fn app_args(args: MonitorArgs) -> Result<AppArgs, Box<dyn Error>> {
+    let MonitorArgs { project, monitor } = args;
+
+    let pio = if project.pio || project.pio_env.is_some() {
+        let pio = PioEnv::load(".", project.pio_env.as_deref())?;
+        println!("Using PlatformIO environment {}", pio.name);
+        Some(pio)
     } else {
-        #[allow(clippy::redundant_closure)]
-        let chip = args.opt_value_from_fn("--chip", |s| Chip::try_from(s))?.unwrap_or_default();
-        Ok(Some(AppArgs {
-            chip,
-            framework: Framework::default(),
-            speed: args.opt_value_from_fn("--speed", |s| s.parse::<usize>())?,
-            reset: args.contains("--reset") || !args.contains("--no-reset"),
-            bin: args.opt_value_from_str("--bin")?,
-            serial: args.free_from_str()?,
-        }))
+        None
+    };
+    let board = project.board.or_else(|| pio.as_ref().and_then(|pio| pio.board.clone()));
+    let detect_chip = monitor.chip.is_none() && board.is_none() && project.target.is_none();
+    let chip = match (monitor.chip, project.target.as_ref()) {
+        (Some(chip), Some(target)) => {
+            let target_chips = Chip::for_target(target);
+            if !target_chips.is_empty() && !target_chips.contains(&chip) {
+                let names = target_chips.iter().map(ToString::to_string).collect::<Vec<_>>().join(" or ");
+                return Err(IoError::new(ErrorKind::InvalidInput, format!("--chip {} conflicts with --target {}, which is for the {}", chip, target, names)).into());
+            }
+            chip
+        },
+        (Some(chip), None) => chip,
+        (None, Some(target)) => Chip::from_target(target)?,
+        (None, None) => board.as_ref().map(Chip::from_board).transpose()?.unwrap_or_default(),
+    };
+    let target_framework = project.target.as_ref().map(Framework::from_target).transpose()?;
+    let framework = match target_framework.or(project.framework).or_else(|| pio.as_ref().and_then(|pio| pio.framework)) {
+        Some(framework) => framework,
+        None if board.is_some() => Framework::Arduino,
+        None => Framework::from_project(".").unwrap_or_default(),
+    };
+    let auto_bin = !project.no_auto_bin;
+    // The first --bin is the application, any others are e.g. the
+    // bootloader.
+    let mut extra_bins = project.bin;
+    let bin = if extra_bins.is_empty() { None } else { Some(extra_bins.remove(0)) };
+    if extra_bins.is_empty() && auto_bin && pio.is_none() && framework != Framework::Arduino {
+        if let Some(bootloader) = find_bootloader_elf(".") {
+            println!("Found bootloader image {}", bootloader.display());
+            extra_bins.push(bootloader.into_os_string());
+        }
     }
+    let bin = match bin {
+        Some(bin) => Some(bin),
+        None => match pio.as_ref() {
+            Some(pio) => Some(pio.elf.clone().into_os_string()),
+            None if framework == Framework::Arduino => find_arduino_elf(".").map(OsString::from),
+            None if !auto_bin => None,
+            None => find_elf(chip, framework, ".").map(|bin| {
+                println!("Found flash image {} (use --bin to pick another, or --no-auto-bin)", bin.display());
+                bin.into_os_string()
+            }),
+        },
+    };
+
+    let mut app_args = monitor.into_app_args(pio.as_ref().and_then(|pio| pio.port.clone()))?;
+    app_args.chip = chip;
+    app_args.detect_chip = detect_chip;
+    app_args.framework = framework;
+    app_args.speed = app_args.speed.or_else(|| pio.as_ref().and_then(|pio| pio.speed));
+    app_args.bin = bin;
+    app_args.extra_bins = extra_bins;
+
+    Ok(app_args)
 }
 
-fn print_usage() {
-    let usage = "Usage: espmonitor [OPTIONS] SERIAL_DEVICE\n\
-        \n\
-        \x20   --chip {esp32|esp32c3|esp8266}   Which ESP chip to target\n\
-        \x20   --reset                          Reset the chip on start (default)\n\
-        \x20   --no-reset                       Do not reset thechip on start\n\
-        \x20   --speed BAUD                     Baud rate of serial device (default: 115200)\n\
-        \x20   --bin BINARY                     Path to executable matching what is on the device\n\
-        \x20   SERIAL_DEVICE                    Path to the serial device";
+fn replay_args(opts: ReplayOpts) -> ReplayArgs {
+    let mut bins = opts.bin.into_iter();
+    ReplayArgs {
+        chip: opts.chip.unwrap_or_default(),
+        bin: bins.next(),
+        extra_bins: bins.collect(),
+        rate: opts.rate,
+        file: opts.file,
+    }
+}
 
-    println!("{}", usage);
+fn decode_args(opts: DecodeOpts) -> DecodeArgs {
+    let mut bins = opts.bin.into_iter();
+    DecodeArgs {
+        file: opts.file,
+        bin: bins.next(),
+        extra_bins: bins.collect(),
+        chip: opts.chip.unwrap_or_default(),
+        detect_chip: opts.chip.is_none(),
+        output_format: opts.output.unwrap_or_default(),
+        show_source: opts.show_source,
+        source_path: opts.source_path,
+        path_remaps: opts.remap_path,
+        demangle: !opts.no_demangle,
+        app_offset: opts.app_offset,
+        link_offset: opts.link_offset,
+        toolchain_prefix: opts.toolchain_prefix,
+        addr2line: opts.addr2line,
+    }
 }
@@ -0,0 +1,132 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    io,
+    path::Path,
+    sync::mpsc::Receiver,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// Numbered marks put into the output from the keyboard, for bracketing
+// something done by hand (pressing a button, sending a request) while
+// watching what the device does.
+pub(crate) struct Stopwatch {
+    started_at: Option<Instant>,
+    last_mark_at: Option<Instant>,
+    marks: usize,
+}
+
+impl Stopwatch {
+    pub fn new() -> Self {
+        Self {
+            started_at: None,
+            last_mark_at: None,
+            marks: 0,
+        }
+    }
+
+    // The first mark starts the clock; later ones show the time since the
+    // first and since the previous one.
+    pub fn mark(&mut self) -> String {
+        let now = Instant::now();
+        self.marks += 1;
+        let text = match (self.started_at, self.last_mark_at) {
+            (Some(started_at), Some(last_mark_at)) => format!(
+                "---- Mark {}: +{} (lap {}) ----",
+                self.marks,
+                format_duration(now - started_at),
+                format_duration(now - last_mark_at),
+            ),
+            _ => {
+                self.started_at = Some(now);
+                format!("---- Mark {}: stopwatch started ----", self.marks)
+            },
+        };
+        self.last_mark_at = Some(now);
+        text
+    }
+}
+
+// Lines written to a FIFO by other programs (e.g. a test script saying
+// which step it's on), shown in between the device's output.
+pub(crate) struct MarkerPipe {
+    markers: Receiver<String>,
+}
+
+impl MarkerPipe {
+    // Creates the FIFO if it isn't there yet.
+    #[cfg(unix)]
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        use nix::{sys::stat::Mode, unistd::mkfifo};
+        use std::{fs::File, io::{BufRead, BufReader}, sync::mpsc, thread};
+
+        let path = path.as_ref().to_path_buf();
+        if !path.exists() {
+            mkfifo(&path, Mode::S_IRUSR | Mode::S_IWUSR).map_err(|err| io::Error::new(io::ErrorKind::Other, format!("Unable to create FIFO {}: {}", path.display(), err)))?;
+        }
+
+        let (tx, markers) = mpsc::channel();
+        thread::spawn(move || {
+            // Each writer that closes the FIFO ends the read, so keep
+            // opening it again for the next one.
+            while let Ok(file) = File::open(&path) {
+                for line in BufReader::new(file).lines() {
+                    let line = match line {
+                        Ok(line) => line,
+                        Err(_) => break,
+                    };
+                    if !line.trim().is_empty() && tx.send(line).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Self { markers })
+    }
+
+    #[cfg(windows)]
+    pub fn open<P: AsRef<Path>>(_path: P) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Other, "marker FIFOs are not supported on Windows"))
+    }
+
+    pub fn try_recv(&self) -> Option<String> {
+        self.markers.try_recv().ok()
+    }
+}
+
+// "[host 12:34:56.789] text", in UTC like the rest of the timestamps.
+pub(crate) fn format_marker(text: &str, time: SystemTime) -> String {
+    format!("[host {}] {}", format_time_of_day(time), text.trim_end())
+}
+
+// "12:34:56.789"
+pub(crate) fn format_time_of_day(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() % 86400;
+    format!("{:02}:{:02}:{:02}.{:03}", secs / 3600, secs / 60 % 60, secs % 60, since_epoch.subsec_millis())
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 {
+        format!("{}m{:02}.{:03}s", secs / 60, secs % 60, duration.subsec_millis())
+    } else {
+        format!("{}.{:03}s", secs, duration.subsec_millis())
+    }
+}
@@ -0,0 +1,171 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::{ResetStep, SerialSource, port::READ_TIMEOUT};
+use std::{
+    collections::VecDeque,
+    io::{self, Error as IoError, ErrorKind, Read},
+    thread,
+    time::Duration,
+};
+
+enum MockEvent {
+    Data(Vec<u8>),
+    Wait(Duration),
+    Disconnect,
+}
+
+// A stand-in for a device, for exercising the monitor without hardware.
+// It plays back a script of output and pauses, and keeps track of what
+// was done to it: control line changes (as ResetSteps), data sent, and
+// speed changes.  Once the script runs out it reads as disconnected.
+pub struct MockPort {
+    script: VecDeque<MockEvent>,
+    timeout: Duration,
+    disconnected: bool,
+    control_changes: Vec<ResetStep>,
+    sent: Vec<u8>,
+    speed: Option<usize>,
+    breaks: usize,
+}
+
+impl MockPort {
+    pub fn new() -> Self {
+        Self {
+            script: VecDeque::new(),
+            timeout: READ_TIMEOUT,
+            disconnected: false,
+            control_changes: Vec::new(),
+            sent: Vec::new(),
+            speed: None,
+            breaks: 0,
+        }
+    }
+
+    pub fn data<D: AsRef<[u8]>>(mut self, data: D) -> Self {
+        self.script.push_back(MockEvent::Data(data.as_ref().to_vec()));
+        self
+    }
+
+    // Nothing arrives for this long; reads time out as they would on a
+    // quiet serial port.
+    pub fn wait(mut self, duration: Duration) -> Self {
+        self.script.push_back(MockEvent::Wait(duration));
+        self
+    }
+
+    // Like the device being unplugged, even if there's more script left.
+    pub fn disconnect(mut self) -> Self {
+        self.script.push_back(MockEvent::Disconnect);
+        self
+    }
+
+    pub fn control_changes(&self) -> &[ResetStep] {
+        &self.control_changes
+    }
+
+    pub fn sent(&self) -> &[u8] {
+        &self.sent
+    }
+
+    pub fn speed(&self) -> Option<usize> {
+        self.speed
+    }
+
+    pub fn breaks(&self) -> usize {
+        self.breaks
+    }
+}
+
+impl Default for MockPort {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Read for MockPort {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.disconnected || buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.script.pop_front() {
+            Some(MockEvent::Data(mut data)) => {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                if len < data.len() {
+                    self.script.push_front(MockEvent::Data(data.split_off(len)));
+                }
+                Ok(len)
+            },
+            Some(MockEvent::Wait(duration)) if duration > self.timeout => {
+                thread::sleep(self.timeout);
+                self.script.push_front(MockEvent::Wait(duration - self.timeout));
+                Err(IoError::new(ErrorKind::TimedOut, "read timed out"))
+            },
+            Some(MockEvent::Wait(duration)) => {
+                thread::sleep(duration);
+                self.read(buf)
+            },
+            Some(MockEvent::Disconnect) | None => {
+                self.disconnected = true;
+                Ok(0)
+            },
+        }
+    }
+}
+
+impl SerialSource for MockPort {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn is_disconnected(&mut self) -> bool {
+        self.disconnected
+    }
+
+    fn has_control_lines(&self) -> bool {
+        true
+    }
+
+    fn set_dtr(&mut self, level: bool) -> io::Result<()> {
+        self.control_changes.push(ResetStep::Dtr(level));
+        Ok(())
+    }
+
+    fn set_rts(&mut self, level: bool) -> io::Result<()> {
+        self.control_changes.push(ResetStep::Rts(level));
+        Ok(())
+    }
+
+    fn set_speed(&mut self, speed: usize) -> io::Result<()> {
+        self.speed = Some(speed);
+        Ok(())
+    }
+
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.sent.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn send_break(&mut self) -> io::Result<()> {
+        self.breaks += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    io::{self, Error as IoError, ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+const DEFAULT_PORT: u16 = 1883;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const IO_TIMEOUT: Duration = Duration::from_secs(2);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub topic: String,
+    pub json: bool,
+}
+
+impl MqttConfig {
+    // Accepts mqtt://[user[:password]@]host[:port]
+    pub fn from_url(url: &str, topic: String, json: bool) -> Result<Self, IoError> {
+        let invalid = || IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid MQTT URL (expected mqtt://[user[:password]@]host[:port])", url));
+
+        let rest = url.strip_prefix("mqtt://").ok_or_else(invalid)?;
+        let (userinfo, hostport) = match rest.rfind('@') {
+            Some(at) => (Some(&rest[..at]), &rest[at + 1..]),
+            None => (None, rest),
+        };
+        let hostport = hostport.trim_end_matches('/');
+        let (host, port) = match hostport.rfind(':') {
+            Some(colon) => (&hostport[..colon], hostport[colon + 1..].parse::<u16>().map_err(|_| invalid())?),
+            None => (hostport, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let (username, password) = match userinfo.map(|userinfo| userinfo.splitn(2, ':').collect::<Vec<_>>()) {
+            Some(parts) => (parts.first().map(|s| s.to_string()), parts.get(1).map(|s| s.to_string())),
+            None => (None, None),
+        };
+
+        Ok(MqttConfig {
+            host: host.to_string(),
+            port,
+            username,
+            password,
+            topic,
+            json,
+        })
+    }
+}
+
+// A minimal MQTT 3.1.1 publisher (QoS 0 only), which is all that's needed
+// to forward log lines and avoids dragging in an async runtime. Each line
+// written is published as one message; it's meant to sit behind a queue so
+// a slow or unreachable broker can't stall the monitor.
+pub struct MqttSink {
+    config: MqttConfig,
+    stream: Option<TcpStream>,
+    pending: Vec<u8>,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl MqttSink {
+    pub fn connect(config: MqttConfig) -> io::Result<Self> {
+        let mut sink = Self {
+            config,
+            stream: None,
+            pending: Vec::new(),
+            backoff: MIN_BACKOFF,
+            retry_at: None,
+        };
+        sink.reconnect()?;
+        Ok(sink)
+    }
+
+    pub fn publish_line(&mut self, line: &str) -> io::Result<()> {
+        let mut packet = Vec::with_capacity(line.len() + self.config.topic.len() + 4);
+        put_string(&mut packet, &self.config.topic);
+        packet.extend_from_slice(line.as_bytes());
+
+        if self.stream.is_some() && self.send(0x30, &packet).is_ok() {
+            return Ok(());
+        }
+
+        // The broker dropped us or was unreachable; lines are dropped until
+        // it's back, retrying less often the longer it stays away.
+        if self.retry_at.map(|retry_at| Instant::now() < retry_at).unwrap_or(false) {
+            return Err(IoError::new(ErrorKind::NotConnected, "Not connected to MQTT broker"));
+        }
+        match self.reconnect() {
+            Ok(()) => {
+                self.backoff = MIN_BACKOFF;
+                self.retry_at = None;
+                self.send(0x30, &packet)
+            },
+            Err(err) => {
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                Err(err)
+            },
+        }
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        self.stream = None;
+
+        let mut stream = connect_timeout(&self.config.host, self.config.port)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let mut flags = 0x02;  // clean session
+        if self.config.username.is_some() {
+            flags |= 0x80;
+        }
+        if self.config.password.is_some() {
+            flags |= 0x40;
+        }
+
+        let mut packet = Vec::new();
+        put_string(&mut packet, "MQTT");
+        packet.push(4);  // protocol level 3.1.1
+        packet.push(flags);
+        // No keep-alive: nothing would send pings while the device is quiet,
+        // and a broken connection is noticed on the next publish anyway.
+        packet.extend_from_slice(&0u16.to_be_bytes());
+        put_string(&mut packet, &format!("espmonitor-{}", std::process::id()));
+        if let Some(username) = self.config.username.as_ref() {
+            put_string(&mut packet, username);
+        }
+        if let Some(password) = self.config.password.as_ref() {
+            put_string(&mut packet, password);
+        }
+        write_packet(&mut stream, 0x10, &packet)?;
+
+        let mut connack = [0u8; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != 0x20 || connack[3] != 0 {
+            return Err(IoError::new(ErrorKind::ConnectionRefused, format!("MQTT broker refused connection (return code {})", connack[3])));
+        }
+
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn send(&mut self, packet_type: u8, body: &[u8]) -> io::Result<()> {
+        let stream = self.stream.as_mut().ok_or_else(|| IoError::new(ErrorKind::NotConnected, "Not connected to MQTT broker"))?;
+        let result = write_packet(stream, packet_type, body);
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+}
+
+impl Write for MqttSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(newline) = self.pending.iter().position(|b| *b == b'\n') {
+            let line = self.pending.drain(..=newline).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            // Unpublished lines are lost either way; keep going with the rest.
+            let _ = self.publish_line(line.trim_end_matches(&['\r', '\n'][..]));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MqttSink {
+    fn drop(&mut self) {
+        let _ = self.send(0xe0, &[]);
+    }
+}
+
+fn connect_timeout(host: &str, port: u16) -> io::Result<TcpStream> {
+    let mut last_err = IoError::new(ErrorKind::NotFound, format!("Could not resolve MQTT broker '{}'", host));
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn write_packet<W: Write>(writer: &mut W, packet_type: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = Vec::with_capacity(body.len() + 5);
+    packet.push(packet_type);
+
+    let mut remaining = body.len();
+    loop {
+        let mut byte = (remaining % 128) as u8;
+        remaining /= 128;
+        if remaining > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if remaining == 0 {
+            break;
+        }
+    }
+
+    packet.extend_from_slice(body);
+    writer.write_all(&packet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SinkConfig;
+    use std::{net::TcpListener, thread};
+
+    #[test]
+    fn parses_urls() {
+        let config = MqttConfig::from_url("mqtt://user:pa:ss@broker.local:1884/", "t".to_string(), true).unwrap();
+        assert_eq!(config.host, "broker.local");
+        assert_eq!(config.port, 1884);
+        assert_eq!(config.username.as_deref(), Some("user"));
+        assert_eq!(config.password.as_deref(), Some("pa:ss"));
+
+        let config = MqttConfig::from_url("mqtt://broker", "t".to_string(), false).unwrap();
+        assert_eq!((config.host.as_str(), config.port, config.username, config.password), ("broker", DEFAULT_PORT, None, None));
+    }
+
+    #[test]
+    fn rejects_bad_urls() {
+        for url in &["broker", "http://broker", "mqtt://", "mqtt://:1883", "mqtt://broker:port", "mqtt://broker:99999"] {
+            let err = MqttConfig::from_url(url, "t".to_string(), false).err().unwrap_or_else(|| panic!("'{}' parsed", url));
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn encodes_remaining_length() {
+        let mut packet = Vec::new();
+        write_packet(&mut packet, 0x30, &[0; 200]).unwrap();
+        assert_eq!(&packet[..3], &[0x30, 0xc8, 0x01]);
+        assert_eq!(packet.len(), 203);
+    }
+
+    // Accepts one client and returns everything it sent.
+    fn broker() -> (u16, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 256];
+            let mut acked = false;
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => received.extend_from_slice(&buf[..n]),
+                }
+                if !acked {
+                    stream.write_all(&[0x20, 0x02, 0x00, 0x00]).unwrap();
+                    acked = true;
+                }
+            }
+            received
+        });
+        (port, broker)
+    }
+
+    #[test]
+    fn publishes_each_line() {
+        let (port, broker) = broker();
+        let config = MqttConfig::from_url(&format!("mqtt://127.0.0.1:{}", port), "log".to_string(), false).unwrap();
+        let mut sink = MqttSink::connect(config).unwrap();
+        sink.write_all(b"one\r\ntw").unwrap();
+        sink.write_all(b"o\n").unwrap();
+        drop(sink);
+
+        let received = broker.join().unwrap();
+        assert!(received.ends_with(b"\x30\x08\x00\x03logone\x30\x08\x00\x03logtwo\xe0\x00"));
+    }
+
+    #[test]
+    fn publishes_the_same_json_as_log_files() {
+        let (port, broker) = broker();
+        let config = MqttConfig::from_url(&format!("mqtt://127.0.0.1:{}", port), "log".to_string(), true).unwrap();
+        let mut sink = SinkConfig::Mqtt(config).open("/dev/ttyUSB0", None).unwrap();
+        sink.line("E (1234) wifi: Disconnected", false).unwrap();
+        drop(sink);
+
+        let received = String::from_utf8_lossy(&broker.join().unwrap()).into_owned();
+        for field in &["\"port\":\"/dev/ttyUSB0\"", "\"line\":\"E (1234) wifi: Disconnected\"", "\"panic\":false", "\"level\":\"error\"", "\"tag\":\"wifi\"", "\"message\":\"Disconnected\""] {
+            assert!(received.contains(field), "{} isn't in {:?}", field, received);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::idflog::strip_ansi;
+use std::time::{Duration, Instant};
+
+// A crash loop would otherwise pop up a notification per boot.
+const MIN_INTERVAL: Duration = Duration::from_secs(10);
+
+// Raises a desktop notification for panics and chosen keywords, for soak
+// tests where nobody is watching the terminal.
+pub(crate) struct Notifier {
+    enabled: bool,
+    keywords: Vec<String>,
+    last_shown_at: Option<Instant>,
+}
+
+impl Notifier {
+    pub fn new(enabled: bool, keywords: Vec<String>) -> Self {
+        Self {
+            enabled: enabled || !keywords.is_empty(),
+            keywords,
+            last_shown_at: None,
+        }
+    }
+
+    pub fn check(&mut self, line: &str, is_panic: bool, port: &str) {
+        if !self.enabled {
+            return;
+        }
+
+        let plain = strip_ansi(line);
+        let reason = if is_panic {
+            "Panic"
+        } else if self.keywords.iter().any(|keyword| plain.contains(keyword.as_str())) {
+            "Match"
+        } else {
+            return;
+        };
+
+        if self.last_shown_at.map(|at| at.elapsed() < MIN_INTERVAL).unwrap_or(false) {
+            return;
+        }
+        self.last_shown_at = Some(Instant::now());
+
+        show(&format!("ESPMonitor: {} on {}", reason, port), plain.trim());
+    }
+}
+
+#[cfg(feature = "notify")]
+fn show(summary: &str, body: &str) {
+    // Notification daemons come and go (e.g. over SSH); not fatal.
+    let _ = notify_rust::Notification::new()
+        .appname("espmonitor")
+        .summary(summary)
+        .body(body)
+        .show();
+}
+
+#[cfg(not(feature = "notify"))]
+fn show(_summary: &str, _body: &str) {}
+
+pub(crate) fn is_supported() -> bool {
+    cfg!(feature = "notify")
+}
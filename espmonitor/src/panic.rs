@@ -0,0 +1,207 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::collections::HashMap;
+
+lazy_static! {
+    static ref GURU_RE: Regex = Regex::new(r"Guru Meditation Error: Core\s+(\d+) panic'ed \(([^)]+)\)")
+        .expect("Failed to parse guru meditation regex");
+    static ref REGISTER_RE: Regex = Regex::new(r"\b([A-Z][A-Z0-9]*)\s*:\s*0x([0-9a-fA-F]{8})\b")
+        .expect("Failed to parse register regex");
+    static ref CANARY_TASK_RE: Regex = Regex::new(r"Stack canary watchpoint triggered \(([^)]+)\)")
+        .expect("Failed to parse stack canary regex");
+}
+
+// Collects the register dump the ESP-IDF panic handler prints after a
+// "Guru Meditation Error" line, and once it's complete, summarizes it.
+pub(crate) struct RegisterDumpDecoder {
+    dump: Option<RegisterDump>,
+}
+
+struct RegisterDump {
+    core: String,
+    reason: String,
+    task: Option<String>,
+    registers: HashMap<String, u64>,
+}
+
+impl RegisterDumpDecoder {
+    pub fn new() -> Self {
+        Self {
+            dump: None,
+        }
+    }
+
+    pub fn feed(&mut self, line: &str) -> Option<String> {
+        if let Some(caps) = GURU_RE.captures(line) {
+            self.dump = Some(RegisterDump {
+                core: caps[1].to_string(),
+                reason: caps[2].to_string(),
+                task: None,
+                registers: HashMap::new(),
+            });
+            return None;
+        }
+
+        let dump = self.dump.as_mut()?;
+
+        if let Some(caps) = CANARY_TASK_RE.captures(line) {
+            dump.task = Some(caps[1].to_string());
+        }
+
+        let mut found_registers = false;
+        for caps in REGISTER_RE.captures_iter(line) {
+            if let Ok(value) = u64::from_str_radix(&caps[2], 16) {
+                dump.registers.insert(caps[1].to_string(), value);
+                found_registers = true;
+            }
+        }
+
+        if !found_registers && !dump.registers.is_empty() {
+            // First line after the dump (blank, or the backtrace).
+            self.dump.take().map(|dump| dump.summary())
+        } else {
+            None
+        }
+    }
+}
+
+impl RegisterDump {
+    fn summary(&self) -> String {
+        let mut parts = vec![format!("Core {} panic'ed ({})", self.core, self.reason)];
+
+        if let Some(task) = self.task.as_ref() {
+            parts.push(format!("in task '{}'", task));
+        }
+
+        if let Some(pc) = self.registers.get("PC") {
+            // Xtensa
+            if let Some(cause) = self.registers.get("EXCCAUSE") {
+                parts.push(format!("cause: {}", xtensa_cause(*cause)));
+            }
+            if let Some(vaddr) = self.registers.get("EXCVADDR") {
+                parts.push(format!("fault address 0x{:08x}", vaddr));
+            }
+            parts.push(format!("PC 0x{:08x}", pc));
+            if let Some(a0) = self.registers.get("A0") {
+                parts.push(format!("called from 0x{:08x}", xtensa_return_address(*a0)));
+            }
+        } else if let Some(mepc) = self.registers.get("MEPC") {
+            // RISC-V
+            if let Some(cause) = self.registers.get("MCAUSE") {
+                parts.push(format!("cause: {}", riscv_cause(*cause)));
+            }
+            if let Some(mtval) = self.registers.get("MTVAL") {
+                parts.push(format!("fault address 0x{:08x}", mtval));
+            }
+            parts.push(format!("PC 0x{:08x}", mepc));
+            if let Some(ra) = self.registers.get("RA") {
+                parts.push(format!("called from 0x{:08x}", ra));
+            }
+        }
+
+        parts.join(", ")
+    }
+}
+
+// The top two bits of a windowed return address hold the call size rather
+// than part of the address; the call instruction itself is 3 bytes back.
+fn xtensa_return_address(a0: u64) -> u64 {
+    ((a0 & 0x3fff_ffff) | 0x4000_0000).saturating_sub(3)
+}
+
+pub(crate) fn xtensa_cause(cause: u64) -> String {
+    let description = match cause {
+        0 => "illegal instruction",
+        1 => "syscall",
+        2 => "instruction fetch error",
+        3 => "load/store error",
+        4 => "level 1 interrupt",
+        5 => "alloca",
+        6 => "integer divide by zero",
+        8 => "privileged instruction",
+        9 => "unaligned load/store",
+        12 => "instruction fetch PIF data error",
+        13 => "load/store PIF data error",
+        14 => "instruction fetch PIF address error",
+        15 => "load/store PIF address error",
+        16 => "instruction TLB miss",
+        17 => "instruction TLB multi-hit",
+        18 => "instruction fetch privilege violation",
+        20 => "instruction fetch from prohibited address",
+        24 => "load/store TLB miss",
+        25 => "load/store TLB multi-hit",
+        26 => "load/store privilege violation",
+        28 => "load from prohibited address",
+        29 => "store to prohibited address",
+        32..=39 => return format!("coprocessor {} disabled (EXCCAUSE {})", cause - 32, cause),
+        _ => "unknown",
+    };
+    format!("{} (EXCCAUSE {})", description, cause)
+}
+
+fn riscv_cause(cause: u64) -> String {
+    let description = match cause {
+        0 => "instruction address misaligned",
+        1 => "instruction access fault",
+        2 => "illegal instruction",
+        3 => "breakpoint",
+        4 => "load address misaligned",
+        5 => "load access fault",
+        6 => "store address misaligned",
+        7 => "store access fault",
+        8 => "environment call from U-mode",
+        11 => "environment call from M-mode",
+        _ => "unknown",
+    };
+    format!("{} (MCAUSE {})", description, cause)
+}
+
+lazy_static! {
+    static ref STACK_OVERFLOW_RE: Regex = Regex::new(r"\*\*\*ERROR\*\*\* A stack overflow in task (.+?) has been detected")
+        .expect("Failed to parse stack overflow regex");
+    static ref HEAP_CANARY_RE: Regex = Regex::new(r"CORRUPT HEAP: Bad (head|tail) at (0x[0-9a-fA-F]+)\. Expected (0x[0-9a-fA-F]+) got (0x[0-9a-fA-F]+)")
+        .expect("Failed to parse heap canary regex");
+    static ref HEAP_CORRUPT_RE: Regex = Regex::new(r"CORRUPT HEAP: (.*?)(?: at (0x[0-9a-fA-F]+))?\s*$")
+        .expect("Failed to parse heap corruption regex");
+    static ref HEAP_ASSERT_RE: Regex = Regex::new(r"assert failed: (multi_heap\w*|tlsf\w*) (\S+)")
+        .expect("Failed to parse heap assertion regex");
+}
+
+// Recognizes the one-line crash reports that don't come with a register
+// dump, and explains them.
+pub(crate) fn explain_crash_message(line: &str) -> Option<String> {
+    if let Some(caps) = STACK_OVERFLOW_RE.captures(line) {
+        Some(format!("Stack overflow in task '{}'; increase its stack size", caps[1].trim()))
+    } else if let Some(caps) = HEAP_CANARY_RE.captures(line) {
+        let (which, when) = if &caps[1] == "head" { ("start", "before") } else { ("end", "after") };
+        Some(format!(
+            "Heap corruption: the canary at the {} of the block at {} was overwritten ({} expected, found {}); something wrote {} the block",
+            which, &caps[2], &caps[3], &caps[4], when,
+        ))
+    } else if let Some(caps) = HEAP_CORRUPT_RE.captures(line) {
+        Some(match caps.get(2) {
+            Some(addr) => format!("Heap corruption detected at {}: {}", addr.as_str(), &caps[1]),
+            None => format!("Heap corruption detected: {}", &caps[1]),
+        })
+    } else {
+        HEAP_ASSERT_RE.captures(line)
+            .map(|caps| format!("Heap integrity check failed in {} ({}); likely a double free or a corrupted heap", &caps[1], &caps[2]))
+    }
+}
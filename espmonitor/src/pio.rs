@@ -0,0 +1,101 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::Framework;
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Error as IoError, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+// The parts of a PlatformIO environment that matter for monitoring.
+#[derive(Debug, Clone)]
+pub struct PioEnv {
+    pub name: String,
+    pub board: Option<String>,
+    pub framework: Option<Framework>,
+    pub port: Option<String>,
+    pub speed: Option<usize>,
+    pub elf: PathBuf,
+}
+
+impl PioEnv {
+    // Reads platformio.ini in `project_dir`.  Without an explicit
+    // environment, the first of `default_envs` is used, or failing that the
+    // first environment in the file.
+    pub fn load<P: AsRef<Path>>(project_dir: P, env: Option<&str>) -> io::Result<Self> {
+        let project_dir = project_dir.as_ref();
+        let ini_path = project_dir.join("platformio.ini");
+        let contents = fs::read_to_string(&ini_path)
+            .map_err(|err| IoError::new(err.kind(), format!("Unable to read {}: {}", ini_path.display(), err)))?;
+        let sections = parse_ini(&contents);
+
+        let name = match env {
+            Some(env) => env.to_string(),
+            None => sections
+                .iter()
+                .find(|(section, _)| section == "platformio")
+                .and_then(|(_, values)| values.get("default_envs"))
+                .and_then(|envs| envs.split(|c: char| c == ',' || c.is_whitespace()).find(|env| !env.is_empty()).map(str::to_string))
+                .or_else(|| sections.iter().find_map(|(section, _)| section.strip_prefix("env:").map(str::to_string)))
+                .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, format!("No environments in {}", ini_path.display())))?,
+        };
+
+        let common = sections.iter().find(|(section, _)| section == "env").map(|(_, values)| values);
+        let values = sections
+            .iter()
+            .find(|(section, _)| section.strip_prefix("env:") == Some(name.as_str()))
+            .map(|(_, values)| values)
+            .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, format!("No environment '{}' in {}", name, ini_path.display())))?;
+        // Settings in [env] apply to every environment that doesn't
+        // override them.
+        let get = |key: &str| values.get(key).or_else(|| common.and_then(|common| common.get(key))).cloned();
+
+        Ok(PioEnv {
+            board: get("board"),
+            framework: get("framework").and_then(|framework| match framework.split(',').next().map(str::trim) {
+                Some("arduino") => Some(Framework::Arduino),
+                Some("espidf") => Some(Framework::EspIdf),
+                _ => None,
+            }),
+            port: get("monitor_port").or_else(|| get("upload_port")),
+            speed: get("monitor_speed").and_then(|speed| speed.parse().ok()),
+            elf: project_dir.join(".pio").join("build").join(&name).join("firmware.elf"),
+            name,
+        })
+    }
+}
+
+// Sections in file order, each with its key/value pairs.  Indented
+// continuation lines (multi-line values like lib_deps) are skipped, since
+// none of the settings used here take them.
+fn parse_ini(contents: &str) -> Vec<(String, HashMap<String, String>)> {
+    let mut sections: Vec<(String, HashMap<String, String>)> = Vec::new();
+    for line in contents.lines() {
+        if line.starts_with(|c: char| c.is_whitespace()) {
+            continue;
+        }
+        let line = line.split(&[';', '#'][..]).next().unwrap_or("").trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            sections.push((line[1..line.len() - 1].trim().to_string(), HashMap::new()));
+        } else if let (Some((_, values)), Some((key, value))) = (sections.last_mut(), line.split_once('=')) {
+            values.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    sections
+}
@@ -0,0 +1,67 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{self, Write};
+
+enum EscapeState {
+    Text,
+    Escape,
+    Csi,
+}
+
+// Strips colors, cursor movement and CRs from the terminal rendering, for
+// when output goes to a pipe or file rather than a raw-mode terminal.
+pub(crate) struct PlainWriter<W: Write> {
+    inner: W,
+    state: EscapeState,
+}
+
+impl<W: Write> PlainWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            state: EscapeState::Text,
+        }
+    }
+}
+
+impl<W: Write> Write for PlainWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut plain = Vec::with_capacity(buf.len());
+        for &b in buf {
+            self.state = match (&self.state, b) {
+                (EscapeState::Text, 0x1b) => EscapeState::Escape,
+                (EscapeState::Text, b'\r') => EscapeState::Text,
+                (EscapeState::Text, b) => {
+                    plain.push(b);
+                    EscapeState::Text
+                },
+                (EscapeState::Escape, b'[') => EscapeState::Csi,
+                (EscapeState::Escape, _) => EscapeState::Text,
+                // CSI sequences end with a byte in the 0x40..=0x7e range.
+                (EscapeState::Csi, 0x40..=0x7e) => EscapeState::Text,
+                (EscapeState::Csi, _) => EscapeState::Csi,
+            };
+        }
+        self.inner.write_all(&plain)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
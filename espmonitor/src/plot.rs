@@ -0,0 +1,88 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::collections::VecDeque;
+
+const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+// More than any terminal is wide, so a resize still has history to show.
+const MAX_POINTS: usize = 512;
+
+struct Series {
+    name: String,
+    values: VecDeque<f64>,
+}
+
+// Sparklines of the extracted values, one row per capture, scaled to the
+// range of their recent history.
+pub(crate) struct Plot {
+    series: Vec<Series>,
+}
+
+impl Plot {
+    pub fn new(names: Vec<String>) -> Self {
+        Self {
+            series: names.into_iter().map(|name| Series { name, values: VecDeque::new() }).collect(),
+        }
+    }
+
+    pub fn height(&self) -> u16 {
+        self.series.len() as u16
+    }
+
+    // Captures that didn't parse as numbers just leave a gap in time.
+    pub fn push(&mut self, values: &[Option<f64>]) {
+        for (series, value) in self.series.iter_mut().zip(values) {
+            if let Some(value) = value {
+                if series.values.len() == MAX_POINTS {
+                    series.values.pop_front();
+                }
+                series.values.push_back(*value);
+            }
+        }
+    }
+
+    pub fn render(&self, cols: u16) -> Vec<String> {
+        let name_width = self.series.iter().map(|series| series.name.chars().count()).max().unwrap_or(0);
+        self.series.iter().map(|series| {
+            let last = match series.values.back() {
+                Some(last) => *last,
+                None => return format!("{:<width$}", series.name, width = name_width),
+            };
+            // The range of the whole history, so it stays put once the
+            // line fills up.
+            let (min, max) = series.values.iter().fold((last, last), |(min, max), value| (min.min(*value), max.max(*value)));
+            let label = format!("{:<width$} ", series.name, width = name_width);
+            let suffix = format!(" {} [{}..{}]", last, min, max);
+            let width = (cols as usize).saturating_sub(label.chars().count() + suffix.chars().count());
+            let bars = series.values.iter()
+                .skip(series.values.len().saturating_sub(width))
+                .map(|value| bar(*value, min, max))
+                .collect::<String>();
+            format!("{}{}{}", label, bars, suffix)
+        }).collect()
+    }
+}
+
+fn bar(value: f64, min: f64, max: f64) -> char {
+    if max <= min {
+        return BARS[BARS.len() / 2];
+    }
+    let level = ((value - min) / (max - min) * (BARS.len() - 1) as f64).round() as usize;
+    BARS[level.min(BARS.len() - 1)]
+}
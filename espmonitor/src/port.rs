@@ -0,0 +1,379 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{lockfile::PortLock, qemu::Qemu, websocket::WebSocket};
+use serial::{BaudRate, SerialPort, SystemPort};
+use std::{
+    fs::File,
+    io::{self, Error as IoError, ErrorKind, Read, Write},
+    time::Duration,
+};
+
+pub(crate) const READ_TIMEOUT: Duration = Duration::from_millis(200);
+
+// Anything the monitor can read device output from.  Only real serial
+// ports have settings and control lines; everything else gets the
+// defaults, which report that they're missing.
+pub trait SerialSource: Read {
+    // How long a read waits for data before failing with TimedOut.
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()>;
+
+    // A source that reads as empty has either ended or is just quiet.
+    fn is_disconnected(&mut self) -> bool;
+
+    // The descriptor to wait on for data, if there's one that works.
+    #[cfg(unix)]
+    fn poll_fd(&mut self) -> Option<std::os::unix::io::RawFd> {
+        None
+    }
+
+    fn has_control_lines(&self) -> bool {
+        false
+    }
+
+    fn set_dtr(&mut self, _level: bool) -> io::Result<()> {
+        Err(no_control_lines())
+    }
+
+    fn set_rts(&mut self, _level: bool) -> io::Result<()> {
+        Err(no_control_lines())
+    }
+
+    fn set_speed(&mut self, _speed: usize) -> io::Result<()> {
+        Err(IoError::new(ErrorKind::Other, "this port has no speed setting"))
+    }
+
+    fn send(&mut self, _data: &[u8]) -> io::Result<()> {
+        Err(IoError::new(ErrorKind::Other, "this port is read-only"))
+    }
+
+    fn send_break(&mut self) -> io::Result<()> {
+        Err(no_control_lines())
+    }
+}
+
+impl SerialSource for SystemPort {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        Ok(SerialPort::set_timeout(self, timeout)?)
+    }
+
+    // An unplugged device reads as empty rather than failing, but then
+    // fails any control line query.
+    fn is_disconnected(&mut self) -> bool {
+        self.read_dsr().is_err()
+    }
+
+    #[cfg(unix)]
+    fn poll_fd(&mut self) -> Option<std::os::unix::io::RawFd> {
+        use std::os::unix::io::AsRawFd;
+        Some(self.as_raw_fd())
+    }
+
+    fn has_control_lines(&self) -> bool {
+        true
+    }
+
+    fn set_dtr(&mut self, level: bool) -> io::Result<()> {
+        Ok(SerialPort::set_dtr(self, level)?)
+    }
+
+    fn set_rts(&mut self, level: bool) -> io::Result<()> {
+        Ok(SerialPort::set_rts(self, level)?)
+    }
+
+    fn set_speed(&mut self, speed: usize) -> io::Result<()> {
+        Ok(self.reconfigure(&|settings| settings.set_baud_rate(BaudRate::from_speed(speed)))?)
+    }
+
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_all(data)?;
+        self.flush()
+    }
+
+    #[cfg(unix)]
+    fn send_break(&mut self) -> io::Result<()> {
+        use std::os::unix::io::AsRawFd;
+        nix::sys::termios::tcsendbreak(self.as_raw_fd(), 0).map_err(|err| IoError::new(ErrorKind::Other, err))
+    }
+
+    #[cfg(windows)]
+    fn send_break(&mut self) -> io::Result<()> {
+        Err(IoError::new(ErrorKind::Other, "sending a break is not supported on Windows"))
+    }
+}
+
+// A saved log has ended once it reads as empty.
+impl SerialSource for File {
+    fn set_timeout(&mut self, _timeout: Duration) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn is_disconnected(&mut self) -> bool {
+        true
+    }
+}
+
+// What the monitor reads from: a real serial port, a plain byte stream
+// such as a FIFO or a Unix socket (e.g. an emulator's serial chardev),
+// which has no settings or control lines, or an emulator we started.
+// A pty has serial settings but no control lines, and doesn't go away
+// when whatever's on the other end closes it.  Serial ports and ptys hold
+// their lock file, if we could make one, while open.
+pub(crate) enum Port {
+    Serial(SystemPort, #[allow(dead_code)] Option<PortLock>),
+    Pty(SystemPort, #[allow(dead_code)] Option<PortLock>),
+    Stream(StreamPort),
+    Qemu(Qemu),
+}
+
+impl Port {
+    fn source(&mut self) -> &mut dyn SerialSource {
+        match self {
+            Port::Serial(dev, _) | Port::Pty(dev, _) => dev,
+            Port::Stream(stream) => stream,
+            Port::Qemu(qemu) => qemu.stream(),
+        }
+    }
+}
+
+impl SerialSource for Port {
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.source().set_timeout(timeout)
+    }
+
+    fn is_disconnected(&mut self) -> bool {
+        match self {
+            Port::Pty(..) => false,
+            _ => self.source().is_disconnected(),
+        }
+    }
+
+    #[cfg(unix)]
+    fn poll_fd(&mut self) -> Option<std::os::unix::io::RawFd> {
+        self.source().poll_fd()
+    }
+
+    fn has_control_lines(&self) -> bool {
+        matches!(self, Port::Serial(..))
+    }
+
+    fn set_dtr(&mut self, level: bool) -> io::Result<()> {
+        self.source().set_dtr(level)
+    }
+
+    fn set_rts(&mut self, level: bool) -> io::Result<()> {
+        self.source().set_rts(level)
+    }
+
+    fn set_speed(&mut self, speed: usize) -> io::Result<()> {
+        self.source().set_speed(speed)
+    }
+
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.source().send(data)
+    }
+
+    fn send_break(&mut self) -> io::Result<()> {
+        self.source().send_break()
+    }
+}
+
+impl Read for Port {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.source().read(buf)
+    }
+}
+
+// A pty opens like a serial port, but fails any query of its modem lines
+// (TIOCMGET gives ENOTTY).
+pub(crate) fn lacks_control_lines(dev: &mut SystemPort) -> bool {
+    SerialPort::read_dsr(dev).is_err()
+}
+
+// For --no-touch: both lines stay asserted, as drivers leave them on open,
+// which holds neither EN nor GPIO0 low through the usual auto-reset
+// circuit. On Unix the driver is also told not to drop them when the port
+// is closed. Virtual ports like ptys have no lines to set, which is fine.
+pub(crate) fn hold_control_lines(dev: &mut SystemPort) -> io::Result<()> {
+    let _ = SerialPort::set_dtr(dev, true);
+    let _ = SerialPort::set_rts(dev, true);
+    #[cfg(unix)]
+    {
+        use nix::sys::termios::{ControlFlags, SetArg, tcgetattr, tcsetattr};
+        use std::os::unix::io::AsRawFd;
+        let fd = dev.as_raw_fd();
+        let mut termios = tcgetattr(fd).map_err(|err| IoError::new(ErrorKind::Other, err))?;
+        termios.control_flags.remove(ControlFlags::HUPCL);
+        tcsetattr(fd, SetArg::TCSANOW, &termios).map_err(|err| IoError::new(ErrorKind::Other, err))?;
+    }
+    Ok(())
+}
+
+// The serial crate opens ports with TIOCEXCL, so nobody else (short of
+// root) can open them too; --shared undoes that, e.g. for a separate
+// logger. Windows only ever opens COM ports for one program at a time.
+#[cfg(unix)]
+pub(crate) fn allow_shared_open(dev: &mut SystemPort) -> io::Result<()> {
+    use nix::ioctl_none_bad;
+    use std::os::unix::io::AsRawFd;
+
+    ioctl_none_bad!(tiocnxcl, nix::libc::TIOCNXCL);
+    // TIOCNXCL takes no argument and only clears a flag on the tty.
+    unsafe { tiocnxcl(dev.as_raw_fd()) }
+        .map(drop)
+        .map_err(|err| IoError::new(ErrorKind::Other, err))
+}
+
+#[cfg(windows)]
+pub(crate) fn allow_shared_open(_dev: &mut SystemPort) -> io::Result<()> {
+    Err(IoError::new(ErrorKind::Other, "Windows doesn't let programs share a COM port"))
+}
+
+fn no_control_lines() -> IoError {
+    IoError::new(ErrorKind::Other, "this port has no control lines")
+}
+
+pub(crate) struct StreamPort {
+    reader: Box<dyn Read>,
+    // Sources that can't time out reads themselves are polled first.
+    #[cfg(unix)]
+    poll_fd: Option<std::os::unix::io::RawFd>,
+    timeout: Duration,
+    eof: bool,
+}
+
+impl StreamPort {
+    // Accepts "unix:/path/to.sock" for sockets, "tcp:HOST:PORT" for e.g.
+    // ser2net, and "ws://..." for WebSockets; anything else is opened as a
+    // file (which for a FIFO waits until there's a writer).
+    #[cfg(unix)]
+    pub fn open(path: &str) -> io::Result<Self> {
+        use std::{net::TcpStream, os::unix::{io::AsRawFd, net::UnixStream}};
+
+        let (reader, poll_fd): (Box<dyn Read>, _) =
+            if path.starts_with("ws://") {
+                (Box::new(WebSocket::connect(path, READ_TIMEOUT)?), None)
+            } else if let Some(addr) = path.strip_prefix("tcp:") {
+                let socket = TcpStream::connect(addr)?;
+                let fd = socket.as_raw_fd();
+                (Box::new(socket), Some(fd))
+            } else if let Some(socket_path) = path.strip_prefix("unix:") {
+                let socket = UnixStream::connect(socket_path)?;
+                let fd = socket.as_raw_fd();
+                (Box::new(socket), Some(fd))
+            } else {
+                let file = File::open(path)?;
+                let fd = file.as_raw_fd();
+                (Box::new(file), Some(fd))
+            };
+        Ok(Self {
+            reader,
+            poll_fd,
+            timeout: READ_TIMEOUT,
+            eof: false,
+        })
+    }
+
+    #[cfg(windows)]
+    pub fn open(path: &str) -> io::Result<Self> {
+        if path.starts_with("ws://") {
+            Ok(Self {
+                reader: Box::new(WebSocket::connect(path, READ_TIMEOUT)?),
+                timeout: READ_TIMEOUT,
+                eof: false,
+            })
+        } else if let Some(addr) = path.strip_prefix("tcp:") {
+            let socket = std::net::TcpStream::connect(addr)?;
+            socket.set_read_timeout(Some(READ_TIMEOUT))?;
+            Ok(Self {
+                reader: Box::new(socket),
+                timeout: READ_TIMEOUT,
+                eof: false,
+            })
+        } else {
+            Err(IoError::new(ErrorKind::Other, format!("{} is not a serial port, and FIFOs and Unix sockets are only supported on Unix", path)))
+        }
+    }
+
+    pub fn stdin() -> Self {
+        Self {
+            reader: Box::new(io::stdin()),
+            #[cfg(unix)]
+            poll_fd: Some(0),
+            timeout: READ_TIMEOUT,
+            eof: false,
+        }
+    }
+}
+
+impl SerialSource for StreamPort {
+    // (A WebSocket keeps the timeout it was connected with.)
+    fn set_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        self.timeout = timeout;
+        Ok(())
+    }
+
+    fn is_disconnected(&mut self) -> bool {
+        self.eof
+    }
+
+    #[cfg(unix)]
+    fn poll_fd(&mut self) -> Option<std::os::unix::io::RawFd> {
+        self.poll_fd
+    }
+}
+
+impl Read for StreamPort {
+    // Waits at most `timeout` for data, like a serial port would.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.eof {
+            return Ok(0);
+        }
+
+        #[cfg(unix)]
+        if let Some(fd) = self.poll_fd {
+            use nix::poll::{PollFd, PollFlags, poll};
+
+            let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+            match poll(&mut fds, self.timeout.as_millis() as i32) {
+                Ok(0) => return Err(IoError::new(ErrorKind::TimedOut, "read timed out")),
+                Ok(_) => (),
+                Err(err) => return Err(IoError::new(ErrorKind::Other, err)),
+            }
+        }
+
+        let bytes = self.reader.read(buf)?;
+        if bytes == 0 {
+            self.eof = true;
+        }
+        Ok(bytes)
+    }
+}
+
+pub(crate) fn is_stream_path(path: &str) -> bool {
+    if path.starts_with("unix:") || path.starts_with("tcp:") || path.starts_with("ws://") {
+        return true;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        std::fs::metadata(path).map(|metadata| metadata.file_type().is_fifo() || metadata.file_type().is_socket()).unwrap_or(false)
+    }
+    #[cfg(windows)]
+    false
+}
@@ -0,0 +1,271 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crossterm::{
+    QueueableCommand,
+    cursor::MoveUp,
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    style::{Print, PrintStyledContent, Stylize},
+    terminal::{Clear, ClearType, disable_raw_mode, enable_raw_mode},
+    tty::IsTty,
+};
+use crate::history;
+use regex::Regex;
+use std::{
+    io::{self, Error as IoError, ErrorKind, Write, stdout},
+    path::Path,
+};
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PortInfo {
+    pub path: String,
+    pub vid: Option<u16>,
+    pub pid: Option<u16>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl PortInfo {
+    pub fn description(&self) -> String {
+        let mut parts = Vec::new();
+        if let (Some(vid), Some(pid)) = (self.vid, self.pid) {
+            parts.push(format!("{:04x}:{:04x}", vid, pid));
+        }
+        let names = [self.manufacturer.as_deref(), self.product.as_deref()]
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        if !names.is_empty() {
+            parts.push(names);
+        }
+        if let Some(serial_number) = self.serial_number.as_ref() {
+            parts.push(format!("SN {}", serial_number));
+        }
+        parts.join(", ")
+    }
+}
+
+// Only USB adapters are listed; the legacy on-board ports most PCs
+// expose are never what someone monitoring an ESP wants.
+#[cfg(target_os = "linux")]
+pub fn available_ports() -> Vec<PortInfo> {
+    use std::fs;
+
+    fn read_attr(dir: &Path, name: &str) -> Option<String> {
+        fs::read_to_string(dir.join(name)).ok().map(|value| value.trim().to_string()).filter(|value| !value.is_empty())
+    }
+
+    let entries = match fs::read_dir("/sys/class/tty") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut ports = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with("ttyUSB") && !name.starts_with("ttyACM") {
+                return None;
+            }
+
+            let mut info = PortInfo {
+                path: format!("/dev/{}", name),
+                ..PortInfo::default()
+            };
+            // The USB device's attributes live a few levels above the
+            // interface the tty hangs off.
+            let device = fs::canonicalize(entry.path().join("device")).ok();
+            if let Some(usb_dir) = device.as_deref().and_then(|device| device.ancestors().find(|dir| dir.join("idVendor").exists())) {
+                info.vid = read_attr(usb_dir, "idVendor").and_then(|vid| u16::from_str_radix(&vid, 16).ok());
+                info.pid = read_attr(usb_dir, "idProduct").and_then(|pid| u16::from_str_radix(&pid, 16).ok());
+                info.manufacturer = read_attr(usb_dir, "manufacturer");
+                info.product = read_attr(usb_dir, "product");
+                info.serial_number = read_attr(usb_dir, "serial");
+            }
+            Some(info)
+        })
+        .collect::<Vec<_>>();
+
+    ports.sort_by(|a, b| a.path.cmp(&b.path));
+    ports
+}
+
+#[cfg(target_os = "macos")]
+pub fn available_ports() -> Vec<PortInfo> {
+    const IGNORED: &[&str] = &["cu.Bluetooth-Incoming-Port", "cu.debug-console", "cu.wlan-debug"];
+
+    let mut ports = std::fs::read_dir("/dev")
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.starts_with("cu.") && !IGNORED.contains(&name.as_str()))
+                .map(|name| PortInfo {
+                    path: format!("/dev/{}", name),
+                    ..PortInfo::default()
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    ports.sort_by(|a, b| a.path.cmp(&b.path));
+    ports
+}
+
+// Enumerating COM ports needs the SetupAPI, which the serial crate doesn't
+// expose, so a port always has to be named explicitly here.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn available_ports() -> Vec<PortInfo> {
+    Vec::new()
+}
+
+// Picks out a port by what's plugged into it rather than its path, which
+// can change between plug cycles.
+#[derive(Debug, Clone)]
+pub enum PortSelector {
+    SerialNumber(String),
+    // Matched against both the path and the description.
+    Pattern(Regex),
+}
+
+impl PortSelector {
+    pub fn pattern(pattern: &str) -> Result<Self, regex::Error> {
+        Regex::new(pattern).map(PortSelector::Pattern)
+    }
+
+    pub fn matches(&self, port: &PortInfo) -> bool {
+        match self {
+            PortSelector::SerialNumber(serial_number) => port.serial_number.as_ref() == Some(serial_number),
+            PortSelector::Pattern(re) => re.is_match(&port.path) || re.is_match(&port.description()),
+        }
+    }
+
+    pub fn resolve(&self) -> io::Result<String> {
+        let mut matching = available_ports().into_iter().filter(|port| self.matches(port));
+        match (matching.next(), matching.next()) {
+            (Some(port), None) => Ok(port.path),
+            (Some(first), Some(second)) => Err(IoError::new(
+                ErrorKind::InvalidInput,
+                format!("More than one serial device matches {} (e.g. {} and {})", self, first.path, second.path),
+            )),
+            (None, _) => Err(IoError::new(ErrorKind::NotFound, format!("No serial device matches {}", self))),
+        }
+    }
+}
+
+impl std::fmt::Display for PortSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PortSelector::SerialNumber(serial_number) => write!(f, "serial number '{}'", serial_number),
+            PortSelector::Pattern(re) => write!(f, "'{}'", re.as_str()),
+        }
+    }
+}
+
+// Used when no port is given on the command line: the port last used from
+// this directory is preferred if it's still there, then a lone candidate is
+// used as-is, and several are offered in a picker if there's a terminal to
+// show it on.
+pub fn select_port() -> io::Result<String> {
+    if let Some(port) = history::last_port().filter(|port| cfg!(windows) || Path::new(port).exists()) {
+        println!("Using {} (last used from this directory)", port);
+        return Ok(port);
+    }
+
+    let ports = available_ports();
+    match ports.len() {
+        0 => Err(IoError::new(ErrorKind::NotFound, "No serial device given, and none could be found")),
+        1 => {
+            println!("Using {}", describe(&ports[0]));
+            Ok(ports[0].path.clone())
+        },
+        _ if stdout().is_tty() => pick_port(&ports),
+        _ => Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "No serial device given, and several were found: {}",
+                ports.iter().map(|port| port.path.as_str()).collect::<Vec<_>>().join(", "),
+            ),
+        )),
+    }
+}
+
+pub fn list_ports() {
+    let ports = available_ports();
+    if ports.is_empty() {
+        println!("No serial devices found");
+    }
+    for port in ports {
+        println!("{}", describe(&port));
+    }
+}
+
+fn describe(port: &PortInfo) -> String {
+    let description = port.description();
+    if description.is_empty() {
+        port.path.clone()
+    } else {
+        format!("{} ({})", port.path, description)
+    }
+}
+
+fn pick_port(ports: &[PortInfo]) -> io::Result<String> {
+    enable_raw_mode()?;
+    let result = run_picker(ports);
+    disable_raw_mode()?;
+    result
+}
+
+fn run_picker(ports: &[PortInfo]) -> io::Result<String> {
+    let mut output = stdout();
+    let mut selected = 0;
+
+    output.queue(Print("Select a serial device (arrow keys, then enter):\r\n"))?;
+    loop {
+        for (i, port) in ports.iter().enumerate() {
+            let label = format!("{} {}", if i == selected { ">" } else { " " }, describe(port));
+            if i == selected {
+                output.queue(PrintStyledContent(label.bold()))?;
+            } else {
+                output.queue(Print(label))?;
+            }
+            output.queue(Clear(ClearType::UntilNewLine))?;
+            output.queue(Print("\r\n"))?;
+        }
+        output.flush()?;
+
+        match event::read()? {
+            Event::Key(KeyEvent { code: KeyCode::Up, .. }) | Event::Key(KeyEvent { code: KeyCode::Char('k'), .. }) => {
+                selected = selected.checked_sub(1).unwrap_or(ports.len() - 1);
+            },
+            Event::Key(KeyEvent { code: KeyCode::Down, .. }) | Event::Key(KeyEvent { code: KeyCode::Char('j'), .. }) => {
+                selected = (selected + 1) % ports.len();
+            },
+            Event::Key(KeyEvent { code: KeyCode::Enter, .. }) => break Ok(ports[selected].path.clone()),
+            Event::Key(KeyEvent { code: KeyCode::Esc, .. }) |
+            Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers: KeyModifiers::CONTROL }) => {
+                break Err(IoError::new(ErrorKind::Interrupted, "No serial device selected"));
+            },
+            _ => (),
+        }
+
+        output.queue(MoveUp(ports.len() as u16))?;
+    }
+}
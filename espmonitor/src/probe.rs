@@ -0,0 +1,177 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::{AppArgs, BOOT_RE, Error, LogRecord, SerialSource, apply_line_levels, chipdetect, open_free_port, reset, reset_chip};
+use std::{
+    io::{self, ErrorKind},
+    time::{Duration, Instant},
+};
+
+const PROBE_TIME: Duration = Duration::from_secs(3);
+const MAX_PROBE_BYTES: usize = 64 * 1024;
+
+// Long enough for a reset and the boot messages that follow.
+const SCAN_TIME: Duration = Duration::from_millis(1500);
+
+// What --detect-speed tries, in order.
+pub(crate) const SCAN_SPEEDS: &[usize] = &[74_880, 115_200, 230_400, 460_800, 921_600];
+
+// Below this share of text-like bytes, the speed is probably wrong.
+const TEXT_THRESHOLD: f64 = 0.9;
+
+// What ESP firmware and USB-serial adapters are usually set to, slowest
+// first; 74880 is what the ESP8266 ROM prints its boot messages at.
+pub(crate) const COMMON_SPEEDS: &[usize] = &[9600, 19_200, 38_400, 57_600, 74_880, 115_200, 230_400, 460_800, 921_600];
+
+pub(crate) struct Analysis {
+    // Printable ASCII, whitespace, ANSI escapes and valid UTF-8.
+    pub text_ratio: f64,
+    // Bytes made of one run of zeros and one of ones (0x00, 0x80, 0xc0, ...,
+    // 0xff), which is what reading a slower device's long bits gives.
+    pub stretched_ratio: f64,
+}
+
+pub(crate) fn analyze(data: &[u8]) -> Analysis {
+    let text = text_bytes(data);
+    let stretched = data.iter().filter(|b| b.leading_ones() + b.trailing_zeros() >= 8).count();
+    let len = data.len().max(1) as f64;
+    Analysis {
+        text_ratio: text as f64 / len,
+        stretched_ratio: stretched as f64 / len,
+    }
+}
+
+fn text_bytes(mut data: &[u8]) -> usize {
+    let mut count = 0;
+    while !data.is_empty() {
+        let (valid, rest) = match std::str::from_utf8(data) {
+            Ok(text) => (text, &data[data.len()..]),
+            Err(err) => {
+                let (valid, rest) = data.split_at(err.valid_up_to());
+                // Skip the offending byte.
+                (std::str::from_utf8(valid).unwrap_or(""), rest.get(1..).unwrap_or(&[]))
+            },
+        };
+        count += valid.chars().filter(|c| !c.is_control() || matches!(c, '\r' | '\n' | '\t' | '\x1b')).map(char::len_utf8).sum::<usize>();
+        data = rest;
+    }
+    count
+}
+
+// Common speeds to try instead of `speed`, most likely first: a device
+// that's slower shows up as stretched bits, one that's faster as random
+// garbage.
+pub(crate) fn suggest_speeds(speed: usize, analysis: &Analysis) -> Vec<usize> {
+    let slower = COMMON_SPEEDS.iter().rev().copied().filter(|s| *s < speed);
+    let faster = COMMON_SPEEDS.iter().copied().filter(|s| *s > speed);
+    if analysis.stretched_ratio > 0.3 {
+        slower.chain(faster).collect()
+    } else {
+        faster.chain(slower).collect()
+    }
+}
+
+fn listen(dev: &mut dyn SerialSource, duration: Duration) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 1024];
+    let started_at = Instant::now();
+    while started_at.elapsed() < duration && data.len() < MAX_PROBE_BYTES {
+        match dev.read(&mut buf) {
+            Ok(0) if dev.is_disconnected() => break,
+            Ok(count) => data.extend_from_slice(&buf[..count]),
+            Err(err) if err.kind() == ErrorKind::TimedOut || err.kind() == ErrorKind::WouldBlock => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(data)
+}
+
+// How much of `data` reads as firmware output: text, weighted by the log
+// lines, reset reasons and ROM banners in it.
+fn score(data: &[u8]) -> f64 {
+    let analysis = analyze(data);
+    let recognized = String::from_utf8_lossy(data)
+        .lines()
+        .filter(|line| LogRecord::parse(line).is_some() || BOOT_RE.is_match(line) || chipdetect::detect_chip(line).is_some())
+        .count();
+    analysis.text_ratio * (1 + recognized) as f64
+}
+
+pub(crate) struct ScanResult {
+    pub speed: usize,
+    pub bytes: usize,
+    pub text_ratio: f64,
+    pub score: f64,
+}
+
+// Switches to `speed`, resets the device (unless resets are off) and scores
+// what comes out.
+pub(crate) fn try_speed(dev: &mut dyn SerialSource, args: &AppArgs, speed: usize) -> io::Result<ScanResult> {
+    dev.set_speed(speed)?;
+    if args.reset && !args.no_touch && dev.has_control_lines() {
+        reset::run_steps(dev, &args.reset_mode().steps(args.reset_delay))?;
+        apply_line_levels(dev, args)?;
+    }
+    let data = listen(dev, SCAN_TIME)?;
+    Ok(ScanResult {
+        speed,
+        bytes: data.len(),
+        text_ratio: analyze(&data).text_ratio,
+        score: score(&data),
+    })
+}
+
+// Output that is mostly text counts even without a single recognized line.
+pub(crate) fn is_plausible(result: &ScanResult) -> bool {
+    result.score >= TEXT_THRESHOLD
+}
+
+pub(crate) fn run(args: &AppArgs) -> Result<(), Error> {
+    let speed = args.speed.unwrap_or_else(|| args.framework.default_speed(args.chip));
+    let mut dev = open_free_port(args).map_err(|source| Error::SerialOpen { port: args.serial.clone(), source })?;
+    if args.reset {
+        reset_chip(&mut dev, args).map_err(Error::SerialIo)?;
+    }
+
+    println!("Listening on {} at {} baud for {} seconds...", args.serial, speed, PROBE_TIME.as_secs());
+    let data = listen(&mut dev, PROBE_TIME).map_err(Error::SerialIo)?;
+
+    if data.is_empty() {
+        println!("Nothing was received. Check that the device is powered and running, that this is its port, and the wiring (TX to RX); without a reset, it may simply be quiet.");
+        return Ok(());
+    }
+
+    let analysis = analyze(&data);
+    println!("Received {} bytes; {:.0}% of them look like text", data.len(), analysis.text_ratio * 100.0);
+    if analysis.text_ratio >= TEXT_THRESHOLD {
+        println!("{} baud looks right", speed);
+        return Ok(());
+    }
+
+    let suggestions = suggest_speeds(speed, &analysis);
+    println!(
+        "{} baud is probably wrong; the device seems to be {} than that",
+        speed,
+        if analysis.stretched_ratio > 0.3 { "slower" } else { "faster" },
+    );
+    println!(
+        "Try {}",
+        suggestions.iter().take(3).map(|speed| format!("--speed {}", speed)).collect::<Vec<_>>().join(", then "),
+    );
+    Ok(())
+}
@@ -0,0 +1,344 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::{
+    env,
+    ffi::OsString,
+    fs,
+    io::{self, Error as IoError, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+const PROJECT_CONFIG_FILE: &str = "espmonitor.toml";
+
+// A named set of options from a config file, e.g.
+//
+//     [profile.release-board]
+//     port = "/dev/ttyUSB1"
+//     speed = 921600
+//     bin = "target/xtensa-esp32-espidf/release/app"
+//     highlight = ["wifi:green", "http"]
+//     no-reset = true
+//
+// Every key but `port` is a long option; `true` gives a flag, and a list
+// repeats the option.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub path: PathBuf,
+    pub port: Option<String>,
+    pub args: Vec<OsString>,
+}
+
+#[derive(Debug)]
+enum Value {
+    Bool(bool),
+    Text(String),
+    List(Vec<String>),
+}
+
+type Section = (String, Vec<(String, Value)>);
+
+impl Profile {
+    // Looks in the project's espmonitor.toml first, then the user's.
+    pub fn load(name: &str) -> io::Result<Self> {
+        let files = config_files();
+        for path in files.iter() {
+            let contents = match fs::read_to_string(path) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(IoError::new(err.kind(), format!("Unable to read {}: {}", path.display(), err))),
+            };
+            let section = format!("profile.{}", name);
+            let values = parse_config(&contents)
+                .map_err(|err| IoError::new(ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))?
+                .into_iter()
+                .find(|(name, _)| *name == section);
+            if let Some((_, values)) = values {
+                return Ok(Profile::from_values(name, path, values));
+            }
+        }
+        let searched = files.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(" or ");
+        Err(IoError::new(ErrorKind::NotFound, format!("No profile '{}' in {}", name, searched)))
+    }
+
+    fn from_values(name: &str, path: &Path, values: Vec<(String, Value)>) -> Self {
+        let mut port = None;
+        let mut args = Vec::new();
+        for (key, value) in values {
+            if key == "port" {
+                if let Value::Text(value) = value {
+                    port = Some(value);
+                }
+                continue;
+            }
+            let option = OsString::from(format!("--{}", key.replace('_', "-")));
+            match value {
+                Value::Bool(true) => args.push(option),
+                Value::Bool(false) => (),
+                Value::Text(value) => args.extend(vec![option, OsString::from(value)]),
+                Value::List(values) => {
+                    for value in values {
+                        args.extend(vec![option.clone(), OsString::from(value)]);
+                    }
+                },
+            }
+        }
+        Profile { name: name.to_string(), path: path.to_path_buf(), port, args }
+    }
+}
+
+fn config_files() -> Vec<PathBuf> {
+    let user_dir =
+        if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        };
+    let mut files = vec![PathBuf::from(PROJECT_CONFIG_FILE)];
+    files.extend(user_dir.map(|dir| dir.join("espmonitor").join("config.toml")));
+    files
+}
+
+// The small part of TOML profiles need: tables, and keys set to strings,
+// numbers, booleans or one-line lists of those.
+fn parse_config(contents: &str) -> Result<Vec<Section>, String> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |what: &str| format!("line {}: {}", index + 1, what);
+        if line.starts_with('[') {
+            let header = strip_comment(line);
+            let name = header
+                .strip_prefix('[')
+                .and_then(|header| header.strip_suffix(']'))
+                .ok_or_else(|| invalid("unterminated table header"))?
+                .trim()
+                .trim_matches('"');
+            if name.is_empty() || name.contains(&['[', ']'][..]) {
+                return Err(invalid("expected a table name like [profile.NAME]"));
+            }
+            sections.push((name.to_string(), Vec::new()));
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or_else(|| invalid("expected 'key = value'"))?;
+        let value = parse_value(strip_comment(value.trim())).ok_or_else(|| invalid("expected a string, number, boolean or list"))?;
+        match sections.last_mut() {
+            Some((_, values)) => values.push((key.trim().trim_matches('"').to_string(), value)),
+            None => return Err(invalid("setting outside of a table")),
+        }
+    }
+    Ok(sections)
+}
+
+// Drops a trailing comment, leaving #s inside strings alone.
+fn strip_comment(text: &str) -> &str {
+    let mut quotes = Quotes::default();
+    for (index, c) in text.char_indices() {
+        if !quotes.in_string(c) && c == '#' {
+            return text[..index].trim_end();
+        }
+    }
+    text
+}
+
+// Tracks whether a scan is inside a "basic" (with escapes) or 'literal'
+// string.
+#[derive(Default)]
+struct Quotes {
+    quote: Option<char>,
+    escaped: bool,
+}
+
+impl Quotes {
+    // Whether c is part of a string, quotes included.
+    fn in_string(&mut self, c: char) -> bool {
+        match (self.quote, c) {
+            (Some(_), _) if self.escaped => self.escaped = false,
+            (Some('"'), '\\') => self.escaped = true,
+            (Some(quote), c) if c == quote => self.quote = None,
+            (Some(_), _) => (),
+            (None, '"') | (None, '\'') => self.quote = Some(c),
+            (None, _) => return false,
+        }
+        true
+    }
+}
+
+fn parse_value(text: &str) -> Option<Value> {
+    if let Some(items) = text.strip_prefix('[').and_then(|text| text.strip_suffix(']')) {
+        split_list(items)
+            .into_iter()
+            .map(|item| match parse_value(item)? {
+                Value::Text(text) => Some(text),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(Value::List)
+    } else if text.starts_with('"') {
+        basic_string(text).map(Value::Text)
+    } else if let Some(literal) = text.strip_prefix('\'') {
+        // Literal strings have no escapes, so can't contain a quote at all.
+        literal.strip_suffix('\'').filter(|inner| !inner.contains('\'')).map(|inner| Value::Text(inner.to_string()))
+    } else {
+        match text {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ if !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || "._+-".contains(c)) => Some(Value::Text(text.to_string())),
+            _ => None,
+        }
+    }
+}
+
+fn split_list(items: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quotes = Quotes::default();
+    for (index, c) in items.char_indices() {
+        if !quotes.in_string(c) && c == ',' {
+            parts.push(items[start..index].trim());
+            start = index + 1;
+        }
+    }
+    parts.push(items[start..].trim());
+    parts.into_iter().filter(|part| !part.is_empty()).collect()
+}
+
+// A whole "..." string, which mustn't have anything after its closing
+// quote.
+fn basic_string(text: &str) -> Option<String> {
+    let inner = text.strip_prefix('"')?;
+    let mut escaped = false;
+    for (index, c) in inner.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' if index + 1 == inner.len() => return Some(unescape(&inner[..index])),
+            '"' => return None,
+            _ => (),
+        }
+    }
+    None
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(config: &str) -> Vec<(String, Vec<(String, String)>)> {
+        parse_config(config)
+            .unwrap()
+            .into_iter()
+            .map(|(name, values)| (name, values.into_iter().map(|(key, value)| (key, format!("{:?}", value))).collect()))
+            .collect()
+    }
+
+    fn value(text: &str) -> String {
+        let config = format!("[profile.test]\nkey = {}", text);
+        let (_, mut values) = parse_config(&config).unwrap().pop().unwrap();
+        format!("{:?}", values.pop().unwrap().1)
+    }
+
+    #[test]
+    fn parses_profiles() {
+        let config = "# boards\n\n[profile.a]\nport = \"/dev/ttyUSB1\"\nspeed = 921600\n\n[ \"profile.b\" ] # quoted\nno-reset = true\n";
+        assert_eq!(values(config), vec![
+            ("profile.a".to_string(), vec![
+                ("port".to_string(), "Text(\"/dev/ttyUSB1\")".to_string()),
+                ("speed".to_string(), "Text(\"921600\")".to_string()),
+            ]),
+            ("profile.b".to_string(), vec![("no-reset".to_string(), "Bool(true)".to_string())]),
+        ]);
+    }
+
+    #[test]
+    fn keeps_hashes_inside_strings() {
+        assert_eq!(value("\"a # b\" # comment"), "Text(\"a # b\")");
+        assert_eq!(value("'c:\\tmp#1'"), "Text(\"c:\\\\tmp#1\")");
+        assert_eq!(value("true # comment"), "Bool(true)");
+    }
+
+    #[test]
+    fn unescapes_strings() {
+        assert_eq!(value(r#""say \"hi\" # not a comment""#), r#"Text("say \"hi\" # not a comment")"#);
+        assert_eq!(value(r#""tab\there\\""#), r#"Text("tab\there\\")"#);
+    }
+
+    #[test]
+    fn parses_lists() {
+        assert_eq!(value(r#"["wifi:green", "a, b", 'c # d, e', 42] # four"#), r#"List(["wifi:green", "a, b", "c # d, e", "42"])"#);
+        assert_eq!(value("[]"), "List([])");
+    }
+
+    #[test]
+    fn rejects_bad_configs() {
+        for config in &[
+            "[profile.a",
+            "[]",
+            "[[profile.a]]",
+            "key = 1",
+            "[profile.a]\nkey",
+            "[profile.a]\nkey =",
+            "[profile.a]\nkey = \"unterminated",
+            "[profile.a]\nkey = \"escaped end\\\"",
+            "[profile.a]\nkey = \"one\" \"two\"",
+            "[profile.a]\nkey = 'one' 'two'",
+            "[profile.a]\nkey = 'unterminated",
+            "[profile.a]\nkey = [\"a\", true]",
+            "[profile.a]\nkey = [\"a\",",
+            "[profile.a]\nkey = has spaces",
+        ] {
+            let err = parse_config(config).err().unwrap_or_else(|| panic!("{:?} parsed", config));
+            assert!(err.starts_with("line "), "{}", err);
+        }
+    }
+
+    #[test]
+    fn turns_values_into_options() {
+        let (_, values) = parse_config("[profile.p]\nport = \"COM3\"\nno_reset = true\nstream = false\nspeed = 115200\nhighlight = [\"wifi\", \"http\"]")
+            .unwrap()
+            .pop()
+            .unwrap();
+        let profile = Profile::from_values("p", Path::new("espmonitor.toml"), values);
+        assert_eq!(profile.port.as_deref(), Some("COM3"));
+        assert_eq!(profile.args, vec!["--no-reset", "--speed", "115200", "--highlight", "wifi", "--highlight", "http"]);
+    }
+}
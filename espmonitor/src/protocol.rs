@@ -0,0 +1,343 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::{
+    convert::TryFrom,
+    io::{Error as IoError, ErrorKind},
+    time::Instant,
+};
+
+lazy_static! {
+    static ref NMEA_RE: Regex = Regex::new(r"\$([A-Z]{2})([A-Z]{3}),([^*$]*)\*([0-9A-Fa-f]{2})")
+        .expect("Failed to parse NMEA regex");
+    // A command at the end of a line, e.g. "modem: > AT+CSQ".  Requiring a
+    // word boundary keeps "DATA" and the like from matching.
+    static ref AT_COMMAND_RE: Regex = Regex::new(r"\b(AT(?:[+&#%$^]?[A-Za-z0-9_*#]+)?(?:=.*?|\?)?)\s*$")
+        .expect("Failed to parse AT command regex");
+    static ref AT_RESULT_RE: Regex = Regex::new(r"(?:^|[\s>:])(OK|ERROR|NO CARRIER|NO DIALTONE|NO ANSWER|BUSY|CONNECT(?: \d+)?|\+CM[ES] ERROR: *(\d+|.+?))\s*$")
+        .expect("Failed to parse AT result regex");
+    static ref AT_RESPONSE_RE: Regex = Regex::new(r"(?:^|[\s>:])(\+[A-Z0-9]+): *(.*?)\s*$")
+        .expect("Failed to parse AT response regex");
+}
+
+// Devices that ESP projects often tunnel through their logs, like GPS
+// receivers and cellular modems.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protocol {
+    Nmea,
+    At,
+}
+
+impl TryFrom<&str> for Protocol {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "nmea" | "gps" => Ok(Protocol::Nmea),
+            "at" => Ok(Protocol::At),
+            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid protocol (expected nmea or at)", value))),
+        }
+    }
+}
+
+struct PendingCommand {
+    command: String,
+    sent_at: Instant,
+    responses: Vec<String>,
+}
+
+// Explains lines in the enabled protocols; the explanation is printed
+// after the line itself.
+pub(crate) struct ProtocolPrinter {
+    nmea: bool,
+    at: bool,
+    pending: Option<PendingCommand>,
+}
+
+impl ProtocolPrinter {
+    pub fn new(protocols: &[Protocol]) -> Self {
+        Self {
+            nmea: protocols.contains(&Protocol::Nmea),
+            at: protocols.contains(&Protocol::At),
+            pending: None,
+        }
+    }
+
+    pub fn describe(&mut self, line: &str) -> Option<String> {
+        if self.nmea {
+            if let Some(caps) = NMEA_RE.captures(line) {
+                return Some(describe_nmea(&caps[0][1..caps[0].len() - 3], &caps[1], &caps[2], &caps[3], &caps[4]));
+            }
+        }
+        if self.at {
+            return self.at_line(line);
+        }
+        None
+    }
+
+    // Pairs up commands with their responses, which only get explained
+    // once the final result code arrives.
+    fn at_line(&mut self, line: &str) -> Option<String> {
+        if let Some(caps) = AT_RESULT_RE.captures(line) {
+            let pending = self.pending.take()?;
+            let result = match caps.get(2) {
+                Some(code) => format!("{} ({})", &caps[1], describe_cme_error(code.as_str())),
+                None => caps[1].to_string(),
+            };
+            let mut description = format!("{} -> {} after {} ms", pending.command, result, pending.sent_at.elapsed().as_millis());
+            if !pending.responses.is_empty() {
+                description.push_str(&format!(": {}", pending.responses.join(", ")));
+            }
+            return Some(description);
+        }
+
+        if let Some(caps) = AT_RESPONSE_RE.captures(line) {
+            if let Some(pending) = self.pending.as_mut() {
+                pending.responses.push(describe_at_response(&caps[1], &caps[2]));
+            }
+            return None;
+        }
+
+        if let Some(caps) = AT_COMMAND_RE.captures(line) {
+            let command = caps[1].to_string();
+            // Modems echo commands back unless told not to (ATE0).
+            let echo = self.pending.as_ref()
+                .map(|pending| pending.command == command && pending.responses.is_empty())
+                .unwrap_or(false);
+            if !echo {
+                self.pending = Some(PendingCommand { command, sent_at: Instant::now(), responses: Vec::new() });
+            }
+        }
+        None
+    }
+}
+
+fn describe_nmea(body: &str, talker: &str, kind: &str, data: &str, checksum: &str) -> String {
+    let expected = body.bytes().fold(0u8, |sum, byte| sum ^ byte);
+    let system = match talker {
+        "GP" => "GPS",
+        "GL" => "GLONASS",
+        "GA" => "Galileo",
+        "GB" | "BD" => "BeiDou",
+        "GN" => "GNSS",
+        _ => talker,
+    };
+    if u8::from_str_radix(checksum, 16).ok() != Some(expected) {
+        return format!("NMEA {} ({}): bad checksum {} (expected {:02X})", kind, system, checksum, expected);
+    }
+
+    let fields = data.split(',').collect::<Vec<_>>();
+    let field = |index: usize| fields.get(index).copied().unwrap_or("");
+    let parts = match kind {
+        "GGA" => vec![
+            utc_time(field(0)),
+            position(field(1), field(2), field(3), field(4)),
+            Some(match field(5) {
+                "0" => "no fix".to_string(),
+                "1" => "GPS fix".to_string(),
+                "2" => "DGPS fix".to_string(),
+                "4" => "RTK fixed".to_string(),
+                "5" => "RTK float".to_string(),
+                "6" => "estimated".to_string(),
+                other => format!("fix quality {}", other),
+            }),
+            field(6).parse::<u32>().ok().map(|sats| format!("{} satellites", sats)),
+            non_empty(field(7)).map(|hdop| format!("HDOP {}", hdop)),
+            non_empty(field(8)).map(|alt| format!("altitude {} m", alt)),
+        ],
+        "RMC" => vec![
+            utc_time(field(0)),
+            Some(if field(1) == "A" { "valid".to_string() } else { "no fix".to_string() }),
+            position(field(2), field(3), field(4), field(5)),
+            speed_knots(field(6)),
+            non_empty(field(7)).map(|course| format!("course {}°", course)),
+            date(field(8)),
+        ],
+        "GLL" => vec![
+            position(field(0), field(1), field(2), field(3)),
+            utc_time(field(4)),
+            Some(if field(5) == "A" { "valid".to_string() } else { "no fix".to_string() }),
+        ],
+        "VTG" => vec![
+            non_empty(field(0)).map(|course| format!("course {}°", course)),
+            non_empty(field(6)).map(|speed| format!("{} km/h", speed)).or_else(|| speed_knots(field(4))),
+        ],
+        "GSA" => vec![
+            Some(match field(1) {
+                "2" => "2D fix".to_string(),
+                "3" => "3D fix".to_string(),
+                _ => "no fix".to_string(),
+            }),
+            Some(format!("{} satellites used", fields.iter().skip(2).take(12).filter(|sat| !sat.is_empty()).count())),
+            non_empty(field(14)).map(|pdop| format!("PDOP {}", pdop)),
+            non_empty(field(15)).map(|hdop| format!("HDOP {}", hdop)),
+            non_empty(field(16)).map(|vdop| format!("VDOP {}", vdop)),
+        ],
+        "GSV" => vec![
+            field(2).parse::<u32>().ok().map(|sats| format!("{} satellites in view", sats)),
+            Some(format!("message {} of {}", field(1), field(0))),
+        ],
+        _ => vec![],
+    };
+
+    let parts = parts.into_iter().flatten().collect::<Vec<_>>();
+    if parts.is_empty() {
+        format!("NMEA {} ({})", kind, system)
+    } else {
+        format!("NMEA {} ({}): {}", kind, system, parts.join(", "))
+    }
+}
+
+fn non_empty(field: &str) -> Option<&str> {
+    Some(field).filter(|field| !field.is_empty())
+}
+
+// "123519.00" is 12:35:19 UTC.
+fn utc_time(field: &str) -> Option<String> {
+    let time = six_digits(field)?;
+    Some(format!("{}:{}:{} UTC", &time[..2], &time[2..4], &time[4..]))
+}
+
+// "230326" is 2026-03-23; NMEA 0183 leaves out the century, and any
+// receiver still in use is from this one.
+fn date(field: &str) -> Option<String> {
+    let date = six_digits(field)?;
+    Some(format!("20{}-{}-{}", &date[4..], &date[2..4], &date[..2]))
+}
+
+fn six_digits(field: &str) -> Option<&str> {
+    field.get(..6).filter(|digits| digits.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+// Coordinates are (d)ddmm.mmmm, with the hemisphere separately.
+fn position(lat: &str, lat_hemisphere: &str, lon: &str, lon_hemisphere: &str) -> Option<String> {
+    let degrees = |value: &str, degree_digits: usize| -> Option<f64> {
+        let whole = value.get(..degree_digits)?.parse::<f64>().ok()?;
+        let minutes = value.get(degree_digits..)?.parse::<f64>().ok()?;
+        Some(whole + minutes / 60.0)
+    };
+    Some(format!("{:.5}°{} {:.5}°{}", degrees(lat, 2)?, lat_hemisphere, degrees(lon, 3)?, lon_hemisphere))
+}
+
+fn speed_knots(field: &str) -> Option<String> {
+    let knots = field.parse::<f64>().ok()?;
+    Some(format!("{:.1} km/h", knots * 1.852))
+}
+
+fn describe_at_response(name: &str, value: &str) -> String {
+    let values = value.split(',').map(str::trim).collect::<Vec<_>>();
+    let detail = match name {
+        "+CSQ" => values.first().and_then(|rssi| rssi.parse::<i32>().ok()).map(|rssi| match rssi {
+            99 => "signal unknown".to_string(),
+            0..=31 => format!("signal {} dBm", -113 + 2 * rssi),
+            _ => format!("signal {}", rssi),
+        }),
+        // The solicited form repeats the <n> setting before the status.
+        "+CREG" | "+CGREG" | "+CEREG" => values.get(if values.len() > 1 && values[0].len() == 1 && values[1].len() == 1 { 1 } else { 0 })
+            .map(|stat| match *stat {
+                "0" => "not registered".to_string(),
+                "1" => "registered, home network".to_string(),
+                "2" => "searching".to_string(),
+                "3" => "registration denied".to_string(),
+                "5" => "registered, roaming".to_string(),
+                other => format!("status {}", other),
+            }),
+        _ => None,
+    };
+    match detail {
+        Some(detail) => format!("{} {} ({})", name, value, detail),
+        None => format!("{} {}", name, value),
+    }
+}
+
+// The handful of +CME ERROR codes that come up most; the rest are in
+// 3GPP TS 27.007.
+fn describe_cme_error(code: &str) -> String {
+    match code {
+        "3" => "operation not allowed".to_string(),
+        "4" => "operation not supported".to_string(),
+        "10" => "SIM not inserted".to_string(),
+        "11" => "SIM PIN required".to_string(),
+        "13" => "SIM failure".to_string(),
+        "14" => "SIM busy".to_string(),
+        "30" => "no network service".to_string(),
+        "100" => "unknown error".to_string(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sentence(body: &str) -> String {
+        format!("gps: ${}*{:02X}", body, body.bytes().fold(0u8, |sum, byte| sum ^ byte))
+    }
+
+    fn nmea(line: &str) -> Option<String> {
+        ProtocolPrinter::new(&[Protocol::Nmea]).describe(line)
+    }
+
+    #[test]
+    fn describes_nmea_sentences() {
+        assert_eq!(
+            nmea(&sentence("GPGGA,123519.00,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,")).unwrap(),
+            "NMEA GGA (GPS): 12:35:19 UTC, 48.11730°N 11.51667°E, GPS fix, 8 satellites, HDOP 0.9, altitude 545.4 m",
+        );
+        assert_eq!(
+            nmea(&sentence("GNRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230326,003.1,W")).unwrap(),
+            "NMEA RMC (GNSS): 12:35:19 UTC, valid, 48.11730°N 11.51667°E, 41.5 km/h, course 084.4°, 2026-03-23",
+        );
+        assert_eq!(nmea(&sentence("GPXYZ,1,2")).unwrap(), "NMEA XYZ (GPS)");
+        assert_eq!(nmea("$GPGLL,4916.45,N,12311.12,W,225444,A*00").unwrap(), "NMEA GLL (GPS): bad checksum 00 (expected 31)");
+        assert_eq!(nmea("no sentence here"), None);
+    }
+
+    #[test]
+    fn skips_garbled_nmea_fields() {
+        assert_eq!(
+            nmea(&sentence("GPRMC,\u{fffd}\u{fffd}1,V,,,,,,,\u{fffd}0326")).unwrap(),
+            "NMEA RMC (GPS): no fix",
+        );
+        assert_eq!(nmea(&sentence("GPGLL,49x6.45,N,1231,W,12:345,V")).unwrap(), "NMEA GLL (GPS): no fix");
+    }
+
+    #[test]
+    fn pairs_at_commands_with_results() {
+        let mut printer = ProtocolPrinter::new(&[Protocol::At]);
+        assert_eq!(printer.describe("modem: > AT+CSQ"), None);
+        assert_eq!(printer.describe("AT+CSQ"), None);
+        assert_eq!(printer.describe("+CSQ: 20,99"), None);
+        let description = printer.describe("OK").unwrap();
+        assert!(description.starts_with("AT+CSQ -> OK after "), "{}", description);
+        assert!(description.ends_with(" ms: +CSQ 20,99 (signal -73 dBm)"), "{}", description);
+
+        assert_eq!(printer.describe("OK"), None);
+        assert_eq!(printer.describe("AT+CPIN?"), None);
+        let description = printer.describe("+CME ERROR: 10").unwrap();
+        assert!(description.starts_with("AT+CPIN? -> +CME ERROR: 10 (SIM not inserted)"), "{}", description);
+    }
+
+    #[test]
+    fn explains_registration() {
+        assert_eq!(describe_at_response("+CREG", "0,5"), "+CREG 0,5 (registered, roaming)");
+        assert_eq!(describe_at_response("+CEREG", "2"), "+CEREG 2 (searching)");
+        assert_eq!(describe_at_response("+COPS", "0,0,\"Operator\""), "+COPS 0,0,\"Operator\"");
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{Chip, port::StreamPort};
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    io::{self, Error as IoError, ErrorKind, Read},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const DEFAULT_QEMU_IMAGE: &str = "build/qemu_flash.bin";
+
+// Runs Espressif's QEMU fork with the chip's serial port and the QEMU
+// monitor on Unix sockets, so the monitor can read the former and reset the
+// machine through the latter.
+pub(crate) struct Qemu {
+    child: Child,
+    stream: StreamPort,
+    serial_socket: PathBuf,
+    monitor_socket: PathBuf,
+}
+
+impl Qemu {
+    pub fn launch(chip: Chip, image: &OsStr) -> io::Result<Self> {
+        let (binary, machine_args) = match &chip.spec().qemu {
+            Some(machine) => (machine.binary, machine.args),
+            None => return Err(IoError::new(ErrorKind::InvalidInput, format!("QEMU doesn't support the {:?}", chip))),
+        };
+        fs::metadata(image).map_err(|err| IoError::new(err.kind(), format!("Unable to use QEMU flash image {}: {}", image.to_string_lossy(), err)))?;
+
+        let dir = env::temp_dir();
+        let serial_socket = dir.join(format!("espmonitor-qemu-{}-serial.sock", std::process::id()));
+        let monitor_socket = dir.join(format!("espmonitor-qemu-{}-monitor.sock", std::process::id()));
+        let _ = fs::remove_file(&serial_socket);
+        let _ = fs::remove_file(&monitor_socket);
+
+        let mut drive = std::ffi::OsString::from("file=");
+        drive.push(image);
+        drive.push(",if=mtd,format=raw");
+
+        let mut child = Command::new(binary)
+            .args(machine_args)
+            .arg("-display").arg("none")
+            .arg("-drive").arg(drive)
+            // Waiting for a client means no boot output is lost.
+            .arg("-serial").arg(format!("unix:{},server=on,wait=on", serial_socket.to_string_lossy()))
+            .arg("-monitor").arg(format!("unix:{},server=on,wait=off", monitor_socket.to_string_lossy()))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| IoError::new(err.kind(), format!("Unable to run {} (is Espressif's QEMU installed?): {}", binary, err)))?;
+
+        let started_at = Instant::now();
+        let stream = loop {
+            if let Some(status) = child.try_wait()? {
+                let mut stderr = String::new();
+                if let Some(mut pipe) = child.stderr.take() {
+                    let _ = pipe.read_to_string(&mut stderr);
+                }
+                return Err(IoError::new(ErrorKind::Other, format!("QEMU exited ({}): {}", status, stderr.trim())));
+            }
+            match StreamPort::open(&format!("unix:{}", serial_socket.to_string_lossy())) {
+                Ok(stream) => break stream,
+                Err(_) if started_at.elapsed() < STARTUP_TIMEOUT => thread::sleep(Duration::from_millis(100)),
+                Err(err) => {
+                    let _ = child.kill();
+                    return Err(err);
+                },
+            }
+        };
+
+        Ok(Self {
+            child,
+            stream,
+            serial_socket,
+            monitor_socket,
+        })
+    }
+
+    pub fn stream(&mut self) -> &mut StreamPort {
+        &mut self.stream
+    }
+
+    #[cfg(unix)]
+    pub fn reset(&mut self) -> io::Result<()> {
+        use std::{io::Write, os::unix::net::UnixStream};
+        let mut monitor = UnixStream::connect(&self.monitor_socket)?;
+        monitor.write_all(b"system_reset\n")
+    }
+
+    #[cfg(windows)]
+    pub fn reset(&mut self) -> io::Result<()> {
+        Err(IoError::new(ErrorKind::Other, "QEMU integration is only supported on Unix"))
+    }
+}
+
+impl Drop for Qemu {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_file(&self.serial_socket);
+        let _ = fs::remove_file(&self.monitor_socket);
+    }
+}
@@ -0,0 +1,250 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::{
+    io::{self, Write},
+    mem,
+    sync::{
+        Arc,
+        Condvar,
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread::{self, JoinHandle},
+};
+
+// Enough for several seconds of output at the fastest common baud rates.
+pub const DEFAULT_QUEUE_BYTES: usize = 256 * 1024;
+
+struct Queue {
+    chunks: Vec<Vec<u8>>,
+    bytes: usize,
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    changed: Condvar,
+}
+
+// Hands writes to a background thread so a slow terminal or sink can't
+// stall reading from the port. Writes are queued a line (or a flush) at a
+// time; when more than `limit` bytes are waiting, whole lines are dropped
+// instead, and a "[N bytes dropped]" marker takes their place once the
+// writer catches up.  A blocking one waits for room instead.
+pub(crate) struct QueuedWriter {
+    shared: Arc<Shared>,
+    pending: Vec<u8>,
+    limit: usize,
+    blocking: bool,
+    line_ending: &'static str,
+    unreported: u64,
+    dropped: Arc<AtomicU64>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl QueuedWriter {
+    pub fn new<W: Write + Send + 'static>(mut inner: W, limit: usize, line_ending: &'static str) -> Self {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                chunks: Vec::new(),
+                bytes: 0,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let thread_shared = Arc::clone(&shared);
+        let thread = thread::spawn(move || loop {
+            let chunks = {
+                let mut queue = thread_shared.queue.lock().expect("Output queue lock poisoned");
+                while queue.chunks.is_empty() && !queue.closed {
+                    queue = thread_shared.changed.wait(queue).expect("Output queue lock poisoned");
+                }
+                if queue.chunks.is_empty() {
+                    break;
+                }
+                mem::take(&mut queue.chunks)
+            };
+
+            let mut written = 0;
+            for chunk in chunks.iter() {
+                // A hiccup writing the output shouldn't stop the monitor.
+                let _ = inner.write_all(chunk);
+                written += chunk.len();
+            }
+            let _ = inner.flush();
+
+            let mut queue = thread_shared.queue.lock().expect("Output queue lock poisoned");
+            queue.bytes -= written;
+            thread_shared.changed.notify_all();
+        });
+
+        Self {
+            shared,
+            pending: Vec::new(),
+            limit,
+            blocking: false,
+            line_ending,
+            unreported: 0,
+            dropped: Arc::new(AtomicU64::new(0)),
+            thread: Some(thread),
+        }
+    }
+
+    // For outputs that have to be complete, like log files, where it's
+    // better for the monitor to fall behind than to lose lines.
+    pub fn blocking<W: Write + Send + 'static>(inner: W, limit: usize, line_ending: &'static str) -> Self {
+        let mut writer = Self::new(inner, limit, line_ending);
+        writer.blocking = true;
+        writer
+    }
+
+    // Total bytes dropped so far, shared so it can be read after the
+    // writer has been handed off.
+    pub fn dropped(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.dropped)
+    }
+
+    // Blocks until everything queued so far has been written, e.g. before
+    // printing directly to the terminal or handing it to another process.
+    pub fn drain(&mut self) {
+        self.submit();
+        let mut queue = self.shared.queue.lock().expect("Output queue lock poisoned");
+        while queue.bytes > 0 {
+            queue = self.shared.changed.wait(queue).expect("Output queue lock poisoned");
+        }
+    }
+
+    fn submit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let mut queue = self.shared.queue.lock().expect("Output queue lock poisoned");
+        if self.blocking {
+            // Something bigger than the whole queue goes once it's empty.
+            while queue.bytes > 0 && queue.bytes + self.pending.len() > self.limit {
+                queue = self.shared.changed.wait(queue).expect("Output queue lock poisoned");
+            }
+        } else if queue.bytes + self.pending.len() > self.limit {
+            self.unreported += self.pending.len() as u64;
+            self.dropped.fetch_add(self.pending.len() as u64, Ordering::Relaxed);
+            self.pending.clear();
+            return;
+        }
+
+        if self.unreported > 0 {
+            let marker = marker(self.unreported, self.line_ending);
+            queue.bytes += marker.len();
+            queue.chunks.push(marker);
+            self.unreported = 0;
+        }
+        queue.bytes += self.pending.len();
+        queue.chunks.push(mem::take(&mut self.pending));
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Write for QueuedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        if self.pending.ends_with(b"\n") || self.pending.len() >= self.limit {
+            self.submit();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.submit();
+        Ok(())
+    }
+}
+
+impl Drop for QueuedWriter {
+    fn drop(&mut self) {
+        self.submit();
+        let mut queue = self.shared.queue.lock().expect("Output queue lock poisoned");
+        if self.unreported > 0 {
+            let marker = marker(self.unreported, self.line_ending);
+            queue.bytes += marker.len();
+            queue.chunks.push(marker);
+        }
+        queue.closed = true;
+        drop(queue);
+        self.shared.changed.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn marker(dropped: u64, line_ending: &str) -> Vec<u8> {
+    format!("[{} bytes dropped]{}", dropped, line_ending).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Takes its time over each write, like a slow disk or peer.
+    #[derive(Clone, Default)]
+    struct SlowWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SlowWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(5));
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_lines(mut writer: QueuedWriter) -> u64 {
+        for index in 0..20 {
+            writeln!(writer, "line {:02}", index).unwrap();
+        }
+        let dropped = writer.dropped();
+        drop(writer);
+        dropped.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn drops_lines_when_full() {
+        let output = SlowWriter::default();
+        let dropped = write_lines(QueuedWriter::new(output.clone(), 32, "\n"));
+        let written = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(dropped > 0);
+        assert!(written.starts_with("line 00\n"), "{}", written);
+        assert!(written.contains(&format!("[{} bytes dropped]\n", dropped)), "{}", written);
+    }
+
+    #[test]
+    fn blocking_writers_keep_every_line() {
+        let output = SlowWriter::default();
+        let dropped = write_lines(QueuedWriter::blocking(output.clone(), 32, "\n"));
+        let written = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let expected = (0..20).map(|index| format!("line {:02}\n", index)).collect::<String>();
+        assert_eq!(dropped, 0);
+        assert_eq!(written, expected);
+    }
+}
@@ -0,0 +1,137 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    convert::TryInto,
+    ffi::OsStr,
+    fs::File,
+    io::{self, BufReader, Error as IoError, ErrorKind, Read, Write},
+    time::{Duration, Instant},
+};
+
+const MAGIC: &[u8; 8] = b"ESPMREC1";
+
+// Captures raw serial data as it arrives, so a session can be replayed
+// through the decoders later.  Each chunk is stored as a little-endian u64
+// of milliseconds since the start, a u32 length, and the bytes themselves.
+pub(crate) struct Recorder {
+    file: File,
+    started_at: Instant,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<OsStr>>(path: P) -> io::Result<Self> {
+        let mut file = File::create(path.as_ref())?;
+        file.write_all(MAGIC)?;
+        Ok(Self {
+            file,
+            started_at: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut chunk = Vec::with_capacity(data.len() + 12);
+        chunk.extend_from_slice(&(self.started_at.elapsed().as_millis() as u64).to_le_bytes());
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        self.file.write_all(&chunk)
+    }
+}
+
+pub(crate) struct Recording {
+    reader: BufReader<File>,
+}
+
+impl Recording {
+    pub fn open<P: AsRef<OsStr>>(path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path.as_ref())?);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(IoError::new(ErrorKind::InvalidData, "Not an espmonitor recording"));
+        }
+        Ok(Self { reader })
+    }
+
+    // Returns the chunk's offset from the start of the recording, or None
+    // at the end.
+    pub fn next_chunk(&mut self) -> io::Result<Option<(Duration, Vec<u8>)>> {
+        let mut header = [0u8; 12];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+
+        let millis = u64::from_le_bytes(header[0..8].try_into().expect("slice is 8 bytes"));
+        let len = u32::from_le_bytes(header[8..12].try_into().expect("slice is 4 bytes"));
+        // A recording cut short by a crash or CTRL+C may end mid-chunk;
+        // that's still worth replaying up to that point.  Reading only
+        // what's there also keeps a garbage length from allocating 4 GiB.
+        let mut data = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut data)?;
+        if data.is_empty() && len > 0 {
+            return Ok(None);
+        }
+        Ok(Some((Duration::from_millis(millis), data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, path::PathBuf, process};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("espmonitor-{}-{}.rec", name, process::id()))
+    }
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let path = temp_path("roundtrip");
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record(b"boot\r\n").unwrap();
+        recorder.record(b"").unwrap();
+        recorder.record(b"ready\r\n").unwrap();
+        drop(recorder);
+
+        let mut recording = Recording::open(&path).unwrap();
+        let mut chunks = Vec::new();
+        while let Some((_, data)) = recording.next_chunk().unwrap() {
+            chunks.push(data);
+        }
+        fs::remove_file(&path).unwrap();
+        assert_eq!(chunks, vec![b"boot\r\n".to_vec(), Vec::new(), b"ready\r\n".to_vec()]);
+    }
+
+    #[test]
+    fn replays_a_truncated_chunk() {
+        let path = temp_path("truncated");
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record(b"boot\r\n").unwrap();
+        recorder.record(b"Guru Meditation Error").unwrap();
+        drop(recorder);
+        let len = fs::metadata(&path).unwrap().len();
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 11).unwrap();
+
+        let mut recording = Recording::open(&path).unwrap();
+        assert_eq!(recording.next_chunk().unwrap().unwrap().1, b"boot\r\n");
+        assert_eq!(recording.next_chunk().unwrap().unwrap().1, b"Guru Medit");
+        assert_eq!(recording.next_chunk().unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
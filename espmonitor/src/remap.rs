@@ -0,0 +1,72 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::Location;
+use std::{
+    convert::TryFrom,
+    io::{Error as IoError, ErrorKind},
+};
+
+// Replaces the start of decoded file names, since ESP-IDF and Docker builds
+// bake in paths from the machine or container they ran in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathRemap {
+    from: String,
+    to: String,
+}
+
+impl PathRemap {
+    fn apply(&self, path: &str) -> Option<String> {
+        let rest = path.strip_prefix(self.from.as_str())?;
+        // "/build" shouldn't match "/buildroot/..."
+        let at_boundary = rest.is_empty()
+            || self.from.ends_with(&['/', '\\'][..])
+            || rest.starts_with(&['/', '\\'][..]);
+        if at_boundary {
+            Some(format!("{}{}", self.to, rest))
+        } else {
+            None
+        }
+    }
+}
+
+impl TryFrom<&str> for PathRemap {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.split_once('=') {
+            Some((from, to)) if !from.is_empty() => Ok(PathRemap {
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' should be FROM=TO", value))),
+        }
+    }
+}
+
+// The first remap that matches wins.
+pub(crate) fn remap_location(remaps: &[PathRemap], mut location: Location) -> Location {
+    if let Some(file) = location.file.as_deref() {
+        if let Some(remapped) = remaps.iter().find_map(|remap| remap.apply(file)) {
+            location.file = Some(remapped);
+        }
+    }
+    location.inlined_into = location.inlined_into
+        .into_iter()
+        .map(|caller| remap_location(remaps, caller))
+        .collect();
+    location
+}
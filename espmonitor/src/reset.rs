@@ -0,0 +1,192 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{Chip, SerialSource};
+use std::{
+    convert::TryFrom,
+    io::{self, Error as IoError, ErrorKind},
+    thread,
+    time::Duration,
+};
+
+const CLASSIC_RESET_DELAY: Duration = Duration::from_millis(100);
+const USB_JTAG_RESET_DELAY: Duration = Duration::from_millis(200);
+const BOOTLOADER_HOLD_DELAY: Duration = Duration::from_millis(50);
+const MAX_WAIT_SECS: f64 = 60.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetStep {
+    Dtr(bool),
+    Rts(bool),
+    Wait(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResetMode {
+    // EN wired to RTS and GPIO0 to DTR through the usual two-transistor
+    // auto-reset circuit found on most devkits.
+    Classic,
+    // The built-in USB-Serial-JTAG peripheral, which needs longer pulses.
+    UsbJtag,
+    None,
+    Custom(Vec<ResetStep>),
+}
+
+impl ResetMode {
+    pub fn default_for(chip: Chip) -> Self {
+        if chip.spec().usb_jtag {
+            ResetMode::UsbJtag
+        } else {
+            ResetMode::Classic
+        }
+    }
+
+    // Parses a sequence in the same format as esptool's custom reset
+    // sequences, e.g. "D0|R1|W0.1|R0": D/R set DTR/RTS, W waits N seconds.
+    pub fn custom(sequence: &str) -> Result<Self, IoError> {
+        let invalid = |step: &str| IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid reset step (expected D0, D1, R0, R1, or W<seconds>)", step));
+
+        sequence
+            .split('|')
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+            .map(|step| {
+                match (step.get(..1).unwrap_or(""), step.get(1..).unwrap_or("")) {
+                    ("D", "0") => Ok(ResetStep::Dtr(false)),
+                    ("D", "1") => Ok(ResetStep::Dtr(true)),
+                    ("R", "0") => Ok(ResetStep::Rts(false)),
+                    ("R", "1") => Ok(ResetStep::Rts(true)),
+                    ("W", secs) => secs
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|secs| secs.is_finite() && *secs >= 0.0 && *secs <= MAX_WAIT_SECS)
+                        .map(|secs| ResetStep::Wait(Duration::from_secs_f64(secs)))
+                        .ok_or_else(|| invalid(step)),
+                    _ => Err(invalid(step)),
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|steps| if steps.is_empty() {
+                Err(IoError::new(ErrorKind::InvalidInput, "Custom reset sequence is empty"))
+            } else {
+                Ok(ResetMode::Custom(steps))
+            })
+    }
+
+    pub fn steps(&self, delay: Option<Duration>) -> Vec<ResetStep> {
+        match self {
+            ResetMode::Classic => vec![
+                ResetStep::Dtr(false),
+                ResetStep::Rts(true),
+                ResetStep::Wait(delay.unwrap_or(CLASSIC_RESET_DELAY)),
+                ResetStep::Rts(false),
+            ],
+            ResetMode::UsbJtag => vec![
+                ResetStep::Dtr(false),
+                ResetStep::Rts(true),
+                ResetStep::Wait(delay.unwrap_or(USB_JTAG_RESET_DELAY)),
+                // Some drivers only propagate RTS changes along with DTR.
+                ResetStep::Dtr(false),
+                ResetStep::Rts(false),
+                ResetStep::Wait(delay.unwrap_or(USB_JTAG_RESET_DELAY)),
+            ],
+            ResetMode::None => Vec::new(),
+            ResetMode::Custom(steps) => steps.clone(),
+        }
+    }
+
+    // Holds GPIO0 (DTR) low while releasing EN (RTS) so the ROM starts the
+    // serial download mode instead of the application.
+    pub fn bootloader_steps(&self, delay: Option<Duration>) -> Vec<ResetStep> {
+        match self {
+            ResetMode::UsbJtag => vec![
+                ResetStep::Rts(false),
+                ResetStep::Dtr(false),
+                ResetStep::Wait(delay.unwrap_or(CLASSIC_RESET_DELAY)),
+                ResetStep::Dtr(true),
+                ResetStep::Rts(false),
+                ResetStep::Wait(delay.unwrap_or(CLASSIC_RESET_DELAY)),
+                ResetStep::Rts(true),
+                ResetStep::Dtr(false),
+                ResetStep::Rts(true),
+                ResetStep::Wait(delay.unwrap_or(CLASSIC_RESET_DELAY)),
+                ResetStep::Dtr(false),
+                ResetStep::Rts(false),
+            ],
+            ResetMode::None => Vec::new(),
+            ResetMode::Classic | ResetMode::Custom(_) => vec![
+                ResetStep::Dtr(false),
+                ResetStep::Rts(true),
+                ResetStep::Wait(delay.unwrap_or(CLASSIC_RESET_DELAY)),
+                ResetStep::Dtr(true),
+                ResetStep::Rts(false),
+                ResetStep::Wait(BOOTLOADER_HOLD_DELAY),
+                ResetStep::Dtr(false),
+            ],
+        }
+    }
+}
+
+impl TryFrom<&str> for ResetMode {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "classic" => Ok(ResetMode::Classic),
+            "usb-jtag" | "usbjtag" => Ok(ResetMode::UsbJtag),
+            "none" => Ok(ResetMode::None),
+            "custom" => Err(IoError::new(ErrorKind::InvalidInput, "The custom reset mode requires --reset-sequence")),
+            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid reset mode", value))),
+        }
+    }
+}
+
+pub(crate) fn run_steps(dev: &mut dyn SerialSource, steps: &[ResetStep]) -> io::Result<()> {
+    for step in steps {
+        match *step {
+            ResetStep::Dtr(level) => dev.set_dtr(level)?,
+            ResetStep::Rts(level) => dev.set_rts(level)?,
+            ResetStep::Wait(duration) => thread::sleep(duration),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_custom_sequences() {
+        assert_eq!(
+            ResetMode::custom("D0| R1 |W0.1|R0|").unwrap(),
+            ResetMode::Custom(vec![
+                ResetStep::Dtr(false),
+                ResetStep::Rts(true),
+                ResetStep::Wait(Duration::from_millis(100)),
+                ResetStep::Rts(false),
+            ]),
+        );
+    }
+
+    #[test]
+    fn rejects_bad_custom_sequences() {
+        for sequence in &["", " | ", "D2", "X1", "R", "W", "W-1", "Winf", "WNaN", "W1e30", "D0|é"] {
+            let err = ResetMode::custom(sequence).err().unwrap_or_else(|| panic!("'{}' parsed", sequence));
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
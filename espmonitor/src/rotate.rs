@@ -0,0 +1,203 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use std::{
+    convert::TryFrom,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Error as IoError, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_LOG_KEEP: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RotateWhen {
+    Size(u64),
+    Interval(Duration),
+}
+
+// Sizes are "10M", "512K", "1GB" and so on; intervals are "30min", "6h",
+// "1d" or "90s".  (A bare "m" would be ambiguous.)
+impl TryFrom<&str> for RotateWhen {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let invalid = || IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid log rotation size (e.g. 10M) or interval (e.g. 6h)", value));
+        let digits = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+        let count = value[..digits].parse::<u64>().map_err(|_| invalid())?;
+        let unit = &value[digits..];
+        let scaled = |factor: u64| count.checked_mul(factor).ok_or_else(invalid);
+        let when = match unit {
+            "s" => RotateWhen::Interval(Duration::from_secs(count)),
+            "min" => RotateWhen::Interval(Duration::from_secs(scaled(60)?)),
+            "h" => RotateWhen::Interval(Duration::from_secs(scaled(60 * 60)?)),
+            "d" => RotateWhen::Interval(Duration::from_secs(scaled(24 * 60 * 60)?)),
+            _ => match unit.to_ascii_lowercase().trim_end_matches('b') {
+                "" => RotateWhen::Size(count),
+                "k" => RotateWhen::Size(scaled(1 << 10)?),
+                "m" => RotateWhen::Size(scaled(1 << 20)?),
+                "g" => RotateWhen::Size(scaled(1 << 30)?),
+                _ => return Err(invalid()),
+            },
+        };
+        match when {
+            RotateWhen::Size(0) => Err(invalid()),
+            RotateWhen::Interval(interval) if interval == Duration::ZERO => Err(invalid()),
+            when => Ok(when),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRotation {
+    pub when: RotateWhen,
+    // Rotated files to keep, as LOG.1 (the newest) to LOG.<keep>.
+    pub keep: usize,
+    pub gzip: bool,
+}
+
+// Appends to a log file, moving it aside once it's big or old enough.
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    rotation: LogRotation,
+    file: File,
+    written: u64,
+    opened_at: Instant,
+}
+
+impl RotatingFile {
+    pub fn open(path: &OsString, rotation: LogRotation) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        let file = append(&path)?;
+        // Picking up where an earlier session left off.
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            rotation,
+            file,
+            written,
+            opened_at: Instant::now(),
+        })
+    }
+
+    fn is_due(&self) -> bool {
+        match self.rotation.when {
+            RotateWhen::Size(size) => self.written >= size,
+            RotateWhen::Interval(interval) => self.opened_at.elapsed() >= interval,
+        }
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        if self.rotation.gzip {
+            name.push(".gz");
+        }
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.rotation.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.rotation.keep));
+            for index in (1..self.rotation.keep).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            if self.rotation.gzip {
+                compress(&self.path, &self.rotated_path(1))?;
+                fs::remove_file(&self.path)?;
+            } else {
+                fs::rename(&self.path, self.rotated_path(1))?;
+            }
+        }
+
+        self.file = append(&self.path)?;
+        self.written = 0;
+        self.opened_at = Instant::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_due() {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(feature = "gzip")]
+fn compress(from: &Path, to: &Path) -> io::Result<()> {
+    use flate2::{Compression, write::GzEncoder};
+
+    let mut encoder = GzEncoder::new(File::create(to)?, Compression::default());
+    io::copy(&mut File::open(from)?, &mut encoder)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "gzip"))]
+fn compress(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(IoError::new(ErrorKind::Other, "This build of espmonitor can't compress logs (rebuild with the 'gzip' feature)"))
+}
+
+pub(crate) fn is_gzip_supported() -> bool {
+    cfg!(feature = "gzip")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes_and_intervals() {
+        assert_eq!(RotateWhen::try_from("1000").unwrap(), RotateWhen::Size(1000));
+        assert_eq!(RotateWhen::try_from("512K").unwrap(), RotateWhen::Size(512 * 1024));
+        assert_eq!(RotateWhen::try_from("10mb").unwrap(), RotateWhen::Size(10 * 1024 * 1024));
+        assert_eq!(RotateWhen::try_from("1GB").unwrap(), RotateWhen::Size(1 << 30));
+        assert_eq!(RotateWhen::try_from("90s").unwrap(), RotateWhen::Interval(Duration::from_secs(90)));
+        assert_eq!(RotateWhen::try_from("30min").unwrap(), RotateWhen::Interval(Duration::from_secs(30 * 60)));
+        assert_eq!(RotateWhen::try_from("6h").unwrap(), RotateWhen::Interval(Duration::from_secs(6 * 60 * 60)));
+        assert_eq!(RotateWhen::try_from("1d").unwrap(), RotateWhen::Interval(Duration::from_secs(24 * 60 * 60)));
+    }
+
+    #[test]
+    fn rejects_bad_rotations() {
+        for value in &["", "M", "0", "0h", "10x", "-1M", "1.5G", "6 h", "18446744073709551615G", "18446744073709551615d", "99999999999999999999"] {
+            let err = RotateWhen::try_from(*value).err().unwrap_or_else(|| panic!("'{}' parsed", value));
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,351 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::{
+    SerialSource,
+    console::unescape,
+    idflog::strip_ansi,
+    junit::{Outcome, TestCase},
+};
+use regex::Regex;
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Error as IoError, ErrorKind},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Longer than any script should need, but short of what overflows a
+// Duration.
+const MAX_SECS: f64 = u32::MAX as f64;
+// Lines kept for the next expect while the script is busy with something
+// else, e.g. a long sleep.
+const MAX_PENDING_LINES: usize = 1000;
+
+// One line of a --script file:
+//
+//   expect REGEX    wait for a line matching REGEX; the script fails if
+//                   none arrives within the timeout
+//   timeout SECS    how long later expects wait (10 seconds to start with)
+//   send TEXT       send TEXT (\r, \n, \t and \xHH escapes work)
+//   sendline TEXT   send TEXT followed by \r\n
+//   sleep SECS      pause
+//   reset           reset the chip
+//   fail REGEX      fail as soon as a line matches REGEX from here on
+//
+// Blank lines and lines starting with '#' are skipped.
+#[derive(Debug)]
+enum Step {
+    Expect(Regex),
+    Timeout(Duration),
+    Send(Vec<u8>),
+    Sleep(Duration),
+    Reset,
+    Fail(Regex),
+}
+
+#[derive(Clone)]
+pub(crate) enum ScriptStatus {
+    Running,
+    // The next step resets the chip, which the caller knows how to do for
+    // the port at hand; step() carries on after it when called again.
+    Reset,
+    Passed,
+    Failed { line: usize, message: String },
+}
+
+pub(crate) struct Script {
+    path: String,
+    // Each step with its line number in the file.
+    steps: Vec<(usize, Step)>,
+    next: usize,
+    timeout: Duration,
+    fail_on: Vec<(usize, Regex)>,
+    // When the current expect or sleep started.
+    started_at: Instant,
+    // Lines that arrived since the last expect looked, oldest first. A
+    // single read can hold the lines for several expects in a row.
+    lines: VecDeque<String>,
+    failed: Option<ScriptStatus>,
+    // How each expect and fail went, for --junit.
+    results: Vec<TestCase>,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut script = Self::parse(&text).map_err(|err| IoError::new(ErrorKind::InvalidInput, format!("{}:{}", path.display(), err)))?;
+        script.path = path.display().to_string();
+        Ok(script)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, IoError> {
+        let steps = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(number, line)| {
+                parse_step(line)
+                    .map(|step| (number, step))
+                    .map_err(|err| IoError::new(ErrorKind::InvalidInput, format!("{}: {}", number, err)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            path: String::new(),
+            steps,
+            next: 0,
+            timeout: DEFAULT_EXPECT_TIMEOUT,
+            fail_on: Vec::new(),
+            started_at: Instant::now(),
+            lines: VecDeque::new(),
+            failed: None,
+            results: Vec::new(),
+        })
+    }
+
+    // Starts the first step's clock, once the device is being monitored.
+    pub fn start(&mut self) {
+        self.started_at = Instant::now();
+    }
+
+    // Called with every line from the device.
+    pub fn check_line(&mut self, line: &str) {
+        if self.failed.is_some() {
+            return;
+        }
+        let plain = strip_ansi(line);
+        if let Some((number, re)) = self.fail_on.iter().find(|(_, re)| re.is_match(&plain)).cloned() {
+            let message = format!("'{}' matched '{}'", plain.trim_end(), re);
+            self.record(number, format!("fail {}", re), Outcome::Failed(message.clone()));
+            self.failed = Some(ScriptStatus::Failed { line: number, message });
+        } else {
+            if self.lines.len() == MAX_PENDING_LINES {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(plain.into_owned());
+        }
+    }
+
+    // Runs steps until one has to wait for the device or the clock, or
+    // the chip needs resetting.
+    pub fn step(&mut self, dev: &mut dyn SerialSource) -> io::Result<ScriptStatus> {
+        loop {
+            if let Some(failed) = self.failed.as_ref() {
+                return Ok(failed.clone());
+            }
+            let (number, step) = match self.steps.get(self.next) {
+                Some((number, step)) => (*number, step),
+                None => return Ok(ScriptStatus::Passed),
+            };
+            match step {
+                Step::Expect(re) => {
+                    let name = format!("expect {}", re);
+                    let matched = self.lines.iter().position(|line| re.is_match(line));
+                    // Later expects only get to see what came after the match.
+                    match matched {
+                        Some(pos) => drop(self.lines.drain(..=pos)),
+                        None => self.lines.clear(),
+                    }
+                    if matched.is_none() {
+                        if self.started_at.elapsed() < self.timeout {
+                            return Ok(ScriptStatus::Running);
+                        }
+                        let message = format!("Nothing matched '{}' within {}s", re, self.timeout.as_secs_f64());
+                        self.record(number, name, Outcome::Failed(message.clone()));
+                        self.failed = Some(ScriptStatus::Failed { line: number, message });
+                        self.next += 1;
+                        continue;
+                    }
+                    self.record(number, name, Outcome::Passed);
+                },
+                Step::Timeout(timeout) => self.timeout = *timeout,
+                // Anything printed before now can't be a reply.
+                Step::Send(data) => {
+                    dev.send(data)?;
+                    self.lines.clear();
+                },
+                Step::Sleep(duration) => if self.started_at.elapsed() < *duration {
+                    return Ok(ScriptStatus::Running);
+                },
+                Step::Reset => {
+                    self.next += 1;
+                    self.started_at = Instant::now();
+                    self.lines.clear();
+                    return Ok(ScriptStatus::Reset);
+                },
+                Step::Fail(re) => self.fail_on.push((number, re.clone())),
+            }
+            self.next += 1;
+            self.started_at = Instant::now();
+        }
+    }
+
+    fn record(&mut self, number: usize, name: String, outcome: Outcome) {
+        self.results.push(TestCase {
+            name: format!("line {}: {}", number, name),
+            classname: self.path.clone(),
+            time: self.started_at.elapsed(),
+            outcome,
+            location: Some((self.path.clone(), number as u32)),
+        });
+    }
+
+    // Every expect and fail so far, plus the expects the script didn't get
+    // to.
+    pub fn cases(&self) -> Vec<TestCase> {
+        let mut cases = self.results.clone();
+        for (number, step) in self.steps.iter().skip(self.next) {
+            if let Step::Expect(re) = step {
+                cases.push(TestCase {
+                    name: format!("line {}: expect {}", number, re),
+                    classname: self.path.clone(),
+                    time: Duration::ZERO,
+                    outcome: Outcome::Skipped("Not reached".to_string()),
+                    location: Some((self.path.clone(), *number as u32)),
+                });
+            }
+        }
+        cases
+    }
+}
+
+fn parse_step(line: &str) -> Result<Step, IoError> {
+    let (name, arg) = match line.find(char::is_whitespace) {
+        Some(pos) => (&line[..pos], line[pos..].trim()),
+        None => (line, ""),
+    };
+    let regex = |pattern: &str| Regex::new(pattern).map_err(|err| IoError::new(ErrorKind::InvalidInput, format!("Invalid pattern: {}", err)));
+    let secs = |value: &str| value
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite() && *secs >= 0.0 && *secs <= MAX_SECS)
+        .map(Duration::from_secs_f64)
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid number of seconds", value)));
+
+    match (name, arg) {
+        ("expect", pattern) if !pattern.is_empty() => regex(pattern).map(Step::Expect),
+        ("timeout", value) => secs(value).map(Step::Timeout),
+        ("send", text) if !text.is_empty() => unescape(text).map(Step::Send),
+        ("sendline", text) => unescape(text).map(|mut data| {
+            data.extend_from_slice(b"\r\n");
+            Step::Send(data)
+        }),
+        ("sleep", value) => secs(value).map(Step::Sleep),
+        ("reset", "") => Ok(Step::Reset),
+        ("fail", pattern) if !pattern.is_empty() => regex(pattern).map(Step::Fail),
+        _ => Err(IoError::new(ErrorKind::InvalidInput, format!("Unknown or incomplete command '{}'", line))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockPort;
+
+    fn run(script: &mut Script, dev: &mut MockPort) -> ScriptStatus {
+        loop {
+            match script.step(dev).expect("step failed") {
+                ScriptStatus::Reset => (),
+                status => return status,
+            }
+        }
+    }
+
+    #[test]
+    fn parses_steps() {
+        let script = Script::parse("# comment\n\nexpect ^ready\ntimeout 2.5\nsendline hi\\t\nsleep 0\nreset\nfail Guru").unwrap();
+        let lines = script.steps.iter().map(|(number, _)| *number).collect::<Vec<_>>();
+        assert_eq!(lines, vec![3, 4, 5, 6, 7, 8]);
+        assert!(matches!(&script.steps[1].1, Step::Timeout(timeout) if *timeout == Duration::from_millis(2500)));
+        assert!(matches!(&script.steps[2].1, Step::Send(data) if data == b"hi\t\r\n"));
+    }
+
+    #[test]
+    fn rejects_bad_steps() {
+        for text in &["expect", "expect (", "frobnicate", "sleep -1", "sleep inf", "sleep NaN", "timeout 1e30", "send \\q", "reset now"] {
+            let err = Script::parse(text).err().unwrap_or_else(|| panic!("'{}' parsed", text));
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+            assert!(err.to_string().starts_with("1: "), "{}", err);
+        }
+    }
+
+    #[test]
+    fn expects_lines_from_one_read() {
+        let mut script = Script::parse("expect ^A\nexpect ^B").unwrap();
+        let mut dev = MockPort::new();
+        script.check_line("A");
+        script.check_line("B");
+        assert!(matches!(run(&mut script, &mut dev), ScriptStatus::Passed));
+        assert!(script.cases().iter().all(|case| case.outcome == Outcome::Passed));
+    }
+
+    #[test]
+    fn expects_only_see_later_lines() {
+        let mut script = Script::parse("expect ^B\nexpect ^A\ntimeout 0").unwrap();
+        let mut dev = MockPort::new();
+        script.check_line("A");
+        script.check_line("B");
+        assert!(matches!(run(&mut script, &mut dev), ScriptStatus::Running));
+    }
+
+    #[test]
+    fn sends_and_forgets_earlier_lines() {
+        let mut script = Script::parse("timeout 0\nsendline x\nexpect ^ok").unwrap();
+        let mut dev = MockPort::new();
+        script.check_line("ok");
+        assert!(matches!(run(&mut script, &mut dev), ScriptStatus::Failed { line: 3, .. }));
+        assert_eq!(dev.sent(), b"x\r\n");
+    }
+
+    #[test]
+    fn times_out() {
+        let mut script = Script::parse("timeout 0\nexpect ^never").unwrap();
+        let mut dev = MockPort::new();
+        match run(&mut script, &mut dev) {
+            ScriptStatus::Failed { line, message } => {
+                assert_eq!(line, 2);
+                assert!(message.contains("^never"), "{}", message);
+            },
+            _ => panic!("expected a failure"),
+        }
+    }
+
+    #[test]
+    fn fails_on_pattern() {
+        let mut script = Script::parse("fail Guru\nexpect ^done").unwrap();
+        let mut dev = MockPort::new();
+        assert!(matches!(run(&mut script, &mut dev), ScriptStatus::Running));
+        script.check_line("\x1b[0;31mGuru Meditation Error\x1b[0m");
+        assert!(matches!(run(&mut script, &mut dev), ScriptStatus::Failed { line: 1, .. }));
+        let cases = script.cases();
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[1].outcome, Outcome::Skipped("Not reached".to_string()));
+    }
+
+    #[test]
+    fn asks_for_resets() {
+        let mut script = Script::parse("reset\nexpect ^boot").unwrap();
+        let mut dev = MockPort::new();
+        assert!(matches!(script.step(&mut dev).unwrap(), ScriptStatus::Reset));
+        script.check_line("boot");
+        assert!(matches!(script.step(&mut dev).unwrap(), ScriptStatus::Passed));
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{crash::timestamped_path, idflog::strip_ansi};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+pub(crate) const DEFAULT_SCROLLBACK_LINES: usize = 10_000;
+
+// The most recent lines, so something that just scrolled by can be saved
+// with CTRL+S.
+pub(crate) struct Scrollback {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl Scrollback {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, line: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        let entry = recycle(&mut self.lines, self.capacity, line);
+        self.lines.push_back(entry);
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<PathBuf> {
+        let path = timestamped_path(dir, "espmonitor", SystemTime::now(), "log");
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    pub fn save_to(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        for line in &self.lines {
+            writeln!(file, "{}", strip_ansi(line))?;
+        }
+        file.flush()
+    }
+}
+
+// Copies line into the oldest entry's allocation once lines is full, so a
+// long session doesn't allocate for every line.
+pub(crate) fn recycle(lines: &mut VecDeque<String>, capacity: usize, line: &str) -> String {
+    let mut entry = if lines.len() >= capacity { lines.pop_front().unwrap_or_default() } else { String::new() };
+    entry.clear();
+    entry.push_str(line);
+    entry
+}
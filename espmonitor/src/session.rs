@@ -0,0 +1,43 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::{AppArgs, SinkConfig, crash::timestamped_path};
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+// Gives each run its own directory under dir holding the processed log,
+// the raw capture (for espmonitor replay), crash reports and the summary,
+// so test automation has one thing to archive.  Anything given explicitly
+// still goes where it was asked to.
+pub(crate) fn prepare(args: &mut AppArgs, dir: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let session = timestamped_path(dir, "session", SystemTime::now(), "");
+    fs::create_dir(&session)?;
+
+    let path = |name: &str| session.join(name).into_os_string();
+    args.sinks.push(SinkConfig::File(path("monitor.log")));
+    args.record.get_or_insert_with(|| path("capture.rec"));
+    args.crash_dir.get_or_insert_with(|| path("crashes"));
+    args.summary.get_or_insert_with(|| path("summary.txt"));
+    args.summary_json.get_or_insert_with(|| path("summary.json"));
+    Ok(session)
+}
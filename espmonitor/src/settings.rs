@@ -0,0 +1,189 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::port::READ_TIMEOUT;
+use serial::{CharSize, FlowControl, Parity, StopBits};
+use std::{
+    convert::TryFrom,
+    io::{Error as IoError, ErrorKind},
+    time::Duration,
+};
+
+// How long a read waits for data before the monitor gets a chance to look
+// at the keyboard and flush stale partial lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Latency {
+    Low,
+    Normal,
+}
+
+impl Latency {
+    pub fn read_timeout(&self) -> Duration {
+        match self {
+            Latency::Low => Duration::from_millis(10),
+            Latency::Normal => READ_TIMEOUT,
+        }
+    }
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::Normal
+    }
+}
+
+impl TryFrom<&str> for Latency {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "low" => Ok(Latency::Low),
+            "normal" => Ok(Latency::Normal),
+            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid latency (expected low or normal)", value))),
+        }
+    }
+}
+
+// How a device reboot is marked in the terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResetBehavior {
+    // A full-width rule with the time of the reset.
+    Separator,
+    Clear,
+    Nothing,
+}
+
+impl Default for ResetBehavior {
+    fn default() -> Self {
+        ResetBehavior::Nothing
+    }
+}
+
+impl TryFrom<&str> for ResetBehavior {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "separator" => Ok(ResetBehavior::Separator),
+            "clear" => Ok(ResetBehavior::Clear),
+            "nothing" => Ok(ResetBehavior::Nothing),
+            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid reset behavior (expected separator, clear or nothing)", value))),
+        }
+    }
+}
+
+// How decoded addresses and warnings are printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Terminal,
+    // "file:line:col: severity: message", which editors (e.g. a VS Code
+    // problem matcher) can turn into clickable diagnostics.
+    Vscode,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Terminal
+    }
+}
+
+impl TryFrom<&str> for OutputFormat {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "terminal" => Ok(OutputFormat::Terminal),
+            "vscode" => Ok(OutputFormat::Vscode),
+            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid output format (expected terminal or vscode)", value))),
+        }
+    }
+}
+
+// Data bits, parity and stop bits in the usual shorthand, e.g. "8N1".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameFormat {
+    pub char_size: CharSize,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl Default for FrameFormat {
+    fn default() -> Self {
+        Self {
+            char_size: CharSize::Bits8,
+            parity: Parity::ParityNone,
+            stop_bits: StopBits::Stop1,
+        }
+    }
+}
+
+impl TryFrom<&str> for FrameFormat {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        let invalid = || IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid serial config (expected e.g. 8N1 or 7E2)", value));
+
+        let chars = value.chars().collect::<Vec<_>>();
+        if chars.len() != 3 {
+            return Err(invalid());
+        }
+
+        Ok(FrameFormat {
+            char_size: match chars[0] {
+                '5' => CharSize::Bits5,
+                '6' => CharSize::Bits6,
+                '7' => CharSize::Bits7,
+                '8' => CharSize::Bits8,
+                _ => return Err(invalid()),
+            },
+            parity: match chars[1].to_ascii_uppercase() {
+                'N' => Parity::ParityNone,
+                'E' => Parity::ParityEven,
+                'O' => Parity::ParityOdd,
+                _ => return Err(invalid()),
+            },
+            stop_bits: match chars[2] {
+                '1' => StopBits::Stop1,
+                '2' => StopBits::Stop2,
+                _ => return Err(invalid()),
+            },
+        })
+    }
+}
+
+pub fn parse_flow_control(value: &str) -> Result<FlowControl, IoError> {
+    match value {
+        "none" => Ok(FlowControl::FlowNone),
+        "software" | "xonxoff" => Ok(FlowControl::FlowSoftware),
+        "hardware" | "rtscts" => Ok(FlowControl::FlowHardware),
+        _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid flow control mode (expected none, software, or hardware)", value))),
+    }
+}
+
+// Flash offsets, usually given in hex ("0x110000").
+pub fn parse_offset(value: &str) -> Result<u64, IoError> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    };
+    parsed.map_err(|_| IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid offset", value)))
+}
+
+// "high" means asserted, matching what set_dtr()/set_rts() call it.
+pub fn parse_line_level(value: &str) -> Result<bool, IoError> {
+    match value {
+        "high" | "on" | "1" => Ok(true),
+        "low" | "off" | "0" => Ok(false),
+        _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid line level (expected high or low)", value))),
+    }
+}
@@ -0,0 +1,40 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    io::{self, ErrorKind},
+    sync::{Once, atomic::{AtomicBool, Ordering}},
+};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+
+// SIGINT/SIGTERM/SIGHUP (console control events on Windows) only set a
+// flag; the read loops check it and wind down the session themselves, so
+// buffers get flushed and the terminal gets restored.
+pub(crate) fn install_handler() -> io::Result<()> {
+    let mut result = Ok(());
+    INSTALL.call_once(|| {
+        result = ctrlc::set_handler(|| REQUESTED.store(true, Ordering::SeqCst))
+            .map_err(|err| io::Error::new(ErrorKind::Other, format!("Unable to install signal handler: {}", err)));
+    });
+    result
+}
+
+pub(crate) fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
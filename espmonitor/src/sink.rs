@@ -0,0 +1,202 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{LogRecord, LogRotation, MqttConfig, MqttSink, SyslogSink, SyslogTarget, json, syslog, queue::{DEFAULT_QUEUE_BYTES, QueuedWriter}, rotate::RotatingFile};
+use std::{
+    ffi::OsString,
+    fmt,
+    fs::OpenOptions,
+    io::{self, LineWriter, Write},
+    net::TcpStream,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// Somewhere processed lines are copied to, in addition to the terminal.
+pub trait OutputSink {
+    fn line(&mut self, line: &str, is_panic: bool) -> io::Result<()>;
+
+    // Called when the device has been quiet for a while; sinks that hold
+    // connections open can use it to keep them alive.
+    fn idle(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Called once when the session ends.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkConfig {
+    File(OsString),
+    Json(OsString),
+    Tcp(String),
+    Syslog(SyslogTarget),
+    Mqtt(MqttConfig),
+}
+
+impl SinkConfig {
+    // Log files are rotated if there's a rotation.
+    pub fn open(&self, port: &str, rotation: Option<&LogRotation>) -> io::Result<Box<dyn OutputSink>> {
+        Ok(match (self, rotation) {
+            (SinkConfig::File(path), Some(rotation)) => Box::new(WriterSink::new(queued_file(LineWriter::new(RotatingFile::open(path, rotation.clone())?)))),
+            (SinkConfig::Json(path), Some(rotation)) => Box::new(JsonSink::new(queued_file(LineWriter::new(RotatingFile::open(path, rotation.clone())?)), port)),
+            (SinkConfig::File(path), None) => Box::new(WriterSink::new(queued_file(LineWriter::new(append(path)?)))),
+            (SinkConfig::Json(path), None) => Box::new(JsonSink::new(queued_file(LineWriter::new(append(path)?)), port)),
+            (SinkConfig::Tcp(addr), _) => Box::new(WriterSink::new(queued(TcpStream::connect(addr.as_str())?))),
+            (SinkConfig::Syslog(target), _) => Box::new(SyslogLineSink::new(queued(SyslogSink::connect(*target, port)?))),
+            // Each line written is one message, so JSON records go through
+            // the same sink as --log-json.
+            (SinkConfig::Mqtt(config), _) if config.json => Box::new(JsonSink::new(queued(MqttSink::connect(config.clone())?), port)),
+            (SinkConfig::Mqtt(config), _) => Box::new(WriterSink::new(queued(MqttSink::connect(config.clone())?))),
+        })
+    }
+}
+
+impl fmt::Display for SinkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkConfig::File(path) => write!(f, "log file {}", path.to_string_lossy()),
+            SinkConfig::Json(path) => write!(f, "JSON log file {}", path.to_string_lossy()),
+            SinkConfig::Tcp(addr) => write!(f, "TCP {}", addr),
+            SinkConfig::Syslog(SyslogTarget::Syslog) => write!(f, "syslog"),
+            SinkConfig::Syslog(SyslogTarget::Journald) => write!(f, "journald"),
+            SinkConfig::Mqtt(config) => write!(f, "MQTT topic '{}' on {}:{}", config.topic, config.host, config.port),
+        }
+    }
+}
+
+// A slow peer drops lines (leaving a marker) instead of stalling the
+// monitor.
+fn queued<W: Write + Send + 'static>(writer: W) -> QueuedWriter {
+    QueuedWriter::new(writer, DEFAULT_QUEUE_BYTES, "\n")
+}
+
+// Log files are kept complete; a slow disk holds up the monitor instead.
+fn queued_file<W: Write + Send + 'static>(writer: W) -> QueuedWriter {
+    QueuedWriter::blocking(writer, DEFAULT_QUEUE_BYTES, "\n")
+}
+
+fn append(path: &OsString) -> io::Result<std::fs::File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+// Plain lines, one per line, e.g. to a file or socket.
+pub struct WriterSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> WriterSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> OutputSink for WriterSink<W> {
+    fn line(&mut self, line: &str, _is_panic: bool) -> io::Result<()> {
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.write_all(b"\n")
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// One JSON object per line, for feeding into log processors.
+pub struct JsonSink<W: Write> {
+    writer: W,
+    port: String,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(writer: W, port: &str) -> Self {
+        Self {
+            writer,
+            port: port.to_string(),
+        }
+    }
+}
+
+impl<W: Write> OutputSink for JsonSink<W> {
+    fn line(&mut self, line: &str, is_panic: bool) -> io::Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        let log_fields = LogRecord::parse(line)
+            .map(|record| format!(
+                ",\"level\":{},\"tag\":{},\"device_timestamp\":{},\"message\":{}",
+                json::string(record.level.as_str()),
+                json::string(&record.tag),
+                json::string(&record.timestamp),
+                json::string(&record.message),
+            ))
+            .unwrap_or_default();
+        writeln!(
+            self.writer,
+            "{{\"timestamp\":{:.3},\"port\":{},\"line\":{},\"panic\":{}{}}}",
+            timestamp,
+            json::string(&self.port),
+            json::string(line),
+            is_panic,
+            log_fields,
+        )
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Lets library users capture lines programmatically.
+pub struct CallbackSink<F: FnMut(&str, bool)> {
+    callback: F,
+}
+
+impl<F: FnMut(&str, bool)> CallbackSink<F> {
+    pub fn new(callback: F) -> Self {
+        Self { callback }
+    }
+}
+
+impl<F: FnMut(&str, bool)> OutputSink for CallbackSink<F> {
+    fn line(&mut self, line: &str, is_panic: bool) -> io::Result<()> {
+        (self.callback)(line, is_panic);
+        Ok(())
+    }
+}
+
+// Decides each line's severity before it goes into the queue, since the
+// panic flag doesn't survive the trip.
+struct SyslogLineSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> SyslogLineSink<W> {
+    fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> OutputSink for SyslogLineSink<W> {
+    fn line(&mut self, line: &str, is_panic: bool) -> io::Result<()> {
+        writeln!(self.writer, "{}", syslog::prefixed(line, is_panic))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+// Lines shown on each side of the one a decoded address points at.
+const CONTEXT_LINES: u32 = 2;
+
+// Reads the source files decoded addresses point into, so the code around
+// a backtrace frame can be shown without switching to an editor.
+pub(crate) struct SourceSnippets {
+    // Where to look for files whose build-time path doesn't exist here,
+    // e.g. because they were built in a container or on another machine.
+    source_path: Option<PathBuf>,
+    // None for files that couldn't be found or read.
+    files: HashMap<String, Option<Vec<String>>>,
+}
+
+impl SourceSnippets {
+    pub fn new(source_path: Option<PathBuf>) -> Self {
+        Self {
+            source_path,
+            files: HashMap::new(),
+        }
+    }
+
+    // The numbered lines around `line`.
+    pub fn around(&mut self, file: &str, line: u32) -> Vec<(u32, String)> {
+        let source_path = self.source_path.as_deref();
+        let lines = self.files
+            .entry(file.to_string())
+            .or_insert_with(|| find_file(file, source_path).and_then(|path| fs::read_to_string(path).ok()).map(|text| text.lines().map(str::to_string).collect()));
+        let lines = match lines {
+            Some(lines) if line > 0 => lines,
+            _ => return Vec::new(),
+        };
+
+        let first = line.saturating_sub(CONTEXT_LINES).max(1);
+        let last = (line + CONTEXT_LINES).min(lines.len() as u32);
+        (first..=last)
+            .map(|number| (number, lines[number as usize - 1].clone()))
+            .collect()
+    }
+}
+
+// Tries the path as it is, then ever shorter tails of it under the source
+// path: /build/project/main/app.c is looked for as main/app.c, then app.c.
+fn find_file(file: &str, source_path: Option<&Path>) -> Option<PathBuf> {
+    let path = Path::new(file);
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+
+    let source_path = source_path?;
+    let components = path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect::<Vec<_>>();
+    (0..components.len())
+        .map(|skip| components[skip..].iter().fold(source_path.to_path_buf(), |path, component| path.join(component)))
+        .find(|candidate| candidate.is_file())
+}
@@ -0,0 +1,144 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::{Duration, Instant};
+
+const DEFAULT_STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+pub(crate) struct Stats {
+    enabled: bool,
+    interval: Duration,
+    last_report_at: Instant,
+    bytes: u64,
+    lines: u64,
+    dropped_bytes: u64,
+    output_dropped_bytes: u64,
+    panics: u64,
+    resets: u64,
+    bytes_at_last_report: u64,
+    lines_at_last_report: u64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            interval: DEFAULT_STATS_INTERVAL,
+            last_report_at: Instant::now(),
+            bytes: 0,
+            lines: 0,
+            dropped_bytes: 0,
+            output_dropped_bytes: 0,
+            panics: 0,
+            resets: 0,
+            bytes_at_last_report: 0,
+            lines_at_last_report: 0,
+        }
+    }
+
+    pub fn enable_with_interval(&mut self, interval: Duration) {
+        self.enabled = true;
+        self.interval = interval;
+    }
+
+    pub fn toggle(&mut self) -> bool {
+        self.enabled = !self.enabled;
+        self.enabled
+    }
+
+    // Bytes that didn't decode as UTF-8 are a decent hint that the UART
+    // overran or the baud rate is off, so count them as (probably) dropped.
+    pub fn record_bytes(&mut self, count: usize, garbled: usize) {
+        self.bytes += count as u64;
+        self.dropped_bytes += garbled as u64;
+    }
+
+    // Bytes the terminal couldn't keep up with, as opposed to ones lost
+    // before they reached us.
+    pub fn record_output_dropped(&mut self, count: u64) {
+        self.output_dropped_bytes = count;
+    }
+
+    pub fn record_line(&mut self) {
+        self.lines += 1;
+    }
+
+    pub fn record_panic(&mut self) {
+        self.panics += 1;
+    }
+
+    pub fn record_reset(&mut self) {
+        self.resets += 1;
+    }
+
+    pub fn panic_count(&self) -> usize {
+        self.panics as usize
+    }
+
+    pub fn counts(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("bytes", self.bytes),
+            ("lines", self.lines),
+            ("dropped_bytes", self.dropped_bytes),
+            ("output_dropped_bytes", self.output_dropped_bytes),
+            ("panics", self.panics),
+            ("resets", self.resets),
+        ]
+    }
+
+    pub fn summary(&self, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs();
+        let mut summary = format!(
+            "Session lasted {}h{:02}m{:02}s: {} bytes, {} lines, ~{} bytes dropped, {} panics, {} resets",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            self.bytes,
+            self.lines,
+            self.dropped_bytes,
+            self.panics,
+            self.resets,
+        );
+        if self.output_dropped_bytes > 0 {
+            summary.push_str(&format!(" ({} bytes not shown because the terminal fell behind)", self.output_dropped_bytes));
+        }
+        summary
+    }
+
+    pub fn take_report(&mut self, force: bool) -> Option<String> {
+        let elapsed = self.last_report_at.elapsed();
+        if !self.enabled || (!force && elapsed < self.interval) {
+            return None;
+        }
+
+        let secs = elapsed.as_secs_f64().max(0.001);
+        let report = format!(
+            "[stats] {:.0} bytes/s, {:.1} lines/s, ~{} bytes dropped, {} panics, {} resets",
+            (self.bytes - self.bytes_at_last_report) as f64 / secs,
+            (self.lines - self.lines_at_last_report) as f64 / secs,
+            self.dropped_bytes,
+            self.panics,
+            self.resets,
+        );
+
+        self.last_report_at = Instant::now();
+        self.bytes_at_last_report = self.bytes;
+        self.lines_at_last_report = self.lines;
+
+        Some(report)
+    }
+}
@@ -0,0 +1,122 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crossterm::{
+    QueueableCommand,
+    cursor::{MoveTo, MoveUp, RestorePosition, SavePosition},
+    style::{Print, PrintStyledContent, Stylize},
+    terminal::{Clear, ClearType},
+};
+use std::io::{self, Write};
+
+// Keeps the bottom rows of the terminal for a pane (the plot) and a line
+// of status by limiting scrolling to the rows above them.
+pub(crate) struct StatusLine {
+    cols: u16,
+    rows: u16,
+    pane_height: u16,
+    pane: Vec<String>,
+    // Without gauges there's nothing to put in the status line.
+    show_text: bool,
+    text: String,
+}
+
+impl StatusLine {
+    pub fn enable(cols: u16, rows: u16, pane_height: u16, show_text: bool, output: &mut dyn Write) -> io::Result<Self> {
+        let status = Self {
+            cols,
+            rows,
+            pane_height,
+            pane: Vec::new(),
+            show_text,
+            text: String::new(),
+        };
+        status.reserve_rows(output)?;
+        Ok(status)
+    }
+
+    pub fn cols(&self) -> u16 {
+        self.cols
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16, output: &mut dyn Write) -> io::Result<()> {
+        self.cols = cols;
+        self.rows = rows;
+        self.reserve_rows(output)?;
+        self.draw(output)
+    }
+
+    pub fn set(&mut self, pane: Vec<String>, text: String, output: &mut dyn Write) -> io::Result<()> {
+        self.pane = pane;
+        self.text = text;
+        self.draw(output)
+    }
+
+    // Also needed after the screen has been cleared.
+    pub fn draw(&self, output: &mut dyn Write) -> io::Result<()> {
+        let top = self.rows.saturating_sub(self.height());
+        output.queue(SavePosition)?;
+        for row in 0..self.pane_height {
+            let line = self.pane.get(row as usize).map(|line| self.truncate(line)).unwrap_or_default();
+            output.queue(MoveTo(0, top + row))?;
+            output.queue(Clear(ClearType::CurrentLine))?;
+            output.queue(Print(line))?;
+        }
+        if self.show_text {
+            output.queue(MoveTo(0, top + self.pane_height))?;
+            output.queue(Clear(ClearType::CurrentLine))?;
+            output.queue(PrintStyledContent(self.truncate(&self.text).reverse()))?;
+        }
+        output.queue(RestorePosition)?;
+        output.flush()
+    }
+
+    pub fn disable(self, output: &mut dyn Write) -> io::Result<()> {
+        let top = self.rows.saturating_sub(self.height());
+        output.queue(SavePosition)?;
+        output.write_all(b"\x1b[r")?;
+        for row in top..self.rows {
+            output.queue(MoveTo(0, row))?;
+            output.queue(Clear(ClearType::CurrentLine))?;
+        }
+        output.queue(RestorePosition)?;
+        output.flush()
+    }
+
+    fn height(&self) -> u16 {
+        self.pane_height + self.show_text as u16
+    }
+
+    fn truncate(&self, line: &str) -> String {
+        line.chars().take(self.cols as usize).collect()
+    }
+
+    // Newlines and back up make sure the cursor isn't in the reserved rows
+    // (scrolling everything up if it was), and then the scrolling region
+    // is set to end above them.  Setting the region homes the cursor, hence
+    // saving and restoring it.
+    fn reserve_rows(&self, output: &mut dyn Write) -> io::Result<()> {
+        let height = self.height();
+        output.write_all("\n".repeat(height as usize).as_bytes())?;
+        output.queue(MoveUp(height))?;
+        output.queue(SavePosition)?;
+        write!(output, "\x1b[1;{}r", self.rows.saturating_sub(height).max(1))?;
+        output.queue(RestorePosition)?;
+        output.flush()
+    }
+}
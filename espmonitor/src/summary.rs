@@ -0,0 +1,126 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{Backtrace, Location, PathRemap, Symbols, crash::format_utc, json, remap::remap_location, resolve_address};
+use std::{
+    ffi::OsString,
+    time::{Duration, SystemTime},
+};
+
+// How many of a panic's frames make it into the summary.
+const TOP_FRAMES: usize = 3;
+
+// What's printed when the monitor exits, for reading after an overnight
+// soak test.
+pub(crate) struct SessionSummary<'a> {
+    pub totals: String,
+    // The same numbers, for the JSON version.
+    pub counts: Vec<(&'static str, u64)>,
+    pub elapsed: Duration,
+    // Where each gauge ended up, and its extremes.
+    pub gauges: Vec<String>,
+    pub started_at: SystemTime,
+    // The most recent panics, oldest first, and how many there were in all.
+    pub panics: &'a [Backtrace],
+    pub panic_count: usize,
+    pub logs: Vec<OsString>,
+    // Frames the async decoder never resolved for the event stream.
+    pub symbols: Option<&'a Symbols>,
+    pub remaps: &'a [PathRemap],
+}
+
+impl SessionSummary<'_> {
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![self.totals.clone()];
+        lines.extend(self.gauges.iter().map(|gauge| format!("Gauge {}", gauge)));
+
+        if !self.panics.is_empty() {
+            if self.panic_count > self.panics.len() {
+                lines.push(format!("Last {} of {} panics:", self.panics.len(), self.panic_count));
+            } else {
+                lines.push("Panics:".to_string());
+            }
+            for panic in self.panics {
+                let offset = panic.timestamp.duration_since(self.started_at).unwrap_or_default();
+                lines.push(format!("  {} {}", format_offset(offset), panic.message.trim()));
+                for frame in panic.frames.iter().take(TOP_FRAMES) {
+                    let location = frame.location.clone()
+                        .or_else(|| self.symbols.map(|symbols| remap_location(self.remaps, resolve_address(symbols, frame.address))));
+                    lines.push(format!("      0x{:08x} {}", frame.address, describe(location.as_ref())));
+                }
+            }
+        }
+
+        for log in &self.logs {
+            lines.push(format!("Output logged to {}", log.to_string_lossy()));
+        }
+        lines
+    }
+
+    // For test automation to pick up after a run.
+    pub fn json(&self) -> String {
+        let counts = self.counts.iter()
+            .map(|(name, count)| format!(",\"{}\":{}", name, count))
+            .collect::<String>();
+        let panics = self.panics.iter()
+            .map(|panic| {
+                let frames = panic.frames.iter()
+                    .map(|frame| {
+                        let location = frame.location.clone()
+                            .or_else(|| self.symbols.map(|symbols| remap_location(self.remaps, resolve_address(symbols, frame.address))));
+                        format!("{{\"address\":\"0x{:08x}\",\"location\":{}}}", frame.address, json::string(&describe(location.as_ref())))
+                    })
+                    .collect::<Vec<_>>();
+                format!(
+                    "{{\"time\":{},\"message\":{},\"frames\":[{}]}}",
+                    json::string(&format_utc(panic.timestamp)),
+                    json::string(panic.message.trim()),
+                    frames.join(","),
+                )
+            })
+            .collect::<Vec<_>>();
+        let gauges = self.gauges.iter().map(|gauge| json::string(gauge)).collect::<Vec<_>>();
+        let logs = self.logs.iter().map(|log| json::string(&log.to_string_lossy())).collect::<Vec<_>>();
+
+        format!(
+            "{{\"started_at\":{},\"duration_secs\":{:.3}{},\"gauges\":[{}],\"recent_panics\":[{}],\"logs\":[{}]}}\n",
+            json::string(&format_utc(self.started_at)),
+            self.elapsed.as_secs_f64(),
+            counts,
+            gauges.join(","),
+            panics.join(","),
+            logs.join(","),
+        )
+    }
+}
+
+fn format_offset(offset: Duration) -> String {
+    let secs = offset.as_secs();
+    format!("+{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn describe(location: Option<&Location>) -> String {
+    match location {
+        Some(location) => format!(
+            "{} at {}:{}",
+            location.function.as_deref().unwrap_or("??"),
+            location.file.as_deref().unwrap_or("??"),
+            location.line.map(|line| line.to_string()).unwrap_or_else(|| "??".to_string()),
+        ),
+        None => "??".to_string(),
+    }
+}
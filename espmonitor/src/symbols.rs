@@ -0,0 +1,243 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{AppInfo, toolchain::Addr2LineChain};
+use addr2line::{Context, FunctionName};
+use gimli::{EndianArcSlice, RunTimeEndian};
+use lazy_static::lazy_static;
+use object::{SectionKind, read::{Object, ObjectSection}};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    error::Error,
+    ffi::OsString,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+};
+
+// Rust and C++ names are demangled unless --no-demangle is given; set once
+// at startup, and read by the decoder thread too.
+static DEMANGLE: AtomicBool = AtomicBool::new(true);
+
+lazy_static! {
+    // The addr2lines asked about addresses in an ELF's code that the
+    // built-in reader couldn't place; also set once at startup.
+    static ref ADDR2LINE: Mutex<Option<Addr2LineChain>> = Mutex::new(None);
+}
+
+pub(crate) fn set_demangle(demangle: bool) {
+    DEMANGLE.store(demangle, Ordering::Relaxed);
+}
+
+pub(crate) fn set_addr2line_tools(tools: Vec<OsString>) {
+    *ADDR2LINE.lock().expect("addr2line lock poisoned") = Some(Addr2LineChain::new(tools));
+}
+
+// Everything here is owned (addr2line copies the DWARF sections into
+// Arc'd slices), so symbols can be swapped out at runtime when the binary
+// is rebuilt, and handed off to the decoder thread.
+pub struct Symbols {
+    context: Context<EndianArcSlice<RunTimeEndian>>,
+    symbol_map: Vec<(u64, String)>,
+    app_info: AppInfo,
+    code_ranges: Vec<Range<u64>>,
+    // Other images that may be running, e.g. the second-stage bootloader;
+    // addresses in their code are decoded with them instead.
+    others: Vec<Symbols>,
+    // Where the ELF was loaded from, for handing to addr2line.
+    path: Option<PathBuf>,
+    external: Mutex<HashMap<u64, Option<Location>>>,
+}
+
+impl Symbols {
+    pub fn app_info(&self) -> &AppInfo {
+        &self.app_info
+    }
+
+    pub fn add_other(&mut self, other: Symbols) {
+        self.others.push(other);
+    }
+
+    fn for_address(&self, addr: u64) -> &Symbols {
+        if self.others.is_empty() || self.contains(addr) {
+            return self;
+        }
+        self.others.iter().find(|other| other.contains(addr)).unwrap_or(self)
+    }
+
+    fn contains(&self, addr: u64) -> bool {
+        self.code_ranges.iter().any(|range| range.contains(&addr))
+    }
+
+    fn symbol_name(&self, addr: u64) -> Option<&str> {
+        let index = match self.symbol_map.binary_search_by_key(&addr, |(sym_addr, _)| *sym_addr) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        self.symbol_map.get(index).map(|(_, name)| name.as_str())
+    }
+
+    // Only for addresses in this ELF's code, and only asked once each.
+    fn external_location(&self, addr: u64) -> Option<Location> {
+        let path = self.path.as_ref().filter(|_| self.contains(addr))?;
+        let mut cache = self.external.lock().expect("addr2line cache lock poisoned");
+        cache
+            .entry(addr)
+            .or_insert_with(|| ADDR2LINE
+                .lock()
+                .expect("addr2line lock poisoned")
+                .as_mut()
+                .and_then(|chain| chain.resolve(path, addr, DEMANGLE.load(Ordering::Relaxed))))
+            .clone()
+    }
+}
+
+pub fn load_bin_context(data: &[u8]) -> Result<Symbols, Box<dyn Error + Send + Sync + 'static>> {
+    let obj = object::File::parse(data)?;
+    let endian = if obj.is_little_endian() { RunTimeEndian::Little } else { RunTimeEndian::Big };
+    let dwarf = gimli::Dwarf::load(|id| -> Result<_, gimli::Error> {
+        let data = obj
+            .section_by_name(id.name())
+            .and_then(|section| section.uncompressed_data().ok())
+            .unwrap_or(Cow::Borrowed(&[]));
+        Ok(EndianArcSlice::new(Arc::from(&*data), endian))
+    })?;
+    let context = Context::from_dwarf(dwarf)?;
+    let symbol_map = obj.symbol_map()
+        .symbols()
+        .iter()
+        .map(|sym| (sym.address(), sym.name().to_string()))
+        .collect();
+    let code_ranges = obj.sections()
+        .filter(|section| section.kind() == SectionKind::Text && section.size() > 0)
+        .map(|section| section.address()..section.address() + section.size())
+        .collect();
+    let app_info = AppInfo::from_elf(data, &obj);
+    Ok(Symbols {
+        context,
+        symbol_map,
+        app_info,
+        code_ranges,
+        others: Vec::new(),
+        path: None,
+        external: Mutex::new(HashMap::new()),
+    })
+}
+
+pub(crate) fn load_bin_file<P: AsRef<Path>>(path: P) -> Result<Symbols, Box<dyn Error + Send + Sync + 'static>> {
+    let data = fs::read(path.as_ref())?;
+    let mut symbols = load_bin_context(&data)?;
+    symbols.path = Some(path.as_ref().to_path_buf());
+    Ok(symbols)
+}
+
+// The application, plus any other images (e.g. the bootloader) whose
+// addresses should be decoded too.
+pub(crate) fn load_bin_files<P: AsRef<Path>>(app: P, others: &[OsString]) -> Result<Symbols, Box<dyn Error + Send + Sync + 'static>> {
+    let mut symbols = load_bin_file(app)?;
+    for other in others {
+        symbols.add_other(load_bin_file(other).map_err(|err| format!("{}: {}", other.to_string_lossy(), err))?);
+    }
+    Ok(symbols)
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Location {
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    // With inlining, the functions this code ended up in, innermost first,
+    // each with the line the (inlined) call is on. Empty otherwise.
+    pub inlined_into: Vec<Location>,
+}
+
+pub fn resolve_address(symbols: &Symbols, addr: u64) -> Location {
+    let symbols = symbols.for_address(addr);
+    let (file, line) = find_location(symbols, addr);
+    let location = Location {
+        function: find_function_name(symbols, addr),
+        file,
+        line,
+        inlined_into: find_inlined_callers(symbols, addr),
+    };
+    if location.file.is_none() {
+        if let Some(external) = symbols.external_location(addr) {
+            return external;
+        }
+    }
+    location
+}
+
+pub fn find_function_name(symbols: &Symbols, addr: u64) -> Option<String> {
+    let symbols = symbols.for_address(addr);
+    let demangle = DEMANGLE.load(Ordering::Relaxed);
+    symbols.context
+        .find_frames(addr)
+        .ok()
+        .and_then(|mut frames| frames.next().ok().flatten())
+        .and_then(|frame| frame.function.and_then(|f| function_name(&f, demangle)))
+        // The symbol table doesn't say what language a name is from, so
+        // guess from the mangling.
+        .or_else(|| symbols.symbol_name(addr).map(|name| if demangle {
+            addr2line::demangle_auto(Cow::Borrowed(name), None).into_owned()
+        } else {
+            name.to_string()
+        }))
+}
+
+pub fn find_inlined_callers(symbols: &Symbols, addr: u64) -> Vec<Location> {
+    let symbols = symbols.for_address(addr);
+    let demangle = DEMANGLE.load(Ordering::Relaxed);
+    let mut frames = match symbols.context.find_frames(addr) {
+        Ok(frames) => frames,
+        Err(_) => return Vec::new(),
+    };
+
+    // The first frame is the innermost function, i.e. the one the address
+    // itself is in.
+    let mut callers = Vec::new();
+    let _ = frames.next();
+    while let Ok(Some(frame)) = frames.next() {
+        callers.push(Location {
+            function: frame.function.and_then(|f| function_name(&f, demangle)),
+            file: frame.location.as_ref().and_then(|location| location.file).map(|file| file.to_string()),
+            line: frame.location.as_ref().and_then(|location| location.line),
+            inlined_into: Vec::new(),
+        });
+    }
+    callers
+}
+
+fn function_name(function: &FunctionName<EndianArcSlice<RunTimeEndian>>, demangle: bool) -> Option<String> {
+    if demangle { function.demangle().ok() } else { function.raw_name().ok() }
+        .map(|name| name.into_owned())
+}
+
+pub fn find_location(symbols: &Symbols, addr: u64) -> (Option<String>, Option<u32>) {
+    let symbols = symbols.for_address(addr);
+    symbols.context
+        .find_location(addr)
+        .ok()
+        .map(|location| (
+            location.as_ref().and_then(|location| location.file).map(|file| file.to_string()),
+            location.as_ref().and_then(|location| location.line)
+        ))
+        .unwrap_or((None, None))
+}
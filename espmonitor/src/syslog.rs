@@ -0,0 +1,174 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use crate::{LogLevel, LogRecord, idflog::strip_ansi};
+use std::io::{self, Write};
+
+#[cfg(unix)]
+const SYSLOG_SOCKET: &str = "/dev/log";
+#[cfg(unix)]
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+const FACILITY_USER: u8 = 1;
+
+const SEVERITY_CRIT: u8 = 2;
+const SEVERITY_ERR: u8 = 3;
+const SEVERITY_WARNING: u8 = 4;
+const SEVERITY_INFO: u8 = 6;
+const SEVERITY_DEBUG: u8 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyslogTarget {
+    Syslog,
+    Journald,
+}
+
+// Forwards lines to the local syslog daemon (RFC 3164 over /dev/log) or to
+// journald's native protocol, mapping ESP-IDF log levels to severities.
+// Each line written is sent as one message; a line may start with a
+// "<severity>" prefix (see `prefixed`) to override the mapping, so the
+// severity can be decided before the line is handed to a queue.
+pub struct SyslogSink {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+    target: SyslogTarget,
+    port: String,
+    pending: Vec<u8>,
+}
+
+impl SyslogSink {
+    #[cfg(unix)]
+    pub fn connect(target: SyslogTarget, port: &str) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(match target {
+            SyslogTarget::Syslog => SYSLOG_SOCKET,
+            SyslogTarget::Journald => JOURNALD_SOCKET,
+        })?;
+        Ok(Self {
+            socket,
+            target,
+            port: port.to_string(),
+            pending: Vec::new(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn connect(_target: SyslogTarget, _port: &str) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Other, "syslog and journald output are only supported on Unix"))
+    }
+
+    pub fn send(&self, line: &str, is_panic: bool) -> io::Result<()> {
+        let message = strip_ansi(line);
+        let severity = if is_panic { SEVERITY_CRIT } else { severity(&message) };
+        self.send_message(&message, severity)
+    }
+
+    fn send_message(&self, message: &str, severity: u8) -> io::Result<()> {
+        let datagram = match self.target {
+            SyslogTarget::Syslog => format!(
+                "<{}>espmonitor[{}]: {}: {}",
+                FACILITY_USER * 8 + severity,
+                std::process::id(),
+                self.port,
+                message,
+            ),
+            SyslogTarget::Journald => format!(
+                "MESSAGE={}\nPRIORITY={}\nSYSLOG_IDENTIFIER=espmonitor\nESPMONITOR_PORT={}\n",
+                message.replace('\n', " "),
+                severity,
+                self.port,
+            ),
+        };
+
+        self.send_datagram(datagram.as_bytes())
+    }
+
+    #[cfg(unix)]
+    fn send_datagram(&self, datagram: &[u8]) -> io::Result<()> {
+        self.socket.send(datagram).map(|_| ())
+    }
+
+    #[cfg(not(unix))]
+    fn send_datagram(&self, _datagram: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Write for SyslogSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        while let Some(newline) = self.pending.iter().position(|b| *b == b'\n') {
+            let line = self.pending.drain(..=newline).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            // A daemon that's gone away shouldn't stop the rest from going out.
+            let _ = match unprefixed(line) {
+                Some((severity, message)) => self.send_message(message, severity),
+                None => self.send(line, false),
+            };
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Formats a line for writing to a `SyslogSink`, with its severity decided
+// up front.
+pub(crate) fn prefixed(line: &str, is_panic: bool) -> String {
+    let message = strip_ansi(line);
+    let severity = if is_panic { SEVERITY_CRIT } else { severity(&message) };
+    format!("<{}>{}", severity, message.replace('\n', " "))
+}
+
+fn unprefixed(line: &str) -> Option<(u8, &str)> {
+    let rest = line.strip_prefix('<')?;
+    let (severity, message) = rest.split_once('>')?;
+    Some((severity.parse::<u8>().ok().filter(|severity| *severity <= SEVERITY_DEBUG)?, message))
+}
+
+fn severity(line: &str) -> u8 {
+    match LogRecord::parse(line).map(|record| record.level) {
+        Some(LogLevel::Error) => SEVERITY_ERR,
+        Some(LogLevel::Warn) => SEVERITY_WARNING,
+        Some(LogLevel::Debug) | Some(LogLevel::Verbose) => SEVERITY_DEBUG,
+        _ => SEVERITY_INFO,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_severities() {
+        assert_eq!(prefixed("\x1b[0;31mE (10) wifi: failed\x1b[0m", false), "<3>E (10) wifi: failed");
+        assert_eq!(prefixed("W (10) wifi: slow", false), "<4>W (10) wifi: slow");
+        assert_eq!(prefixed("Backtrace: 0x400d1234", true), "<2>Backtrace: 0x400d1234");
+        assert_eq!(prefixed("hello", false), "<6>hello");
+    }
+
+    #[test]
+    fn unprefixes_lines() {
+        assert_eq!(unprefixed("<2>Backtrace: 0x400d1234"), Some((2, "Backtrace: 0x400d1234")));
+        assert_eq!(unprefixed("<3><4>nested"), Some((3, "<4>nested")));
+        assert_eq!(unprefixed("[12 bytes dropped]"), None);
+        assert_eq!(unprefixed("<9>out of range"), None);
+        assert_eq!(unprefixed("<x>not a number"), None);
+    }
+}
@@ -0,0 +1,280 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::junit::{Outcome, TestCase};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::time::Instant;
+
+lazy_static! {
+    // Unity: "path/test_foo.c:42:test_foo:PASS", or FAIL/IGNORE with a
+    // message after another ':'.
+    static ref UNITY_RESULT_RE: Regex = Regex::new(r"^(.+?):(\d+):([^:]+):(PASS|FAIL|IGNORE)(?::\s*(.*))?$")
+        .expect("Failed to parse Unity result regex");
+    static ref UNITY_SUMMARY_RE: Regex = Regex::new(r"^\d+ Tests \d+ Failures \d+ Ignored")
+        .expect("Failed to parse Unity summary regex");
+    // ESP-IDF's Unity test menu, and what it prints before each test.
+    static ref UNITY_MENU_RE: Regex = Regex::new(r"^Press ENTER to see the list of tests")
+        .expect("Failed to parse Unity menu regex");
+    static ref UNITY_RUNNING_RE: Regex = Regex::new(r"^Running (.+)\.\.\.$")
+        .expect("Failed to parse Unity running regex");
+    // defmt-test and embedded-test: "(1/3) running `test_foo`...", then a
+    // panic for a failure or the next test for a pass.
+    static ref EMBEDDED_RUNNING_RE: Regex = Regex::new(r"^\(\d+/\d+\) running `([^`]+)`")
+        .expect("Failed to parse embedded-test running regex");
+    static ref EMBEDDED_DONE_RE: Regex = Regex::new(r"^all tests passed!?$")
+        .expect("Failed to parse embedded-test done regex");
+    // libtest, as printed by `cargo test` binaries built for ESP-IDF.
+    static ref LIBTEST_RESULT_RE: Regex = Regex::new(r"^test (\S+) \.\.\. (ok|FAILED|ignored)")
+        .expect("Failed to parse libtest result regex");
+    static ref LIBTEST_SUMMARY_RE: Regex = Regex::new(r"^test result: (ok|FAILED)\.")
+        .expect("Failed to parse libtest summary regex");
+}
+
+// Collects test results from an on-target test run's output.
+pub(crate) struct TestRun {
+    cases: Vec<TestCase>,
+    // The test that has started but not reported a result yet, and when it
+    // started.
+    running: Option<(String, Instant)>,
+    // When the last result came in, for timing tests that don't say when
+    // they start.
+    last_result_at: Instant,
+    finished: bool,
+    menu_answered: bool,
+    input: Option<Vec<u8>>,
+}
+
+impl TestRun {
+    pub fn new() -> Self {
+        Self {
+            cases: Vec::new(),
+            running: None,
+            last_result_at: Instant::now(),
+            finished: false,
+            menu_answered: false,
+            input: None,
+        }
+    }
+
+    pub fn line(&mut self, line: &str, is_panic: bool) {
+        if self.finished {
+            return;
+        }
+        let line = line.trim_end();
+
+        if is_panic {
+            // A crash ends the run, and fails whatever was running.
+            let name = self.running.as_ref().map(|(name, _)| name.clone()).unwrap_or_else(|| "crash".to_string());
+            self.finish_case(name, String::new(), None, Outcome::Failed(line.to_string()));
+            self.finished = true;
+        } else if let Some(captures) = UNITY_RESULT_RE.captures(line) {
+            let message = captures.get(5).map(|m| m.as_str().to_string()).unwrap_or_default();
+            let outcome = match &captures[4] {
+                "PASS" => Outcome::Passed,
+                "FAIL" => Outcome::Failed(format!("{}:{}: {}", &captures[1], &captures[2], message)),
+                _ => Outcome::Skipped(message),
+            };
+            let location = captures[2].parse().ok().map(|line| (captures[1].to_string(), line));
+            self.finish_case(captures[3].to_string(), captures[1].to_string(), location, outcome);
+        } else if UNITY_SUMMARY_RE.is_match(line) || LIBTEST_SUMMARY_RE.is_match(line) {
+            self.finished = true;
+        } else if UNITY_MENU_RE.is_match(line) && !self.menu_answered {
+            // Run everything.
+            self.menu_answered = true;
+            self.input = Some(b"*\n".to_vec());
+        } else if let Some(captures) = UNITY_RUNNING_RE.captures(line) {
+            self.running = Some((captures[1].to_string(), Instant::now()));
+        } else if let Some(captures) = EMBEDDED_RUNNING_RE.captures(line) {
+            // Getting to the next test means the last one passed.
+            if let Some((name, _)) = self.running.clone() {
+                self.finish_case(name, String::new(), None, Outcome::Passed);
+            }
+            self.running = Some((captures[1].to_string(), Instant::now()));
+        } else if EMBEDDED_DONE_RE.is_match(line) {
+            if let Some((name, _)) = self.running.clone() {
+                self.finish_case(name, String::new(), None, Outcome::Passed);
+            }
+            self.finished = true;
+        } else if let Some(captures) = LIBTEST_RESULT_RE.captures(line) {
+            let outcome = match &captures[2] {
+                "ok" => Outcome::Passed,
+                "FAILED" => Outcome::Failed(String::new()),
+                _ => Outcome::Skipped(String::new()),
+            };
+            self.finish_case(captures[1].to_string(), String::new(), None, outcome);
+        }
+    }
+
+    fn finish_case(&mut self, name: String, classname: String, location: Option<(String, u32)>, outcome: Outcome) {
+        let started_at = match self.running.take() {
+            Some((running, started_at)) if running == name => started_at,
+            _ => self.last_result_at,
+        };
+        self.last_result_at = Instant::now();
+        self.cases.push(TestCase {
+            name,
+            classname,
+            time: started_at.elapsed(),
+            outcome,
+            location,
+        });
+    }
+
+    // Something to type into the device, like the answer to a test menu.
+    pub fn take_input(&mut self) -> Option<Vec<u8>> {
+        self.input.take()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn cases(&self) -> &[TestCase] {
+        &self.cases
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &TestCase> {
+        self.cases.iter().filter(|case| matches!(case.outcome, Outcome::Failed(_)))
+    }
+
+    pub fn summary(&self) -> String {
+        let skipped = self.cases.iter().filter(|case| matches!(case.outcome, Outcome::Skipped(_))).count();
+        let failed = self.failed().count();
+        format!("{} tests: {} passed, {} failed, {} skipped", self.cases.len(), self.cases.len() - failed - skipped, failed, skipped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(lines: &[&str]) -> TestRun {
+        let mut tests = TestRun::new();
+        for line in lines {
+            tests.line(line, line.starts_with("panicked at") || line.starts_with("Guru Meditation Error"));
+        }
+        tests
+    }
+
+    fn outcomes(tests: &TestRun) -> Vec<(&str, &Outcome)> {
+        tests.cases().iter().map(|case| (case.name.as_str(), &case.outcome)).collect()
+    }
+
+    #[test]
+    fn collects_unity_results() {
+        let mut tests = TestRun::new();
+        tests.line("I (312) cpu_start: Starting scheduler on PRO CPU.", false);
+        tests.line("Press ENTER to see the list of tests.", false);
+        assert_eq!(tests.take_input().as_deref(), Some(&b"*\n"[..]));
+        // The menu comes back once the run is over; it's only answered once.
+        tests.line("Press ENTER to see the list of tests.", false);
+        assert_eq!(tests.take_input(), None);
+
+        for line in &[
+            "Running mean of an empty array is zero...",
+            "/home/user/app/components/testable/test/test_mean.c:16:mean of an empty array is zero:PASS",
+            "Running mean of a test vector...",
+            "/home/user/app/components/testable/test/test_mean.c:22:mean of a test vector:FAIL: Expected 3 Was 4",
+            "Running another test...",
+            "/home/user/app/components/testable/test/test_mean.c:30:another test:IGNORE: Not ready yet",
+            "-----------------------",
+        ] {
+            tests.line(line, false);
+        }
+        assert!(!tests.is_finished());
+        tests.line("3 Tests 1 Failures 1 Ignored ", false);
+        assert!(tests.is_finished());
+        tests.line("/home/user/app/test_late.c:1:late:PASS", false);
+
+        assert_eq!(outcomes(&tests), vec![
+            ("mean of an empty array is zero", &Outcome::Passed),
+            ("mean of a test vector", &Outcome::Failed("/home/user/app/components/testable/test/test_mean.c:22: Expected 3 Was 4".to_string())),
+            ("another test", &Outcome::Skipped("Not ready yet".to_string())),
+        ]);
+        let failed = &tests.cases()[1];
+        assert_eq!(failed.classname, "/home/user/app/components/testable/test/test_mean.c");
+        assert_eq!(failed.location, Some(("/home/user/app/components/testable/test/test_mean.c".to_string(), 22)));
+        assert_eq!(tests.summary(), "3 tests: 1 passed, 1 failed, 1 skipped");
+    }
+
+    #[test]
+    fn infers_embedded_test_passes_from_the_next_test() {
+        let tests = run(&[
+            "(1/3) running `gpio_reads_back`...",
+            "(2/3) running `timer_fires`...",
+            "(3/3) running `uart_loopback`...",
+        ]);
+        assert_eq!(outcomes(&tests), vec![("gpio_reads_back", &Outcome::Passed), ("timer_fires", &Outcome::Passed)]);
+        assert!(!tests.is_finished());
+
+        let tests = run(&[
+            "(1/3) running `gpio_reads_back`...",
+            "(2/3) running `timer_fires`...",
+            "(3/3) running `uart_loopback`...",
+            "all tests passed!",
+        ]);
+        assert_eq!(tests.cases().len(), 3);
+        assert!(tests.is_finished());
+        assert_eq!(tests.summary(), "3 tests: 3 passed, 0 failed, 0 skipped");
+    }
+
+    #[test]
+    fn fails_the_running_test_on_a_panic() {
+        let tests = run(&[
+            "(1/2) running `gpio_reads_back`...",
+            "(2/2) running `timer_fires`...",
+            "panicked at 'assertion failed: fired', tests/hil.rs:40:9",
+            "(1/2) running `gpio_reads_back`...",
+        ]);
+        assert_eq!(outcomes(&tests), vec![
+            ("gpio_reads_back", &Outcome::Passed),
+            ("timer_fires", &Outcome::Failed("panicked at 'assertion failed: fired', tests/hil.rs:40:9".to_string())),
+        ]);
+        assert!(tests.is_finished());
+
+        let tests = run(&["Guru Meditation Error: Core  0 panic'ed (LoadProhibited). Exception was unhandled."]);
+        assert_eq!(tests.cases()[0].name, "crash");
+        assert!(tests.is_finished());
+    }
+
+    #[test]
+    fn collects_libtest_results() {
+        let tests = run(&[
+            "running 3 tests",
+            "test tests::adds ... ok",
+            "test tests::divides ... FAILED",
+            "test tests::slow ... ignored, needs hardware",
+            "",
+            "failures:",
+            "---- tests::divides stdout ----",
+            "thread 'tests::divides' panicked at 'attempt to divide by zero', src/lib.rs:12:5",
+        ]);
+        assert_eq!(outcomes(&tests), vec![
+            ("tests::adds", &Outcome::Passed),
+            ("tests::divides", &Outcome::Failed(String::new())),
+            ("tests::slow", &Outcome::Skipped(String::new())),
+        ]);
+        assert!(!tests.is_finished());
+
+        let mut tests = tests;
+        tests.line("test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out; finished in 0.02s", false);
+        assert!(tests.is_finished());
+        assert_eq!(tests.failed().count(), 1);
+    }
+}
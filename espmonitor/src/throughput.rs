@@ -0,0 +1,103 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::{AppArgs, Error, handle_eof, handle_serial, new_serial_state, symbols};
+use std::{
+    io,
+    time::Instant,
+};
+
+pub(crate) const DEFAULT_THROUGHPUT_TEST_BYTES: usize = 16 * 1024 * 1024;
+
+// About what a USB-serial adapter hands over per read at high baud rates.
+const CHUNK_SIZE: usize = 4096;
+
+// The fastest baud rate in common use, to put the results in perspective.
+const REFERENCE_BAUD: f64 = 921_600.0;
+
+// Roughly what an ESP-IDF app logs: colored log lines, some with
+// addresses in them, and the occasional panic with a backtrace. The
+// addresses are made up, so with an ELF most won't resolve, but they're
+// still looked up.
+pub fn synthetic_log(len: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(len + 256);
+    // A fixed LCG rather than a random number crate, so every run (and
+    // every benchmark) sees the same data.
+    let mut seed: u32 = 0x1234_5678;
+    let mut next = move || {
+        seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        seed >> 8
+    };
+
+    let mut millis = 0u32;
+    let mut count = 0u32;
+    while data.len() < len {
+        millis += next() % 50;
+        count += 1;
+        let line = match count % 500 {
+            0 => format!(
+                "Guru Meditation Error: Core  0 panic'ed (LoadProhibited). Exception was unhandled.\r\n\
+                 PC      : 0x400d{:04x}  PS      : 0x00060030  A0      : 0x800d{:04x}  A1      : 0x3ffb{:04x}\r\n\
+                 \r\n\
+                 Backtrace:0x400d{:04x}:0x3ffb{:04x} 0x400d{:04x}:0x3ffb{:04x} 0x40081{:03x}:0x3ffb{:04x}\r\n",
+                next() & 0xfffc, next() & 0xfffc, next() & 0xfff0,
+                next() & 0xfffc, next() & 0xfff0, next() & 0xfffc, next() & 0xfff0, next() & 0xffc, next() & 0xfff0,
+            ),
+            n if n % 7 == 0 => format!("\x1b[0;33mW ({}) wifi: Task watchdog got triggered at 0x400d{:04x}\x1b[0m\r\n", millis, next() & 0xfffc),
+            n if n % 3 == 0 => format!("\x1b[0;32mI ({}) app: Free heap: {} bytes\x1b[0m\r\n", millis, 100_000 + next() % 50_000),
+            _ => format!("\x1b[0;32mI ({}) sensor: reading {} = {}.{:02}\x1b[0m\r\n", millis, count, next() % 100, next() % 100),
+        };
+        data.extend_from_slice(line.as_bytes());
+    }
+    data.truncate(len);
+    data
+}
+
+// Feeds generated output through the same processing a live device's
+// would go through with these options, and reports how fast that was.
+pub(crate) fn run(args: &AppArgs, len: usize) -> Result<(), Error> {
+    let symbols = match args.bin.as_ref() {
+        Some(bin_name) => Some(symbols::load_bin_files(bin_name, &args.extra_bins).map_err(|source| Error::Symbolication { bin: bin_name.clone(), source })?),
+        None => None,
+    };
+    let mut serial_state = new_serial_state(args, symbols)?;
+    serial_state.set_stream(false);
+
+    let data = synthetic_log(len);
+    let mut output = io::sink();
+    let started_at = Instant::now();
+    for chunk in data.chunks(CHUNK_SIZE) {
+        handle_serial(&mut serial_state, chunk, &mut output)?;
+    }
+    handle_eof(&mut serial_state, &mut output)?;
+    let secs = started_at.elapsed().as_secs_f64().max(0.001);
+
+    let lines = serial_state.stats.counts().into_iter().find(|(name, _)| *name == "lines").map(|(_, count)| count).unwrap_or(0);
+    let bytes_per_sec = data.len() as f64 / secs;
+    println!(
+        "Processed {:.1} MiB ({} lines) in {:.2}s: {:.1} MiB/s, {:.0} lines/s, {:.0}x what {} baud can deliver",
+        data.len() as f64 / (1024.0 * 1024.0),
+        lines,
+        secs,
+        bytes_per_sec / (1024.0 * 1024.0),
+        lines as f64 / secs,
+        bytes_per_sec / (REFERENCE_BAUD / 10.0),
+        REFERENCE_BAUD,
+    );
+    Ok(())
+}
@@ -0,0 +1,139 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::{Chip, Location};
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    path::Path,
+    process::Command,
+};
+
+// Tried after the toolchain's own addr2line; both read ELFs for any
+// architecture they were built with (LLVM's usually has Xtensa and RISC-V).
+pub(crate) const GENERIC_ADDR2LINES: &[&str] = &["llvm-addr2line", "addr2line"];
+
+// The addr2lines to try, in order: the given one or the toolchain's, then
+// generic ones.
+pub(crate) fn addr2line_tools(chip: Chip, prefix: Option<&str>, addr2line: Option<&OsStr>) -> Vec<OsString> {
+    let mut tools = vec![match addr2line {
+        Some(tool) => tool.to_os_string(),
+        None => OsString::from(format!("{}addr2line", prefix.unwrap_or_else(|| chip.tool_prefix()))),
+    }];
+    for tool in GENERIC_ADDR2LINES.iter().map(OsString::from) {
+        if !tools.contains(&tool) {
+            tools.push(tool);
+        }
+    }
+    tools
+}
+
+// Works through the addr2lines until one runs, and sticks with that one.
+// Those that can't be found or can't read the ELF aren't tried again.
+pub(crate) struct Addr2LineChain {
+    tools: Vec<OsString>,
+    broken: Vec<bool>,
+    working: Option<usize>,
+}
+
+impl Addr2LineChain {
+    pub fn new(tools: Vec<OsString>) -> Self {
+        Self {
+            broken: vec![false; tools.len()],
+            tools,
+            working: None,
+        }
+    }
+
+    pub fn resolve(&mut self, elf: &Path, addr: u64, demangle: bool) -> Option<Location> {
+        let order = self.working.into_iter().chain(0..self.tools.len()).collect::<Vec<_>>();
+        for index in order {
+            if self.broken[index] {
+                continue;
+            }
+            match run_addr2line(&self.tools[index], elf, addr, demangle) {
+                // It ran, so if it doesn't know, none of them will.
+                Ok(location) => {
+                    self.working = Some(index);
+                    return location;
+                },
+                Err(_) => {
+                    self.broken[index] = true;
+                    if self.working == Some(index) {
+                        self.working = None;
+                    }
+                },
+            }
+        }
+        None
+    }
+}
+
+// Asks the toolchain's addr2line about an address, for ELFs whose debug
+// info the built-in reader can't make sense of.  With -a and -i it prints
+// the address, then a function name and "file:line" pair for the function
+// the address is in, followed by one for each function it was inlined
+// into; anything unknown is "??".
+fn run_addr2line(tool: &OsStr, elf: &Path, addr: u64, demangle: bool) -> io::Result<Option<Location>> {
+    let mut command = Command::new(tool);
+    command.arg("-a").arg("-f").arg("-i");
+    if demangle {
+        command.arg("-C");
+    }
+    let output = command.arg("-e").arg(elf).arg(format!("0x{:08x}", addr)).output()?;
+    if !output.status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other, String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut frames = stdout
+        .lines()
+        .skip_while(|line| !line.starts_with("0x"))
+        .skip(1)
+        .collect::<Vec<_>>()
+        .chunks(2)
+        .map(|pair| parse_frame(pair[0], pair.get(1).copied().unwrap_or("??:0")))
+        .collect::<Vec<_>>();
+    if frames.is_empty() {
+        return Ok(None);
+    }
+
+    let mut location = frames.remove(0);
+    location.inlined_into = frames;
+    if location.function.is_none() && location.file.is_none() {
+        Ok(None)
+    } else {
+        Ok(Some(location))
+    }
+}
+
+fn parse_frame(function: &str, file_line: &str) -> Location {
+    let known = |value: &str| if value.is_empty() || value.starts_with("??") { None } else { Some(value.to_string()) };
+    // The line may be followed by e.g. " (discriminator 2)".
+    let file_line = file_line.split(" (").next().unwrap_or(file_line);
+    let (file, line) = match file_line.rsplit_once(':') {
+        Some((file, line)) => (file, line.parse::<u32>().ok().filter(|line| *line > 0)),
+        None => (file_line, None),
+    };
+    Location {
+        function: known(function),
+        file: known(file),
+        line,
+        inlined_into: Vec::new(),
+    }
+}
@@ -0,0 +1,325 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+use crate::SerialSource;
+use std::{
+    convert::TryFrom,
+    fs,
+    io::{self, Error as IoError, ErrorKind},
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_MODE: u8 = b'C';
+const PAD: u8 = 0x1a;
+
+const BLOCK_SIZE: usize = 1024;
+const HEADER_SIZE: usize = 128;
+const MAX_RETRIES: usize = 10;
+
+// How long the receiver gets to start the transfer, and then to answer
+// each block.
+const START_TIMEOUT: Duration = Duration::from_secs(30);
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Enough for firmware that reads one line at a time into a small buffer.
+const LINE_DELAY: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferMode {
+    // The file as is; --flow-control applies if the firmware needs it.
+    Raw,
+    // One line at a time, with a pause after each.
+    Lines,
+    // YMODEM batch transfer with 1K blocks and CRC-16.
+    Ymodem,
+}
+
+impl Default for TransferMode {
+    fn default() -> Self {
+        TransferMode::Raw
+    }
+}
+
+impl TryFrom<&str> for TransferMode {
+    type Error = IoError;
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "raw" => Ok(TransferMode::Raw),
+            "lines" => Ok(TransferMode::Lines),
+            "ymodem" => Ok(TransferMode::Ymodem),
+            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid transfer mode (expected raw, lines, or ymodem)", value))),
+        }
+    }
+}
+
+// Sends `path` to the device, returning how many bytes of it were sent.
+// Anything the device prints before a YMODEM transfer starts ends up in
+// `received`, so it can still be shown.
+pub(crate) fn send_file(dev: &mut dyn SerialSource, path: &Path, mode: TransferMode, received: &mut Vec<u8>) -> io::Result<usize> {
+    let data = fs::read(path)?;
+    match mode {
+        TransferMode::Raw => dev.send(&data)?,
+        TransferMode::Lines => for line in data.split_inclusive(|b| *b == b'\n') {
+            dev.send(line)?;
+            thread::sleep(LINE_DELAY);
+        },
+        TransferMode::Ymodem => {
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            send_ymodem(dev, &name, &data, received)?;
+        },
+    }
+    Ok(data.len())
+}
+
+fn send_ymodem(dev: &mut dyn SerialSource, name: &str, data: &[u8], received: &mut Vec<u8>) -> io::Result<()> {
+    wait_for_start(dev, received)?;
+
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(data.len().to_string().as_bytes());
+    header.push(0);
+    if header.len() > HEADER_SIZE {
+        return Err(IoError::new(ErrorKind::InvalidInput, format!("File name '{}' is too long for YMODEM", name)));
+    }
+    send_block(dev, SOH, 0, &header, 0)?;
+    wait_for_start(dev, &mut Vec::new())?;
+
+    for (index, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+        send_block(dev, STX, (index + 1) as u8, chunk, PAD)?;
+    }
+
+    // The first EOT is often NAKed to make sure it wasn't line noise.
+    let mut retries = 0;
+    loop {
+        dev.send(&[EOT])?;
+        match read_reply(dev, BLOCK_TIMEOUT)? {
+            ACK => break,
+            _ if retries < MAX_RETRIES => retries += 1,
+            _ => return Err(IoError::new(ErrorKind::TimedOut, "Receiver never acknowledged the end of the file")),
+        }
+    }
+
+    // An empty header ends the batch.
+    wait_for_start(dev, &mut Vec::new())?;
+    send_block(dev, SOH, 0, &[], 0)
+}
+
+// Receivers poll with 'C' until the transfer starts.  To tell that apart
+// from a 'C' in the device's output, it has to start a line or follow
+// another 'C', and be followed by another 'C', a line break or silence.
+// Everything else is passed on in `received`.
+fn wait_for_start(dev: &mut dyn SerialSource, received: &mut Vec<u8>) -> io::Result<()> {
+    let started_at = Instant::now();
+    let mut buf = [0u8; 256];
+    let mut prev = None;
+    let mut holding = false;
+    while started_at.elapsed() < START_TIMEOUT {
+        let count = match dev.read(&mut buf) {
+            Ok(0) if dev.is_disconnected() => break,
+            Ok(count) => count,
+            Err(err) if err.kind() == ErrorKind::TimedOut || err.kind() == ErrorKind::WouldBlock => 0,
+            Err(err) => return Err(err),
+        };
+
+        if count == 0 && holding {
+            drop_polls(received);
+            return Ok(());
+        }
+        for (pos, byte) in buf[..count].iter().enumerate() {
+            if holding {
+                if [CRC_MODE, b'\r', b'\n'].contains(byte) {
+                    let rest = &buf[pos..count];
+                    let polls = rest.iter().take_while(|b| **b == CRC_MODE).count();
+                    drop_polls(received);
+                    received.extend_from_slice(&rest[polls..]);
+                    return Ok(());
+                }
+                received.push(CRC_MODE);
+                holding = false;
+            }
+            if *byte == CRC_MODE && matches!(prev, None | Some(b'\r') | Some(b'\n') | Some(CRC_MODE)) {
+                holding = true;
+            } else {
+                received.push(*byte);
+            }
+            prev = Some(*byte);
+        }
+    }
+    Err(IoError::new(ErrorKind::TimedOut, "Receiver didn't start a YMODEM transfer"))
+}
+
+// The start of a run of polls went out as text before the rest of it
+// showed up.
+fn drop_polls(received: &mut Vec<u8>) {
+    let polls = received.iter().rev().take_while(|b| **b == CRC_MODE).count();
+    received.truncate(received.len() - polls);
+}
+
+fn send_block(dev: &mut dyn SerialSource, kind: u8, number: u8, data: &[u8], pad: u8) -> io::Result<()> {
+    let size = if kind == STX { BLOCK_SIZE } else { HEADER_SIZE };
+    let mut block = Vec::with_capacity(size + 5);
+    block.extend_from_slice(&[kind, number, !number]);
+    block.extend_from_slice(data);
+    block.resize(3 + size, pad);
+    let crc = crc16(&block[3..]);
+    block.extend_from_slice(&crc.to_be_bytes());
+
+    for _ in 0..MAX_RETRIES {
+        dev.send(&block)?;
+        match read_reply(dev, BLOCK_TIMEOUT)? {
+            ACK => return Ok(()),
+            CAN => return Err(IoError::new(ErrorKind::Interrupted, "Receiver cancelled the transfer")),
+            _ => (),
+        }
+    }
+    Err(IoError::new(ErrorKind::TimedOut, format!("Receiver kept rejecting block {}", number)))
+}
+
+// The first ACK, NAK or CAN, or NAK if nothing arrives in time.
+fn read_reply(dev: &mut dyn SerialSource, timeout: Duration) -> io::Result<u8> {
+    let started_at = Instant::now();
+    let mut buf = [0u8; 1];
+    while started_at.elapsed() < timeout {
+        match dev.read(&mut buf) {
+            Ok(1) if [ACK, NAK, CAN].contains(&buf[0]) => return Ok(buf[0]),
+            Ok(0) if dev.is_disconnected() => break,
+            Ok(_) => (),
+            Err(err) if err.kind() == ErrorKind::TimedOut || err.kind() == ErrorKind::WouldBlock => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(NAK)
+}
+
+// CRC-16/XMODEM.
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ (u16::from(*byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MockPort;
+
+    const QUIET: Duration = Duration::from_millis(5);
+
+    fn port() -> MockPort {
+        let mut dev = MockPort::new();
+        dev.set_timeout(Duration::from_millis(1)).unwrap();
+        dev
+    }
+
+    #[test]
+    fn computes_crc16_xmodem() {
+        assert_eq!(crc16(b""), 0);
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn frames_blocks() {
+        let mut dev = port().data([ACK]);
+        send_block(&mut dev, SOH, 0, b"name\x00", 0).unwrap();
+        let block = dev.sent();
+        assert_eq!(block.len(), 3 + HEADER_SIZE + 2);
+        assert_eq!(&block[..7], b"\x01\x00\xffname");
+        assert!(block[8..3 + HEADER_SIZE].iter().all(|b| *b == 0));
+        assert_eq!(&block[3 + HEADER_SIZE..], &crc16(&block[3..3 + HEADER_SIZE]).to_be_bytes());
+
+        let mut dev = port().data([ACK]);
+        send_block(&mut dev, STX, 0xfe, b"data", PAD).unwrap();
+        let block = dev.sent();
+        assert_eq!(block.len(), 3 + BLOCK_SIZE + 2);
+        assert_eq!(&block[..7], b"\x02\xfe\x01data");
+        assert_eq!(block[3 + BLOCK_SIZE - 1], PAD);
+    }
+
+    #[test]
+    fn resends_rejected_blocks() {
+        let mut dev = port().data([NAK]).data([ACK]);
+        send_block(&mut dev, SOH, 1, b"x", 0).unwrap();
+        assert_eq!(dev.sent().len(), 2 * (3 + HEADER_SIZE + 2));
+
+        let mut dev = port().data([CAN]);
+        assert_eq!(send_block(&mut dev, SOH, 1, b"x", 0).unwrap_err().kind(), ErrorKind::Interrupted);
+    }
+
+    #[test]
+    fn starts_on_polls() {
+        let mut received = Vec::new();
+        let mut dev = port().data("Ready\r\n").data("C").wait(QUIET);
+        wait_for_start(&mut dev, &mut received).unwrap();
+        assert_eq!(received, b"Ready\r\n");
+
+        let mut received = Vec::new();
+        let mut dev = port().data("waiting...CCCafter");
+        wait_for_start(&mut dev, &mut received).unwrap();
+        assert_eq!(received, b"waiting...after");
+    }
+
+    #[test]
+    fn ignores_c_in_text() {
+        let mut received = Vec::new();
+        let mut dev = port().data("CPU up\r\nACCESS granted\r\nabc").wait(QUIET).data("\nC\r\n");
+        wait_for_start(&mut dev, &mut received).unwrap();
+        assert_eq!(received, b"CPU up\r\nACCESS granted\r\nabc\n\r\n");
+    }
+
+    #[test]
+    fn sends_a_batch() {
+        let dir = std::env::temp_dir().join(format!("espmonitor-ymodem-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hello.txt");
+        fs::write(&path, vec![b'x'; BLOCK_SIZE + 1]).unwrap();
+
+        let mut dev = port()
+            .data("C").wait(QUIET)
+            .data([ACK]).data("C").wait(QUIET)
+            .data([ACK]).data([ACK])
+            .data([NAK]).data([ACK])
+            .data("C").wait(QUIET)
+            .data([ACK]);
+        let mut received = Vec::new();
+        assert_eq!(send_file(&mut dev, &path, TransferMode::Ymodem, &mut received).unwrap(), BLOCK_SIZE + 1);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let header = 3 + HEADER_SIZE + 2;
+        let block = 3 + BLOCK_SIZE + 2;
+        let sent = dev.sent();
+        assert_eq!(sent.len(), header + 2 * block + 2 + header);
+        assert_eq!(&sent[3..17], b"hello.txt\x001025");
+        assert_eq!(&sent[header..header + 2], &[STX, 1]);
+        assert_eq!(&sent[header + block..header + block + 2], &[STX, 2]);
+        assert_eq!(&sent[header + 2 * block..header + 2 * block + 2], &[EOT, EOT]);
+        assert_eq!(&sent[sent.len() - header..sent.len() - header + 3], &[SOH, 0, 0xff]);
+        assert!(sent[sent.len() - header + 3..sent.len() - 2].iter().all(|b| *b == 0));
+        assert!(received.is_empty());
+    }
+}
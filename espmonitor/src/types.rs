@@ -15,16 +15,23 @@
 // You should have received a copy of the GNU General Public License
 // along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
 
+use crate::{FlowControl, FrameFormat, Framing, GaugeRule, HighlightRule, Latency, LogRotation, MatchAction, OutputFormat, PathRemap, PortSelector, Protocol, ResetBehavior, ResetMode, SinkConfig, TransferMode, toolchain};
+use regex::Regex;
 use std::{
     convert::TryFrom,
     ffi::OsString,
     io::{Error as IoError, ErrorKind},
+    num::NonZeroUsize,
+    ops::Range,
+    path::Path,
+    time::Duration,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Framework {
     Baremetal,
     EspIdf,
+    Arduino,
 }
 
 impl Framework {
@@ -38,6 +45,26 @@ impl Framework {
             Err(IoError::new(ErrorKind::InvalidInput, format!("Can't figure out framework from target '{}'", target)))
         }
     }
+
+    // ESP-IDF and the Rust esp-idf templates always come with an sdkconfig
+    // (or the defaults it's generated from).
+    pub fn from_project<P: AsRef<Path>>(project_dir: P) -> Option<Self> {
+        let project_dir = project_dir.as_ref();
+        if project_dir.join("sdkconfig").is_file() || project_dir.join("sdkconfig.defaults").is_file() {
+            Some(Framework::EspIdf)
+        } else {
+            None
+        }
+    }
+
+    // Consoles run at 115200 out of the box, except the ESP8266 RTOS SDK's,
+    // which sticks to the 74880 the ROM prints its boot messages at.
+    pub fn default_speed(&self, chip: Chip) -> usize {
+        match (self, chip) {
+            (Framework::EspIdf, Chip::ESP8266) => 74_880,
+            _ => 115_200,
+        }
+    }
 }
 
 impl TryFrom<&str> for Framework {
@@ -46,6 +73,7 @@ impl TryFrom<&str> for Framework {
         match value {
             "baremetal" => Ok(Framework::Baremetal),
             "esp-idf" | "espidf" => Ok(Framework::EspIdf),
+            "arduino" => Ok(Framework::Arduino),
             _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid framework", value))),
         }
     }
@@ -57,62 +85,299 @@ impl Default for Framework {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arch {
+    Xtensa,
+    RiscV,
+}
+
+// Everything that differs between chips. Adding a chip means adding a
+// variant to Chip and an entry to CHIP_SPECS; the rest of espmonitor only
+// looks things up here.
+#[derive(Debug)]
+pub struct ChipSpec {
+    pub chip: Chip,
+    pub name: &'static str,
+    pub arch: Arch,
+    // The first part of the chip's Rust target triples.
+    pub rust_arch: &'static str,
+    // What the GCC toolchain's binaries start with, e.g. for addr2line.
+    pub tool_prefix: &'static str,
+    // Address ranges that hold executable code: ROM, IRAM, RTC/LP memory,
+    // and the flash instruction cache mapping.
+    pub code_ranges: &'static [Range<u64>],
+    // Where code executed from flash is mapped.
+    pub flash_code_range: Range<u64>,
+    // How the ROM's boot banner identifies the chip: newer ROMs print
+    // "ESP-ROM:<name>-...", older ones only their build date.
+    pub rom_banner: RomBanner,
+    // Chips with a built-in USB-Serial-JTAG peripheral are usually
+    // connected through it rather than through a USB-UART bridge.
+    pub usb_jtag: bool,
+    pub qemu: Option<QemuMachine>,
+}
+
+#[derive(Debug)]
+pub enum RomBanner {
+    Named(&'static str),
+    Dated(&'static str),
+}
+
+#[derive(Debug)]
+pub struct QemuMachine {
+    pub binary: &'static str,
+    pub args: &'static [&'static str],
+}
+
+pub const CHIP_SPECS: &[ChipSpec] = &[
+    ChipSpec {
+        chip: Chip::ESP32,
+        name: "esp32",
+        arch: Arch::Xtensa,
+        rust_arch: "xtensa",
+        tool_prefix: "xtensa-esp32-elf-",
+        code_ranges: &[
+            0x4000_0000..0x400c_2000,
+            0x400d_0000..0x4040_0000,
+        ],
+        flash_code_range: 0x400d_0000..0x4040_0000,
+        rom_banner: RomBanner::Dated("ets Jun  8 2016"),
+        usb_jtag: false,
+        qemu: Some(QemuMachine {
+            binary: "qemu-system-xtensa",
+            args: &["-machine", "esp32", "-global", "driver=timer.esp32.timg,property=wdt_disable,value=true"],
+        }),
+    },
+    ChipSpec {
+        chip: Chip::ESP32S2,
+        name: "esp32s2",
+        arch: Arch::Xtensa,
+        rust_arch: "xtensa",
+        tool_prefix: "xtensa-esp32s2-elf-",
+        code_ranges: &[
+            0x4000_0000..0x4007_2000,
+            0x4008_0000..0x4080_0000,
+        ],
+        flash_code_range: 0x4008_0000..0x4080_0000,
+        rom_banner: RomBanner::Named("esp32s2"),
+        usb_jtag: false,
+        qemu: None,
+    },
+    ChipSpec {
+        chip: Chip::ESP32S3,
+        name: "esp32s3",
+        arch: Arch::Xtensa,
+        rust_arch: "xtensa",
+        tool_prefix: "xtensa-esp32s3-elf-",
+        code_ranges: &[
+            0x4000_0000..0x4006_0000,
+            0x4037_0000..0x403e_0000,
+            0x4200_0000..0x4400_0000,
+            0x600f_e000..0x6010_0000,
+        ],
+        flash_code_range: 0x4200_0000..0x4400_0000,
+        rom_banner: RomBanner::Named("esp32s3"),
+        usb_jtag: false,
+        qemu: None,
+    },
+    ChipSpec {
+        chip: Chip::ESP32C3,
+        name: "esp32c3",
+        arch: Arch::RiscV,
+        rust_arch: "riscv32imc",
+        tool_prefix: "riscv32-esp-elf-",
+        code_ranges: &[
+            0x4000_0000..0x4006_0000,
+            0x4037_c000..0x403e_0000,
+            0x4200_0000..0x4280_0000,
+            0x5000_0000..0x5000_2000,
+        ],
+        flash_code_range: 0x4200_0000..0x4280_0000,
+        rom_banner: RomBanner::Named("esp32c3"),
+        usb_jtag: true,
+        qemu: Some(QemuMachine {
+            binary: "qemu-system-riscv32",
+            args: &["-machine", "esp32c3", "-icount", "3"],
+        }),
+    },
+    ChipSpec {
+        chip: Chip::ESP32C6,
+        name: "esp32c6",
+        arch: Arch::RiscV,
+        rust_arch: "riscv32imac",
+        tool_prefix: "riscv32-esp-elf-",
+        code_ranges: &[
+            0x4000_0000..0x4005_0000,
+            0x4080_0000..0x4088_0000,
+            0x4200_0000..0x4280_0000,
+            0x5000_0000..0x5000_4000,
+        ],
+        flash_code_range: 0x4200_0000..0x4280_0000,
+        rom_banner: RomBanner::Named("esp32c6"),
+        usb_jtag: true,
+        qemu: None,
+    },
+    ChipSpec {
+        chip: Chip::ESP32H2,
+        name: "esp32h2",
+        arch: Arch::RiscV,
+        rust_arch: "riscv32imac",
+        tool_prefix: "riscv32-esp-elf-",
+        code_ranges: &[
+            0x4000_0000..0x4002_0000,
+            0x4080_0000..0x4085_0000,
+            0x4200_0000..0x4280_0000,
+            0x5000_0000..0x5000_1000,
+        ],
+        flash_code_range: 0x4200_0000..0x4280_0000,
+        rom_banner: RomBanner::Named("esp32h2"),
+        usb_jtag: true,
+        qemu: None,
+    },
+    ChipSpec {
+        chip: Chip::ESP8266,
+        name: "esp8266",
+        arch: Arch::Xtensa,
+        rust_arch: "xtensa",
+        tool_prefix: "xtensa-lx106-elf-",
+        code_ranges: &[
+            0x4000_0000..0x4001_0000,
+            0x4010_0000..0x4010_c000,
+            0x4020_0000..0x4030_0000,
+        ],
+        flash_code_range: 0x4020_0000..0x4030_0000,
+        rom_banner: RomBanner::Dated("ets Jan  8 2013"),
+        usb_jtag: false,
+        qemu: None,
+    },
+];
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Chip {
     ESP32,
     ESP32S2,
+    ESP32S3,
     ESP8266,
     ESP32C3,
+    ESP32C6,
+    ESP32H2,
 }
 
 impl Chip {
     pub fn from_target<S: AsRef<str>>(target: S) -> Result<Chip, IoError> {
         let target = target.as_ref();
-        if target.contains("-esp32-") {
-            Ok(Chip::ESP32)
-        } else if target.contains("-esp32s2-") {
-            Ok(Chip::ESP32S2)
-        } else if target.contains("-esp8266-") {
+        match Chip::for_target(target).as_slice() {
+            [chip] => Ok(*chip),
+            [] => Err(IoError::new(ErrorKind::InvalidInput, format!("Can't figure out chip from target '{}'; try specifying the --chip option", target))),
+            chips => {
+                let names = chips.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ");
+                Err(IoError::new(ErrorKind::InvalidInput, format!("Target '{}' is used by several chips ({}); try specifying the --chip option", target, names)))
+            },
+        }
+    }
+
+    // Xtensa targets name the chip, but RISC-V ones only name the
+    // instruction set, which several chips may share.
+    pub fn for_target<S: AsRef<str>>(target: S) -> Vec<Chip> {
+        let target = target.as_ref();
+        CHIP_SPECS
+            .iter()
+            .filter(|spec| match spec.arch {
+                Arch::Xtensa => target.contains(&format!("-{}-", spec.name)),
+                Arch::RiscV => target.starts_with(&format!("{}-", spec.rust_arch)),
+            })
+            .map(|spec| spec.chip)
+            .collect()
+    }
+
+    // Arduino fully qualified board names look like "esp32:esp32:esp32s2"
+    // or "esp8266:esp8266:nodemcuv2"; PlatformIO board ids like
+    // "esp32-c3-devkitm-1" are accepted too.
+    pub fn from_board<S: AsRef<str>>(board: S) -> Result<Chip, IoError> {
+        let board = board.as_ref().to_lowercase();
+        let name = board.rsplit(':').next().unwrap_or("").replace('-', "");
+        // The longest matching name wins, so "esp32c3..." isn't taken for
+        // a plain ESP32.
+        let by_name = CHIP_SPECS
+            .iter()
+            .filter(|spec| name.starts_with(spec.name))
+            .max_by_key(|spec| spec.name.len())
+            .map(|spec| spec.chip);
+        if board.starts_with("esp8266:") {
             Ok(Chip::ESP8266)
+        } else if let Some(chip) = by_name {
+            Ok(chip)
+        } else if board.starts_with("esp32:") {
+            Ok(Chip::ESP32)
+        } else if name.starts_with("nodemcu") || name.starts_with("d1_mini") {
+            // These are ESP8266 boards, apart from ESP32 versions like the
+            // "nodemcu-32s".
+            Ok(if name.contains("32") { Chip::ESP32 } else { Chip::ESP8266 })
         } else {
-            Err(IoError::new(ErrorKind::InvalidInput, format!("Can't figure out chip from target '{}'; try specifying the --chip option", target)))
+            Err(IoError::new(ErrorKind::InvalidInput, format!("Can't figure out chip from board '{}'; try specifying the --chip option", board)))
         }
     }
+
+    pub fn all() -> impl Iterator<Item = Chip> {
+        CHIP_SPECS.iter().map(|spec| spec.chip)
+    }
 }
 
 impl Chip {
+    pub fn spec(&self) -> &'static ChipSpec {
+        CHIP_SPECS.iter().find(|spec| spec.chip == *self).expect("Every chip has a spec")
+    }
+
+    pub fn code_ranges(&self) -> &'static [Range<u64>] {
+        self.spec().code_ranges
+    }
+
+    pub fn flash_code_range(&self) -> Range<u64> {
+        self.spec().flash_code_range.clone()
+    }
+
+    pub fn tool_prefix(&self) -> &'static str {
+        self.spec().tool_prefix
+    }
+
+    pub fn is_code_address(&self, addr: u64) -> bool {
+        self.code_ranges().iter().any(|range| range.contains(&addr))
+    }
+
     pub fn target(&self, framework: Framework) -> String {
-        let mut target = String::new();
-        target.push_str(match self {
-            Chip::ESP32C3 => "riscv32imc-",
-            _ => "xtensa-",
-        });
-        target.push_str(match self {
-            Chip::ESP32 => "esp32-",
-            Chip::ESP32S2 => "esp32s2-",
-            Chip::ESP8266 => "esp8266-",
-            Chip::ESP32C3 => match framework {
-                Framework::Baremetal => "unknown-",
-                Framework::EspIdf => "esp-"
-            }
-        });
-        target.push_str(match framework {
-            Framework::Baremetal => "none-elf",
-            Framework::EspIdf=> "espidf",
-        });
-        target
+        let spec = self.spec();
+        // Arduino-ESP32 is built on ESP-IDF, so Rust code in such a
+        // project uses the ESP-IDF targets.
+        let (vendor, os) = match framework {
+            Framework::Baremetal => ("unknown", "none-elf"),
+            Framework::EspIdf | Framework::Arduino => ("esp", "espidf"),
+        };
+        match spec.arch {
+            Arch::Xtensa => format!("{}-{}-{}", spec.rust_arch, spec.name, os),
+            Arch::RiscV => format!("{}-{}-{}", spec.rust_arch, vendor, os),
+        }
     }
 }
 
 impl TryFrom<&str> for Chip {
     type Error = IoError;
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value {
-            "esp32" => Ok(Chip::ESP32),
-            "esp32c3" => Ok(Chip::ESP32C3),
-            "esp8266" => Ok(Chip::ESP8266),
-            _ => Err(IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid chip", value))),
-        }
+        // Espressif writes the names as "ESP32-S3", so take that too.
+        let name = value.to_lowercase().replace(&['-', '_'][..], "");
+        CHIP_SPECS
+            .iter()
+            .find(|spec| spec.name == name)
+            .map(|spec| spec.chip)
+            .ok_or_else(|| {
+                let valid = Chip::all().map(|chip| chip.to_string()).collect::<Vec<_>>().join(", ");
+                IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid chip (expected one of {})", value, valid))
+            })
+    }
+}
+
+impl std::fmt::Display for Chip {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.spec().name)
     }
 }
 
@@ -122,12 +387,186 @@ impl Default for Chip {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct AppArgs {
     pub serial: String,
+    pub port_selector: Option<PortSelector>,
+    pub wait_for_port: bool,
     pub chip: Chip,
+    // The chip wasn't given, so take it from the ROM's boot banner.
+    pub detect_chip: bool,
     pub framework: Framework,
     pub speed: Option<usize>,
+    pub latency: Latency,
+    pub read_buffer_size: Option<NonZeroUsize>,
+    pub frame_format: Option<FrameFormat>,
+    pub flow_control: Option<FlowControl>,
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+    // Drop DTR and RTS when the monitor exits, rather than leaving them to
+    // the driver.
+    pub deassert_on_exit: bool,
+    // Let other programs open the port while it's being monitored.
+    pub shared: bool,
+    // Never change DTR or RTS, for boards that reset whenever they move.
+    pub no_touch: bool,
     pub reset: bool,
+    pub reset_mode: Option<ResetMode>,
+    pub reset_delay: Option<Duration>,
+    pub reset_behavior: ResetBehavior,
+    pub output_format: OutputFormat,
+    pub bin: Option<OsString>,
+    // Other images whose addresses may show up, e.g. the bootloader.
+    pub extra_bins: Vec<OsString>,
+    // Where in flash the running app is, and where the ELF expects it to be
+    // (0x10000 if not given).
+    pub app_offset: Option<u64>,
+    pub link_offset: Option<u64>,
+    pub decode_cache_size: Option<usize>,
+    pub show_source: bool,
+    // Where to look for sources that were built somewhere else.
+    pub source_path: Option<OsString>,
+    pub path_remaps: Vec<PathRemap>,
+    pub demangle: bool,
+    pub scrollback_lines: Option<usize>,
+    pub flash_cmd: Option<String>,
+    pub watch: Option<OsString>,
+    pub line_timeout: Option<Duration>,
+    pub stream: bool,
+    // Wrap long lines to the terminal width, with a hanging indent.
+    pub wrap: bool,
+    pub stats_interval: Option<Duration>,
+    pub dedup: bool,
+    pub highlight_changes: bool,
+    pub highlights: Vec<HighlightRule>,
+    // Track the free heap from the usual log lines, and anything else
+    // the gauge rules pick out, in a status line.
+    pub heap_gauge: bool,
+    pub gauges: Vec<GaugeRule>,
+    // Numbers captured by extract go to a CSV file at extract_out, and/or
+    // get plotted above the status line.
+    pub extract: Option<Regex>,
+    pub extract_out: Option<OsString>,
+    pub plot: bool,
+    // Explain the NMEA sentences and AT commands in the log.
+    pub protocols: Vec<Protocol>,
+    pub framing: Vec<Framing>,
+    // Close the port for a while when a flasher starts using it.
+    pub yield_to_flasher: bool,
+    // Open the port even if another program's lock file says it has it.
+    pub force: bool,
+    // Applies to --log and --log-json files.
+    pub log_rotation: Option<LogRotation>,
+    pub match_actions: Vec<MatchAction>,
+    pub notify: bool,
+    pub notify_keywords: Vec<String>,
+    pub sinks: Vec<SinkConfig>,
+    pub non_interactive: bool,
+    pub exit_after: Option<Duration>,
+    pub record: Option<OsString>,
+    // A file to send once the port is open, and how to send files.
+    pub send_file: Option<OsString>,
+    pub send_mode: TransferMode,
+    // An expect/send script to run against the device.
+    pub script: Option<OsString>,
+    // `espmonitor test`: flash with flash_cmd, then collect test results
+    // until the run ends or test_timeout passes.
+    pub test: bool,
+    pub test_timeout: Option<Duration>,
+    // Where to write script or test results as JUnit XML, and whether to
+    // print failures as GitHub Actions annotations.
+    pub junit: Option<OsString>,
+    pub github_annotations: bool,
+    // A FIFO other programs can write marker lines to.
+    pub marker_pipe: Option<OsString>,
+    // Where to accept commands from scripts: a localhost port or unix:PATH.
+    pub control: Option<String>,
+    // Where to write a report for every panic, and how many lines before
+    // it to include.
+    pub crash_dir: Option<OsString>,
+    pub crash_context: Option<usize>,
+    // Write crash reports as Markdown, ready to paste into a bug tracker.
+    pub markdown_reports: bool,
+    // Where to also write the summary printed on exit.
+    pub summary: Option<OsString>,
+    pub summary_json: Option<OsString>,
+    // Makes a new directory in here for everything the session produces.
+    pub session_dir: Option<OsString>,
+    // Overrides the chip's usual toolchain prefix, e.g. for the unified
+    // "xtensa-esp-elf-" toolchains or one that isn't on the PATH.
+    pub toolchain_prefix: Option<String>,
+    // The addr2line to use, overriding the toolchain prefix.
+    pub addr2line: Option<OsString>,
+    // Times processing generated output instead of monitoring a device.
+    pub throughput_test: bool,
+    // Listen briefly and say whether the speed looks right.
+    pub probe: bool,
+    // Try the usual speeds after opening the port, and stick with the one
+    // that gives readable output.
+    pub detect_speed: bool,
+    // The flash image to run in QEMU instead of monitoring a device.
+    pub qemu: Option<OsString>,
+}
+
+impl AppArgs {
+    pub fn reset_mode(&self) -> ResetMode {
+        self.reset_mode.clone().unwrap_or_else(|| ResetMode::default_for(self.chip))
+    }
+
+    // Asked in order about addresses the built-in decoder can't place.
+    pub fn addr2line_tools(&self) -> Vec<OsString> {
+        toolchain::addr2line_tools(self.chip, self.toolchain_prefix.as_deref(), self.addr2line.as_deref())
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplayArgs {
+    pub file: OsString,
     pub bin: Option<OsString>,
+    pub extra_bins: Vec<OsString>,
+    pub chip: Chip,
+    // Playback speed relative to the original; 0 means as fast as possible.
+    pub rate: f64,
+}
+
+#[derive(Debug)]
+pub struct DecodeArgs {
+    // The saved log; standard input if not given.
+    pub file: Option<OsString>,
+    pub bin: Option<OsString>,
+    pub extra_bins: Vec<OsString>,
+    pub chip: Chip,
+    pub detect_chip: bool,
+    pub output_format: OutputFormat,
+    pub show_source: bool,
+    pub source_path: Option<OsString>,
+    pub path_remaps: Vec<PathRemap>,
+    pub demangle: bool,
+    pub app_offset: Option<u64>,
+    pub link_offset: Option<u64>,
+    pub toolchain_prefix: Option<String>,
+    pub addr2line: Option<OsString>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn figures_out_chips_from_boards() {
+        for (board, chip) in &[
+            ("esp32:esp32:esp32s2", Chip::ESP32S2),
+            ("esp32:esp32:nodemcu-32s", Chip::ESP32),
+            ("esp32:esp32:d1_mini32", Chip::ESP32),
+            ("esp32:esp32:featheresp32", Chip::ESP32),
+            ("esp8266:esp8266:nodemcuv2", Chip::ESP8266),
+            ("esp32-c3-devkitm-1", Chip::ESP32C3),
+            ("nodemcu-32s", Chip::ESP32),
+            ("nodemcuv2", Chip::ESP8266),
+            ("d1_mini", Chip::ESP8266),
+        ] {
+            assert_eq!(Chip::from_board(board).unwrap(), *chip, "{}", board);
+        }
+        assert!(Chip::from_board("uno").is_err());
+    }
 }
@@ -0,0 +1,88 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{borrow::Cow, mem, str};
+
+// Decodes serial data as UTF-8 across reads.  A multibyte character can
+// straddle two reads, so an incomplete sequence at the end of a chunk is
+// held back until the rest arrives instead of becoming U+FFFD.
+pub(crate) struct Utf8Decoder {
+    pending: Vec<u8>,
+}
+
+impl Utf8Decoder {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::new(),
+        }
+    }
+
+    // Returns the decoded text and the number of bytes that weren't valid
+    // UTF-8 (and were replaced).  The usual case of a whole, valid chunk is
+    // just borrowed.
+    pub fn decode<'a>(&mut self, buf: &'a [u8]) -> (Cow<'a, str>, usize) {
+        if self.pending.is_empty() {
+            if let Ok(text) = str::from_utf8(buf) {
+                return (Cow::Borrowed(text), 0);
+            }
+        }
+
+        let mut data = mem::take(&mut self.pending);
+        data.extend_from_slice(buf);
+
+        let mut text = String::with_capacity(data.len());
+        let mut garbled = 0;
+        let mut rest = &data[..];
+        loop {
+            match str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    break;
+                },
+                Err(err) => {
+                    let (valid, after) = rest.split_at(err.valid_up_to());
+                    // Safe to unwrap: from_utf8 just said this much is valid.
+                    text.push_str(str::from_utf8(valid).unwrap());
+                    match err.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            garbled += len;
+                            rest = &after[len..];
+                        },
+                        None => {
+                            self.pending = after.to_vec();
+                            break;
+                        },
+                    }
+                },
+            }
+        }
+
+        (Cow::Owned(text), garbled)
+    }
+
+    // Whatever is still held back once the input ends is never going to be
+    // completed.
+    pub fn finish(&mut self) -> (String, usize) {
+        let pending = mem::take(&mut self.pending);
+        if pending.is_empty() {
+            (String::new(), 0)
+        } else {
+            (char::REPLACEMENT_CHARACTER.to_string(), pending.len())
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Build output and VCS metadata change as a result of building and
+// flashing, so watching them would just trigger an endless rebuild loop.
+const IGNORED_DIRS: &[&str] = &["target", "build", "node_modules"];
+
+// Polls modification times rather than using OS notification APIs; source
+// trees are small enough that a scan per second is cheap, and it behaves
+// the same everywhere (including network and container mounts).
+pub(crate) struct PathWatcher {
+    root: PathBuf,
+    newest: Option<SystemTime>,
+    last_poll_at: Instant,
+}
+
+impl PathWatcher {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        let root = root.as_ref().to_path_buf();
+        let newest = newest_mtime(&root);
+        Self {
+            root,
+            newest,
+            last_poll_at: Instant::now(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn changed(&mut self) -> bool {
+        if self.last_poll_at.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll_at = Instant::now();
+
+        let newest = newest_mtime(&self.root);
+        if newest > self.newest {
+            self.newest = newest;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn rescan(&mut self) {
+        self.newest = newest_mtime(&self.root);
+        self.last_poll_at = Instant::now();
+    }
+}
+
+fn newest_mtime(path: &Path) -> Option<SystemTime> {
+    let metadata = fs::metadata(path).ok()?;
+    let mtime = metadata.modified().ok();
+
+    if metadata.is_dir() {
+        fs::read_dir(path)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                !name.starts_with('.') && !IGNORED_DIRS.contains(&name.as_ref())
+            })
+            .filter_map(|entry| newest_entry_mtime(&entry.path()))
+            .chain(mtime)
+            .max()
+    } else {
+        mtime
+    }
+}
+
+// Symlinked files are followed, but not symlinked directories, which may
+// lead back up the tree.
+fn newest_entry_mtime(path: &Path) -> Option<SystemTime> {
+    if fs::symlink_metadata(path).ok()?.file_type().is_symlink() && path.is_dir() {
+        None
+    } else {
+        newest_mtime(path)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::{env, os::unix::fs::symlink, process};
+
+    #[test]
+    fn skips_symlinked_directories() {
+        let root = env::temp_dir().join(format!("espmonitor-watch-{}", process::id()));
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src").join("main.c"), "int main() {}\n").unwrap();
+        symlink(&root, root.join("src").join("loop")).unwrap();
+        symlink(root.join("src").join("main.c"), root.join("main.c")).unwrap();
+
+        let newest = newest_mtime(&root);
+        let file = fs::metadata(root.join("src").join("main.c")).unwrap().modified().ok();
+        fs::remove_dir_all(&root).unwrap();
+        assert!(newest >= file);
+    }
+}
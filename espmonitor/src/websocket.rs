@@ -0,0 +1,287 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    convert::TryInto,
+    io::{self, Error as IoError, ErrorKind, Read, Write},
+    net::TcpStream,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+
+// Serial output comes in small frames; anything this big is a broken or
+// hostile server, not data worth buffering.
+const MAX_FRAME_LEN: u64 = 1024 * 1024;
+// Control frames can't be longer than this (RFC 6455 section 5.5).
+const MAX_CONTROL_FRAME_LEN: u64 = 125;
+
+// Just enough of a WebSocket client (RFC 6455, ws:// only) to read serial
+// output from simulators such as Wokwi, which expose it that way.  Text and
+// binary frames are both treated as raw serial data.
+pub(crate) struct WebSocket {
+    stream: TcpStream,
+    raw: Vec<u8>,
+    payload: Vec<u8>,
+    closed: bool,
+}
+
+impl WebSocket {
+    pub fn connect(url: &str, read_timeout: Duration) -> io::Result<Self> {
+        let invalid = || IoError::new(ErrorKind::InvalidInput, format!("'{}' is not a valid WebSocket URL (expected ws://host[:port][/path])", url));
+
+        let rest = url.strip_prefix("ws://").ok_or_else(invalid)?;
+        let (hostport, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match hostport.rfind(':') {
+            Some(colon) => (&hostport[..colon], hostport[colon + 1..].parse::<u16>().map_err(|_| invalid())?),
+            None => (hostport, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        let mut stream = TcpStream::connect((host, port))?;
+        // The key only has to be unique-ish; nothing here is a secret.
+        let nonce = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0).to_le_bytes();
+        write!(
+            stream,
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            path,
+            hostport,
+            base64(&nonce),
+        )?;
+
+        // Read the response headers a byte at a time so none of the first
+        // frame is consumed along with them.
+        let mut response = Vec::new();
+        let mut byte = [0u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            if stream.read(&mut byte)? == 0 {
+                return Err(IoError::new(ErrorKind::UnexpectedEof, "WebSocket server closed the connection during the handshake"));
+            }
+            response.push(byte[0]);
+        }
+        let status_line = String::from_utf8_lossy(&response).lines().next().unwrap_or("").to_string();
+        if status_line.split_whitespace().nth(1) != Some("101") {
+            return Err(IoError::new(ErrorKind::ConnectionRefused, format!("WebSocket handshake failed: {}", status_line)));
+        }
+
+        stream.set_read_timeout(Some(read_timeout))?;
+        Ok(Self {
+            stream,
+            raw: Vec::new(),
+            payload: Vec::new(),
+            closed: false,
+        })
+    }
+
+    // Decodes as many complete frames as have arrived.
+    fn parse_frames(&mut self) -> io::Result<()> {
+        loop {
+            if self.raw.len() < 2 {
+                return Ok(());
+            }
+            let opcode = self.raw[0] & 0x0f;
+            let masked = self.raw[1] & 0x80 != 0;
+            let (len, mut offset) = match self.raw[1] & 0x7f {
+                126 if self.raw.len() >= 4 => (u64::from(u16::from_be_bytes(self.raw[2..4].try_into().expect("slice is 2 bytes"))), 4),
+                127 if self.raw.len() >= 10 => (u64::from_be_bytes(self.raw[2..10].try_into().expect("slice is 8 bytes")), 10),
+                126 | 127 => return Ok(()),
+                len => (u64::from(len), 2usize),
+            };
+            let max_len = if opcode & 0x08 != 0 { MAX_CONTROL_FRAME_LEN } else { MAX_FRAME_LEN };
+            if len > max_len {
+                return Err(IoError::new(ErrorKind::InvalidData, format!("WebSocket frame of {} bytes is too long", len)));
+            }
+            let len = len as usize;
+            let mask = if masked {
+                if self.raw.len() < offset + 4 {
+                    return Ok(());
+                }
+                offset += 4;
+                Some([self.raw[offset - 4], self.raw[offset - 3], self.raw[offset - 2], self.raw[offset - 1]])
+            } else {
+                None
+            };
+            let end = offset.checked_add(len).ok_or_else(|| IoError::new(ErrorKind::InvalidData, "WebSocket frame is too long"))?;
+            if self.raw.len() < end {
+                return Ok(());
+            }
+
+            let mut data = self.raw[offset..end].to_vec();
+            if let Some(mask) = mask {
+                for (i, b) in data.iter_mut().enumerate() {
+                    *b ^= mask[i % 4];
+                }
+            }
+            self.raw.drain(..end);
+
+            match opcode {
+                OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY => self.payload.extend_from_slice(&data),
+                OPCODE_CLOSE => self.closed = true,
+                OPCODE_PING => self.send_frame(0xa, &data)?,
+                _ => (),
+            }
+        }
+    }
+
+    // Frames from clients must be masked; the mask itself doesn't matter.
+    fn send_frame(&mut self, opcode: u8, data: &[u8]) -> io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        if data.len() < 126 {
+            frame.push(0x80 | data.len() as u8);
+        } else {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        }
+        frame.extend_from_slice(&[0, 0, 0, 0]);
+        frame.extend_from_slice(data);
+        self.stream.write_all(&frame)
+    }
+}
+
+impl Read for WebSocket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0u8; 4096];
+        while self.payload.is_empty() {
+            if self.closed {
+                return Ok(0);
+            }
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.closed = true,
+                Ok(bytes) => {
+                    self.raw.extend_from_slice(&chunk[..bytes]);
+                    self.parse_frames()?;
+                },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Err(IoError::new(ErrorKind::TimedOut, "read timed out")),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let bytes = buf.len().min(self.payload.len());
+        buf[..bytes].copy_from_slice(&self.payload[..bytes]);
+        self.payload.drain(..bytes);
+        Ok(bytes)
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    // Accepts one client, completes the handshake, sends `frames` and
+    // returns whatever the client sent back.
+    fn serve(frames: Vec<u8>) -> (String, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}/serial", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut byte = [0u8; 1];
+            while !request.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            assert!(request.starts_with(b"GET /serial HTTP/1.1\r\n"));
+            stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\n\r\n").unwrap();
+            stream.write_all(&frames).unwrap();
+            stream.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+            let mut received = Vec::new();
+            let _ = stream.read_to_end(&mut received);
+            received
+        });
+        (url, server)
+    }
+
+    fn read_all(socket: &mut WebSocket) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 64];
+        loop {
+            match socket.read(&mut buf)? {
+                0 => return Ok(data),
+                bytes => data.extend_from_slice(&buf[..bytes]),
+            }
+        }
+    }
+
+    #[test]
+    fn reads_frames() {
+        let mut frames = vec![0x01, 0x05];
+        frames.extend_from_slice(b"hello");
+        frames.extend_from_slice(&[0x80, 0x80 | 0x03, 1, 2, 3, 4, b' ' ^ 1, b'y' ^ 2, b'o' ^ 3]);
+        frames.extend_from_slice(&[0x82, 126, 0x00, 0x02]);
+        frames.extend_from_slice(b"!\n");
+        frames.extend_from_slice(&[0x89, 0x02]);
+        frames.extend_from_slice(b"hi");
+        frames.extend_from_slice(&[0x88, 0x00]);
+        let (url, server) = serve(frames);
+
+        let mut socket = WebSocket::connect(&url, Duration::from_secs(5)).unwrap();
+        assert_eq!(read_all(&mut socket).unwrap(), b"hello yo!\n");
+        drop(socket);
+        assert_eq!(server.join().unwrap(), &[0x8a, 0x82, 0, 0, 0, 0, b'h', b'i']);
+    }
+
+    #[test]
+    fn rejects_huge_frames() {
+        let mut frames = vec![0x82, 127];
+        frames.extend_from_slice(&u64::MAX.to_be_bytes());
+        let (url, server) = serve(frames);
+
+        let mut socket = WebSocket::connect(&url, Duration::from_secs(5)).unwrap();
+        assert_eq!(read_all(&mut socket).unwrap_err().kind(), ErrorKind::InvalidData);
+        drop(socket);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_long_control_frames() {
+        let mut frames = vec![0x89, 126, 0x01, 0x00];
+        frames.extend_from_slice(&[0; 256]);
+        let (url, server) = serve(frames);
+
+        let mut socket = WebSocket::connect(&url, Duration::from_secs(5)).unwrap();
+        assert_eq!(read_all(&mut socket).unwrap_err().kind(), ErrorKind::InvalidData);
+        drop(socket);
+        server.join().unwrap();
+    }
+}
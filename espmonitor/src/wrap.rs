@@ -0,0 +1,92 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Continuation rows are indented this much, so they're easy to tell apart
+// from the lines the device printed.
+const HANGING_INDENT: usize = 4;
+// Narrower than this and the indent eats most of the row.
+const MIN_WIDTH: usize = 20;
+
+// The terminal's width in columns, or 0 if lines shouldn't be wrapped
+// (not a terminal, or --no-wrap).
+static WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn enable(width: usize) {
+    WIDTH.store(width.max(MIN_WIDTH), Ordering::Relaxed);
+}
+
+// Called on terminal resize events; does nothing if wrapping is off.
+pub(crate) fn resize(width: usize) {
+    let _ = WIDTH.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+        if current == 0 { None } else { Some(width.max(MIN_WIDTH)) }
+    });
+}
+
+// Splits a line into rows that fit the terminal. Escape sequences don't
+// take up any columns, and colors that are active at a break are reset at
+// the end of the row and set again after the next row's indent, so the
+// indent itself isn't colored. Each char is assumed to be one column wide.
+pub(crate) fn wrap(line: &str) -> Vec<String> {
+    let width = WIDTH.load(Ordering::Relaxed);
+    if width == 0 || line.chars().count() <= width {
+        return vec![line.to_string()];
+    }
+
+    let mut rows = Vec::new();
+    let mut row = String::new();
+    let mut columns = 0;
+    let mut active_sgr = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            let mut sequence = String::from(c);
+            sequence.push(chars.next().unwrap_or('['));
+            for c in chars.by_ref() {
+                sequence.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            if sequence.ends_with('m') {
+                if sequence == "\x1b[0m" || sequence == "\x1b[m" {
+                    active_sgr.clear();
+                } else {
+                    active_sgr.push_str(&sequence);
+                }
+            }
+            row.push_str(&sequence);
+            continue;
+        }
+
+        if columns == width {
+            if !active_sgr.is_empty() {
+                row.push_str("\x1b[0m");
+            }
+            rows.push(row);
+            row = " ".repeat(HANGING_INDENT);
+            row.push_str(&active_sgr);
+            columns = HANGING_INDENT;
+        }
+        row.push(c);
+        columns += 1;
+    }
+    rows.push(row);
+
+    rows
+}
@@ -0,0 +1,19 @@
+struct sensor {
+    int value;
+};
+
+static int read_sensor(struct sensor *sensor)
+{
+    return sensor->value;
+}
+
+int process_reading(struct sensor *sensor)
+{
+    int value = read_sensor(sensor);
+    return value * 2;
+}
+
+void app_main(void)
+{
+    process_reading((struct sensor *)0);
+}
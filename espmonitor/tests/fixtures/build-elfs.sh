@@ -0,0 +1,18 @@
+#!/bin/sh
+# Rebuilds the fixtures' ELF files from app.c (see README.md).
+#
+# They're built for the host rather than with the ESP toolchains, but
+# linked at the addresses the chips run code from, which is all that
+# decoding needs.  Run from this directory, then update the "ELF file
+# SHA256" lines in the logs and re-bless the expected output.
+set -e
+
+build() {
+    gcc -g -O0 -nostdlib -static -fno-pie -no-pie -fno-asynchronous-unwind-tables \
+        -fdebug-prefix-map="$(pwd)"=/fixtures -Wl,-Ttext="$2" -Wl,--build-id=none \
+        -e app_main -o "$1/app.elf" app.c
+}
+
+build esp32-panic 0x400d0020
+build esp32c3-panic 0x42000020
+build esp8266-exception 0x40201010
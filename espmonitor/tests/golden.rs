@@ -0,0 +1,116 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+// Runs the device logs under tests/fixtures through the same processing
+// as `espmonitor decode`, and compares what comes out with the expected
+// output next to them.  To add a fixture, make a directory with an
+// input.log (and an app.elf, if the addresses should be decoded), then
+// run with ESPMONITOR_BLESS=1 to write its expected.txt and check that
+// over by hand.
+
+use espmonitor::{AppArgs, run_source};
+use std::{
+    env,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+const BLESS_VAR: &str = "ESPMONITOR_BLESS";
+
+fn fixtures() -> Vec<PathBuf> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures");
+    let mut fixtures = fs::read_dir(&root)
+        .expect("Unable to read the fixtures directory")
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.join("input.log").is_file())
+        .collect::<Vec<_>>();
+    fixtures.sort();
+    fixtures
+}
+
+fn decode(fixture: &Path) -> String {
+    let input = fixture.join("input.log");
+    let elf = fixture.join("app.elf");
+    let args = AppArgs {
+        serial: input.to_string_lossy().into_owned(),
+        detect_chip: true,
+        bin: if elf.is_file() { Some(elf.into_os_string()) } else { None },
+        demangle: true,
+        non_interactive: true,
+        ..AppArgs::default()
+    };
+
+    let mut source = File::open(&input).expect("Unable to open input.log");
+    let mut output = Vec::new();
+    run_source(&args, &mut source, &mut output).expect("Unable to decode input.log");
+    plain(&String::from_utf8_lossy(&output))
+}
+
+// Drops colors and CRs, so the expected output is readable and diffable.
+fn plain(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            },
+            '\r' => (),
+            c => plain.push(c),
+        }
+    }
+    plain
+}
+
+#[test]
+fn decoded_output_matches_expected() {
+    let bless = env::var_os(BLESS_VAR).is_some();
+    let mut failures = Vec::new();
+
+    for fixture in fixtures() {
+        let name = fixture.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let actual = decode(&fixture);
+        let expected_path = fixture.join("expected.txt");
+        if bless {
+            fs::write(&expected_path, &actual).expect("Unable to write expected.txt");
+            continue;
+        }
+
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+        if actual != expected {
+            let line = actual.lines().zip(expected.lines()).position(|(a, e)| a != e)
+                .unwrap_or_else(|| actual.lines().count().min(expected.lines().count()));
+            failures.push(format!(
+                "{}: output differs from expected.txt at line {}\n  expected: {:?}\n    actual: {:?}",
+                name,
+                line + 1,
+                expected.lines().nth(line).unwrap_or("<end of output>"),
+                actual.lines().nth(line).unwrap_or("<end of output>"),
+            ));
+        }
+    }
+
+    assert!(failures.is_empty(), "{}\n\nIf the change is intended, re-run with {}=1 and review the diff.", failures.join("\n"), BLESS_VAR);
+}
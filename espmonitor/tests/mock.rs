@@ -0,0 +1,118 @@
+// Copyright 2021 Brian J. Tarricone <brian@tarricone.org>
+//
+// This file is part of ESPMonitor.
+//
+// ESPMonitor is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// ESPMonitor is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with ESPMonitor.  If not, see <https://www.gnu.org/licenses/>.
+
+
+// Runs the monitor against a MockPort, to check what it does to a device
+// and how it copes with data arriving in odd pieces.
+
+use espmonitor::{AppArgs, Chip, MockPort, ResetMode, ResetStep, SerialSource, run_source};
+use std::time::Duration;
+
+fn args(chip: Chip) -> AppArgs {
+    AppArgs {
+        serial: "mock".to_string(),
+        chip,
+        reset_delay: Some(Duration::from_millis(1)),
+        non_interactive: true,
+        ..AppArgs::default()
+    }
+}
+
+fn run(args: &AppArgs, dev: &mut MockPort) -> String {
+    let mut output = Vec::new();
+    run_source(args, dev, &mut output).expect("Monitoring the mock port failed");
+    String::from_utf8_lossy(&output).replace('\r', "")
+}
+
+#[test]
+fn resets_classic_devkits() {
+    let mut dev = MockPort::new().data("boot\r\n");
+    run(&AppArgs { reset: true, ..args(Chip::ESP32) }, &mut dev);
+    assert_eq!(dev.control_changes(), &[ResetStep::Dtr(false), ResetStep::Rts(true), ResetStep::Rts(false)]);
+}
+
+#[test]
+fn resets_usb_jtag_chips() {
+    let mut dev = MockPort::new().data("boot\r\n");
+    run(&AppArgs { reset: true, ..args(Chip::ESP32C3) }, &mut dev);
+    assert_eq!(dev.control_changes(), &[ResetStep::Dtr(false), ResetStep::Rts(true), ResetStep::Dtr(false), ResetStep::Rts(false)]);
+}
+
+#[test]
+fn runs_custom_reset_sequences_then_sets_line_levels() {
+    let mut dev = MockPort::new();
+    let args = AppArgs {
+        reset: true,
+        reset_mode: Some(ResetMode::custom("R1|D1|W0|R0").unwrap()),
+        dtr: Some(false),
+        ..args(Chip::ESP32)
+    };
+    run(&args, &mut dev);
+    assert_eq!(dev.control_changes(), &[ResetStep::Rts(true), ResetStep::Dtr(true), ResetStep::Rts(false), ResetStep::Dtr(false)]);
+}
+
+#[test]
+fn leaves_control_lines_alone_without_reset() {
+    let mut dev = MockPort::new().data("hello\r\n");
+    run(&args(Chip::ESP32), &mut dev);
+    assert!(dev.control_changes().is_empty());
+    assert!(dev.sent().is_empty());
+}
+
+#[test]
+fn assembles_lines_across_reads() {
+    let mut dev = MockPort::new()
+        .data("I (10) app: hel")
+        .wait(Duration::from_millis(20))
+        .data("lo\r")
+        .data("\nI (11) app: wor")
+        .data("ld\r\n");
+    let output = run(&args(Chip::ESP32), &mut dev);
+    let lines = output.lines().collect::<Vec<_>>();
+    assert!(lines.contains(&"I (10) app: hello"), "{:?}", lines);
+    assert!(lines.contains(&"I (11) app: world"), "{:?}", lines);
+    assert!(!lines.iter().any(|line| *line == "I (10) app: hel" || *line == "lo"), "{:?}", lines);
+}
+
+#[test]
+fn decodes_utf8_split_across_reads() {
+    let mut dev = MockPort::new().data(b"temp: 21\xc2").data(b"\xb0C\r\n");
+    let output = run(&args(Chip::ESP32), &mut dev);
+    assert!(output.lines().any(|line| line == "temp: 21°C"), "{:?}", output);
+}
+
+#[test]
+fn stops_at_disconnect() {
+    let mut dev = MockPort::new()
+        .data("before\r\npartial")
+        .disconnect()
+        .data("after\r\n");
+    let output = run(&args(Chip::ESP32), &mut dev);
+    assert!(output.lines().any(|line| line == "before"), "{:?}", output);
+    // What was left of the last line is still shown.
+    assert!(output.lines().any(|line| line == "partial"), "{:?}", output);
+    assert!(!output.contains("after"), "{:?}", output);
+    assert!(dev.is_disconnected());
+}
+
+#[test]
+fn stops_when_the_data_runs_out() {
+    let mut dev = MockPort::new().data("only line\r\n");
+    let output = run(&AppArgs { exit_after: Some(Duration::from_secs(10)), ..args(Chip::ESP32) }, &mut dev);
+    assert!(output.lines().any(|line| line == "only line"), "{:?}", output);
+    assert!(dev.is_disconnected());
+}